@@ -0,0 +1,90 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use general_scp::ca::{
+    cell::{test_make_new_value_cell, Cell, CellData, InnerDelegateCell, InnerValueCell},
+    table::{find_value_cell, longest_prefix_match, Table, TableCollection, TableId},
+};
+
+const CELLS_PER_TABLE: usize = 100_000;
+
+fn value_cell(template: &Cell, value: String) -> Cell {
+    // Table operations do not check signatures, so every cell reuses the
+    // signature of the template instead of signing its own contents.
+    let mut cell = template.clone();
+    cell.inner = CellData::Value(InnerValueCell { value });
+    cell
+}
+
+fn make_table(template: &Cell, name_space: &str) -> Table {
+    let mut table = Table::new(0, name_space.to_owned());
+    for i in 0..CELLS_PER_TABLE {
+        table
+            .add_entry(value_cell(template, format!("{}{:08}", name_space, i)))
+            .unwrap();
+    }
+    table
+}
+
+fn make_tables(template: &Cell) -> TableCollection {
+    // A root table delegating "home/" to a second table, each holding
+    // `CELLS_PER_TABLE` value cells.
+    let mut tables = TableCollection::new(0);
+    let root_id = TableId::root();
+    let mut root = make_table(template, "");
+
+    let mut delegation = template.clone();
+    delegation.inner = CellData::Delegate(InnerDelegateCell {
+        name_space: "home/".to_owned(),
+        allowance: 0,
+        table: Some(TableId("home/".to_owned())),
+    });
+    root.add_entry(delegation).unwrap();
+
+    tables.0.insert(root_id, root);
+    tables
+        .0
+        .insert(TableId("home/".to_owned()), make_table(template, "home/"));
+    tables
+}
+
+fn table_index(c: &mut Criterion) {
+    let template = test_make_new_value_cell("".to_owned(), 0);
+    let tables = make_tables(&template);
+    let root_id = TableId::root();
+    let root = &tables.0[&root_id];
+
+    c.bench_function("check_cell_valid 100k", |b| {
+        let cell = value_cell(&template, "00050000x".to_owned());
+        b.iter(|| black_box(root.check_cell_valid(black_box(&cell))))
+    });
+
+    c.bench_function("add_entry 100k", |b| {
+        // Keys are fresh on every iteration, so the table grows slightly past
+        // `CELLS_PER_TABLE` while the benchmark runs.
+        let mut table = root.clone();
+        let mut i = 0;
+        b.iter(|| {
+            i += 1;
+            table
+                .add_entry(value_cell(&template, format!("x{:08}", i)))
+                .unwrap()
+        })
+    });
+
+    c.bench_function("find_value_cell through delegation 100k", |b| {
+        let key = "home/00050000".to_owned();
+        b.iter(|| black_box(find_value_cell(&tables, &root_id, black_box(&key))))
+    });
+
+    c.bench_function("longest_prefix_match through delegation 100k", |b| {
+        b.iter(|| {
+            black_box(longest_prefix_match(
+                &tables,
+                &root_id,
+                black_box("home/00050000/mail"),
+            ))
+        })
+    });
+}
+
+criterion_group!(benches, table_index);
+criterion_main!(benches);
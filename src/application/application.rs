@@ -1,7 +1,12 @@
 use std::{
     collections::HashMap,
     io,
-    sync::{Arc, Mutex},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime},
 };
 
 use tokio::{
@@ -12,7 +17,11 @@ use tokio::{
 
 use crate::{
     mock::state::MockStateDriverBuilder,
-    overlay::peer::{HPeer, PeerID},
+    overlay::{
+        close_loop::CloseLoopConfig,
+        peer::{HPeer, PeerID},
+        peer_node::PeerNode,
+    },
     overlay_impl::tcp_peer::TCPPeerBuilder,
     rpc::args::RpcArg,
     scp::local_node::LocalNodeInfo,
@@ -23,22 +32,41 @@ use super::{app_config::AppConfig, command::SCPCommand, work_queue::EventQueue};
 pub type PendingRequestQueue = UnboundedReceiver<RpcArg>;
 pub type RpcRequestWriteQueue = Arc<Mutex<UnboundedSender<RpcArg>>>;
 
+// How often the node loop runs the clock events that are due.
+const CLOCK_TICK: Duration = Duration::from_millis(100);
+
 pub fn start_local_node_server() {
     let herder_builder = MockStateDriverBuilder::new();
     let mut tcp_peer_builder = TCPPeerBuilder::new(herder_builder);
     let node_info = LocalNodeInfo::new(false, Default::default(), "node1".to_string());
 
     let tcp_peer = tcp_peer_builder.build_node(node_info);
-    let mut input = String::new();
+    let work_scheduler = tcp_peer.borrow().work_scheduler();
+
+    // Stdin is read on its own thread so the loop below keeps the clock going.
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || loop {
+        let mut input = String::new();
+        let line = io::stdin().read_line(&mut input).map(|_| input);
+        if tx.send(line).is_err() {
+            return;
+        }
+    });
 
     loop {
-        match io::stdin().read_line(&mut input) {
-            Ok(_) => {
+        match rx.recv_timeout(CLOCK_TICK) {
+            Ok(Ok(input)) => {
                 if let Some(cmd) = SCPCommand::parse(&input) {
                     match cmd {
                         SCPCommand::Nominate => {
                             tcp_peer.borrow_mut().slot_nominate_with_default_val(0);
                         }
+                        SCPCommand::Close => {
+                            tcp_peer
+                                .borrow_mut()
+                                .start_close_loop(CloseLoopConfig::default());
+                            PeerNode::schedule_close_loop(&tcp_peer);
+                        }
                         SCPCommand::Hello => {}
                     }
                     println!("{:?}", cmd);
@@ -46,8 +74,14 @@ pub fn start_local_node_server() {
                     println!("Invalid command.");
                 }
             }
-            Err(error) => println!("error: {}", error),
+            Ok(Err(error)) => println!("error: {}", error),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
         }
+
+        let scheduler = work_scheduler.borrow();
+        scheduler.advance_clock(SystemTime::now());
+        scheduler.execute_clock_events();
     }
 }
 
@@ -1,6 +1,7 @@
 #[derive(Debug)]
 pub enum SCPCommand {
     Nominate,
+    Close,
     Hello,
 }
 
@@ -8,6 +9,7 @@ impl SCPCommand {
     pub fn parse(s: &str) -> Option<Self> {
         match s.trim() {
             "nominate" => Some(SCPCommand::Nominate),
+            "close" => Some(SCPCommand::Close),
             "hello" => Some(SCPCommand::Hello),
             _ => None,
         }
@@ -15,6 +15,7 @@ use log::debug;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    ca::crypto::PublicKey,
     crypto::types::{Blake2Hash, Blake2Hashable},
     overlay::peer::PeerID,
     scp::{
@@ -37,6 +38,9 @@ pub type HQuorumSet = Rc<RefCell<QuorumSet>>;
 pub struct QuorumNode {
     pub node_id: NodeID,
     pub ip_addr: Option<SocketAddrV4>,
+    // PEM encoded key the node signs its envelopes with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
 }
 
 impl From<PeerID> for QuorumNode {
@@ -44,6 +48,7 @@ impl From<PeerID> for QuorumNode {
         Self {
             node_id: value,
             ip_addr: None,
+            public_key: None,
         }
     }
 }
@@ -57,14 +62,31 @@ pub fn make_quorum_node_for_test(node_idx: u16) -> QuorumNode {
         BASE_PORT + node_idx,
     ));
 
-    QuorumNode { node_id, ip_addr }
+    QuorumNode {
+        node_id,
+        ip_addr,
+        public_key: None,
+    }
 }
 
 impl QuorumNode {
     const TEST_DATA_DIR: &'static str = "quorum_node";
 
     pub fn new(node_id: NodeID, ip_addr: Option<SocketAddrV4>) -> QuorumNode {
-        QuorumNode { node_id, ip_addr }
+        QuorumNode {
+            node_id,
+            ip_addr,
+            public_key: None,
+        }
+    }
+
+    pub fn with_public_key(mut self, public_key: &PublicKey) -> Self {
+        self.public_key = public_key.to_public_key_pem();
+        self
+    }
+
+    pub fn decode_public_key(&self) -> Option<PublicKey> {
+        PublicKey::from_public_key_pem(self.public_key.as_ref()?)
     }
 
     pub fn write_toml(&self) {
@@ -148,14 +170,17 @@ impl QuorumSet {
         let node1 = QuorumNode {
             node_id: node_id1.into(),
             ip_addr: Some(sock1),
+            public_key: None,
         };
         let node2 = QuorumNode {
             node_id: node_id2.into(),
             ip_addr: Some(sock2),
+            public_key: None,
         };
         let node3 = QuorumNode {
             node_id: node_id3.into(),
             ip_addr: Some(sock3),
+            public_key: None,
         };
 
         let quorum_slice1 = QuorumSlice::from([node1.to_owned(), node2.to_owned()]);
@@ -314,14 +339,17 @@ mod tests {
         let node1 = QuorumNode {
             node_id: node_id1.into(),
             ip_addr: Some(sock1),
+            public_key: None,
         };
         let node2 = QuorumNode {
             node_id: node_id2.into(),
             ip_addr: Some(sock2),
+            public_key: None,
         };
         let node3 = QuorumNode {
             node_id: node_id3.into(),
             ip_addr: Some(sock3),
+            public_key: None,
         };
 
         let quorum_slice1 = QuorumSlice::from([node1.to_owned(), node2.to_owned()]);
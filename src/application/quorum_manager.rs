@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use log::info;
 
@@ -15,6 +15,14 @@ pub struct QuorumManager {
 }
 
 impl QuorumManager {
+    // Knows the local quorum set from the start, since statements the node
+    // emits are checked like any other.
+    pub fn new(local_quorum_set: &QuorumSet) -> Self {
+        let mut quorum_manager = Self::default();
+        quorum_manager.add_quorum_set(local_quorum_set);
+        quorum_manager
+    }
+
     pub fn get_quorum_set<N: NominationValue>(
         &self,
         statement: &SCPStatement<N>,
@@ -31,6 +39,11 @@ impl QuorumManager {
             info!("get_quorum_set: Quorum set added: {:?}", quorum_set);
         }
     }
+
+    // Forgets every quorum set whose hash is not in `in_use`.
+    pub fn retain_quorum_sets(&mut self, in_use: &BTreeSet<HashValue>) {
+        self.quorum_set_map.retain(|hash, _| in_use.contains(hash));
+    }
 }
 
 impl Default for QuorumManager {
@@ -40,3 +53,28 @@ impl Default for QuorumManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        mock::state::MockState,
+        scp::statement::{SCPStatement, SCPStatementNominate},
+    };
+
+    use super::*;
+
+    #[test]
+    fn local_quorum_set_is_known_from_the_start() {
+        let quorum_set = QuorumSet::default();
+        let statement: SCPStatement<MockState> =
+            SCPStatement::Nominate(SCPStatementNominate::new(&quorum_set, vec![], vec![]));
+
+        assert!(QuorumManager::default()
+            .get_quorum_set(&statement)
+            .is_none());
+        assert_eq!(
+            QuorumManager::new(&quorum_set).get_quorum_set(&statement),
+            Some(&quorum_set)
+        );
+    }
+}
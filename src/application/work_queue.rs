@@ -82,11 +82,38 @@ impl WorkScheduler {
         // self.main_thread_queue.borrow_mut().execute_tasks()
     }
 
+    pub fn now(&self) -> SystemTime {
+        *self.event_queue.borrow().clock.borrow().time_now()
+    }
+
     pub fn post_clock_event(&self, timestamp: &SystemTime, clock_event: HClockEvent) {
         self.event_queue
             .borrow_mut()
             .add_task(timestamp, clock_event)
     }
+
+    // Moves the clock forward, e.g. to the wall clock for a real node.
+    pub fn advance_clock(&self, time_now: SystemTime) {
+        self.event_queue
+            .borrow()
+            .clock
+            .borrow_mut()
+            .set_current_virtual_time(time_now);
+    }
+
+    // Runs the clock events that are due. The queue is not borrowed while the
+    // callbacks run, so they can post new events.
+    pub fn execute_clock_events(&self) -> usize {
+        let events = self.event_queue.borrow_mut().take_expired();
+        let mut num_executed = 0;
+        for event in events {
+            if let Some(event) = event.replace(None) {
+                (event.callback)();
+                num_executed += 1;
+            }
+        }
+        num_executed
+    }
 }
 
 struct MainWorkQueue {
@@ -162,6 +189,14 @@ impl EventQueue {
     }
 
     pub fn execute_task(&mut self) {
+        for cb in self.take_expired() {
+            if let Some(event) = cb.replace(None) {
+                (event.callback)();
+            }
+        }
+    }
+
+    fn take_expired(&mut self) -> Vec<HClockEvent> {
         let mut elapsed_timestamps = vec![];
 
         // TODO: Can we avoid copying system time?
@@ -171,14 +206,10 @@ impl EventQueue {
             }
         }
 
-        for ts in elapsed_timestamps {
-            let callbacks = self.tasks.remove(&ts).unwrap();
-            for cb in callbacks {
-                if let Some(event) = cb.replace(None) {
-                    (event.callback)();
-                }
-            }
-        }
+        elapsed_timestamps
+            .into_iter()
+            .flat_map(|ts| self.tasks.remove(&ts).unwrap())
+            .collect()
     }
 }
 
@@ -237,4 +268,28 @@ mod tests {
         assert_eq!(*pt.lock().unwrap(), 2);
         assert_eq!(work_scheduler.main_thread_queue.borrow().tasks.len(), 0);
     }
+
+    #[test]
+    fn clock_events_run_once_due_and_can_post_more() {
+        let work_scheduler = Rc::new(WorkScheduler::default());
+        let start = work_scheduler.now();
+        let due = start + std::time::Duration::from_secs(1);
+        let fired = Rc::new(RefCell::new(vec![]));
+
+        let fired_copy = fired.clone();
+        let scheduler_copy = work_scheduler.clone();
+        let callback = move || {
+            fired_copy.borrow_mut().push(1);
+            let fired_copy = fired_copy.clone();
+            let follow_up = move || fired_copy.borrow_mut().push(2);
+            scheduler_copy.post_clock_event(&due, ClockEvent::new(due, Box::new(follow_up)).into());
+        };
+        work_scheduler.post_clock_event(&due, ClockEvent::new(due, Box::new(callback)).into());
+
+        assert_eq!(work_scheduler.execute_clock_events(), 0);
+        work_scheduler.advance_clock(due);
+        assert_eq!(work_scheduler.execute_clock_events(), 1);
+        assert_eq!(work_scheduler.execute_clock_events(), 1);
+        assert_eq!(*fired.borrow(), vec![1, 2]);
+    }
 }
@@ -0,0 +1,14 @@
+use std::process;
+
+use clap::Parser;
+use general_scp::ca::arg::CACli;
+
+fn main() {
+    match CACli::parse().run() {
+        Ok(output) => println!("{}", output),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
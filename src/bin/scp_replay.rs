@@ -0,0 +1,59 @@
+use std::{path::PathBuf, process};
+
+use clap::Parser;
+use general_scp::{
+    mock::{
+        builder::MockInMemoryNodeBuilder,
+        state::{MockState, MockStateDriver},
+    },
+    overlay::trace::{read_trace, replay_trace, TraceEvent},
+    scp::builder::InMemoryNodeBuilder,
+};
+
+// Replays a trace recorded by a node running on `MockState` values and checks
+// that a fresh node built from the same quorum data behaves the same way.
+#[derive(Parser)]
+struct ReplayCli {
+    // Directory of the node's quorum data, e.g. "sim".
+    quorum_dir: String,
+    trace_path: PathBuf,
+}
+
+fn main() {
+    let cli = ReplayCli::parse();
+    let events = read_trace::<MockState>(&cli.trace_path).unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    });
+    let Some(TraceEvent::Start { node_id, .. }) = events.first() else {
+        eprintln!("Error: trace does not start with a start event");
+        process::exit(1);
+    };
+
+    let mut builder: MockInMemoryNodeBuilder = InMemoryNodeBuilder::new(&cli.quorum_dir);
+    let mut node = builder.build_node(node_id).unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    });
+    // Peers only receive what the node sends.
+    let peers = node
+        .peer_conns
+        .keys()
+        .filter(|peer| *peer != node_id)
+        .cloned()
+        .collect::<Vec<_>>();
+    for peer in peers {
+        if let Err(err) = builder.build_node(&peer) {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+
+    match replay_trace::<MockState, MockStateDriver, _, _>(&mut node, &events) {
+        Ok(()) => println!("Replayed {} events", events.len()),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
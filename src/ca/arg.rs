@@ -1,18 +1,82 @@
+use std::{fmt::Display, fs, io, path::PathBuf, sync::Arc};
+
 use clap::{Args, Parser, Subcommand};
 
-use super::{local_state::LocalCAState, operation::SCPCAOperation};
+use crate::{mock::builder::NodeBuilderDir, scp::scp::NodeID};
+
+use super::{
+    auditor::{Auditor, WatchedPrefix},
+    builder::CALocalNetwork,
+    ca_type::Timestamp,
+    cell::{timestamp_now, Cell, CellData, InnerDelegateCell, InnerValueCell},
+    codec::{CodecError, KeyCodec},
+    crypto::{PrivateKey, PublicKey, SignatureAlgorithm},
+    error::{CAError, ErrorContext},
+    local_state::LocalCAState,
+    operation::{CAOperation, GetOperation, SCPCAOperation, SetOperation},
+    root::FlaggedEntriesPolicy,
+    signing::NetworkId,
+    state::CAStateOpError,
+    store::CAStore,
+    table::TableId,
+    transparency::TransparencyOpError,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
-struct CACli {
+pub struct CACli {
+    /// PEM encoded PKCS#8 private key used to sign operations.
+    #[arg(long, default_value = "test_private.pem")]
+    key: PathBuf,
+    /// Directory holding the persisted CA state. Queries and updates of
+    /// existing cells are built against this state.
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Quorum set directory of the local in-memory network externalizing the
+    /// operations.
+    #[arg(long, default_value = NodeBuilderDir::Test.get_dir_path())]
+    quorum_dir: String,
+    /// Nodes of the local in-memory network. The first one is the leader.
+    #[arg(long, value_delimiter = ',', default_value = "node1,node2")]
+    nodes: Vec<NodeID>,
+    /// Namespaces the nodes of the local in-memory network vote to remove.
+    #[arg(long, value_delimiter = ',')]
+    flag_removal: Vec<String>,
     #[command(subcommand)]
     command: CACmd,
 }
 
 #[derive(Subcommand, Debug)]
 enum CACmd {
+    GenerateKey(GenerateKeyArg),
     CreateNamespace(CreateNamespaceArg),
+    RemoveNamespace(RemoveNamespaceArg),
+    Delegate(DelegateArg),
+    SetValue(ValueArg),
+    UpdateValue(UpdateValueArg),
+    RemoveValue(ValueArg),
+    Lookup(LookupArg),
+    DumpTable(DumpTableArg),
+    /// Prints the signed head of the transparency log.
+    TreeHead,
+    /// Proves an entry of the transparency log is included in its head.
+    ProveInclusion(ProveInclusionArg),
+    /// Proves the transparency log with `old_size` entries is a prefix of the
+    /// current one.
+    ProveConsistency(ProveConsistencyArg),
+    /// Replays the transparency log in a node's data directory and prints an
+    /// alert per line for unexpected changes to a prefix.
+    Audit(AuditArg),
+}
+
+#[derive(Args, Debug)]
+struct GenerateKeyArg {
+    /// Writes the PKCS#8 private key here and the public key next to it with
+    /// a `.pub.pem` extension.
+    out: PathBuf,
+    #[arg(long, value_enum, default_value_t = SignatureAlgorithm::Ed25519)]
+    algorithm: SignatureAlgorithm,
 }
 
 #[derive(Args, Debug)]
@@ -20,35 +84,479 @@ struct CreateNamespaceArg {
     namespace: String,
 }
 
-impl From<String> for CACli {
-    fn from(s: String) -> Self {
+#[derive(Args, Debug)]
+struct RemoveNamespaceArg {
+    namespace: String,
+    /// Why the namespace should be removed, e.g. how its application
+    /// misbehaves. Recorded with the operation for auditors.
+    reason: String,
+}
+
+#[derive(Args, Debug)]
+struct DelegateArg {
+    namespace: String,
+    prefix: String,
+    allowance: u32,
+    /// Table holding the delegated namespace. Defaults to the prefix.
+    #[arg(long)]
+    table: Option<String>,
+    /// PEM encoded public key of the delegee. Defaults to the signing key.
+    #[arg(long)]
+    delegee: Option<PathBuf>,
+    /// Seconds the delegation is committed for.
+    #[arg(long, default_value_t = 0)]
+    commitment: Timestamp,
+    /// How the prefix is mapped to a lookup key, e.g. `dns` for domain names.
+    #[arg(long, value_enum, default_value_t = KeyCodec::Raw)]
+    codec: KeyCodec,
+}
+
+#[derive(Args, Debug)]
+struct ValueArg {
+    namespace: String,
+    value: String,
+    /// Seconds the cell is committed for.
+    #[arg(long, default_value_t = 0)]
+    commitment: Timestamp,
+    /// How the value is mapped to a lookup key, e.g. `dns` for domain names.
+    #[arg(long, value_enum, default_value_t = KeyCodec::Raw)]
+    codec: KeyCodec,
+}
+
+#[derive(Args, Debug)]
+struct UpdateValueArg {
+    namespace: String,
+    value: String,
+    /// Seconds the cell is committed for. Keeps the current commitment if
+    /// not given.
+    #[arg(long)]
+    commitment: Option<Timestamp>,
+    /// PEM encoded public key of the new owner of the cell.
+    #[arg(long)]
+    new_owner: Option<PathBuf>,
+    /// How the value is mapped to a lookup key, e.g. `dns` for domain names.
+    #[arg(long, value_enum, default_value_t = KeyCodec::Raw)]
+    codec: KeyCodec,
+}
+
+#[derive(Args, Debug)]
+struct LookupArg {
+    namespace: String,
+    key: String,
+    /// How the key is mapped to a lookup key, e.g. `dns` for domain names.
+    #[arg(long, value_enum, default_value_t = KeyCodec::Raw)]
+    codec: KeyCodec,
+}
+
+#[derive(Args, Debug)]
+struct DumpTableArg {
+    namespace: String,
+    /// Defaults to the root table of the namespace.
+    #[arg(long, default_value = "")]
+    table: String,
+}
+
+#[derive(Args, Debug)]
+struct ProveInclusionArg {
+    index: u64,
+}
+
+#[derive(Args, Debug)]
+struct ProveConsistencyArg {
+    old_size: u64,
+}
+
+#[derive(Args, Debug)]
+struct AuditArg {
+    /// Data directory of the node to audit.
+    node_data_dir: PathBuf,
+    namespace: String,
+    prefix: String,
+    /// PEM encoded public key the node signs its tree heads with.
+    #[arg(long)]
+    node_key: PathBuf,
+    /// PEM encoded public keys allowed to change cells under the prefix.
+    #[arg(long, value_delimiter = ',')]
+    owners: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value_t = KeyCodec::Raw)]
+    codec: KeyCodec,
+}
+
+#[derive(Debug)]
+pub enum CACliError {
+    InvalidKey(PathBuf),
+    Io(io::Error),
+    State(CAError),
+    Codec(CodecError),
+    Transparency(TransparencyOpError),
+    InvalidNetwork,
+    NotExternalized,
+    NotAnOperation,
+}
+
+impl From<io::Error> for CACliError {
+    fn from(err: io::Error) -> Self {
+        CACliError::Io(err)
+    }
+}
+
+impl From<CAError> for CACliError {
+    fn from(err: CAError) -> Self {
+        CACliError::State(err)
+    }
+}
+
+impl From<CodecError> for CACliError {
+    fn from(err: CodecError) -> Self {
+        CACliError::Codec(err)
+    }
+}
+
+impl From<TransparencyOpError> for CACliError {
+    fn from(err: TransparencyOpError) -> Self {
+        CACliError::Transparency(err)
+    }
+}
+
+impl Display for CACliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CACliError::InvalidKey(path) => write!(f, "invalid key in {}", path.display()),
+            CACliError::Io(err) => write!(f, "{}", err),
+            CACliError::State(err) => write!(f, "{}", err),
+            CACliError::Codec(err) => write!(f, "{}", err),
+            CACliError::Transparency(err) => write!(f, "{}", err),
+            CACliError::InvalidNetwork => write!(f, "invalid local network"),
+            CACliError::NotExternalized => write!(f, "operation was not externalized"),
+            CACliError::NotAnOperation => write!(f, "command is not an operation"),
+        }
+    }
+}
+
+// Like `CAStateOpError`, the wrappers print the wrapped error, so its source
+// is theirs.
+impl std::error::Error for CACliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CACliError::Io(err) => err.source(),
+            CACliError::State(err) => err.source(),
+            CACliError::Codec(err) => err.source(),
+            CACliError::Transparency(err) => err.source(),
+            _ => None,
+        }
+    }
+}
+
+pub type CACliResult<T> = std::result::Result<T, CACliError>;
+
+impl TryFrom<String> for CACli {
+    type Error = clap::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
         let mut cli = vec![""];
         cli.extend(s.split_whitespace());
-        let arg = CACli::try_parse_from(cli);
-        arg.unwrap()
+        CACli::try_parse_from(cli)
     }
 }
 
+fn read_private_key(path: &PathBuf) -> CACliResult<PrivateKey> {
+    let pem = fs::read_to_string(path)?;
+    PrivateKey::try_from_pkcs8_pem(&pem).ok_or(CACliError::InvalidKey(path.to_owned()))
+}
+
+fn read_public_key(path: &PathBuf) -> CACliResult<PublicKey> {
+    let pem = fs::read_to_string(path)?;
+    PublicKey::from_public_key_pem(&pem).ok_or(CACliError::InvalidKey(path.to_owned()))
+}
+
+impl CACli {
+    pub fn run(self) -> CACliResult<String> {
+        // The scheme of the root entry and the cells signed by this key is the
+        // algorithm of the key.
+        if let CACmd::GenerateKey(arg) = &self.command {
+            return generate_key(arg);
+        }
+        if let CACmd::Audit(arg) = &self.command {
+            return audit(arg);
+        }
+
+        let private_key = read_private_key(&self.key)?;
+        let local_state = match &self.data_dir {
+            Some(data_dir) => {
+                let mut store = CAStore::new(data_dir, CAStore::DEFAULT_SNAPSHOT_INTERVAL)?;
+                let (state, last_externalized_slot) = store.load()?;
+                let transparency_log = store.load_transparency_log()?;
+                LocalCAState {
+                    state,
+                    store: Some(store),
+                    last_externalized_slot,
+                    transparency_log,
+                    ..LocalCAState::init_state_from_private_key(private_key)
+                }
+            }
+            None => LocalCAState::init_state_from_private_key(private_key),
+        };
+
+        if let Some(output) = self.command.query(&local_state)? {
+            return Ok(output);
+        }
+
+        let scp_operation = self.command.to_scp_operation(&local_state)?;
+        let local_state = LocalCAState {
+            root_removal_policy: Arc::new(FlaggedEntriesPolicy::new(self.flag_removal.to_owned())),
+            ..local_state
+        };
+        let mut network = CALocalNetwork::new(&self.quorum_dir, &self.nodes, local_state)
+            .ok_or(CACliError::InvalidNetwork)?;
+        let slot_index = network
+            .externalize(scp_operation)
+            .ok_or(CACliError::NotExternalized)?;
+        Ok(format!("Externalized at slot {}", slot_index))
+    }
+}
+
+fn generate_key(arg: &GenerateKeyArg) -> CACliResult<String> {
+    let private_key = PrivateKey::generate(arg.algorithm);
+    let public_key_path = arg.out.with_extension("pub.pem");
+
+    let private_key_pem = private_key
+        .to_pkcs8_pem()
+        .ok_or(CACliError::InvalidKey(arg.out.to_owned()))?;
+    let public_key_pem = private_key
+        .public_key()
+        .to_public_key_pem()
+        .ok_or(CACliError::InvalidKey(public_key_path.to_owned()))?;
+    fs::write(&arg.out, private_key_pem)?;
+    fs::write(&public_key_path, public_key_pem)?;
+
+    Ok(format!(
+        "Generated {:?} key {:?} and {:?}",
+        arg.algorithm, arg.out, public_key_path
+    ))
+}
+
+fn audit(arg: &AuditArg) -> CACliResult<String> {
+    let owner_keys = arg
+        .owners
+        .iter()
+        .map(read_public_key)
+        .collect::<CACliResult<Vec<PublicKey>>>()?;
+    let mut auditor = Auditor::new(read_public_key(&arg.node_key)?, NetworkId::default());
+    auditor.watch(WatchedPrefix {
+        application_identifier: arg.namespace.to_owned(),
+        prefix: arg.codec.encode(&arg.prefix)?,
+        owner_keys,
+    });
+
+    let store = CAStore::new(&arg.node_data_dir, CAStore::DEFAULT_SNAPSHOT_INTERVAL)?;
+    let alerts = auditor
+        .follow(&store)?
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(io::Error::from)?;
+    Ok(alerts.join("\n"))
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> CACliResult<Option<String>> {
+    let json = serde_json::to_string(value).map_err(io::Error::from)?;
+    Ok(Some(json))
+}
+
 impl CACmd {
-    pub fn to_scp_operation(self, local_state: &LocalCAState) -> Option<SCPCAOperation> {
+    pub fn to_scp_operation(&self, local_state: &LocalCAState) -> CACliResult<SCPCAOperation> {
+        let now = timestamp_now();
+        let private_key = &local_state.private_key;
+
+        let operation = match self {
+            CACmd::CreateNamespace(arg) => local_state.create_name_space(&arg.namespace)?,
+            CACmd::RemoveNamespace(arg) => {
+                local_state.propose_removal(&arg.namespace, &arg.reason)?
+            }
+            CACmd::Delegate(arg) => {
+                let owner_key = match &arg.delegee {
+                    Some(path) => read_public_key(path)?,
+                    None => private_key.public_key(),
+                };
+                let prefix = arg.codec.encode(&arg.prefix)?;
+                let inner = CellData::Delegate(InnerDelegateCell {
+                    table: Some(TableId(arg.table.to_owned().unwrap_or(prefix.to_owned()))),
+                    name_space: prefix,
+                    allowance: arg.allowance,
+                });
+                let context = local_state.state.signing_context(&arg.namespace);
+                let cell = Cell::new_signed(
+                    now,
+                    now + arg.commitment,
+                    owner_key,
+                    inner,
+                    private_key,
+                    &context,
+                );
+                CAOperation::Set(SetOperation::new(arg.namespace.to_owned(), cell))
+            }
+            CACmd::SetValue(arg) => {
+                let inner = CellData::Value(InnerValueCell {
+                    value: arg.codec.encode(&arg.value)?,
+                });
+                let context = local_state.state.signing_context(&arg.namespace);
+                let cell = Cell::new_signed(
+                    now,
+                    now + arg.commitment,
+                    private_key.public_key(),
+                    inner,
+                    private_key,
+                    &context,
+                );
+                CAOperation::Set(SetOperation::new(arg.namespace.to_owned(), cell))
+            }
+            CACmd::UpdateValue(arg) => {
+                // Updates are signed by the current owner, which may hand the
+                // cell over to a new owner.
+                let get_opt =
+                    GetOperation::with_codec(arg.namespace.to_owned(), &arg.value, arg.codec)?;
+                let mut cell = local_state.state.get(&get_opt)?.entry_cell;
+                cell.revision_time = now.max(cell.revision_time + 1);
+                if let Some(commitment) = arg.commitment {
+                    cell.commitment_time = now + commitment;
+                }
+                if let Some(path) = &arg.new_owner {
+                    cell.owner_key = read_public_key(path)?;
+                }
+                cell.sign(
+                    private_key,
+                    &local_state.state.signing_context(&arg.namespace),
+                );
+                CAOperation::Set(SetOperation::new(arg.namespace.to_owned(), cell))
+            }
+            CACmd::RemoveValue(arg) => {
+                // Removals are signed by the authority of the table.
+                let get_opt =
+                    GetOperation::with_codec(arg.namespace.to_owned(), &arg.value, arg.codec)?;
+                let cell = local_state.state.get(&get_opt)?.entry_cell;
+                let mut removed = cell.to_removed(
+                    &private_key.public_key(),
+                    now.max(cell.revision_time + 1),
+                    now + arg.commitment,
+                );
+                removed.sign_removal(
+                    private_key,
+                    &local_state.state.signing_context(&arg.namespace),
+                    &get_opt.full_lookup_key,
+                );
+                CAOperation::Set(SetOperation::remove(
+                    get_opt.application_identifier,
+                    get_opt.full_lookup_key,
+                    removed,
+                ))
+            }
+            CACmd::GenerateKey(_)
+            | CACmd::Lookup(_)
+            | CACmd::DumpTable(_)
+            | CACmd::TreeHead
+            | CACmd::ProveInclusion(_)
+            | CACmd::ProveConsistency(_)
+            | CACmd::Audit(_) => return Err(CACliError::NotAnOperation),
+        };
+
+        Ok(SCPCAOperation::new(vec![operation], now))
+    }
+
+    pub fn query(&self, local_state: &LocalCAState) -> CACliResult<Option<String>> {
+        // Answers lookups and table dumps from the local state. Returns `None`
+        // for commands that change the state.
         match self {
-            CACmd::CreateNamespace(arg) => {
-                let operation = local_state.create_name_space(&arg.namespace).ok()?;
-                let scp_operation = SCPCAOperation(vec![operation]);
-                Some(scp_operation)
+            CACmd::Lookup(arg) => {
+                let get_opt =
+                    GetOperation::with_codec(arg.namespace.to_owned(), &arg.key, arg.codec)?;
+                let proof = local_state.lookup(&get_opt)?;
+                proof.verify(&local_state.state.signing_context(&arg.namespace))?;
+                Ok(Some(format!(
+                    "{} -> {:?}\n{:?}\nverified inclusion at index {} of root {:?}",
+                    arg.key,
+                    proof.key,
+                    proof.entry_cell,
+                    proof.idx,
+                    proof.root.as_bytes()
+                )))
+            }
+            CACmd::DumpTable(arg) => {
+                let table = local_state
+                    .state
+                    .get_table(&arg.namespace, &TableId(arg.table.to_owned()))
+                    .ok_or_else(|| {
+                        CAError::new(
+                            ErrorContext::namespace(&arg.namespace),
+                            CAStateOpError::NoExist,
+                        )
+                    })?;
+                let mut output = format!(
+                    "table {:?} namespace {:?} allowance {}/{} root {:?}",
+                    arg.table,
+                    table.name_space,
+                    table.used_allowance(),
+                    table.allowance,
+                    table.merkle_tree.root().as_bytes()
+                );
+                for cell in table
+                    .delegate_entries
+                    .values()
+                    .chain(table.value_entries.values())
+                    .chain(&table.removed_entries)
+                {
+                    output.push_str(&format!("\n{:?}", cell));
+                }
+                Ok(Some(output))
             }
+            CACmd::TreeHead => to_json(&local_state.signed_tree_head()),
+            CACmd::ProveInclusion(arg) => {
+                let log = &local_state.transparency_log;
+                let entry = log
+                    .entry(arg.index)
+                    .ok_or(TransparencyOpError::InvalidIndex)?;
+                to_json(&(entry, log.prove_inclusion(arg.index)?))
+            }
+            CACmd::ProveConsistency(arg) => to_json(
+                &local_state
+                    .transparency_log
+                    .prove_consistency(arg.old_size)?,
+            ),
+            _ => Ok(None),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use clap::Parser;
+    use std::{error::Error, sync::Arc};
+
+    use clap::{error::ErrorKind, Parser};
 
-    use crate::ca::arg::{CACmd, CreateNamespaceArg};
+    use crate::ca::{
+        arg::{CACliError, CACmd, CreateNamespaceArg},
+        builder::CALocalNetwork,
+        crypto::TEST_OPENSSL_PRIVATE_KEY,
+        error::{CAError, ErrorContext},
+        local_state::LocalCAState,
+        root::{FlaggedEntriesPolicy, RootEntryKey},
+        state::CAStateOpError,
+        table::TableId,
+        transparency::{LogConsistencyProof, SignedTreeHead},
+    };
 
     use super::CACli;
 
+    fn test_run_on_network(network: &mut CALocalNetwork, cmd: &str, close_time_offset: u64) {
+        let cli = CACli::try_from(cmd.to_string()).unwrap();
+        let mut scp_operation = cli
+            .command
+            .to_scp_operation(network.leader_state())
+            .unwrap();
+        scp_operation.close_time += close_time_offset;
+
+        assert!(network.externalize(scp_operation).is_some(), "{}", cmd);
+    }
+
     #[test]
     fn create_new_namespace_cmd_ok() {
         let cmd = CACmd::CreateNamespace(CreateNamespaceArg {
@@ -60,8 +568,8 @@ mod test {
 
         let scp_operation = cmd.to_scp_operation(&local_state).unwrap();
 
-        assert_eq!(scp_operation.0.len(), 1);
-        match &scp_operation.0[0] {
+        assert_eq!(scp_operation.operations.len(), 1);
+        match &scp_operation.operations[0] {
             crate::ca::operation::CAOperation::SetRoot(set_root_operation) => {
                 assert_eq!(
                     set_root_operation.entry.application_identifier,
@@ -85,7 +593,7 @@ mod test {
         }
 
         let cli_str = "create-namespace namespace1";
-        let arg = CACli::from(cli_str.to_string());
+        let arg = CACli::try_from(cli_str.to_string()).unwrap();
 
         match arg.command {
             CACmd::CreateNamespace(create_namespace_arg) => {
@@ -94,4 +602,152 @@ mod test {
             _ => panic!("not reached"),
         }
     }
+
+    #[test]
+    fn parse_cli_options() {
+        let arg = CACli::try_from(
+            "--key other.pem --nodes node1,node2,node3 delegate namespace1 home/ 10 --table home"
+                .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(arg.key.to_str(), Some("other.pem"));
+        assert_eq!(arg.nodes, vec!["node1", "node2", "node3"]);
+        match arg.command {
+            CACmd::Delegate(delegate_arg) => {
+                assert_eq!(delegate_arg.namespace, "namespace1");
+                assert_eq!(delegate_arg.prefix, "home/");
+                assert_eq!(delegate_arg.allowance, 10);
+                assert_eq!(delegate_arg.table, Some("home".to_string()));
+                assert!(delegate_arg.delegee.is_none());
+            }
+            _ => panic!("not reached"),
+        }
+
+        let err = CACli::try_from("delegate namespace1 home/ many".to_string()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn cli_errors_report_causes_once() {
+        let err = CACliError::State(CAError::new(
+            ErrorContext::namespace("namespace1"),
+            CAStateOpError::NoExist,
+        ));
+        assert_eq!(err.to_string(), "namespace \"namespace1\": does not exist");
+        assert!(err.source().is_none());
+    }
+
+    #[test]
+    fn value_cell_commands_on_local_network() {
+        let local_state = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        let mut network = CALocalNetwork::new(
+            "test",
+            &["node1".to_string(), "node2".to_string()],
+            local_state,
+        )
+        .unwrap();
+
+        test_run_on_network(&mut network, "create-namespace namespace1", 0);
+        test_run_on_network(&mut network, "set-value namespace1 alice", 0);
+        test_run_on_network(&mut network, "update-value namespace1 alice", 0);
+
+        let lookup = CACli::try_from("lookup namespace1 alice".to_string()).unwrap();
+        let output = lookup.command.query(network.leader_state()).unwrap();
+        assert!(output.unwrap().contains("verified inclusion at index 0"));
+
+        let dump = CACli::try_from("dump-table namespace1".to_string()).unwrap();
+        let output = dump.command.query(network.leader_state()).unwrap();
+        assert_eq!(output.unwrap().lines().count(), 2);
+
+        // The commitment of the cell has to expire before it can be removed.
+        test_run_on_network(&mut network, "remove-value namespace1 alice", 1);
+
+        let lookup = CACli::try_from("lookup namespace1 alice".to_string()).unwrap();
+        assert!(matches!(
+            lookup.command.query(network.leader_state()),
+            Err(CACliError::State(err)) if err.source == CAStateOpError::NoExist
+        ));
+
+        // Every externalized value is in the transparency log.
+        let leader_state = network.leader_state();
+        let tree_head = CACli::try_from("tree-head".to_string()).unwrap();
+        let output = tree_head.command.query(leader_state).unwrap().unwrap();
+        let sth: SignedTreeHead = serde_json::from_str(&output).unwrap();
+        assert_eq!(sth.tree_head.tree_size, 4);
+        assert!(sth
+            .verify(
+                &leader_state.private_key.public_key(),
+                &leader_state.state.network_id
+            )
+            .is_ok());
+
+        let consistency = CACli::try_from("prove-consistency 2".to_string()).unwrap();
+        let output = consistency.command.query(leader_state).unwrap().unwrap();
+        let proof: LogConsistencyProof = serde_json::from_str(&output).unwrap();
+        assert_eq!((proof.old_size, proof.new_size), (2, 4));
+    }
+
+    #[test]
+    fn delegate_and_look_up_domain_names() {
+        let local_state = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        let mut network = CALocalNetwork::new(
+            "test",
+            &["node1".to_string(), "node2".to_string()],
+            local_state,
+        )
+        .unwrap();
+
+        test_run_on_network(&mut network, "create-namespace dns", 0);
+        test_run_on_network(&mut network, "delegate dns example.com 10 --codec dns", 0);
+        test_run_on_network(&mut network, "set-value dns www.example.com --codec dns", 0);
+
+        let tables = &network.leader_state().state.tables[&RootEntryKey("dns".to_string())];
+        let delegated = &tables.0[&TableId("com.example.".to_string())];
+        assert!(delegated.get_entry("com.example.www.").is_some());
+
+        let lookup = CACli::try_from("lookup dns www.example.com --codec dns".to_string()).unwrap();
+        let output = lookup.command.query(network.leader_state()).unwrap();
+        assert!(output
+            .unwrap()
+            .starts_with("www.example.com -> \"com.example.www.\""));
+
+        let lookup =
+            CACli::try_from("lookup dns www..example.com --codec dns".to_string()).unwrap();
+        assert!(matches!(
+            lookup.command.query(network.leader_state()),
+            Err(CACliError::Codec(_))
+        ));
+    }
+
+    #[test]
+    fn remove_namespace_needs_flagging_nodes() {
+        let local_state = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        let nodes = ["node1".to_string(), "node2".to_string()];
+        let mut network = CALocalNetwork::new("test", &nodes, local_state).unwrap();
+        test_run_on_network(&mut network, "create-namespace namespace1", 0);
+
+        let remove = CACli::try_from("remove-namespace namespace1 phishing".to_string()).unwrap();
+        let scp_operation = remove
+            .command
+            .to_scp_operation(network.leader_state())
+            .unwrap();
+        assert!(network.externalize(scp_operation.clone()).is_none());
+        assert!(network
+            .leader_state()
+            .state
+            .contains_root_entry(&"namespace1".to_string()));
+
+        // Once the nodes flag the namespace the removal is externalized.
+        let local_state = LocalCAState {
+            root_removal_policy: Arc::new(FlaggedEntriesPolicy::new(["namespace1".to_string()])),
+            ..network.leader_state().clone()
+        };
+        let mut network = CALocalNetwork::new("test", &nodes, local_state).unwrap();
+        assert!(network.externalize(scp_operation).is_some());
+        assert!(!network
+            .leader_state()
+            .state
+            .contains_root_entry(&"namespace1".to_string()));
+    }
 }
@@ -0,0 +1,476 @@
+use serde::Serialize;
+
+use crate::scp::slot::SlotIndex;
+
+use super::{
+    crypto::PublicKey,
+    merkle::MerkleHash,
+    operation::{CAOperation, CellMerkleProof, SCPCAOperation, SetOperation},
+    signing::{NetworkId, SigningContext},
+    state::{BatchResult, CAState},
+    store::{CAOperationLogEntry, CAStore},
+    transparency::{SignedTreeHead, TransparencyLog},
+};
+
+// An auditor follows the values externalized by a node and replays them into
+// its own state, without taking part in consensus. It reports changes to the
+// prefixes it watches that were not signed by their owners, removals of the
+// namespaces holding them, operations the node externalized but that do not
+// apply, and signed state of the node that does not match its own.
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+#[serde(tag = "alert")]
+pub enum AuditAlert {
+    // A cell under a watched prefix was set or removed with a signature by
+    // none of the owner keys of the prefix.
+    WatchedCellChanged {
+        slot_index: SlotIndex,
+        application_identifier: String,
+        prefix: String,
+        lookup_key: String,
+    },
+    RootEntryRemoved {
+        slot_index: SlotIndex,
+        application_identifier: String,
+        reason: Option<String>,
+    },
+    // An externalized operation that fails to apply, e.g. because of an
+    // invalid signature.
+    RejectedOperation {
+        slot_index: SlotIndex,
+        operation_index: usize,
+        error: String,
+    },
+    // The node skipped or repeated slots.
+    UnexpectedSlot {
+        expected: SlotIndex,
+        received: SlotIndex,
+    },
+    InvalidTreeHeadSignature {
+        tree_size: u64,
+    },
+    // A tree head signed by the node is not a head of the log the auditor
+    // followed.
+    TreeHeadMismatch {
+        tree_size: u64,
+        signed_root: MerkleHash,
+    },
+    // A lookup proof served by the node is against a table root the auditor
+    // does not have.
+    TableRootMismatch {
+        application_identifier: String,
+        lookup_key: String,
+    },
+    // A lookup proof whose root is not signed by the node.
+    InvalidTableRootSignature {
+        application_identifier: String,
+        lookup_key: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct WatchedPrefix {
+    pub application_identifier: String,
+    pub prefix: String,
+    // Keys allowed to change cells under the prefix.
+    pub owner_keys: Vec<PublicKey>,
+}
+
+impl WatchedPrefix {
+    fn covers(&self, set_opt: &SetOperation) -> bool {
+        set_opt.application_identifier == self.application_identifier
+            && set_opt.full_lookup_key.starts_with(&self.prefix)
+    }
+
+    fn is_signed_by_owner(&self, set_opt: &SetOperation, context: &SigningContext) -> bool {
+        self.owner_keys.iter().any(|owner_key| {
+            if set_opt.cell.is_empty() {
+                set_opt
+                    .cell
+                    .is_removal_signed_by(owner_key, context, &set_opt.full_lookup_key)
+                    .is_ok()
+            } else {
+                set_opt.cell.is_signed_by(owner_key, context).is_ok()
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Auditor {
+    pub state: CAState,
+    pub transparency_log: TransparencyLog,
+    // Key the followed node signs its tree heads with.
+    node_key: PublicKey,
+    watched: Vec<WatchedPrefix>,
+    next_slot: Option<SlotIndex>,
+}
+
+impl Auditor {
+    pub fn new(node_key: PublicKey, network_id: NetworkId) -> Self {
+        Self {
+            state: CAState {
+                network_id,
+                ..Default::default()
+            },
+            transparency_log: Default::default(),
+            node_key,
+            watched: vec![],
+            next_slot: None,
+        }
+    }
+
+    pub fn watch(&mut self, watched_prefix: WatchedPrefix) {
+        self.watched.push(watched_prefix);
+    }
+
+    pub fn follow(&mut self, store: &CAStore) -> std::io::Result<Vec<AuditAlert>> {
+        // Processes the entries of the node's transparency log not seen yet.
+        let seen = self.transparency_log.len() as usize;
+        Ok(store
+            .transparency_entries()?
+            .iter()
+            .skip(seen)
+            .flat_map(|entry| self.on_log_entry(entry))
+            .collect())
+    }
+
+    pub fn on_log_entry(&mut self, entry: &CAOperationLogEntry) -> Vec<AuditAlert> {
+        self.on_externalized(entry.slot_index, &entry.operation)
+    }
+
+    pub fn on_externalized(
+        &mut self,
+        slot_index: SlotIndex,
+        value: &SCPCAOperation,
+    ) -> Vec<AuditAlert> {
+        let mut alerts = vec![];
+
+        if let Some(expected) = self.next_slot {
+            if slot_index != expected {
+                alerts.push(AuditAlert::UnexpectedSlot {
+                    expected,
+                    received: slot_index,
+                });
+            }
+        }
+        self.next_slot = Some(slot_index + 1);
+        self.transparency_log.append(slot_index, value);
+
+        // Values are applied as a whole, as nodes do. Only the operations of a
+        // committed value are checked against the watched prefixes.
+        match self.state.on_scp_operation(value) {
+            BatchResult::Committed => {
+                for operation in &value.operations {
+                    alerts.extend(self.check_operation(slot_index, operation));
+                }
+            }
+            BatchResult::Rejected(errors) => {
+                alerts.extend(errors.into_iter().map(|(operation_index, err)| {
+                    AuditAlert::RejectedOperation {
+                        slot_index,
+                        operation_index,
+                        error: err.to_string(),
+                    }
+                }));
+            }
+        }
+
+        alerts
+    }
+
+    fn check_operation(&self, slot_index: SlotIndex, operation: &CAOperation) -> Vec<AuditAlert> {
+        // Checks an operation that was applied against the watched prefixes.
+        match operation {
+            CAOperation::Set(set_opt) => {
+                let context = self.state.signing_context(&set_opt.application_identifier);
+                self.watched
+                    .iter()
+                    .filter(|watched| {
+                        watched.covers(set_opt) && !watched.is_signed_by_owner(set_opt, &context)
+                    })
+                    .map(|watched| AuditAlert::WatchedCellChanged {
+                        slot_index,
+                        application_identifier: set_opt.application_identifier.to_owned(),
+                        prefix: watched.prefix.to_owned(),
+                        lookup_key: set_opt.full_lookup_key.to_owned(),
+                    })
+                    .collect()
+            }
+            CAOperation::SetRoot(set_root_opt) if set_root_opt.remove => {
+                let application_identifier = &set_root_opt.entry.application_identifier;
+                if self
+                    .watched
+                    .iter()
+                    .any(|watched| &watched.application_identifier == application_identifier)
+                {
+                    vec![AuditAlert::RootEntryRemoved {
+                        slot_index,
+                        application_identifier: application_identifier.to_owned(),
+                        reason: set_root_opt.reason.to_owned(),
+                    }]
+                } else {
+                    vec![]
+                }
+            }
+            CAOperation::SetRoot(_) | CAOperation::Empty => vec![],
+        }
+    }
+
+    pub fn check_signed_tree_head(&self, sth: &SignedTreeHead) -> Option<AuditAlert> {
+        // Checks a tree head signed by the node against the log followed so
+        // far. Heads of logs longer than the one followed cannot be checked
+        // until the auditor catches up.
+        let tree_head = &sth.tree_head;
+        if sth.verify(&self.node_key, &self.state.network_id).is_err() {
+            return Some(AuditAlert::InvalidTreeHeadSignature {
+                tree_size: tree_head.tree_size,
+            });
+        }
+
+        let consistent = match tree_head.tree_size {
+            0 => tree_head.root_hash == TransparencyLog::default().tree_head().root_hash,
+            size if size > self.transparency_log.len() => return None,
+            size => self
+                .transparency_log
+                .prove_consistency(size)
+                .and_then(|proof| {
+                    self.transparency_log
+                        .tree_head()
+                        .verify_consistency(tree_head, &proof)
+                })
+                .is_ok(),
+        };
+
+        if consistent {
+            None
+        } else {
+            Some(AuditAlert::TreeHeadMismatch {
+                tree_size: tree_head.tree_size,
+                signed_root: tree_head.root_hash,
+            })
+        }
+    }
+
+    pub fn check_cell_proof(
+        &self,
+        application_identifier: &str,
+        proof: &CellMerkleProof,
+    ) -> Option<AuditAlert> {
+        // Checks a lookup answered by the node is signed by it and against the
+        // same table root as the auditor's own state.
+        let context = self.state.signing_context(application_identifier);
+        if !proof.verify_root_sig(&self.node_key, &context) {
+            return Some(AuditAlert::InvalidTableRootSignature {
+                application_identifier: application_identifier.to_owned(),
+                lookup_key: proof.key.to_owned(),
+            });
+        }
+
+        let matches = self
+            .state
+            .lookup(application_identifier, proof.key)
+            .is_ok_and(|own_proof| own_proof.root == proof.root);
+
+        if matches {
+            None
+        } else {
+            Some(AuditAlert::TableRootMismatch {
+                application_identifier: application_identifier.to_owned(),
+                lookup_key: proof.key.to_owned(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ca::{
+        cell::{timestamp_now, Cell, CellData, InnerValueCell},
+        crypto::{mock_generate_private_key, PrivateKey, TEST_OPENSSL_PRIVATE_KEY},
+        local_state::LocalCAState,
+        operation::{GetOperation, SetRootOperation},
+    };
+
+    use super::*;
+
+    fn test_make_value_operation(
+        local_state: &LocalCAState,
+        value: &str,
+        owner_key: PublicKey,
+        signer: &PrivateKey,
+    ) -> CAOperation {
+        let now = timestamp_now();
+        let cell = Cell::new_signed(
+            now,
+            now,
+            owner_key,
+            CellData::Value(InnerValueCell {
+                value: value.to_string(),
+            }),
+            signer,
+            &local_state.state.signing_context("namespace1"),
+        );
+        CAOperation::Set(SetOperation::new("namespace1".to_string(), cell))
+    }
+
+    #[test]
+    fn alerts_on_watched_prefixes_and_root_removals() {
+        let node = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        let root_key = node.private_key.to_owned();
+        let alice = mock_generate_private_key();
+
+        let mut auditor = Auditor::new(root_key.public_key(), node.state.network_id);
+        auditor.watch(WatchedPrefix {
+            application_identifier: "namespace1".to_string(),
+            prefix: "alice/".to_string(),
+            owner_keys: vec![alice.public_key()],
+        });
+
+        let create = node.create_name_space("namespace1").unwrap();
+        let values = vec![
+            test_make_value_operation(&node, "bob", root_key.public_key(), &root_key),
+            // Inserted by the table authority under alice's prefix.
+            test_make_value_operation(&node, "alice/mail", alice.public_key(), &root_key),
+            // Not signed by the table authority.
+            test_make_value_operation(&node, "carol", alice.public_key(), &alice),
+        ];
+
+        let mut externalize = |slot_index, operations| {
+            auditor.on_externalized(
+                slot_index,
+                &SCPCAOperation::new(operations, timestamp_now()),
+            )
+        };
+
+        assert!(externalize(0, vec![create.to_owned()]).is_empty());
+
+        // A value with an invalid operation is not applied at all.
+        let alerts = externalize(1, values.to_owned());
+        assert_eq!(alerts.len(), 1);
+        assert!(matches!(
+            alerts[0],
+            AuditAlert::RejectedOperation {
+                slot_index: 1,
+                operation_index: 2,
+                ..
+            }
+        ));
+
+        assert_eq!(
+            externalize(2, values[..2].to_vec()),
+            vec![AuditAlert::WatchedCellChanged {
+                slot_index: 2,
+                application_identifier: "namespace1".to_string(),
+                prefix: "alice/".to_string(),
+                lookup_key: "alice/mail".to_string(),
+            }]
+        );
+
+        let CAOperation::SetRoot(set_root_opt) = create else {
+            panic!("not reached");
+        };
+        let remove = CAOperation::SetRoot(SetRootOperation::removal(
+            set_root_opt.entry,
+            "phishing".to_string(),
+        ));
+        assert_eq!(
+            externalize(4, vec![remove]),
+            vec![
+                AuditAlert::UnexpectedSlot {
+                    expected: 3,
+                    received: 4
+                },
+                AuditAlert::RootEntryRemoved {
+                    slot_index: 4,
+                    application_identifier: "namespace1".to_string(),
+                    reason: Some("phishing".to_string()),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_signed_tree_heads() {
+        let mut node = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        let mut auditor = Auditor::new(node.private_key.public_key(), node.state.network_id);
+
+        let create = node.create_name_space("namespace1").unwrap();
+        let value = SCPCAOperation::new(vec![create], timestamp_now());
+        node.on_externalized(0, &value);
+        let old_sth = node.signed_tree_head();
+        assert!(auditor.check_signed_tree_head(&old_sth).is_none());
+
+        auditor.on_externalized(0, &value);
+        assert!(auditor.check_signed_tree_head(&old_sth).is_none());
+
+        // The node rewrites the value of slot 0.
+        let mut forked = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        forked.on_externalized(0, &SCPCAOperation::new(vec![], 0));
+        let forked_sth = forked.signed_tree_head();
+        assert_eq!(
+            auditor.check_signed_tree_head(&forked_sth),
+            Some(AuditAlert::TreeHeadMismatch {
+                tree_size: 1,
+                signed_root: forked_sth.tree_head.root_hash,
+            })
+        );
+
+        let other_key = mock_generate_private_key();
+        let sth = auditor
+            .transparency_log
+            .signed_tree_head(&other_key, &auditor.state.network_id);
+        assert_eq!(
+            auditor.check_signed_tree_head(&sth),
+            Some(AuditAlert::InvalidTreeHeadSignature { tree_size: 1 })
+        );
+    }
+
+    #[test]
+    fn checks_signed_cell_proofs() {
+        let mut node = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        let root_key = node.private_key.to_owned();
+        let mut auditor = Auditor::new(root_key.public_key(), node.state.network_id);
+
+        let create = node.create_name_space("namespace1").unwrap();
+        let bob = test_make_value_operation(&node, "bob", root_key.public_key(), &root_key);
+        let carol = test_make_value_operation(&node, "carol", root_key.public_key(), &root_key);
+        for (slot_index, operation) in [create, bob].into_iter().enumerate() {
+            let value = SCPCAOperation::new(vec![operation], timestamp_now());
+            node.on_externalized(slot_index as SlotIndex, &value);
+            auditor.on_externalized(slot_index as SlotIndex, &value);
+        }
+
+        let get_opt = GetOperation::new("namespace1".to_string(), "bob".to_string());
+        let mut proof = node.lookup(&get_opt).unwrap();
+        assert_eq!(auditor.check_cell_proof("namespace1", &proof), None);
+
+        let invalid_signature = Some(AuditAlert::InvalidTableRootSignature {
+            application_identifier: "namespace1".to_string(),
+            lookup_key: "bob".to_string(),
+        });
+        let context = node.state.signing_context("namespace1");
+        proof.sign_root(&mock_generate_private_key(), &context);
+        assert_eq!(
+            auditor.check_cell_proof("namespace1", &proof),
+            invalid_signature
+        );
+        proof.tree_sig = None;
+        assert_eq!(
+            auditor.check_cell_proof("namespace1", &proof),
+            invalid_signature
+        );
+
+        // A signed root the auditor never saw.
+        let value = SCPCAOperation::new(vec![carol], timestamp_now());
+        node.on_externalized(2, &value);
+        let proof = node.lookup(&get_opt).unwrap();
+        assert_eq!(
+            auditor.check_cell_proof("namespace1", &proof),
+            Some(AuditAlert::TableRootMismatch {
+                application_identifier: "namespace1".to_string(),
+                lookup_key: "bob".to_string(),
+            })
+        );
+    }
+}
@@ -1,64 +1,245 @@
 use crate::application::quorum::QuorumSet;
 use crate::herder::herder::HerderDriver;
+use crate::mock::builder::InMemoryPeerNode;
+use crate::overlay::peer_node::PeerNode;
+use crate::overlay_impl::in_memory_global::InMemoryGlobalState;
 use crate::scp::builder::InMemoryNodeBuilder;
+use crate::scp::scp::NodeID;
+use crate::scp::scp_driver::ValidationLevel;
+use crate::scp::slot::SlotIndex;
 use crate::scp::statement::SCPStatement;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
+use super::ca_type::Timestamp;
+use super::cell::timestamp_now;
+use super::crypto::{PrivateKey, SignatureAlgorithm};
 use super::local_state::LocalCAState;
 use super::operation::{CAOperation, SCPCAOperation};
 
 #[derive(Clone, Debug)]
 pub struct CAStateDriver(pub LocalCAState);
 
+impl CAStateDriver {
+    // How far ahead of the local clock a close time may be for us to nominate
+    // it.
+    const MAX_CLOSE_TIME_DRIFT: Timestamp = 60;
+}
+
 impl HerderDriver<SCPCAOperation> for CAStateDriver {
+    fn validate_value(&self, value: &SCPCAOperation, nomination: bool) -> ValidationLevel {
+        if value.close_time < self.0.state.close_time || !self.0.accepts_root_entries(value) {
+            ValidationLevel::Invalid
+        } else if nomination && value.close_time > timestamp_now() + Self::MAX_CLOSE_TIME_DRIFT {
+            // The local clock only decides what we nominate. Whether a value is
+            // valid does not depend on it, so nodes with skewed clocks still
+            // agree in the ballot protocol.
+            ValidationLevel::MaybeValid
+        } else {
+            ValidationLevel::FullyValidated
+        }
+    }
+
     fn combine_candidates(
         &self,
         candidates: &BTreeSet<Arc<SCPCAOperation>>,
     ) -> Option<SCPCAOperation> {
-        // TODO: need to filter out conflicting operations
-        Some(SCPCAOperation(Vec::from_iter(
+        let close_time = candidates.iter().map(|val| val.close_time).max()?;
+        // Candidates are combined from the operations of several nodes, so
+        // the local policies apply again, e.g. removals need our flag too.
+        let operations = self.0.state.applicable_operations(
             candidates
                 .iter()
-                .map(|val| val.0.iter())
-                .flatten()
-                .map(|val| val.to_owned()),
-        )))
+                .flat_map(|val| val.operations.iter())
+                .filter(|operation| self.0.accepts_root_operation(operation)),
+            close_time,
+        );
+
+        Some(SCPCAOperation::new(operations, close_time))
     }
 
     fn extract_valid_value(&self, value: &SCPCAOperation) -> Option<SCPCAOperation> {
         None
     }
 
-    fn externalize_value(&mut self, value: &SCPCAOperation) {
+    fn externalize_value(&mut self, slot_index: SlotIndex, value: &SCPCAOperation) {
         println!("Externalize value: {:?}", value);
-        self.0.state.on_scp_operation(value);
+        self.0.on_externalized(slot_index, value);
+    }
+
+    fn last_externalized_slot(&self) -> Option<SlotIndex> {
+        self.0.last_externalized_slot
     }
 
     fn new() -> Self {
-        panic!()
+        // A node with a fresh key and an empty state.
+        CAStateDriver(LocalCAState::init_state_from_private_key(
+            PrivateKey::generate(SignatureAlgorithm::Ed25519),
+        ))
     }
 }
 
 pub type CAInMemoryNodeBuilder = InMemoryNodeBuilder<SCPCAOperation, CAStateDriver>;
+pub type CAInMemoryPeerNode = InMemoryPeerNode<SCPCAOperation, CAStateDriver>;
+
+// A network of in-memory CA nodes built from the quorum sets in
+// `quorum_dir_path`. The first node is the leader and nominates every value.
+// Only the leader keeps the store of the local state, so each externalized
+// value is persisted once.
+pub struct CALocalNetwork {
+    builder: CAInMemoryNodeBuilder,
+    pub nodes: BTreeMap<NodeID, CAInMemoryPeerNode>,
+    leader: NodeID,
+}
+
+impl CALocalNetwork {
+    pub fn new(
+        quorum_dir_path: &str,
+        node_ids: &[NodeID],
+        local_state: LocalCAState,
+    ) -> Option<Self> {
+        let leader = node_ids.first()?.to_owned();
+        // The nodes agree on values signed for the network of the state.
+        let mut builder = CAInMemoryNodeBuilder::new(quorum_dir_path)
+            .with_network_id(local_state.state.network_id);
+
+        let mut nodes = BTreeMap::new();
+        for node_id in node_ids {
+            let mut node_state = local_state.clone();
+            if node_id != &leader {
+                node_state.store = None;
+            }
+            let node = builder
+                .build_node_with_herder(node_id, CAStateDriver(node_state))
+                .ok()?;
+            nodes.insert(node_id.to_owned(), node);
+        }
+
+        PeerNode::add_leader_for_nodes(nodes.values_mut(), &leader);
+
+        Some(Self {
+            builder,
+            nodes,
+            leader,
+        })
+    }
+
+    pub fn leader_state(&self) -> &LocalCAState {
+        &self.nodes[&self.leader].scp.herder.0
+    }
+
+    pub fn externalize(&mut self, value: SCPCAOperation) -> Option<SlotIndex> {
+        // Nominates `value` for the next slot and delivers messages until the
+        // network is quiet. Returns the slot if every node externalized it.
+        let slot_index = self.leader_state().next_slot_index();
+        self.nodes
+            .get_mut(&self.leader)?
+            .slot_nominate(slot_index, value);
+        InMemoryGlobalState::process_messages(&self.builder.global_state, &mut self.nodes);
+
+        self.nodes
+            .values()
+            .all(|node| node.scp.herder.0.last_externalized_slot == Some(slot_index))
+            .then_some(slot_index)
+    }
+}
 
 #[cfg(test)]
 mod test {
-    use std::collections::BTreeMap;
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        sync::Arc,
+    };
 
     use crate::{
         ca::{
             builder::{CAInMemoryNodeBuilder, CAStateDriver},
+            cell::timestamp_now,
             crypto::TEST_OPENSSL_PRIVATE_KEY,
             local_state::LocalCAState,
-            operation::SCPCAOperation,
+            operation::{CAOperation, SCPCAOperation, SetRootOperation},
+            root::FlaggedEntriesPolicy,
         },
+        herder::herder::HerderDriver,
         mock::builder::NodeBuilderDir,
         overlay::peer_node::PeerNode,
         overlay_impl::in_memory_global::InMemoryGlobalState,
-        scp::nomination_protocol::NominationProtocolState,
+        scp::{nomination_protocol::NominationProtocolState, scp_driver::ValidationLevel},
     };
 
+    #[test]
+    fn combined_candidates_drop_repeated_and_failing_operations() {
+        let mut herder = CAStateDriver(LocalCAState::init_state_from_pkcs8_pem(
+            TEST_OPENSSL_PRIVATE_KEY,
+        ));
+        let shared = herder.0.create_name_space("namespace1").unwrap();
+        let other = herder.0.create_name_space("namespace2").unwrap();
+        let CAOperation::SetRoot(set_root_operation) = &shared else {
+            panic!("Creating a namespace sets a root entry");
+        };
+        let failing = CAOperation::SetRoot(SetRootOperation::removal(
+            set_root_operation.entry.clone(),
+            String::new(),
+        ));
+        let close_time = timestamp_now();
+        let candidates = BTreeSet::from([
+            Arc::new(SCPCAOperation::new(
+                vec![shared.clone(), failing],
+                close_time,
+            )),
+            Arc::new(SCPCAOperation::new(
+                vec![other.clone(), shared.clone()],
+                close_time,
+            )),
+        ]);
+
+        let combined = herder.combine_candidates(&candidates).unwrap();
+        assert_eq!(combined.operations.len(), 2);
+        assert!(combined.operations.contains(&shared));
+        assert!(combined.operations.contains(&other));
+        assert!(herder.0.state.on_scp_operation(&combined).is_committed());
+    }
+
+    #[test]
+    fn combined_candidates_drop_unflagged_removals() {
+        let mut herder = CAStateDriver(LocalCAState::init_state_from_pkcs8_pem(
+            TEST_OPENSSL_PRIVATE_KEY,
+        ));
+        let operation = herder.0.create_name_space("namespace1").unwrap();
+        assert!(herder.0.state.apply_operation(&operation, 0).is_ok());
+        let removal = herder.0.propose_removal("namespace1", "spam").unwrap();
+        let candidates = BTreeSet::from([Arc::new(SCPCAOperation::new(
+            vec![removal.clone()],
+            timestamp_now(),
+        ))]);
+
+        let combined = herder.combine_candidates(&candidates).unwrap();
+        assert!(combined.operations.is_empty());
+
+        herder.0.root_removal_policy =
+            Arc::new(FlaggedEntriesPolicy::new(["namespace1".to_string()]));
+        let combined = herder.combine_candidates(&candidates).unwrap();
+        assert_eq!(combined.operations, vec![removal]);
+    }
+
+    #[test]
+    fn only_nomination_checks_the_local_clock() {
+        let mut herder = CAStateDriver(LocalCAState::init_state_from_pkcs8_pem(
+            TEST_OPENSSL_PRIVATE_KEY,
+        ));
+        let now = timestamp_now();
+        herder.0.state.close_time = now;
+
+        let ahead = SCPCAOperation::new(vec![], now + 2 * CAStateDriver::MAX_CLOSE_TIME_DRIFT);
+        assert!(herder.validate_value(&ahead, true) == ValidationLevel::MaybeValid);
+        assert!(herder.validate_value(&ahead, false) == ValidationLevel::FullyValidated);
+
+        let behind = SCPCAOperation::new(vec![], now - 1);
+        for nomination in [true, false] {
+            assert!(herder.validate_value(&behind, nomination) == ValidationLevel::Invalid);
+        }
+    }
+
     #[test]
     fn ca_in_memory_peer_nominate_from_local_node_on_file() {
         let mut builder = CAInMemoryNodeBuilder::new(NodeBuilderDir::Test.get_dir_path());
@@ -86,7 +267,7 @@ mod test {
         );
 
         for node in nodes.values() {
-            assert_eq!(node.leaders, vec!["node1".to_string()]);
+            assert_eq!(node.scp.leaders, vec!["node1".to_string()]);
         }
 
         assert!(nodes["node1"].get_current_nomination_state(&0).is_none());
@@ -95,11 +276,12 @@ mod test {
         let operation = nodes
             .get_mut("node1")
             .unwrap()
+            .scp
             .herder
             .0
             .create_name_space("namespace1")
             .unwrap();
-        let scp_operation = SCPCAOperation(vec![operation]);
+        let scp_operation = SCPCAOperation::new(vec![operation], timestamp_now());
 
         // todo!("Nominate with an input value");
         nodes
@@ -110,7 +292,7 @@ mod test {
         assert!(InMemoryGlobalState::process_messages(&builder.global_state, &mut nodes) > 0);
 
         for node in nodes.values() {
-            assert_eq!(node.leaders, vec!["node1".to_string()]);
+            assert_eq!(node.scp.leaders, vec!["node1".to_string()]);
         }
 
         let node1_nomnination_state: NominationProtocolState<SCPCAOperation> =
@@ -122,8 +304,8 @@ mod test {
             node2_nomnination_state.round_leaders
         );
 
-        assert_eq!(nodes["node1"].scp_envelope_controller.envs_to_emit.len(), 0);
-        assert_eq!(nodes["node2"].scp_envelope_controller.envs_to_emit.len(), 0);
+        assert_eq!(nodes["node1"].scp.envelope_controller.envs_to_emit.len(), 0);
+        assert_eq!(nodes["node2"].scp.envelope_controller.envs_to_emit.len(), 0);
 
         assert_eq!(node1_nomnination_state.nomination_started, false);
         assert_eq!(node2_nomnination_state.nomination_started, false);
@@ -146,6 +328,7 @@ mod test {
         assert!(nodes
             .get("node1")
             .unwrap()
+            .scp
             .herder
             .0
             .state
@@ -157,6 +340,7 @@ mod test {
         assert!(nodes
             .get("node2")
             .unwrap()
+            .scp
             .herder
             .0
             .state
@@ -1,32 +1,59 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::runtime::Handle;
 
 use super::{
-    crypto::{mock_public_key, mock_sig, PublicKey, SCPSignature},
+    crypto::{mock_private_key, mock_public_key, mock_sig, PrivateKey, PublicKey, SCPSignature},
     merkle::MerkleHash,
-    table::{HTable, TableId},
+    signing::{mock_signing_context, SigningContext, SigningDomain},
+    table::TableId,
 };
 use crate::ca::ca_type::Timestamp;
 use std::{
-    fmt::Debug, hash::Hash, time::{SystemTime, UNIX_EPOCH}
+    fmt::{Debug, Display}, hash::Hash, time::{SystemTime, UNIX_EPOCH}
 };
 
 type CellOpResult<T> = std::result::Result<T, CellOpError>;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug)]
 pub enum CellOpError {
     CommitmentNotExpires,
     InvalidSignature,
-    Unknown,
+    // The update changes the lookup key or the type of the cell.
+    MismatchedCell,
+    // The update does not advance the revision timestamp.
+    StaleRevision,
+    // A removed cell must be owned by the table authority.
+    InvalidOwner,
+}
+
+impl Display for CellOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CellOpError::CommitmentNotExpires => {
+                write!(f, "commitment of the cell has not expired")
+            }
+            CellOpError::InvalidSignature => write!(f, "invalid cell signature"),
+            CellOpError::MismatchedCell => {
+                write!(f, "update changes the lookup key or type of the cell")
+            }
+            CellOpError::StaleRevision => write!(f, "update does not advance the revision time"),
+            CellOpError::InvalidOwner => {
+                write!(f, "removed cell is not owned by the table authority")
+            }
+        }
+    }
 }
 
+impl std::error::Error for CellOpError {}
+
 #[derive(PartialEq)]
 pub enum InnerCellType {
     Value,
     Delegate,
     Invalid,
 }
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum InnerCell {
     ValueCell(InnerValueCell),
     DelegateCell(InnerDelegateCell),
@@ -65,10 +92,10 @@ pub struct InnerDelegateCell {
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Hash)]
 pub struct InnerValueCell {
     // opaque value<>
-    value: String,
+    pub value: String,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct ValueCell {
     pub create_time: Timestamp,
     pub revision_time: Timestamp,
@@ -77,14 +104,16 @@ pub struct ValueCell {
     pub authority_sig: SCPSignature,
 }
 
-#[derive(Clone)]
+// The delegated table is referenced by its ID in the `TableCollection` of the
+// namespace.
+#[derive(Clone, Deserialize, Serialize)]
 pub struct DelegateCell {
     pub create_time: Timestamp,
     pub revision_time: Timestamp,
     pub commitment_time: Timestamp,
     pub inner_cell: Option<InnerDelegateCell>,
     pub authority_sig: SCPSignature,
-    pub table: Option<HTable>,
+    pub table: Option<TableId>,
 }
 
 // AsRef<[u8]>,
@@ -103,15 +132,19 @@ impl Hash for Cell {
         self.create_time.hash(state);
         self.revision_time.hash(state);
         self.commitment_time.hash(state);
+        self.owner_key.to_bytes().hash(state);
         self.inner.hash(state);
     }
 }
 
+// Two versions of a cell that only differ in their owner key are different
+// cells, otherwise a key rotation can be deduplicated away during nomination.
 impl PartialEq for Cell {
     fn eq(&self, other: &Self) -> bool {
         self.create_time == other.create_time
             && self.revision_time == other.revision_time
             && self.commitment_time == other.commitment_time
+            && self.owner_key == other.owner_key
             && self.inner == other.inner
     }
 }
@@ -125,6 +158,11 @@ impl Ord for Cell {
             .then(self.revision_time.cmp(&other.revision_time))
             .then(self.commitment_time.cmp(&other.commitment_time))
             .then(self.inner.cmp(&other.inner))
+            .then_with(|| {
+                self.owner_key
+                    .to_bytes()
+                    .cmp(&other.owner_key.to_bytes())
+            })
     }
 }
 
@@ -151,12 +189,61 @@ pub enum CellData {
     Delegate(InnerDelegateCell),
 }
 
+// The canonical contents of a cell, i.e. everything except the signature. This
+// is what the owner (or the table authority) signs when creating or updating a
+// cell.
+#[derive(Serialize)]
+struct CellContents<'a> {
+    create_time: Timestamp,
+    revision_time: Timestamp,
+    commitment_time: Timestamp,
+    owner_key: &'a PublicKey,
+    inner: &'a CellData,
+}
+
 pub fn timestamp_now() -> u64 {
     let now = SystemTime::now();
-    now.duration_since(UNIX_EPOCH).unwrap().as_secs()
+    // A clock set before the epoch reads as the epoch.
+    now.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
 }
 
 impl Cell {
+    pub fn new_signed(
+        create_time: Timestamp,
+        commitment_time: Timestamp,
+        owner_key: PublicKey,
+        inner: CellData,
+        private_key: &PrivateKey,
+        context: &SigningContext,
+    ) -> Self {
+        let contents = CellContents {
+            create_time,
+            revision_time: create_time,
+            commitment_time,
+            owner_key: &owner_key,
+            inner: &inner,
+        };
+        let domain = Self::signing_domain(&inner);
+        let lookup_key = match &inner {
+            CellData::Value(value_cell) => &value_cell.value,
+            CellData::Delegate(delegate_cell) => &delegate_cell.name_space,
+        };
+        let sig = SCPSignature::sign(
+            private_key,
+            &context.payload(domain, lookup_key, &contents),
+        );
+
+        Self {
+            create_time,
+            revision_time: create_time,
+            commitment_time,
+            sig,
+            owner_key,
+            inner,
+        }
+    }
+
     pub fn contains_prefix(&self, prefix: &str) -> bool {
         match &self.inner {
             CellData::Value(value_cell) => value_cell.value.starts_with(prefix),
@@ -164,9 +251,9 @@ impl Cell {
         }
     }
 
-    pub fn set_modify_timestamp(&mut self) -> CellOpResult<()> {
-        let now = timestamp_now();
-
+    pub fn set_modify_timestamp(&mut self, now: Timestamp) -> CellOpResult<()> {
+        // `now` is the close time agreed on by consensus rather than the local
+        // clock, so that every node reaches the same decision.
         if now <= self.commitment_time {
             return Err(CellOpError::CommitmentNotExpires);
         }
@@ -177,14 +264,126 @@ impl Cell {
         Ok(())
     }
 
-    pub fn is_valid(&self) -> CellOpResult<()> {
-        // TODO: for now, just check the signature is valid
-        // TODO: need to check this is safe
-        if !self.sig.verify(&self.owner_key, b"Ok") {
-            return Err(CellOpError::InvalidSignature);
+    pub fn is_empty(&self) -> bool {
+        self.name_space_or_value().is_empty()
+    }
+
+    fn signing_domain(inner: &CellData) -> SigningDomain {
+        // An empty cell only ever replaces another cell in a removal.
+        match inner {
+            CellData::Value(value_cell) if value_cell.value.is_empty() => {
+                SigningDomain::RemovedCell
+            }
+            CellData::Delegate(delegate_cell) if delegate_cell.name_space.is_empty() => {
+                SigningDomain::RemovedCell
+            }
+            CellData::Value(_) => SigningDomain::ValueCell,
+            CellData::Delegate(_) => SigningDomain::DelegateCell,
         }
+    }
 
-        Ok(())
+    pub fn signing_bytes(&self, context: &SigningContext, lookup_key: &str) -> Vec<u8> {
+        let contents = CellContents {
+            create_time: self.create_time,
+            revision_time: self.revision_time,
+            commitment_time: self.commitment_time,
+            owner_key: &self.owner_key,
+            inner: &self.inner,
+        };
+
+        context.payload(Self::signing_domain(&self.inner), lookup_key, &contents)
+    }
+
+    pub fn sign(&mut self, private_key: &PrivateKey, context: &SigningContext) {
+        let lookup_key = self.name_space_or_value().to_owned();
+        self.sign_at(private_key, context, &lookup_key);
+    }
+
+    // Signs an empty cell removing the cell at `lookup_key`.
+    pub fn sign_removal(
+        &mut self,
+        private_key: &PrivateKey,
+        context: &SigningContext,
+        lookup_key: &str,
+    ) {
+        self.sign_at(private_key, context, lookup_key);
+    }
+
+    fn sign_at(&mut self, private_key: &PrivateKey, context: &SigningContext, lookup_key: &str) {
+        self.sig = SCPSignature::sign(private_key, &self.signing_bytes(context, lookup_key));
+    }
+
+    pub fn is_signed_by(
+        &self,
+        public_key: &PublicKey,
+        context: &SigningContext,
+    ) -> CellOpResult<()> {
+        self.is_signed_at(public_key, context, self.name_space_or_value())
+    }
+
+    // Checks the signature of an empty cell removing the cell at `lookup_key`.
+    pub fn is_removal_signed_by(
+        &self,
+        public_key: &PublicKey,
+        context: &SigningContext,
+        lookup_key: &str,
+    ) -> CellOpResult<()> {
+        self.is_signed_at(public_key, context, lookup_key)
+    }
+
+    fn is_signed_at(
+        &self,
+        public_key: &PublicKey,
+        context: &SigningContext,
+        lookup_key: &str,
+    ) -> CellOpResult<()> {
+        if self
+            .sig
+            .verify(public_key, &self.signing_bytes(context, lookup_key))
+        {
+            Ok(())
+        } else {
+            Err(CellOpError::InvalidSignature)
+        }
+    }
+
+    pub fn is_valid(&self, context: &SigningContext) -> CellOpResult<()> {
+        // A value cell authenticates its current version with a signature by its
+        // owner.
+        self.is_signed_by(&self.owner_key, context)
+    }
+
+    pub fn validate_update(
+        &self,
+        update: &Cell,
+        authority_key: &PublicKey,
+        now: Timestamp,
+        context: &SigningContext,
+    ) -> CellOpResult<()> {
+        // Checks that `update` may replace this cell in a table controlled by
+        // `authority_key`.
+        //
+        // The owner of a value cell can update the cell (including rotating its
+        // owner key) at any time by signing the new version with the current
+        // owner key. Delegate cells are controlled by the delegator, so the
+        // delegee cannot modify its own namespace. Once the commitment timestamp
+        // has expired, the table authority can update or remove the cell.
+        if self.inner_cell_type() != update.inner_cell_type()
+            || self.name_space_or_value() != update.name_space_or_value()
+        {
+            return Err(CellOpError::MismatchedCell);
+        }
+
+        if update.create_time != self.create_time || update.revision_time <= self.revision_time {
+            return Err(CellOpError::StaleRevision);
+        }
+
+        if self.is_value_cell() && update.is_signed_by(&self.owner_key, context).is_ok() {
+            return Ok(());
+        }
+
+        self.commitment_expires(&now)?;
+        update.is_signed_by(authority_key, context)
     }
 
     pub fn is_value_cell(&self) -> bool {
@@ -194,7 +393,7 @@ impl Cell {
         }
     }
 
-    fn commitment_expires(&self, timestamp: &Timestamp) -> CellOpResult<()> {
+    pub fn commitment_expires(&self, timestamp: &Timestamp) -> CellOpResult<()> {
         if &self.commitment_time < timestamp {
             Ok(())
         } else {
@@ -202,17 +401,76 @@ impl Cell {
         }
     }
 
-    fn modify(&mut self) -> CellOpResult<&Self> {
-        let now = timestamp_now();
-        if let Err(err) = self.commitment_expires(&now) {
-            Err(err)
-        } else {
-            self.revision_time = now;
+    pub fn to_removed(
+        &self,
+        authority_key: &PublicKey,
+        now: Timestamp,
+        commitment_time: Timestamp,
+    ) -> Cell {
+        // Cells are removed by replacing the value or delegated namespace with an
+        // empty value owned by the table authority. A removed delegation keeps
+        // its allowance until the empty cell is garbage collected. The returned
+        // cell still needs to be signed by the table authority with
+        // `sign_removal`.
+        let inner = match &self.inner {
+            CellData::Value(_) => CellData::Value(InnerValueCell {
+                value: "".to_string(),
+            }),
+            CellData::Delegate(inner_delegate_cell) => CellData::Delegate(InnerDelegateCell {
+                name_space: "".to_string(),
+                allowance: inner_delegate_cell.allowance,
+                table: None,
+            }),
+        };
 
-            todo!()
+        Cell {
+            create_time: self.create_time,
+            revision_time: now,
+            commitment_time,
+            sig: self.sig.clone(),
+            owner_key: authority_key.to_owned(),
+            inner,
         }
     }
 
+    pub fn validate_removal(
+        &self,
+        removed: &Cell,
+        authority_key: &PublicKey,
+        now: Timestamp,
+        context: &SigningContext,
+    ) -> CellOpResult<()> {
+        // Only the table authority can remove a cell, and only after its
+        // commitment timestamp has expired.
+        if !removed.is_empty() || self.inner_cell_type() != removed.inner_cell_type() {
+            return Err(CellOpError::MismatchedCell);
+        }
+
+        if &removed.owner_key != authority_key {
+            return Err(CellOpError::InvalidOwner);
+        }
+
+        if removed.create_time != self.create_time || removed.revision_time <= self.revision_time
+        {
+            return Err(CellOpError::StaleRevision);
+        }
+
+        self.commitment_expires(&now)?;
+        removed.is_signed_at(authority_key, context, self.name_space_or_value())
+    }
+
+    pub fn modify(
+        &mut self,
+        update: Cell,
+        authority_key: &PublicKey,
+        now: Timestamp,
+        context: &SigningContext,
+    ) -> CellOpResult<&Self> {
+        self.validate_update(&update, authority_key, now, context)?;
+        *self = update;
+        Ok(self)
+    }
+
     pub fn name_space_or_value<'a>(&'a self) -> &String {
         match &self.inner {
             CellData::Value(value_cell) => &value_cell.value,
@@ -246,7 +504,7 @@ impl Cell {
 }
 
 pub fn test_make_new_delegate_cell(name_space: String, allowance: u32) -> Cell {
-    Cell {
+    let mut cell = Cell {
         create_time: timestamp_now(),
         revision_time: timestamp_now(),
         commitment_time: timestamp_now(),
@@ -257,25 +515,40 @@ pub fn test_make_new_delegate_cell(name_space: String, allowance: u32) -> Cell {
             allowance,
             table: None,
         }),
-    }
+    };
+    cell.sign(&mock_private_key(), &mock_signing_context());
+    cell
 }
 pub fn test_make_new_value_cell(value: String, commitment_time: Timestamp) -> Cell {
-    Cell {
+    let mut cell = Cell {
         create_time: timestamp_now(),
         revision_time: timestamp_now(),
         commitment_time,
         sig: mock_sig(),
         owner_key: mock_public_key(),
         inner: CellData::Value(InnerValueCell { value }),
-    }
+    };
+    cell.sign(&mock_private_key(), &mock_signing_context());
+    cell
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ca::crypto::mock_fake_signature;
+    use crate::ca::{
+        crypto::{mock_fake_signature, mock_generate_private_key},
+        signing::NetworkId,
+    };
 
     use super::*;
 
+    fn test_revise_cell(cell: &Cell, owner_key: PublicKey, signer: &PrivateKey) -> Cell {
+        let mut update = cell.clone();
+        update.revision_time += 1;
+        update.owner_key = owner_key;
+        update.sign(signer, &mock_signing_context());
+        update
+    }
+
     #[test]
     fn error_updating_before_commitment_timestamp_expires() {
         let cell = test_make_new_value_cell("".to_string(), 1);
@@ -303,8 +576,131 @@ mod tests {
         };
 
         assert!(cell_invalid_sig
-            .is_valid()
+            .is_valid(&mock_signing_context())
+            .is_err_and(|err| { err == CellOpError::InvalidSignature }));
+    }
+
+    #[test]
+    fn signature_covers_cell_contents() {
+        let mut cell = test_make_new_value_cell("home/cell".to_string(), 0);
+        assert!(cell.is_valid(&mock_signing_context()).is_ok());
+
+        cell.inner = CellData::Value(InnerValueCell {
+            value: "home/other".to_string(),
+        });
+        assert!(cell
+            .is_valid(&mock_signing_context())
+            .is_err_and(|err| { err == CellOpError::InvalidSignature }));
+    }
+
+    #[test]
+    fn signature_bound_to_namespace_network_and_key() {
+        let cell = test_make_new_value_cell("home/cell".to_string(), 0);
+
+        let other_namespace = SigningContext::new(NetworkId::default(), "other");
+        assert!(cell.is_valid(&other_namespace).is_err());
+
+        let other_network = SigningContext::new(NetworkId::from_passphrase("other"), "namespace");
+        assert!(cell.is_valid(&other_network).is_err());
+
+        // A removal signed for one key does not remove another.
+        let authority = mock_generate_private_key();
+        let now = cell.revision_time + 1;
+        let mut removed = cell.to_removed(&authority.public_key(), now, now);
+        removed.sign_removal(&authority, &mock_signing_context(), "home/cell");
+
+        let mut other_cell = test_make_new_value_cell("home/other".to_string(), 0);
+        other_cell.create_time = cell.create_time;
+        other_cell.revision_time = cell.revision_time;
+        assert!(cell
+            .validate_removal(&removed, &authority.public_key(), now, &mock_signing_context())
+            .is_ok());
+        assert!(other_cell
+            .validate_removal(&removed, &authority.public_key(), now, &mock_signing_context())
+            .is_err_and(|err| err == CellOpError::InvalidSignature));
+    }
+
+    #[test]
+    fn owner_rotates_key_with_old_key() {
+        let authority = mock_generate_private_key();
+        let new_owner = mock_generate_private_key();
+        let mut cell = test_make_new_value_cell("home/cell".to_string(), 10);
+
+        // Signing with the new key does not authorise the rotation.
+        let update = test_revise_cell(&cell, new_owner.public_key(), &new_owner);
+        assert!(cell
+            .validate_update(&update, &authority.public_key(), 0, &mock_signing_context())
+            .is_err_and(|err| { err == CellOpError::CommitmentNotExpires }));
+
+        let update = test_revise_cell(&cell, new_owner.public_key(), &mock_private_key());
+        assert!(cell
+            .modify(update, &authority.public_key(), 0, &mock_signing_context())
+            .is_ok_and(|cell| { cell.owner_key == new_owner.public_key() }));
+
+        // From now on only the new key can update the cell.
+        let update = test_revise_cell(&cell, mock_public_key(), &mock_private_key());
+        assert!(cell
+            .validate_update(&update, &authority.public_key(), 0, &mock_signing_context())
+            .is_err());
+
+        let update = test_revise_cell(&cell, new_owner.public_key(), &new_owner);
+        assert!(cell
+            .validate_update(&update, &authority.public_key(), 0, &mock_signing_context())
+            .is_ok());
+    }
+
+    #[test]
+    fn authority_updates_after_commitment_expires() {
+        let authority = mock_generate_private_key();
+        let cell = test_make_new_value_cell("home/cell".to_string(), 10);
+        let update = test_revise_cell(&cell, authority.public_key(), &authority);
+
+        assert!(cell
+            .validate_update(&update, &authority.public_key(), 10, &mock_signing_context())
+            .is_err_and(|err| { err == CellOpError::CommitmentNotExpires }));
+        assert!(cell
+            .validate_update(&update, &authority.public_key(), 11, &mock_signing_context())
+            .is_ok());
+    }
+
+    #[test]
+    fn delegee_cannot_update_delegation() {
+        let authority = mock_generate_private_key();
+        let cell = test_make_new_delegate_cell("home/".to_string(), 10);
+
+        // The delegate cell is owned by the mock key, but only the delegator can
+        // modify the delegation.
+        let update = test_revise_cell(&cell, mock_public_key(), &mock_private_key());
+        assert!(cell
+            .validate_update(&update, &authority.public_key(), timestamp_now() + 1, &mock_signing_context())
             .is_err_and(|err| { err == CellOpError::InvalidSignature }));
+
+        let update = test_revise_cell(&cell, mock_public_key(), &authority);
+        assert!(cell
+            .validate_update(&update, &authority.public_key(), timestamp_now() + 1, &mock_signing_context())
+            .is_ok());
+    }
+
+    #[test]
+    fn update_cannot_change_lookup_key_or_go_back_in_time() {
+        let authority = mock_generate_private_key();
+        let cell = test_make_new_value_cell("home/cell".to_string(), 10);
+
+        let mut update = cell.clone();
+        update.revision_time += 1;
+        update.inner = CellData::Value(InnerValueCell {
+            value: "home/other".to_string(),
+        });
+        update.sign(&mock_private_key(), &mock_signing_context());
+        assert!(cell
+            .validate_update(&update, &authority.public_key(), 0, &mock_signing_context())
+            .is_err_and(|err| { err == CellOpError::MismatchedCell }));
+
+        let mut update = cell.clone();
+        update.sign(&mock_private_key(), &mock_signing_context());
+        assert!(cell
+            .validate_update(&update, &authority.public_key(), 0, &mock_signing_context())
+            .is_err_and(|err| { err == CellOpError::StaleRevision }));
     }
 }
 
@@ -322,8 +718,18 @@ impl<'a> Default for Cell {
 }
 
 impl Cell {
+    pub fn to_canonical_bytes(&self) -> Option<Vec<u8>> {
+        bincode::serialize(self).ok()
+    }
+
+    pub fn from_canonical_bytes(bytes: &[u8]) -> Option<Self> {
+        bincode::deserialize(bytes).ok()
+    }
+
     pub fn to_merkle_hash(&self) -> Option<MerkleHash> {
-        todo!()
-        // Implement serialization for cell.
+        // The leaf covers the signature as well, so a proof of inclusion also
+        // authenticates who signed the cell.
+        let bytes = self.to_canonical_bytes()?;
+        Some(Sha256::digest(bytes).into())
     }
 }
@@ -0,0 +1,332 @@
+use std::{
+    fmt::Display,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+// Tables only support prefix based delegation (see `Table::check_cell_valid`).
+// Hierarchies that grow to the left, such as domain names, are mapped onto it
+// by reversing the order of their labels, and address blocks by spelling out
+// their bits. Each codec turns an application level name into a lookup key
+// such that a delegated name is a prefix of the keys of every name under it.
+//
+//   Dns    www.example.com      -> com.example.www.
+//   Email  alice@example.com    -> com.example.@alice
+//          example.com          -> com.example.@       (every address)
+//   Ip     10.0.0.0/8           -> 4/00001010
+//          2001:db8::/32        -> 6/00100000000000010000110110111000
+
+pub type CodecResult<T> = std::result::Result<T, CodecError>;
+
+#[derive(PartialEq, Debug)]
+pub enum CodecError {
+    EmptyName,
+    InvalidLabel(String),
+    NameTooLong,
+    InvalidLocalPart(String),
+    InvalidAddress(String),
+    InvalidPrefixLength(String),
+    // The address has bits set past the prefix length.
+    HostBitsSet(String),
+    InvalidKey(String),
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::EmptyName => write!(f, "empty name"),
+            CodecError::InvalidLabel(label) => write!(f, "invalid label {:?}", label),
+            CodecError::NameTooLong => write!(f, "name too long"),
+            CodecError::InvalidLocalPart(local_part) => {
+                write!(f, "invalid local part {:?}", local_part)
+            }
+            CodecError::InvalidAddress(address) => write!(f, "invalid address {:?}", address),
+            CodecError::InvalidPrefixLength(len) => write!(f, "invalid prefix length {:?}", len),
+            CodecError::HostBitsSet(block) => write!(f, "host bits set in {:?}", block),
+            CodecError::InvalidKey(key) => write!(f, "invalid lookup key {:?}", key),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    Default,
+    Serialize,
+    Deserialize,
+    ValueEnum,
+)]
+pub enum KeyCodec {
+    // Keys are used as they are.
+    #[default]
+    Raw,
+    Dns,
+    Email,
+    Ip,
+}
+
+const DNS_MAX_NAME_LEN: usize = 253;
+const DNS_MAX_LABEL_LEN: usize = 63;
+
+impl KeyCodec {
+    pub fn encode(&self, name: &str) -> CodecResult<String> {
+        match self {
+            KeyCodec::Raw => Ok(name.to_owned()),
+            KeyCodec::Dns => encode_dns(name),
+            KeyCodec::Email => encode_email(name),
+            KeyCodec::Ip => encode_ip(name),
+        }
+    }
+
+    pub fn decode(&self, key: &str) -> CodecResult<String> {
+        match self {
+            KeyCodec::Raw => Ok(key.to_owned()),
+            KeyCodec::Dns => decode_dns(key),
+            KeyCodec::Email => decode_email(key),
+            KeyCodec::Ip => decode_ip(key),
+        }
+    }
+}
+
+fn encode_dns(name: &str) -> CodecResult<String> {
+    // Names are case insensitive and may be given fully qualified.
+    let name = name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase();
+    if name.is_empty() {
+        return Err(CodecError::EmptyName);
+    }
+    if name.len() > DNS_MAX_NAME_LEN {
+        return Err(CodecError::NameTooLong);
+    }
+
+    let mut key = String::with_capacity(name.len() + 1);
+    for label in name.rsplit('.') {
+        check_dns_label(label)?;
+        key.push_str(label);
+        key.push('.');
+    }
+    Ok(key)
+}
+
+fn check_dns_label(label: &str) -> CodecResult<()> {
+    let valid = !label.is_empty()
+        && label.len() <= DNS_MAX_LABEL_LEN
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(CodecError::InvalidLabel(label.to_owned()))
+    }
+}
+
+fn decode_dns(key: &str) -> CodecResult<String> {
+    let labels = key
+        .strip_suffix('.')
+        .ok_or(CodecError::InvalidKey(key.to_owned()))?;
+    let name: Vec<&str> = labels.rsplit('.').collect();
+    let name = name.join(".");
+
+    // Only keys produced by `encode_dns` are accepted.
+    if encode_dns(&name)? != key {
+        return Err(CodecError::InvalidKey(key.to_owned()));
+    }
+    Ok(name)
+}
+
+fn encode_email(address: &str) -> CodecResult<String> {
+    // The domain comes first so that delegating a domain covers every address
+    // under it. A bare domain stands for all of its addresses.
+    let (local_part, domain) = address.rsplit_once('@').unwrap_or(("", address));
+    if local_part.contains('@') || local_part.chars().any(char::is_whitespace) {
+        return Err(CodecError::InvalidLocalPart(local_part.to_owned()));
+    }
+    if address.contains('@') && local_part.is_empty() {
+        return Err(CodecError::InvalidLocalPart(local_part.to_owned()));
+    }
+
+    Ok(format!("{}@{}", encode_dns(domain)?, local_part))
+}
+
+fn decode_email(key: &str) -> CodecResult<String> {
+    let (domain, local_part) = key
+        .split_once('@')
+        .ok_or(CodecError::InvalidKey(key.to_owned()))?;
+    let domain = decode_dns(domain)?;
+
+    if local_part.is_empty() {
+        Ok(domain)
+    } else {
+        Ok(format!("{}@{}", local_part, domain))
+    }
+}
+
+fn encode_ip(block: &str) -> CodecResult<String> {
+    // A plain address is a block of a single address.
+    let (address, prefix_len) = match block.split_once('/') {
+        Some((address, prefix_len)) => (address, Some(prefix_len)),
+        None => (block, None),
+    };
+
+    let (version, bits, max_len) = if let Ok(address) = address.parse::<Ipv4Addr>() {
+        (4, u32::from(address) as u128, 32)
+    } else if let Ok(address) = address.parse::<Ipv6Addr>() {
+        (6, u128::from(address), 128)
+    } else {
+        return Err(CodecError::InvalidAddress(address.to_owned()));
+    };
+
+    let prefix_len = match prefix_len {
+        Some(prefix_len) => prefix_len
+            .parse::<u32>()
+            .ok()
+            .filter(|len| *len <= max_len)
+            .ok_or(CodecError::InvalidPrefixLength(prefix_len.to_owned()))?,
+        None => max_len,
+    };
+
+    let host_mask = u128::MAX
+        .checked_shr(128 - (max_len - prefix_len))
+        .unwrap_or(0);
+    if bits & host_mask != 0 {
+        return Err(CodecError::HostBitsSet(block.to_owned()));
+    }
+
+    let mut key = format!("{}/", version);
+    for i in 0..prefix_len {
+        let bit = (bits >> (max_len - 1 - i)) & 1;
+        key.push(if bit == 1 { '1' } else { '0' });
+    }
+    Ok(key)
+}
+
+fn decode_ip(key: &str) -> CodecResult<String> {
+    let invalid_key = || CodecError::InvalidKey(key.to_owned());
+
+    let (version, bit_string) = key.split_once('/').ok_or_else(invalid_key)?;
+    let max_len = match version {
+        "4" => 32,
+        "6" => 128,
+        _ => return Err(invalid_key()),
+    };
+    if bit_string.len() > max_len {
+        return Err(invalid_key());
+    }
+
+    let mut bits = 0u128;
+    for (i, c) in bit_string.chars().enumerate() {
+        match c {
+            '0' => {}
+            '1' => bits |= 1 << (max_len - 1 - i),
+            _ => return Err(invalid_key()),
+        }
+    }
+
+    let address = if max_len == 32 {
+        Ipv4Addr::from(bits as u32).to_string()
+    } else {
+        Ipv6Addr::from(bits).to_string()
+    };
+
+    if bit_string.len() == max_len {
+        Ok(address)
+    } else {
+        Ok(format!("{}/{}", address, bit_string.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dns_keys_are_reversed() {
+        let codec = KeyCodec::Dns;
+        assert_eq!(
+            codec.encode("www.Example.com.").unwrap(),
+            "com.example.www."
+        );
+        assert_eq!(codec.decode("com.example.www.").unwrap(), "www.example.com");
+
+        // A delegated domain is a prefix of its subdomains only.
+        let delegated = codec.encode("example.com").unwrap();
+        assert!(codec
+            .encode("mail.example.com")
+            .unwrap()
+            .starts_with(&delegated));
+        assert!(!codec
+            .encode("example.community")
+            .unwrap()
+            .starts_with(&delegated));
+
+        assert_eq!(codec.encode(""), Err(CodecError::EmptyName));
+        assert_eq!(
+            codec.encode("a..com"),
+            Err(CodecError::InvalidLabel("".to_string()))
+        );
+        assert_eq!(
+            codec.encode("-a.com"),
+            Err(CodecError::InvalidLabel("-a".to_string()))
+        );
+        assert!(codec.decode("com.example").is_err());
+    }
+
+    #[test]
+    fn email_keys_start_with_domain() {
+        let codec = KeyCodec::Email;
+        let address = codec.encode("alice@example.com").unwrap();
+        assert_eq!(address, "com.example.@alice");
+        assert_eq!(codec.decode(&address).unwrap(), "alice@example.com");
+
+        let domain = codec.encode("example.com").unwrap();
+        assert_eq!(domain, "com.example.@");
+        assert_eq!(codec.decode(&domain).unwrap(), "example.com");
+        assert!(address.starts_with(&domain));
+
+        assert!(codec.encode("@example.com").is_err());
+        assert!(codec.encode("a@b@example.com").is_err());
+    }
+
+    #[test]
+    fn ip_keys_spell_out_prefix_bits() {
+        let codec = KeyCodec::Ip;
+        let block = codec.encode("10.0.0.0/8").unwrap();
+        assert_eq!(block, "4/00001010");
+        assert_eq!(codec.decode(&block).unwrap(), "10.0.0.0/8");
+
+        let address = codec.encode("10.1.2.3").unwrap();
+        assert_eq!(address.len(), 2 + 32);
+        assert!(address.starts_with(&block));
+        assert_eq!(codec.decode(&address).unwrap(), "10.1.2.3");
+
+        let block = codec.encode("2001:db8::/32").unwrap();
+        assert_eq!(block, "6/00100000000000010000110110111000");
+        assert_eq!(codec.decode(&block).unwrap(), "2001:db8::/32");
+        assert!(codec.encode("2001:db8::1").unwrap().starts_with(&block));
+        assert_eq!(codec.encode("::/0").unwrap(), "6/");
+
+        assert_eq!(
+            codec.encode("10.1.0.0/8"),
+            Err(CodecError::HostBitsSet("10.1.0.0/8".to_string()))
+        );
+        assert_eq!(
+            codec.encode("10.0.0.0/33"),
+            Err(CodecError::InvalidPrefixLength("33".to_string()))
+        );
+        assert!(codec.encode("example.com").is_err());
+        assert!(codec.decode("4/2").is_err());
+    }
+}
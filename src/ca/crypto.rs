@@ -1,33 +1,273 @@
 use std::fmt::{self, Display};
 
+use clap::ValueEnum;
 use digest::Digest;
-use dsa::{Signature, SigningKey, VerifyingKey};
-use pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePublicKey};
+use pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding};
 use serde::{de, ser, Deserialize, Deserializer, Serialize};
 use sha2::Sha256;
-use signature::{DigestVerifier, RandomizedDigestSigner, SignatureEncoding};
-use syn::token::Pub;
+use signature::{DigestVerifier, RandomizedDigestSigner, SignatureEncoding, Signer, Verifier};
 
 pub const TEST_OPENSSL_PRIVATE_KEY: &str = include_str!("../../test_private.pem");
 
+// Keys and signatures of every supported algorithm are serialized as a one byte
+// algorithm tag followed by the encoding used by the algorithm.
+#[derive(
+    Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize, ValueEnum,
+)]
+pub enum SignatureAlgorithm {
+    Dsa,
+    Ed25519,
+    EcdsaP256,
+}
+
+impl SignatureAlgorithm {
+    pub fn tag(&self) -> u8 {
+        match self {
+            SignatureAlgorithm::Dsa => 0,
+            SignatureAlgorithm::Ed25519 => 1,
+            SignatureAlgorithm::EcdsaP256 => 2,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(SignatureAlgorithm::Dsa),
+            1 => Some(SignatureAlgorithm::Ed25519),
+            2 => Some(SignatureAlgorithm::EcdsaP256),
+            _ => None,
+        }
+    }
+}
+
+pub trait SignatureScheme {
+    const ALGORITHM: SignatureAlgorithm;
+    type SigningKey: Clone;
+    type VerifyingKey: Clone;
+    type Signature: Clone;
+
+    fn generate() -> Self::SigningKey;
+    fn verifying_key(signing_key: &Self::SigningKey) -> Self::VerifyingKey;
+    fn sign(signing_key: &Self::SigningKey, msg: &[u8]) -> Self::Signature;
+    fn verify(verifying_key: &Self::VerifyingKey, msg: &[u8], sig: &Self::Signature) -> bool;
+
+    fn verifying_key_to_bytes(verifying_key: &Self::VerifyingKey) -> Vec<u8>;
+    fn verifying_key_from_bytes(bytes: &[u8]) -> Option<Self::VerifyingKey>;
+    fn signature_to_bytes(sig: &Self::Signature) -> Vec<u8>;
+    fn signature_from_bytes(bytes: &[u8]) -> Option<Self::Signature>;
+}
+
+// DSA with SHA-256. Public keys are DER encoded.
+pub struct Dsa;
+
+impl SignatureScheme for Dsa {
+    const ALGORITHM: SignatureAlgorithm = SignatureAlgorithm::Dsa;
+    type SigningKey = dsa::SigningKey;
+    type VerifyingKey = dsa::VerifyingKey;
+    type Signature = dsa::Signature;
+
+    fn generate() -> Self::SigningKey {
+        let mut rng = rand::thread_rng();
+        let components = dsa::Components::generate(&mut rng, dsa::KeySize::DSA_2048_256);
+        dsa::SigningKey::generate(&mut rng, components)
+    }
+
+    fn verifying_key(signing_key: &Self::SigningKey) -> Self::VerifyingKey {
+        signing_key.verifying_key().clone()
+    }
+
+    fn sign(signing_key: &Self::SigningKey, msg: &[u8]) -> Self::Signature {
+        signing_key.sign_digest_with_rng(&mut rand::thread_rng(), Sha256::new().chain_update(msg))
+    }
+
+    fn verify(verifying_key: &Self::VerifyingKey, msg: &[u8], sig: &Self::Signature) -> bool {
+        verifying_key
+            .verify_digest(Sha256::new().chain_update(msg), sig)
+            .is_ok()
+    }
+
+    fn verifying_key_to_bytes(verifying_key: &Self::VerifyingKey) -> Vec<u8> {
+        verifying_key
+            .to_public_key_der()
+            .expect("Failed to encode public key")
+            .as_bytes()
+            .to_vec()
+    }
+
+    fn verifying_key_from_bytes(bytes: &[u8]) -> Option<Self::VerifyingKey> {
+        dsa::VerifyingKey::from_public_key_der(bytes).ok()
+    }
+
+    fn signature_to_bytes(sig: &Self::Signature) -> Vec<u8> {
+        sig.to_bytes().to_vec()
+    }
+
+    fn signature_from_bytes(bytes: &[u8]) -> Option<Self::Signature> {
+        dsa::Signature::try_from(bytes).ok()
+    }
+}
+
+pub struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    const ALGORITHM: SignatureAlgorithm = SignatureAlgorithm::Ed25519;
+    type SigningKey = ed25519_dalek::SigningKey;
+    type VerifyingKey = ed25519_dalek::VerifyingKey;
+    type Signature = ed25519_dalek::Signature;
+
+    fn generate() -> Self::SigningKey {
+        ed25519_dalek::SigningKey::generate(&mut rand::thread_rng())
+    }
+
+    fn verifying_key(signing_key: &Self::SigningKey) -> Self::VerifyingKey {
+        signing_key.verifying_key()
+    }
+
+    fn sign(signing_key: &Self::SigningKey, msg: &[u8]) -> Self::Signature {
+        signing_key.sign(msg)
+    }
+
+    fn verify(verifying_key: &Self::VerifyingKey, msg: &[u8], sig: &Self::Signature) -> bool {
+        verifying_key.verify_strict(msg, sig).is_ok()
+    }
+
+    fn verifying_key_to_bytes(verifying_key: &Self::VerifyingKey) -> Vec<u8> {
+        verifying_key.to_bytes().to_vec()
+    }
+
+    fn verifying_key_from_bytes(bytes: &[u8]) -> Option<Self::VerifyingKey> {
+        ed25519_dalek::VerifyingKey::from_bytes(bytes.try_into().ok()?).ok()
+    }
+
+    fn signature_to_bytes(sig: &Self::Signature) -> Vec<u8> {
+        sig.to_bytes().to_vec()
+    }
+
+    fn signature_from_bytes(bytes: &[u8]) -> Option<Self::Signature> {
+        ed25519_dalek::Signature::from_slice(bytes).ok()
+    }
+}
+
+// ECDSA over P-256 with SHA-256. Public keys are compressed SEC1 points.
+pub struct EcdsaP256;
+
+impl SignatureScheme for EcdsaP256 {
+    const ALGORITHM: SignatureAlgorithm = SignatureAlgorithm::EcdsaP256;
+    type SigningKey = p256::ecdsa::SigningKey;
+    type VerifyingKey = p256::ecdsa::VerifyingKey;
+    type Signature = p256::ecdsa::Signature;
+
+    fn generate() -> Self::SigningKey {
+        p256::ecdsa::SigningKey::random(&mut rand::thread_rng())
+    }
+
+    fn verifying_key(signing_key: &Self::SigningKey) -> Self::VerifyingKey {
+        *signing_key.verifying_key()
+    }
+
+    fn sign(signing_key: &Self::SigningKey, msg: &[u8]) -> Self::Signature {
+        signing_key.sign(msg)
+    }
+
+    fn verify(verifying_key: &Self::VerifyingKey, msg: &[u8], sig: &Self::Signature) -> bool {
+        verifying_key.verify(msg, sig).is_ok()
+    }
+
+    fn verifying_key_to_bytes(verifying_key: &Self::VerifyingKey) -> Vec<u8> {
+        verifying_key.to_encoded_point(true).as_bytes().to_vec()
+    }
+
+    fn verifying_key_from_bytes(bytes: &[u8]) -> Option<Self::VerifyingKey> {
+        p256::ecdsa::VerifyingKey::from_sec1_bytes(bytes).ok()
+    }
+
+    fn signature_to_bytes(sig: &Self::Signature) -> Vec<u8> {
+        sig.to_bytes().to_vec()
+    }
+
+    fn signature_from_bytes(bytes: &[u8]) -> Option<Self::Signature> {
+        p256::ecdsa::Signature::from_slice(bytes).ok()
+    }
+}
+
+fn tagged_bytes(algorithm: SignatureAlgorithm, bytes: Vec<u8>) -> Vec<u8> {
+    let mut tagged = Vec::with_capacity(bytes.len() + 1);
+    tagged.push(algorithm.tag());
+    tagged.extend(bytes);
+    tagged
+}
+
+fn split_tag(bytes: &[u8]) -> Option<(SignatureAlgorithm, &[u8])> {
+    let (tag, bytes) = bytes.split_first()?;
+    Some((SignatureAlgorithm::from_tag(*tag)?, bytes))
+}
+
 #[derive(Clone, PartialEq, Debug)]
-pub struct PublicKey(pub VerifyingKey);
+pub enum PublicKey {
+    Dsa(<Dsa as SignatureScheme>::VerifyingKey),
+    Ed25519(<Ed25519 as SignatureScheme>::VerifyingKey),
+    EcdsaP256(<EcdsaP256 as SignatureScheme>::VerifyingKey),
+}
+
+impl PublicKey {
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        match self {
+            PublicKey::Dsa(_) => Dsa::ALGORITHM,
+            PublicKey::Ed25519(_) => Ed25519::ALGORITHM,
+            PublicKey::EcdsaP256(_) => EcdsaP256::ALGORITHM,
+        }
+    }
+
+    pub fn from_public_key_pem(pem: &str) -> Option<Self> {
+        if let Ok(key) = dsa::VerifyingKey::from_public_key_pem(pem) {
+            return Some(PublicKey::Dsa(key));
+        }
+        if let Ok(key) = ed25519_dalek::VerifyingKey::from_public_key_pem(pem) {
+            return Some(PublicKey::Ed25519(key));
+        }
+        p256::ecdsa::VerifyingKey::from_public_key_pem(pem)
+            .ok()
+            .map(PublicKey::EcdsaP256)
+    }
+
+    pub fn to_public_key_pem(&self) -> Option<String> {
+        match self {
+            PublicKey::Dsa(key) => key.to_public_key_pem(LineEnding::LF),
+            PublicKey::Ed25519(key) => key.to_public_key_pem(LineEnding::LF),
+            PublicKey::EcdsaP256(key) => key.to_public_key_pem(LineEnding::LF),
+        }
+        .ok()
+    }
+
+    // The tagged encoding also used for serialization.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bytes = match self {
+            PublicKey::Dsa(key) => Dsa::verifying_key_to_bytes(key),
+            PublicKey::Ed25519(key) => Ed25519::verifying_key_to_bytes(key),
+            PublicKey::EcdsaP256(key) => EcdsaP256::verifying_key_to_bytes(key),
+        };
+        tagged_bytes(self.algorithm(), bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (algorithm, bytes) = split_tag(bytes)?;
+        match algorithm {
+            SignatureAlgorithm::Dsa => Dsa::verifying_key_from_bytes(bytes).map(PublicKey::Dsa),
+            SignatureAlgorithm::Ed25519 => {
+                Ed25519::verifying_key_from_bytes(bytes).map(PublicKey::Ed25519)
+            }
+            SignatureAlgorithm::EcdsaP256 => {
+                EcdsaP256::verifying_key_from_bytes(bytes).map(PublicKey::EcdsaP256)
+            }
+        }
+    }
+}
 
 impl Serialize for PublicKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: ser::Serializer,
     {
-        match self.0.to_public_key_der() {
-            Ok(doc) => {
-                let bytes = doc.as_bytes();
-                serializer.serialize_bytes(bytes)
-            }
-            Err(err) => {
-                let err_msg = format!("Failed to serialize public key, err: {:?}", err);
-                Err(ser::Error::custom(err_msg))
-            }
-        }
+        serializer.serialize_bytes(&self.to_bytes())
     }
 }
 
@@ -36,30 +276,64 @@ impl<'de> Deserialize<'de> for PublicKey {
     where
         D: Deserializer<'de>,
     {
-        let bytes: &[u8] = serde_bytes::deserialize(deserializer)?;
-        match VerifyingKey::from_public_key_der(bytes) {
-            Ok(key) => Ok(PublicKey(key)),
-            Err(err) => {
-                let err_msg = format!("Failed to serialize public key, err: {:?}", err);
-                Err(de::Error::custom(err_msg))
-            }
-        }
+        let bytes: serde_bytes::ByteBuf = serde_bytes::deserialize(deserializer)?;
+        PublicKey::from_bytes(&bytes)
+            .ok_or_else(|| de::Error::custom("Failed to deserialize public key from bytes"))
     }
 }
 
 #[derive(Clone, PartialEq, Debug)]
-pub struct PrivateKey(pub SigningKey);
+pub enum PrivateKey {
+    Dsa(<Dsa as SignatureScheme>::SigningKey),
+    Ed25519(<Ed25519 as SignatureScheme>::SigningKey),
+    EcdsaP256(<EcdsaP256 as SignatureScheme>::SigningKey),
+}
 
 impl PrivateKey {
+    pub fn generate(algorithm: SignatureAlgorithm) -> Self {
+        match algorithm {
+            SignatureAlgorithm::Dsa => PrivateKey::Dsa(Dsa::generate()),
+            SignatureAlgorithm::Ed25519 => PrivateKey::Ed25519(Ed25519::generate()),
+            SignatureAlgorithm::EcdsaP256 => PrivateKey::EcdsaP256(EcdsaP256::generate()),
+        }
+    }
+
     pub fn from_pkcs8_pem(pem: &str) -> Self {
-        PrivateKey(
-            SigningKey::from_pkcs8_pem(pem)
-                .expect("Failed to decode PEM encoded OpenSSL signing key"),
-        )
+        Self::try_from_pkcs8_pem(pem).expect("Failed to decode PEM encoded OpenSSL signing key")
+    }
+
+    pub fn try_from_pkcs8_pem(pem: &str) -> Option<Self> {
+        // The algorithm is taken from the key itself.
+        if let Ok(key) = dsa::SigningKey::from_pkcs8_pem(pem) {
+            return Some(PrivateKey::Dsa(key));
+        }
+        if let Ok(key) = ed25519_dalek::SigningKey::from_pkcs8_pem(pem) {
+            return Some(PrivateKey::Ed25519(key));
+        }
+        p256::ecdsa::SigningKey::from_pkcs8_pem(pem)
+            .ok()
+            .map(PrivateKey::EcdsaP256)
+    }
+
+    pub fn to_pkcs8_pem(&self) -> Option<String> {
+        let pem = match self {
+            PrivateKey::Dsa(key) => key.to_pkcs8_pem(LineEnding::LF),
+            PrivateKey::Ed25519(key) => key.to_pkcs8_pem(LineEnding::LF),
+            PrivateKey::EcdsaP256(key) => key.to_pkcs8_pem(LineEnding::LF),
+        };
+        pem.ok().map(|pem| pem.to_string())
+    }
+
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        self.public_key().algorithm()
     }
 
     pub fn public_key(&self) -> PublicKey {
-        PublicKey(self.0.verifying_key().clone())
+        match self {
+            PrivateKey::Dsa(key) => PublicKey::Dsa(Dsa::verifying_key(key)),
+            PrivateKey::Ed25519(key) => PublicKey::Ed25519(Ed25519::verifying_key(key)),
+            PrivateKey::EcdsaP256(key) => PublicKey::EcdsaP256(EcdsaP256::verifying_key(key)),
+        }
     }
 }
 
@@ -88,17 +362,71 @@ impl de::Error for SCPVerifyingKeySerdeError {
 
 impl std::error::Error for SCPVerifyingKeySerdeError {}
 
-impl PublicKey {}
-
 #[derive(Clone)]
-pub struct SCPSignature(pub Signature);
+pub enum SCPSignature {
+    Dsa(<Dsa as SignatureScheme>::Signature),
+    Ed25519(<Ed25519 as SignatureScheme>::Signature),
+    EcdsaP256(<EcdsaP256 as SignatureScheme>::Signature),
+}
+
+impl SCPSignature {
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        match self {
+            SCPSignature::Dsa(_) => Dsa::ALGORITHM,
+            SCPSignature::Ed25519(_) => Ed25519::ALGORITHM,
+            SCPSignature::EcdsaP256(_) => EcdsaP256::ALGORITHM,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bytes = match self {
+            SCPSignature::Dsa(sig) => Dsa::signature_to_bytes(sig),
+            SCPSignature::Ed25519(sig) => Ed25519::signature_to_bytes(sig),
+            SCPSignature::EcdsaP256(sig) => EcdsaP256::signature_to_bytes(sig),
+        };
+        tagged_bytes(self.algorithm(), bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (algorithm, bytes) = split_tag(bytes)?;
+        match algorithm {
+            SignatureAlgorithm::Dsa => Dsa::signature_from_bytes(bytes).map(SCPSignature::Dsa),
+            SignatureAlgorithm::Ed25519 => {
+                Ed25519::signature_from_bytes(bytes).map(SCPSignature::Ed25519)
+            }
+            SignatureAlgorithm::EcdsaP256 => {
+                EcdsaP256::signature_from_bytes(bytes).map(SCPSignature::EcdsaP256)
+            }
+        }
+    }
+
+    pub fn sign(private_key: &PrivateKey, msg: &[u8]) -> Self {
+        match private_key {
+            PrivateKey::Dsa(key) => SCPSignature::Dsa(Dsa::sign(key, msg)),
+            PrivateKey::Ed25519(key) => SCPSignature::Ed25519(Ed25519::sign(key, msg)),
+            PrivateKey::EcdsaP256(key) => SCPSignature::EcdsaP256(EcdsaP256::sign(key, msg)),
+        }
+    }
+
+    pub fn verify(&self, public_key: &PublicKey, msg: &[u8]) -> bool {
+        // A signature never verifies under a key of another algorithm.
+        match (public_key, self) {
+            (PublicKey::Dsa(key), SCPSignature::Dsa(sig)) => Dsa::verify(key, msg, sig),
+            (PublicKey::Ed25519(key), SCPSignature::Ed25519(sig)) => Ed25519::verify(key, msg, sig),
+            (PublicKey::EcdsaP256(key), SCPSignature::EcdsaP256(sig)) => {
+                EcdsaP256::verify(key, msg, sig)
+            }
+            _ => false,
+        }
+    }
+}
 
 impl Serialize for SCPSignature {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: ser::Serializer,
     {
-        serializer.serialize_bytes(&self.0.to_bytes())
+        serializer.serialize_bytes(&self.to_bytes())
     }
 }
 
@@ -107,14 +435,9 @@ impl<'de> Deserialize<'de> for SCPSignature {
     where
         D: Deserializer<'de>,
     {
-        let bytes: &[u8] = serde_bytes::deserialize(deserializer)?;
-        match Signature::try_from(bytes) {
-            Ok(sig) => Ok(SCPSignature(sig)),
-            Err(err) => {
-                let err_msg = format!("Failed to deserialize signature from bytes, err: {:?}", err);
-                Err(de::Error::custom(err_msg))
-            }
-        }
+        let bytes: serde_bytes::ByteBuf = serde_bytes::deserialize(deserializer)?;
+        SCPSignature::from_bytes(&bytes)
+            .ok_or_else(|| de::Error::custom("Failed to deserialize signature from bytes"))
     }
 }
 
@@ -122,60 +445,35 @@ pub fn mock_private_key() -> PrivateKey {
     PrivateKey::from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY)
 }
 
-pub fn mock_public_key() -> PublicKey {
-    let (verifying_key, _) = mock_public_key_sig();
-
-    PublicKey(verifying_key.clone())
-}
-
-fn mock_public_key_sig() -> (VerifyingKey, Signature) {
-    let signing_key: SigningKey = SigningKey::from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY)
+// Generates a fresh key sharing the domain parameters of the test key, which is
+// much cheaper than generating new DSA parameters.
+pub fn mock_generate_private_key() -> PrivateKey {
+    let signing_key = dsa::SigningKey::from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY)
         .expect("Failed to decode PEM encoded OpenSSL signing key");
-    let verifying_key: &VerifyingKey = signing_key.verifying_key();
-    let sig = signing_key
-        .sign_digest_with_rng(&mut rand::thread_rng(), Sha256::new().chain_update(b"Ok"));
+    let components = signing_key.verifying_key().components().clone();
+    PrivateKey::Dsa(dsa::SigningKey::generate(
+        &mut rand::thread_rng(),
+        components,
+    ))
+}
 
-    (verifying_key.clone(), sig)
+pub fn mock_public_key() -> PublicKey {
+    mock_private_key().public_key()
 }
 
 pub fn mock_sig() -> SCPSignature {
-    let (_, sig) = mock_public_key_sig();
-
-    SCPSignature(sig)
+    SCPSignature::sign(&mock_private_key(), b"Ok")
 }
 
 pub fn mock_fake_signature() -> SCPSignature {
-    let private_key = mock_private_key();
-    let mut scp_sig = mock_sig();
-
-    let sig = SCPSignature::sign(&private_key, b"Not ok").0;
-
-    scp_sig.0 = sig;
-    scp_sig
-}
-
-impl SCPSignature {
-    pub fn sign(private_key: &PrivateKey, msg: &[u8]) -> Self {
-        let sig = private_key
-            .0
-            .sign_digest_with_rng(&mut rand::thread_rng(), Sha256::new().chain_update(msg));
-
-        SCPSignature(sig)
-    }
-
-    pub fn verify(&self, public_key: &PublicKey, msg: &[u8]) -> bool {
-        public_key
-            .0
-            .verify_digest(Sha256::new().chain_update(msg), &self.0)
-            .is_ok()
-    }
+    SCPSignature::sign(&mock_private_key(), b"Not ok")
 }
 
 #[cfg(test)]
 mod tests {
 
     use digest::Digest;
-    use dsa::{SigningKey, VerifyingKey};
+    use dsa::{Signature, SigningKey, VerifyingKey};
     use pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding};
 
     use super::*;
@@ -268,7 +566,7 @@ mod tests {
 
     #[test]
     fn sign_and_verify_scp_signature() {
-        let private_key = PrivateKey(
+        let private_key = PrivateKey::Dsa(
             SigningKey::from_pkcs8_pem(OPENSSL_PEM_PRIVATE_KEY)
                 .expect("Failed to decode PEM encoded OpenSSL signing key"),
         );
@@ -283,4 +581,58 @@ mod tests {
         let public_key = mock_private_key().public_key();
         assert!(!corrupted.verify(&public_key, b"Ok"));
     }
+
+    #[test]
+    fn sign_and_verify_with_every_algorithm() {
+        for algorithm in [
+            SignatureAlgorithm::Dsa,
+            SignatureAlgorithm::Ed25519,
+            SignatureAlgorithm::EcdsaP256,
+        ] {
+            let private_key = match algorithm {
+                SignatureAlgorithm::Dsa => mock_generate_private_key(),
+                _ => PrivateKey::generate(algorithm),
+            };
+            let public_key = private_key.public_key();
+            assert_eq!(public_key.algorithm(), algorithm);
+
+            let sig = SCPSignature::sign(&private_key, b"Ok");
+            assert_eq!(sig.algorithm(), algorithm);
+            assert!(sig.verify(&public_key, b"Ok"));
+            assert!(!sig.verify(&public_key, b"Not ok"));
+
+            // Serialized keys and signatures start with the algorithm tag.
+            let key_bytes = bincode::serialize(&public_key).unwrap();
+            let sig_bytes = bincode::serialize(&sig).unwrap();
+            assert_eq!(key_bytes[8], algorithm.tag());
+            assert_eq!(sig_bytes[8], algorithm.tag());
+
+            let deserialized_key: PublicKey = bincode::deserialize(&key_bytes).unwrap();
+            let deserialized_sig: SCPSignature = bincode::deserialize(&sig_bytes).unwrap();
+            assert_eq!(deserialized_key, public_key);
+            assert!(deserialized_sig.verify(&deserialized_key, b"Ok"));
+
+            let pem = private_key.to_pkcs8_pem().unwrap();
+            assert_eq!(PrivateKey::try_from_pkcs8_pem(&pem), Some(private_key));
+            let pem = public_key.to_public_key_pem().unwrap();
+            assert_eq!(PublicKey::from_public_key_pem(&pem), Some(public_key));
+        }
+    }
+
+    #[test]
+    fn signature_does_not_verify_under_other_algorithm() {
+        let ed25519_key = PrivateKey::generate(SignatureAlgorithm::Ed25519);
+        let p256_key = PrivateKey::generate(SignatureAlgorithm::EcdsaP256);
+
+        let sig = SCPSignature::sign(&ed25519_key, b"Ok");
+        assert!(!sig.verify(&p256_key.public_key(), b"Ok"));
+        assert!(!sig.verify(&mock_public_key(), b"Ok"));
+
+        let mut bytes = sig.to_bytes();
+        bytes[0] = SignatureAlgorithm::EcdsaP256.tag();
+        assert!(SCPSignature::from_bytes(&bytes)
+            .map_or(true, |sig| !sig.verify(&p256_key.public_key(), b"Ok")));
+        bytes[0] = 255;
+        assert!(SCPSignature::from_bytes(&bytes).is_none());
+    }
 }
@@ -0,0 +1,114 @@
+use std::fmt::Display;
+
+use super::{state::CAStateOpError, table::TableId};
+
+// Every error of applying an operation to the CA state is a `CAStateOpError`,
+// which wraps the errors of the root listing, tables, cells and Merkle trees.
+// The public entry points of the CA return a `CAError`, which adds where in the
+// state it happened, so that a rejected value can be reported without looking
+// the operation up again.
+
+pub type CAResult<T> = std::result::Result<T, CAError>;
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct ErrorContext {
+    pub application_identifier: Option<String>,
+    pub lookup_key: Option<String>,
+    // The table responsible for the lookup key, if the namespace has one.
+    pub table_id: Option<TableId>,
+}
+
+impl ErrorContext {
+    pub fn namespace(application_identifier: &str) -> Self {
+        Self {
+            application_identifier: Some(application_identifier.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_lookup_key(mut self, lookup_key: &str) -> Self {
+        self.lookup_key = Some(lookup_key.to_owned());
+        self
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct CAError {
+    pub context: ErrorContext,
+    pub source: CAStateOpError,
+}
+
+impl CAError {
+    pub fn new(context: ErrorContext, source: impl Into<CAStateOpError>) -> Self {
+        Self {
+            context,
+            source: source.into(),
+        }
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(application_identifier) = &self.application_identifier {
+            parts.push(format!("namespace {:?}", application_identifier));
+        }
+        if let Some(table_id) = &self.table_id {
+            parts.push(format!("table {:?}", table_id.0));
+        }
+        if let Some(lookup_key) = &self.lookup_key {
+            parts.push(format!("key {:?}", lookup_key));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+impl Display for CAError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.context == ErrorContext::default() {
+            write!(f, "{}", self.source)
+        } else {
+            write!(f, "{}: {}", self.context, self.source)
+        }
+    }
+}
+
+// The message already includes the state error.
+impl std::error::Error for CAError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use crate::ca::{cell::CellOpError, table::TableOpError};
+
+    use super::*;
+
+    #[test]
+    fn causes_are_reported_once() {
+        let err = CAError {
+            context: ErrorContext {
+                application_identifier: Some("namespace".to_string()),
+                lookup_key: Some("home/alice".to_string()),
+                table_id: Some(TableId("home".to_string())),
+            },
+            source: CAStateOpError::TableOpError(TableOpError::CellOpError(
+                CellOpError::StaleRevision,
+            )),
+        };
+        assert_eq!(
+            err.to_string(),
+            "namespace \"namespace\", table \"home\", key \"home/alice\": \
+             update does not advance the revision time"
+        );
+
+        // The wrappers print the cell error, so it is not repeated as a
+        // source.
+        assert!(err.source().is_none());
+        assert!(err.source.source().is_none());
+    }
+}
@@ -1,47 +1,287 @@
+use std::{fs, path::PathBuf, sync::Arc};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::scp::slot::SlotIndex;
+
 use crate::ca::crypto::PrivateKey;
 use crate::ca::operation::SetRootOperation;
 use crate::ca::root::RootEntry;
-use crate::ca::state::{CAState, CAStateOpError};
+use crate::ca::root::RootOpError;
+use crate::ca::state::{BatchResult, CAState, CAStateOpError};
 
-use super::operation::CAOperation;
-use super::state::CAStateOpResult;
+use super::error::{CAError, CAResult, ErrorContext};
+use super::operation::{CAOperation, CellMerkleProof, GetOperation, SCPCAOperation};
+use super::root::{
+    AcceptAllowancePolicy, FlaggedEntriesPolicy, RejectRemovalPolicy, RootEntryPolicy,
+    RootRemovalPolicy,
+};
+use super::store::{CAStore, RootRemovalLogEntry};
+use super::transparency::{SignedTreeHead, TransparencyLog};
+
+#[derive(Serialize, Deserialize)]
+pub struct LocalCAStateToml {
+    pub private_key_path: PathBuf,
+    pub data_dir: PathBuf,
+    pub snapshot_interval: Option<u64>,
+    // Namespaces the node votes to remove from the root listing.
+    pub flagged_root_entries: Option<Vec<String>>,
+}
 
 #[derive(Clone, Debug)]
 pub struct LocalCAState {
     pub private_key: PrivateKey,
     pub state: CAState,
+    pub root_entry_policy: Arc<dyn RootEntryPolicy>,
+    pub root_removal_policy: Arc<dyn RootRemovalPolicy>,
+    // Where externalized values are persisted. An in-memory node has none.
+    pub store: Option<CAStore>,
+    pub last_externalized_slot: Option<SlotIndex>,
+    // Every value externalized by this node, in order.
+    pub transparency_log: TransparencyLog,
 }
 
 impl LocalCAState {
-    pub fn init_from_toml(toml_path: &str) -> Self {
-        todo!()
+    pub fn init_from_toml(toml_path: &str) -> Option<Self> {
+        // Loads the private key and restores the state persisted in the data
+        // directory named by the config file.
+        let toml_str = fs::read_to_string(toml_path).ok()?;
+        let state_toml: LocalCAStateToml = toml::from_str(&toml_str).ok()?;
+
+        let pem = fs::read_to_string(&state_toml.private_key_path).ok()?;
+        let private_key = PrivateKey::from_pkcs8_pem(&pem);
+
+        let mut store = CAStore::new(
+            &state_toml.data_dir,
+            state_toml
+                .snapshot_interval
+                .unwrap_or(CAStore::DEFAULT_SNAPSHOT_INTERVAL),
+        )
+        .ok()?;
+        let (state, last_externalized_slot) = store.load().ok()?;
+        let transparency_log = store.load_transparency_log().ok()?;
+        let root_removal_policy: Arc<dyn RootRemovalPolicy> = match state_toml.flagged_root_entries
+        {
+            Some(flagged) => Arc::new(FlaggedEntriesPolicy::new(flagged)),
+            None => Arc::new(RejectRemovalPolicy),
+        };
+
+        Some(Self {
+            private_key,
+            state,
+            root_entry_policy: Arc::new(AcceptAllowancePolicy),
+            root_removal_policy,
+            store: Some(store),
+            last_externalized_slot,
+            transparency_log,
+        })
     }
 
     pub fn init_state_from_pkcs8_pem(private_key_path: &str) -> Self {
-        let private_key = PrivateKey::from_pkcs8_pem(private_key_path);
+        Self::init_state_from_private_key(PrivateKey::from_pkcs8_pem(private_key_path))
+    }
+
+    pub fn init_state_from_private_key(private_key: PrivateKey) -> Self {
         Self {
             private_key,
             state: Default::default(),
+            root_entry_policy: Arc::new(AcceptAllowancePolicy),
+            root_removal_policy: Arc::new(RejectRemovalPolicy),
+            store: None,
+            last_externalized_slot: None,
+            transparency_log: Default::default(),
+        }
+    }
+
+    pub fn next_slot_index(&self) -> SlotIndex {
+        self.last_externalized_slot
+            .map_or(0, |slot_index| slot_index + 1)
+    }
+
+    pub fn on_externalized(
+        &mut self,
+        slot_index: SlotIndex,
+        value: &SCPCAOperation,
+    ) -> BatchResult {
+        let result = self.state.on_scp_operation(value);
+        let mut removals = vec![];
+        match &result {
+            // Removals are governance actions and are logged along with their
+            // reason, and recorded in the store for audit. The transparency log
+            // keeps the operations for auditors.
+            BatchResult::Committed => {
+                for operation in &value.operations {
+                    if let CAOperation::SetRoot(set_root_operation) = operation {
+                        if let Some(reason) = set_root_operation.removal_reason() {
+                            let application_identifier =
+                                &set_root_operation.entry.application_identifier;
+                            info!(
+                                "Slot {}: removed root entry {} ({})",
+                                slot_index, application_identifier, reason
+                            );
+                            removals.push(RootRemovalLogEntry {
+                                slot_index,
+                                application_identifier: application_identifier.to_owned(),
+                                reason: reason.to_owned(),
+                            });
+                        }
+                    }
+                }
+            }
+            BatchResult::Rejected(errors) => {
+                for (index, err) in errors {
+                    warn!("Slot {}: rejected operation {}: {}", slot_index, index, err);
+                }
+            }
+        }
+
+        self.last_externalized_slot = Some(slot_index);
+        self.transparency_log.append(slot_index, value);
+
+        if let Some(store) = &mut self.store {
+            if let Err(err) = store.on_externalized(&self.state, slot_index, value) {
+                warn!("Failed to persist slot {}: {:?}", slot_index, err);
+            }
+            for removal in &removals {
+                if let Err(err) = store.append_removal(removal) {
+                    warn!(
+                        "Failed to record the removal of {} in slot {}: {:?}",
+                        removal.application_identifier, slot_index, err
+                    );
+                }
+            }
         }
+
+        result
+    }
+
+    // Looks the key up and signs the root of the table answering it, so the
+    // answer can be held against this node.
+    pub fn lookup<'a>(&self, get_opt: &'a GetOperation) -> CAResult<CellMerkleProof<'a>> {
+        let mut proof = self.state.get(get_opt)?;
+        let context = self.state.signing_context(&get_opt.application_identifier);
+        proof.sign_root(&self.private_key, &context);
+        Ok(proof)
+    }
+
+    pub fn signed_tree_head(&self) -> SignedTreeHead {
+        self.transparency_log
+            .signed_tree_head(&self.private_key, &self.state.network_id)
     }
 
-    pub fn create_name_space(&self, name_space: &str) -> CAStateOpResult<CAOperation> {
+    pub fn create_name_space(&self, name_space: &str) -> CAResult<CAOperation> {
         if self.state.root_listing.0.contains_key(name_space) {
-            Err(CAStateOpError::AlreadyExists)
+            Err(CAError::new(
+                ErrorContext::namespace(name_space),
+                CAStateOpError::AlreadyExists,
+            ))
         } else {
-            let entry = RootEntry::new(&self.private_key, name_space.to_owned());
+            let entry = RootEntry::new(
+                &self.private_key,
+                &self.state.network_id,
+                name_space.to_owned(),
+            );
+
+            Ok(CAOperation::SetRoot(SetRootOperation::set(entry)))
+        }
+    }
+
+    pub fn request_allowance(&self, name_space: &str, allowance: u32) -> CAResult<CAOperation> {
+        // Nominates a new root entry for a namespace we own with a larger
+        // allowance. Other nodes decide whether to accept it according to their
+        // `RootEntryPolicy`.
+        let Some(current) = self.state.root_listing.0.get(name_space) else {
+            return Err(CAError::new(
+                ErrorContext::namespace(name_space),
+                CAStateOpError::NoExist,
+            ));
+        };
+
+        let entry = RootEntry::new_with_revision(
+            &self.private_key,
+            &self.state.network_id,
+            name_space.to_owned(),
+            allowance,
+            current.revision + 1,
+        );
+
+        Ok(CAOperation::SetRoot(SetRootOperation::set(entry)))
+    }
+
+    pub fn propose_removal(&self, name_space: &str, reason: &str) -> CAResult<CAOperation> {
+        // Nominates removing a namespace, e.g. one whose application
+        // misbehaves. Other nodes vote for it according to their
+        // `RootRemovalPolicy`.
+        let context = ErrorContext::namespace(name_space);
+        let entry = self
+            .state
+            .root_listing
+            .0
+            .get(name_space)
+            .ok_or_else(|| CAError::new(context.clone(), CAStateOpError::NoExist))?;
+
+        let operation = SetRootOperation::removal(entry.to_owned(), reason.to_owned());
+        if operation.removal_reason().is_none() {
+            return Err(CAError::new(context, RootOpError::MissingRemovalReason));
+        }
+        Ok(CAOperation::SetRoot(operation))
+    }
 
-            Ok(CAOperation::SetRoot(SetRootOperation {
-                entry,
-                remove: false,
-            }))
+    pub fn accepts_root_entries(&self, value: &SCPCAOperation) -> bool {
+        // Checks every root entry in a nominated value is correctly signed and
+        // that the local policy accepts any allowance increases and removals.
+        value
+            .operations
+            .iter()
+            .all(|operation| self.accepts_root_operation(operation))
+    }
+
+    pub fn accepts_root_operation(&self, operation: &CAOperation) -> bool {
+        match operation {
+            CAOperation::SetRoot(set_root_operation) if set_root_operation.remove => {
+                let entry = &set_root_operation.entry;
+                match (
+                    set_root_operation.removal_reason(),
+                    self.state.root_listing.0.get(&entry.application_identifier),
+                ) {
+                    (Some(reason), Some(current)) => {
+                        self.root_removal_policy.flag_for_removal(current, reason)
+                    }
+                    _ => false,
+                }
+            }
+            CAOperation::SetRoot(set_root_operation) if !set_root_operation.remove => {
+                let entry = &set_root_operation.entry;
+                if self
+                    .state
+                    .root_listing
+                    .validate_set_root(entry, &self.state.network_id)
+                    .is_err()
+                {
+                    return false;
+                }
+
+                match self.state.root_listing.0.get(&entry.application_identifier) {
+                    Some(current) if current.is_allowance_increase(entry) => self
+                        .root_entry_policy
+                        .accept_allowance_increase(current, entry),
+                    _ => true,
+                }
+            }
+            _ => true,
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::ca::{crypto::TEST_OPENSSL_PRIVATE_KEY, operation::SCPCAOperation};
+    use crate::ca::{
+        crypto::{mock_generate_private_key, TEST_OPENSSL_PRIVATE_KEY},
+        operation::SCPCAOperation,
+        root::MaxAllowancePolicy,
+        signing::NetworkId,
+    };
 
     use super::*;
 
@@ -55,7 +295,7 @@ mod test {
             _ => panic!("not reached"),
         };
 
-        assert!(local_state.state.on_ca_operation(&operation).is_ok());
+        assert!(local_state.state.apply_operation(&operation, 0).is_ok());
 
         assert_eq!(local_state.state.root_listing.0.len(), 1);
         let added_entry = local_state.state.root_listing.0.get("namespace1").unwrap();
@@ -67,9 +307,9 @@ mod test {
         assert_eq!(added_entry.allowance, entry.allowance);
 
         let operation = local_state.create_name_space("namespace2").unwrap();
-        let scp_operation = SCPCAOperation(vec![operation]);
+        let scp_operation = SCPCAOperation::new(vec![operation], 0);
 
-        let entry = match &scp_operation.0[0] {
+        let entry = match &scp_operation.operations[0] {
             CAOperation::SetRoot(set_root_operation) => set_root_operation.entry.clone(),
             _ => panic!("not reached"),
         };
@@ -84,4 +324,106 @@ mod test {
             entry.application_identifier
         );
     }
+
+    #[test]
+    fn test_request_allowance() {
+        let mut local_state = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        assert!(local_state.request_allowance("namespace1", 20).is_err());
+
+        let entry = RootEntry::new_with_allowance(
+            &local_state.private_key,
+            &local_state.state.network_id,
+            "namespace1".to_string(),
+            10,
+        );
+        let operation = CAOperation::SetRoot(SetRootOperation::set(entry));
+        assert!(local_state.state.apply_operation(&operation, 0).is_ok());
+
+        let operation = local_state.request_allowance("namespace1", 20).unwrap();
+        let value = SCPCAOperation::new(vec![operation.clone()], 0);
+        assert!(local_state.accepts_root_entries(&value));
+
+        local_state.root_entry_policy = Arc::new(MaxAllowancePolicy { max_allowance: 15 });
+        assert!(!local_state.accepts_root_entries(&value));
+
+        assert!(local_state.state.apply_operation(&operation, 0).is_ok());
+        assert_eq!(local_state.state.root_listing.0["namespace1"].allowance, 20);
+    }
+
+    #[test]
+    fn test_cannot_overwrite_namespace_of_other_owner() {
+        let mut local_state = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        let operation = local_state.create_name_space("namespace1").unwrap();
+        assert!(local_state.state.apply_operation(&operation, 0).is_ok());
+
+        let entry = RootEntry::new(
+            &mock_generate_private_key(),
+            &NetworkId::default(),
+            "namespace1".to_string(),
+        );
+        let operation = CAOperation::SetRoot(SetRootOperation::set(entry));
+        assert!(!local_state.accepts_root_entries(&SCPCAOperation::new(vec![operation.clone()], 0)));
+        assert_eq!(
+            local_state
+                .state
+                .apply_operation(&operation, 0)
+                .map_err(|err| err.source),
+            Err(CAStateOpError::RootOpError(RootOpError::NotSignedByRootKey))
+        );
+    }
+
+    #[test]
+    fn test_root_removal_needs_reason_and_flag() {
+        let mut local_state = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        assert_eq!(
+            local_state.propose_removal("namespace1", "spam"),
+            Err(CAError::new(
+                ErrorContext::namespace("namespace1"),
+                CAStateOpError::NoExist
+            ))
+        );
+
+        let operation = local_state.create_name_space("namespace1").unwrap();
+        assert!(local_state.state.apply_operation(&operation, 0).is_ok());
+        assert_eq!(
+            local_state
+                .propose_removal("namespace1", " ")
+                .map_err(|err| err.source),
+            Err(CAStateOpError::RootOpError(
+                RootOpError::MissingRemovalReason
+            ))
+        );
+
+        // Nodes only vote for removing entries they flagged.
+        let operation = local_state.propose_removal("namespace1", "spam").unwrap();
+        let value = SCPCAOperation::new(vec![operation.clone()], 0);
+        assert!(!local_state.accepts_root_entries(&value));
+
+        local_state.root_removal_policy =
+            Arc::new(FlaggedEntriesPolicy::new(["namespace1".to_string()]));
+        assert!(local_state.accepts_root_entries(&value));
+
+        let CAOperation::SetRoot(set_root_operation) = &operation else {
+            panic!("not reached");
+        };
+        let no_reason = CAOperation::SetRoot(SetRootOperation {
+            reason: None,
+            ..set_root_operation.to_owned()
+        });
+        assert!(!local_state.accepts_root_entries(&SCPCAOperation::new(vec![no_reason.clone()], 0)));
+        assert_eq!(
+            local_state
+                .state
+                .apply_operation(&no_reason, 0)
+                .map_err(|err| err.source),
+            Err(CAStateOpError::RootOpError(
+                RootOpError::MissingRemovalReason
+            ))
+        );
+
+        assert!(local_state.state.apply_operation(&operation, 0).is_ok());
+        assert!(!local_state
+            .state
+            .contains_root_entry(&"namespace1".to_string()));
+    }
 }
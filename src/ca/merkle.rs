@@ -1,4 +1,4 @@
-use std::{cell::RefCell, hash::Hasher, rc::Rc};
+use std::{cell::RefCell, fmt::Display, hash::Hasher, rc::Rc};
 
 use ct_merkle::{error::InclusionVerifError, inclusion::InclusionProof, CtMerkleTree, RootHash};
 use sha2::{Digest, Sha256};
@@ -22,6 +22,22 @@ pub enum MerkleOpError {
     InternalTreeError,
 }
 
+impl Display for MerkleOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MerkleOpError::FailureGenerateInclusionProof => {
+                write!(f, "failed to generate inclusion proof")
+            }
+            MerkleOpError::InvalidIndex => write!(f, "leaf index out of range"),
+            MerkleOpError::MalformedProof => write!(f, "malformed inclusion proof"),
+            MerkleOpError::VerificationFailure => write!(f, "inclusion proof does not verify"),
+            MerkleOpError::InternalTreeError => write!(f, "internal Merkle tree error"),
+        }
+    }
+}
+
+impl std::error::Error for MerkleOpError {}
+
 pub type HMerkleTree = Rc<RefCell<MerkleTree>>;
 
 #[derive(Clone, Debug)]
@@ -40,10 +56,22 @@ impl Default for MerkleTree {
 }
 
 impl MerkleTree {
+    pub fn from_leaves(leaves: &[MerkleHash]) -> Self {
+        let mut tree = Self::default();
+        for leaf in leaves {
+            tree.push(*leaf);
+        }
+        tree
+    }
+
     pub fn len(&self) -> usize {
         self.size
     }
 
+    pub fn leaves(&self) -> &[MerkleHash] {
+        self.mktree.items()
+    }
+
     pub fn update(&mut self, val: MerkleHash, idx: usize) -> MerkleOpResult<()> {
         if self.size <= idx {
             return Err(MerkleOpError::InvalidIndex);
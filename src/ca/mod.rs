@@ -1,13 +1,20 @@
 pub mod crypto;
 pub mod cell;
+pub mod codec;
+pub mod error;
 mod merkle;
 pub mod operation;
+pub mod prefix;
 pub mod root;
+pub mod signing;
 pub mod state;
 pub mod table;
+pub mod transparency;
+pub mod store;
 pub mod util;
 mod ca_type;
 pub mod local_state;
 pub mod scp;
 pub mod builder;
 pub mod arg;
+pub mod auditor;
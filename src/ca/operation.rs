@@ -7,15 +7,35 @@ use sha2::Sha256;
 use crate::scp::{nomination_protocol::NominationValue, scp::SCP};
 
 use super::{
+    ca_type::Timestamp,
     cell::Cell,
-    crypto::SCPSignature,
+    codec::{CodecResult, KeyCodec},
+    crypto::{PrivateKey, PublicKey, SCPSignature},
+    error::{CAError, CAResult, ErrorContext},
     merkle::MerkleRoot,
     root::RootEntry,
+    signing::{SigningContext, SigningDomain},
+    state::{CAStateOpError, CAStateOpResult},
     table::{Table, TableMeta},
 };
 
+// The value nominated by CA nodes. Besides the operations, each value carries
+// the close time proposed by the nominating node. Once externalized, the close
+// time is what cell commitment timestamps are checked against.
 #[derive(Default, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash, Clone)]
-pub struct SCPCAOperation(pub Vec<CAOperation>);
+pub struct SCPCAOperation {
+    pub close_time: Timestamp,
+    pub operations: Vec<CAOperation>,
+}
+
+impl SCPCAOperation {
+    pub fn new(operations: Vec<CAOperation>, close_time: Timestamp) -> Self {
+        Self {
+            close_time,
+            operations,
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash, Clone)]
 pub enum CAOperation {
@@ -24,6 +44,19 @@ pub enum CAOperation {
     SetRoot(SetRootOperation),
 }
 
+impl CAOperation {
+    // The namespace whose root entry or tables the operation changes.
+    pub fn application_identifier(&self) -> Option<&str> {
+        match self {
+            CAOperation::Empty => None,
+            CAOperation::Set(set_operation) => Some(&set_operation.application_identifier),
+            CAOperation::SetRoot(set_root_operation) => {
+                Some(&set_root_operation.entry.application_identifier)
+            }
+        }
+    }
+}
+
 impl Default for CAOperation {
     fn default() -> Self {
         CAOperation::Empty
@@ -46,13 +79,33 @@ pub struct CellMerkleProof<'a> {
     pub idx: usize,
     pub sibling_hashes: InclusionProof<Sha256>,
     pub entry_cell: Cell,
-    pub tree_sig: SCPSignature,
+    // Signature over the root by the node serving the lookup.
+    pub tree_sig: Option<SCPSignature>,
     pub root: MerkleRoot,
 }
 
-pub struct GetOperation<'a> {
-    application_identifier: &'a str,
-    full_lookup_key: &'a str,
+pub struct GetOperation {
+    pub application_identifier: String,
+    pub full_lookup_key: String,
+}
+
+impl GetOperation {
+    pub fn new(application_identifier: String, full_lookup_key: String) -> Self {
+        Self {
+            application_identifier,
+            full_lookup_key,
+        }
+    }
+
+    // Looks up an application level name, e.g. a domain name, by the lookup
+    // key `codec` maps it to.
+    pub fn with_codec(
+        application_identifier: String,
+        name: &str,
+        codec: KeyCodec,
+    ) -> CodecResult<Self> {
+        Ok(Self::new(application_identifier, codec.encode(name)?))
+    }
 }
 
 pub enum GetReturnValue<'a> {
@@ -77,9 +130,35 @@ pub struct ReturnError<'a> {
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Hash, Clone)]
 pub struct SetOperation {
-    application_identifier: String,
-    full_lookup_key: String,
-    cell: Cell,
+    pub application_identifier: String,
+    pub full_lookup_key: String,
+    pub cell: Cell,
+}
+
+impl SetOperation {
+    pub fn new(application_identifier: String, cell: Cell) -> Self {
+        Self {
+            application_identifier,
+            full_lookup_key: cell.name_space_or_value().to_owned(),
+            cell,
+        }
+    }
+
+    // Name under which the cell is looked up, as given to `codec`.
+    pub fn decoded_lookup_key(&self, codec: KeyCodec) -> CodecResult<String> {
+        codec.decode(&self.full_lookup_key)
+    }
+
+    // Removes the cell at `full_lookup_key` by replacing it with `removed`, an
+    // empty cell owned and signed by the table authority (see
+    // `Cell::to_removed`).
+    pub fn remove(application_identifier: String, full_lookup_key: String, removed: Cell) -> Self {
+        Self {
+            application_identifier,
+            full_lookup_key,
+            cell: removed,
+        }
+    }
 }
 
 // https://datatracker.ietf.org/doc/html/draft-watson-dinrg-delmap-01#page-7 (p.9)
@@ -92,6 +171,34 @@ pub struct SetOperation {
 pub struct SetRootOperation {
     pub entry: RootEntry,
     pub remove: bool,
+    // Why the entry should be removed. Removals are a governance action and
+    // are only applied with a reason (see `LocalCAState::accepts_root_entries`).
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl SetRootOperation {
+    pub fn set(entry: RootEntry) -> Self {
+        Self {
+            entry,
+            remove: false,
+            reason: None,
+        }
+    }
+
+    pub fn removal(entry: RootEntry, reason: String) -> Self {
+        Self {
+            entry,
+            remove: true,
+            reason: Some(reason),
+        }
+    }
+
+    pub fn removal_reason(&self) -> Option<&str> {
+        self.reason
+            .as_deref()
+            .filter(|reason| self.remove && !reason.trim().is_empty())
+    }
 }
 
 pub enum SetReturnValue<'a> {
@@ -99,4 +206,55 @@ pub enum SetReturnValue<'a> {
     Error(&'a str),
 }
 
-impl<'a> CellMerkleProof<'a> {}
+impl<'a> CellMerkleProof<'a> {
+    pub fn verify(&self, context: &SigningContext) -> CAResult<()> {
+        self.check(context).map_err(|source| {
+            CAError::new(
+                ErrorContext::namespace(&context.application_identifier).with_lookup_key(self.key),
+                source,
+            )
+        })
+    }
+
+    fn check(&self, context: &SigningContext) -> CAStateOpResult<()> {
+        // Checks the cell is the one looked up, is signed by its owner and is
+        // included in the tree with root `self.root`. Whether `self.root` is the
+        // current root of the table has to be checked against a node's state.
+        if self.entry_cell.name_space_or_value() != self.key {
+            return Err(CAStateOpError::InvalidCell);
+        }
+
+        self.entry_cell
+            .is_valid(context)
+            .map_err(CAStateOpError::CellOpError)?;
+
+        let hash = self
+            .entry_cell
+            .to_merkle_hash()
+            .ok_or(CAStateOpError::InvalidProof)?;
+        self.root
+            .verify_inclusion(&hash, self.idx, &self.sibling_hashes)
+            .map_err(|_| CAStateOpError::InvalidProof)
+    }
+
+    fn root_signing_bytes(&self, context: &SigningContext) -> Vec<u8> {
+        context.payload(
+            SigningDomain::TableRoot,
+            self.key,
+            &self.root.as_bytes().as_slice(),
+        )
+    }
+
+    pub fn sign_root(&mut self, private_key: &PrivateKey, context: &SigningContext) {
+        self.tree_sig = Some(SCPSignature::sign(
+            private_key,
+            &self.root_signing_bytes(context),
+        ));
+    }
+
+    pub fn verify_root_sig(&self, public_key: &PublicKey, context: &SigningContext) -> bool {
+        self.tree_sig
+            .as_ref()
+            .is_some_and(|sig| sig.verify(public_key, &self.root_signing_bytes(context)))
+    }
+}
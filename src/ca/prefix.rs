@@ -0,0 +1,139 @@
+use std::{
+    collections::{btree_map, BTreeMap},
+    ops::Bound,
+};
+
+// Ordered index of the cells of a table, keyed by namespace or value.
+//
+// Tables only ever hold prefix free sets of keys (see
+// `Table::check_cell_valid`). In a prefix free set, the key that is a prefix
+// of a lookup key, if there is one, is the greatest key not above it, and a key
+// that extends a lookup key is the least key not below it: any key sorting in
+// between would have to extend the prefix, breaking the invariant. Both checks
+// are therefore a single ordered seek instead of a scan over every cell.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrefixMap<V>(BTreeMap<String, V>);
+
+impl<V> Default for PrefixMap<V> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<V> PrefixMap<V> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.0.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        self.0.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        self.0.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        self.0.remove(key)
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, String, V> {
+        self.0.iter()
+    }
+
+    pub fn values(&self) -> btree_map::Values<'_, String, V> {
+        self.0.values()
+    }
+
+    pub fn values_mut(&mut self) -> btree_map::ValuesMut<'_, String, V> {
+        self.0.values_mut()
+    }
+
+    pub fn prefix_of(&self, key: &str) -> Option<(&String, &V)> {
+        // Returns the entry whose key is a prefix of `key`, including `key`
+        // itself.
+        self.0
+            .range::<str, _>((Bound::Unbounded, Bound::Included(key)))
+            .next_back()
+            .filter(|(k, _)| key.starts_with(k.as_str()))
+    }
+
+    pub fn strict_prefix_of(&self, key: &str) -> Option<(&String, &V)> {
+        // Same as `prefix_of` but leaves out an entry stored under `key`.
+        self.0
+            .range::<str, _>((Bound::Unbounded, Bound::Excluded(key)))
+            .next_back()
+            .filter(|(k, _)| key.starts_with(k.as_str()))
+    }
+
+    pub fn extending(&self, key: &str) -> Option<(&String, &V)> {
+        // Returns an entry whose key has `key` as a prefix, including `key`
+        // itself.
+        self.0
+            .range::<str, _>((Bound::Included(key), Bound::Unbounded))
+            .next()
+            .filter(|(k, _)| k.starts_with(key))
+    }
+}
+
+impl<V> FromIterator<(String, V)> for PrefixMap<V> {
+    fn from_iter<T: IntoIterator<Item = (String, V)>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl<V> IntoIterator for PrefixMap<V> {
+    type Item = (String, V);
+    type IntoIter = btree_map::IntoIter<String, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_map(keys: &[&str]) -> PrefixMap<()> {
+        keys.iter().map(|key| (key.to_string(), ())).collect()
+    }
+
+    #[test]
+    fn prefix_queries() {
+        let map = make_map(&["com.example/", "com.example0", "org/", "org0/a"]);
+
+        assert_eq!(
+            map.prefix_of("com.example/www").map(|(k, _)| k.as_str()),
+            Some("com.example/")
+        );
+        assert_eq!(map.prefix_of("org/").map(|(k, _)| k.as_str()), Some("org/"));
+        assert!(map.strict_prefix_of("org/").is_none());
+        assert!(map.prefix_of("com.exampl").is_none());
+        assert!(map.prefix_of("net/").is_none());
+
+        assert_eq!(
+            map.extending("com.exampl").map(|(k, _)| k.as_str()),
+            Some("com.example/")
+        );
+        assert_eq!(
+            map.extending("org0").map(|(k, _)| k.as_str()),
+            Some("org0/a")
+        );
+        assert!(map.extending("org/a").is_none());
+        assert!(map.extending("p").is_none());
+    }
+}
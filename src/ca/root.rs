@@ -1,5 +1,9 @@
 use core::hash;
-use std::{collections::HashMap, fmt::Debug, hash::Hash};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{Debug, Display},
+    hash::Hash,
+};
 
 use digest::impl_oid_carrier;
 use dsa::Signature;
@@ -8,13 +12,45 @@ use serde::{Deserialize, Serialize};
 use super::{
     crypto::{PrivateKey, PublicKey, SCPSignature},
     merkle::MerkleTree,
+    signing::{NetworkId, SigningContext, SigningDomain},
 };
 
 pub type RootOpResult<T> = std::result::Result<T, RootOpError>;
+#[derive(PartialEq, Debug)]
 pub enum RootOpError {
-    Unknown,
+    InvalidSignature,
+    // The new entry is not signed by the current root key of the namespace.
+    NotSignedByRootKey,
+    // The allowance of the root entry cannot hold the cells already in the
+    // namespace.
+    AllowanceTooSmall,
+    // Root entries are only removed with a reason.
+    MissingRemovalReason,
+    // A replacement has to advance the revision of the current entry, so
+    // earlier entries cannot be replayed.
+    StaleRevision,
+}
+
+impl Display for RootOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RootOpError::InvalidSignature => write!(f, "invalid root entry signature"),
+            RootOpError::NotSignedByRootKey => {
+                write!(f, "root entry is not signed by the current root key")
+            }
+            RootOpError::AllowanceTooSmall => {
+                write!(f, "allowance cannot hold the cells of the namespace")
+            }
+            RootOpError::MissingRemovalReason => write!(f, "root entry removal has no reason"),
+            RootOpError::StaleRevision => {
+                write!(f, "root entry does not advance the current revision")
+            }
+        }
+    }
 }
 
+impl std::error::Error for RootOpError {}
+
 // Each linked group of delegation tables for a particular namespace is
 // rooted by a public key stored in a flat root key listing, which is
 // the entry point for lookup operations.  Well-known application
@@ -37,6 +73,9 @@ pub struct RootEntry {
     pub application_identifier: String,
     pub listing_sig: SCPSignature,
     pub allowance: u32,
+    // Increases with every replacement of the entry.
+    #[serde(default)]
+    pub revision: u64,
     // TODO: This should point to some Merkle tree?
 }
 
@@ -44,12 +83,15 @@ impl Hash for RootEntry {
     fn hash<H: hash::Hasher>(&self, state: &mut H) {
         self.application_identifier.hash(state);
         self.allowance.hash(state);
+        self.revision.hash(state);
     }
 }
 
 impl PartialEq for RootEntry {
     fn eq(&self, other: &Self) -> bool {
-        self.namespace_root_key == other.namespace_root_key && self.allowance == other.allowance
+        self.namespace_root_key == other.namespace_root_key
+            && self.allowance == other.allowance
+            && self.revision == other.revision
     }
 }
 
@@ -66,6 +108,7 @@ impl Ord for RootEntry {
         self.application_identifier
             .cmp(&other.application_identifier)
             .then(self.allowance.cmp(&other.allowance))
+            .then(self.revision.cmp(&other.revision))
     }
 }
 
@@ -74,27 +117,347 @@ impl Debug for RootEntry {
         f.debug_struct("RootEntry")
             .field("application_identifier", &self.application_identifier)
             .field("allowance", &self.allowance)
+            .field("revision", &self.revision)
             .finish()
     }
 }
 
+// The canonical contents of a root entry signed by the namespace root key.
+#[derive(Serialize)]
+struct RootEntryContents<'a> {
+    namespace_root_key: &'a PublicKey,
+    allowance: u32,
+    revision: u64,
+}
+
 impl RootEntry {
-    pub fn new(private_key: &PrivateKey, application_identifier: String) -> Self {
-        let listing_sig = SCPSignature::sign(&private_key, &application_identifier.as_bytes());
-        let namespace_root_key = PublicKey(private_key.0.verifying_key().to_owned());
+    pub fn new(
+        private_key: &PrivateKey,
+        network_id: &NetworkId,
+        application_identifier: String,
+    ) -> Self {
+        Self::new_with_allowance(private_key, network_id, application_identifier, 0)
+    }
+
+    pub fn new_with_allowance(
+        private_key: &PrivateKey,
+        network_id: &NetworkId,
+        application_identifier: String,
+        allowance: u32,
+    ) -> Self {
+        Self::new_with_revision(
+            private_key,
+            network_id,
+            application_identifier,
+            allowance,
+            0,
+        )
+    }
+
+    pub fn new_with_revision(
+        private_key: &PrivateKey,
+        network_id: &NetworkId,
+        application_identifier: String,
+        allowance: u32,
+        revision: u64,
+    ) -> Self {
+        let namespace_root_key = private_key.public_key();
+        let listing_sig = SCPSignature::sign(
+            private_key,
+            &Self::contents_bytes(
+                network_id,
+                &namespace_root_key,
+                &application_identifier,
+                allowance,
+                revision,
+            ),
+        );
 
         Self {
             namespace_root_key,
             application_identifier,
             listing_sig,
-            allowance: 0,
+            allowance,
+            revision,
+        }
+    }
+
+    fn contents_bytes(
+        network_id: &NetworkId,
+        namespace_root_key: &PublicKey,
+        application_identifier: &str,
+        allowance: u32,
+        revision: u64,
+    ) -> Vec<u8> {
+        let contents = RootEntryContents {
+            namespace_root_key,
+            allowance,
+            revision,
+        };
+
+        SigningContext::new(*network_id, application_identifier).payload(
+            SigningDomain::RootEntry,
+            "",
+            &contents,
+        )
+    }
+
+    pub fn signing_bytes(&self, network_id: &NetworkId) -> Vec<u8> {
+        Self::contents_bytes(
+            network_id,
+            &self.namespace_root_key,
+            &self.application_identifier,
+            self.allowance,
+            self.revision,
+        )
+    }
+
+    pub fn sign(&mut self, private_key: &PrivateKey, network_id: &NetworkId) {
+        self.listing_sig = SCPSignature::sign(private_key, &self.signing_bytes(network_id));
+    }
+
+    pub fn is_signed_by(&self, public_key: &PublicKey, network_id: &NetworkId) -> bool {
+        self.listing_sig
+            .verify(public_key, &self.signing_bytes(network_id))
+    }
+
+    pub fn verify(&self, network_id: &NetworkId) -> bool {
+        self.is_signed_by(&self.namespace_root_key, network_id)
+    }
+
+    pub fn validate_replacement(
+        &self,
+        entry: &RootEntry,
+        network_id: &NetworkId,
+    ) -> RootOpResult<()> {
+        // A root entry can only be replaced (e.g. to increase its allowance or
+        // rotate the root key) by a later entry signed with the current root key.
+        if !entry.is_signed_by(&self.namespace_root_key, network_id) {
+            Err(RootOpError::NotSignedByRootKey)
+        } else if entry.revision <= self.revision {
+            Err(RootOpError::StaleRevision)
+        } else {
+            Ok(())
         }
     }
 
-    pub fn verify(&self) -> bool {
-        todo!()
+    pub fn is_allowance_increase(&self, entry: &RootEntry) -> bool {
+        // An allowance of 0 means the namespace is not limited.
+        self.allowance != 0 && (entry.allowance == 0 || entry.allowance > self.allowance)
     }
 }
 
 #[derive(Default, Clone, Debug)]
 pub struct RootListing(pub HashMap<String, RootEntry>);
+
+impl RootListing {
+    pub fn validate_set_root(&self, entry: &RootEntry, network_id: &NetworkId) -> RootOpResult<()> {
+        match self.0.get(&entry.application_identifier) {
+            Some(current) => current.validate_replacement(entry, network_id),
+            None if entry.verify(network_id) => Ok(()),
+            None => Err(RootOpError::InvalidSignature),
+        }
+    }
+}
+
+// Decides whether the local node votes for a root entry asking for a larger
+// allowance. Nodes can base the decision on global knowledge of table sizes and
+// growth rates, or on information outside the protocol.
+pub trait RootEntryPolicy: Debug {
+    fn accept_allowance_increase(&self, current: &RootEntry, proposed: &RootEntry) -> bool;
+}
+
+// Accepts every allowance increase.
+#[derive(Debug, Default)]
+pub struct AcceptAllowancePolicy;
+
+impl RootEntryPolicy for AcceptAllowancePolicy {
+    fn accept_allowance_increase(&self, _current: &RootEntry, _proposed: &RootEntry) -> bool {
+        true
+    }
+}
+
+// Accepts allowance increases up to a fixed limit.
+#[derive(Debug)]
+pub struct MaxAllowancePolicy {
+    pub max_allowance: u32,
+}
+
+impl RootEntryPolicy for MaxAllowancePolicy {
+    fn accept_allowance_increase(&self, _current: &RootEntry, proposed: &RootEntry) -> bool {
+        proposed.allowance != 0 && proposed.allowance <= self.max_allowance
+    }
+}
+
+// Decides whether the local node votes to remove a root entry, e.g. because
+// the application misbehaves. A removal is only externalized if enough nodes
+// flag the entry to form a quorum, so no single party can remove a namespace.
+pub trait RootRemovalPolicy: Debug {
+    fn flag_for_removal(&self, entry: &RootEntry, reason: &str) -> bool;
+}
+
+// Votes against every removal.
+#[derive(Debug, Default)]
+pub struct RejectRemovalPolicy;
+
+impl RootRemovalPolicy for RejectRemovalPolicy {
+    fn flag_for_removal(&self, _entry: &RootEntry, _reason: &str) -> bool {
+        false
+    }
+}
+
+// Votes for removing the namespaces the operator of the node flagged.
+#[derive(Debug, Default)]
+pub struct FlaggedEntriesPolicy {
+    pub flagged: BTreeSet<String>,
+}
+
+impl FlaggedEntriesPolicy {
+    pub fn new<I: IntoIterator<Item = String>>(flagged: I) -> Self {
+        Self {
+            flagged: flagged.into_iter().collect(),
+        }
+    }
+}
+
+impl RootRemovalPolicy for FlaggedEntriesPolicy {
+    fn flag_for_removal(&self, entry: &RootEntry, _reason: &str) -> bool {
+        self.flagged.contains(&entry.application_identifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ca::crypto::{mock_generate_private_key, mock_private_key};
+
+    use super::*;
+
+    #[test]
+    fn verify_listing_signature() {
+        let entry = RootEntry::new(
+            &mock_private_key(),
+            &NetworkId::default(),
+            "namespace".to_string(),
+        );
+        assert!(entry.verify(&NetworkId::default()));
+
+        let mut tampered = entry.clone();
+        tampered.allowance = 10;
+        assert!(!tampered.verify(&NetworkId::default()));
+
+        let mut tampered = entry.clone();
+        tampered.revision = 1;
+        assert!(!tampered.verify(&NetworkId::default()));
+
+        let mut tampered = entry.clone();
+        tampered.namespace_root_key = mock_generate_private_key().public_key();
+        assert!(!tampered.verify(&NetworkId::default()));
+
+        // Neither a listing for another namespace nor on another network.
+        let mut replayed = entry.clone();
+        replayed.application_identifier = "other".to_string();
+        assert!(!replayed.verify(&NetworkId::default()));
+        assert!(!entry.verify(&NetworkId::from_passphrase("other")));
+    }
+
+    #[test]
+    fn only_root_key_replaces_entry() {
+        let root_key = mock_private_key();
+        let other_key = mock_generate_private_key();
+
+        let mut listing = RootListing::default();
+        let entry = RootEntry::new_with_allowance(
+            &root_key,
+            &NetworkId::default(),
+            "namespace".to_string(),
+            10,
+        );
+        assert!(listing
+            .validate_set_root(&entry, &NetworkId::default())
+            .is_ok());
+        listing.0.insert("namespace".to_string(), entry);
+
+        let hijack = RootEntry::new_with_allowance(
+            &other_key,
+            &NetworkId::default(),
+            "namespace".to_string(),
+            10,
+        );
+        assert_eq!(
+            listing.validate_set_root(&hijack, &NetworkId::default()),
+            Err(RootOpError::NotSignedByRootKey)
+        );
+
+        let increase = RootEntry::new_with_revision(
+            &root_key,
+            &NetworkId::default(),
+            "namespace".to_string(),
+            20,
+            1,
+        );
+        assert!(listing
+            .validate_set_root(&increase, &NetworkId::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn replacements_advance_the_revision() {
+        let root_key = mock_private_key();
+        let mut listing = RootListing::default();
+        let entry = |allowance, revision| {
+            RootEntry::new_with_revision(
+                &root_key,
+                &NetworkId::default(),
+                "namespace".to_string(),
+                allowance,
+                revision,
+            )
+        };
+        listing.0.insert("namespace".to_string(), entry(10, 0));
+        assert!(listing
+            .validate_set_root(&entry(20, 1), &NetworkId::default())
+            .is_ok());
+        listing.0.insert("namespace".to_string(), entry(20, 1));
+
+        // The entry with the smaller allowance was signed by the root key too.
+        for replayed in [entry(10, 0), entry(20, 1)] {
+            assert_eq!(
+                listing.validate_set_root(&replayed, &NetworkId::default()),
+                Err(RootOpError::StaleRevision)
+            );
+        }
+    }
+
+    #[test]
+    fn max_allowance_policy() {
+        let root_key = mock_private_key();
+        let policy = MaxAllowancePolicy { max_allowance: 20 };
+        let current = RootEntry::new_with_allowance(
+            &root_key,
+            &NetworkId::default(),
+            "namespace".to_string(),
+            10,
+        );
+
+        let increase = RootEntry::new_with_allowance(
+            &root_key,
+            &NetworkId::default(),
+            "namespace".to_string(),
+            20,
+        );
+        assert!(current.is_allowance_increase(&increase));
+        assert!(policy.accept_allowance_increase(&current, &increase));
+
+        let increase = RootEntry::new_with_allowance(
+            &root_key,
+            &NetworkId::default(),
+            "namespace".to_string(),
+            21,
+        );
+        assert!(!policy.accept_allowance_increase(&current, &increase));
+
+        let unlimited = RootEntry::new(&root_key, &NetworkId::default(), "namespace".to_string());
+        assert!(current.is_allowance_increase(&unlimited));
+        assert!(!policy.accept_allowance_increase(&current, &unlimited));
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Every signature in the CA layer is made over a payload naming the kind of
+// object signed, the network and namespace it belongs to and its lookup key.
+// A signature made for one of them therefore does not verify for any other.
+
+const SIGNING_PREFIX: &str = "general_scp/ca/v1";
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub struct NetworkId(pub [u8; 32]);
+
+impl NetworkId {
+    pub const DEFAULT_PASSPHRASE: &'static str = "general_scp CA network";
+
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        NetworkId(Sha256::digest(passphrase.as_bytes()).into())
+    }
+}
+
+impl Default for NetworkId {
+    fn default() -> Self {
+        Self::from_passphrase(Self::DEFAULT_PASSPHRASE)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub enum SigningDomain {
+    RootEntry,
+    ValueCell,
+    DelegateCell,
+    // Removals, which replace the cell at a lookup key with an empty cell.
+    RemovedCell,
+    // Heads of the transparency log of externalized values.
+    TreeHead,
+    // Roots of the tables answering lookups, signed by the node serving them.
+    TableRoot,
+}
+
+#[derive(Serialize)]
+struct SigningPayload<'a, T: Serialize> {
+    prefix: &'static str,
+    domain: SigningDomain,
+    network_id: &'a NetworkId,
+    application_identifier: &'a str,
+    lookup_key: &'a str,
+    contents: &'a T,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SigningContext {
+    pub network_id: NetworkId,
+    pub application_identifier: String,
+}
+
+impl SigningContext {
+    pub fn new(network_id: NetworkId, application_identifier: &str) -> Self {
+        Self {
+            network_id,
+            application_identifier: application_identifier.to_owned(),
+        }
+    }
+
+    pub fn payload<T: Serialize>(
+        &self,
+        domain: SigningDomain,
+        lookup_key: &str,
+        contents: &T,
+    ) -> Vec<u8> {
+        let payload = SigningPayload {
+            prefix: SIGNING_PREFIX,
+            domain,
+            network_id: &self.network_id,
+            application_identifier: &self.application_identifier,
+            lookup_key,
+            contents,
+        };
+
+        bincode::serialize(&payload).expect("Failed to serialize signing payload")
+    }
+}
+
+pub fn mock_signing_context() -> SigningContext {
+    SigningContext::new(NetworkId::default(), "namespace")
+}
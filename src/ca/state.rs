@@ -1,22 +1,37 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
 
 use serde::Serialize;
 use tracing::Span;
 
-use crate::scp::{self, nomination_protocol::NominationValue};
+use crate::scp::{self, nomination_protocol::NominationValue, slot::SlotIndex};
 
 use super::{
-    cell::Cell,
+    ca_type::Timestamp,
+    cell::{Cell, CellData, CellOpError},
     crypto::PublicKey,
-    operation::{CAOperation, CellMerkleProof, SCPCAOperation, SetOperation},
-    root::{RootEntry, RootEntryKey, RootListing},
-    table::{find_delegation_cell, find_value_cell, TableCollection, TableId, TableOpError},
+    error::{CAError, CAResult, ErrorContext},
+    operation::{CAOperation, CellMerkleProof, GetOperation, SCPCAOperation, SetOperation},
+    root::{RootEntry, RootEntryKey, RootListing, RootOpError},
+    signing::{NetworkId, SigningContext},
+    store::CAStateSnapshot,
+    table::{
+        find_delegation_cell, find_value_cell, resolve_table, Table, TableCollection, TableId,
+        TableOpError,
+    },
 };
 
 #[derive(Clone, Debug)]
 pub struct CAState {
     pub root_listing: RootListing,
     pub tables: HashMap<RootEntryKey, TableCollection>,
+    // Close time of the last externalized value. Commitment timestamps are
+    // checked against this instead of the local clock.
+    pub close_time: Timestamp,
+    // Signatures are only valid on the network they were made for.
+    pub network_id: NetworkId,
 }
 
 #[derive(Hash, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Debug)]
@@ -32,17 +47,85 @@ pub enum CAStateOpError {
     InvalidCell,
     RootTableNotFound,
     TableOpError(TableOpError),
+    CellOpError(CellOpError),
+    RootOpError(RootOpError),
     NoExist,
     AlreadyExists,
 }
 
+impl Display for CAStateOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CAStateOpError::MerkleTreeNotPresent => write!(f, "Merkle tree not present"),
+            CAStateOpError::MerkleTreeChanged => write!(f, "Merkle tree changed"),
+            CAStateOpError::MerkleProofInvalid => write!(f, "invalid Merkle proof"),
+            CAStateOpError::InvalidProof => write!(f, "invalid proof"),
+            CAStateOpError::InvalidCell => write!(f, "cell does not match the lookup key"),
+            CAStateOpError::RootTableNotFound => write!(f, "no table for the lookup key"),
+            CAStateOpError::TableOpError(err) => write!(f, "{}", err),
+            CAStateOpError::CellOpError(err) => write!(f, "{}", err),
+            CAStateOpError::RootOpError(err) => write!(f, "{}", err),
+            CAStateOpError::NoExist => write!(f, "does not exist"),
+            CAStateOpError::AlreadyExists => write!(f, "already exists"),
+        }
+    }
+}
+
+// Wrappers print the wrapped error, so they pass on its source instead of
+// returning it.
+impl std::error::Error for CAStateOpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CAStateOpError::TableOpError(err) => std::error::Error::source(err),
+            CAStateOpError::CellOpError(err) => std::error::Error::source(err),
+            CAStateOpError::RootOpError(err) => std::error::Error::source(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<TableOpError> for CAStateOpError {
+    fn from(err: TableOpError) -> Self {
+        CAStateOpError::TableOpError(err)
+    }
+}
+
+impl From<CellOpError> for CAStateOpError {
+    fn from(err: CellOpError) -> Self {
+        CAStateOpError::CellOpError(err)
+    }
+}
+
+impl From<RootOpError> for CAStateOpError {
+    fn from(err: RootOpError) -> Self {
+        CAStateOpError::RootOpError(err)
+    }
+}
+
 impl NominationValue for CANominationValue {}
 
+// The outcome of applying an externalized value. The operations of a value are
+// applied as a whole or not at all, so that a value is never partially applied.
+#[derive(PartialEq, Debug)]
+pub enum BatchResult {
+    Committed,
+    // The operations that failed, by their index in the value.
+    Rejected(Vec<(usize, CAError)>),
+}
+
+impl BatchResult {
+    pub fn is_committed(&self) -> bool {
+        *self == BatchResult::Committed
+    }
+}
+
 impl Default for CAState {
     fn default() -> Self {
         Self {
             root_listing: Default::default(),
             tables: Default::default(),
+            close_time: Default::default(),
+            network_id: Default::default(),
         }
     }
 }
@@ -70,7 +153,21 @@ impl CAState {
     //     }
     // }
 
-    pub fn validate_merkle_proof_for_root<'a>(
+    pub fn validate_merkle_proof_for_root(
+        &mut self,
+        root_key: &RootEntryKey,
+        merkle_proof: &CellMerkleProof,
+    ) -> CAResult<()> {
+        self.check_merkle_proof_for_root(root_key, merkle_proof)
+            .map_err(|source| {
+                CAError::new(
+                    ErrorContext::namespace(&root_key.0).with_lookup_key(merkle_proof.key),
+                    source,
+                )
+            })
+    }
+
+    fn check_merkle_proof_for_root(
         &mut self,
         root_key: &RootEntryKey,
         merkle_proof: &CellMerkleProof,
@@ -108,28 +205,124 @@ impl CAState {
         }
     }
 
-    pub fn validate_set_operation(&self, set_opt: &SetOperation) -> bool {
-        todo!()
+    pub fn signing_context(&self, application_identifier: &str) -> SigningContext {
+        SigningContext::new(self.network_id, application_identifier)
     }
 
-    pub fn insert_cell(
+    pub fn validate_set_operation(&self, set_opt: &SetOperation, now: Timestamp) -> bool {
+        let mut staged = self.clone();
+        staged.apply_set_operation(set_opt, now).is_ok()
+    }
+
+    fn apply_set_operation(
         &mut self,
-        root_entry_key: &RootEntryKey,
-        cell: Cell,
+        set_opt: &SetOperation,
+        now: Timestamp,
     ) -> CAStateOpResult<()> {
-        let root_tables = self
+        // Creates, updates or removes the cell at the full lookup key. New cells
+        // are authorised by the authority of the table they are placed in,
+        // updates follow the rules in `Cell::validate_update` and removals the
+        // rules in `Cell::validate_removal`.
+        if !set_opt.cell.is_empty()
+            && set_opt.cell.name_space_or_value() != &set_opt.full_lookup_key
+        {
+            return Err(CAStateOpError::InvalidCell);
+        }
+
+        let context = self.signing_context(&set_opt.application_identifier);
+        let root_entry_key = RootEntryKey(set_opt.application_identifier.to_owned());
+        let root_key = self
+            .root_listing
+            .0
+            .get(&set_opt.application_identifier)
+            .ok_or(CAStateOpError::RootTableNotFound)?
+            .namespace_root_key
+            .to_owned();
+        let tables = self
             .tables
-            .get_mut(root_entry_key)
+            .get_mut(&root_entry_key)
             .ok_or(CAStateOpError::RootTableNotFound)?;
-        let root_table = root_tables
+
+        let root_table_id = TableId::root();
+        let (table_id, authority_key) =
+            resolve_table(tables, &root_table_id, &root_key, &set_opt.full_lookup_key)
+                .ok_or(CAStateOpError::RootTableNotFound)?;
+        let (table_id, authority_key) = (table_id.to_owned(), authority_key.to_owned());
+
+        let table = tables
             .0
-            .get_mut(&TableId::root())
+            .get_mut(&table_id)
             .ok_or(CAStateOpError::RootTableNotFound)?;
 
-        match root_table.add_entry(cell) {
-            Ok(_) => Ok(()),
-            Err(err) => Err(CAStateOpError::TableOpError(err)),
+        if set_opt.cell.is_empty() {
+            let removed = table
+                .remove_entry(
+                    &set_opt.full_lookup_key,
+                    set_opt.cell.to_owned(),
+                    &authority_key,
+                    now,
+                    &context,
+                )
+                .map_err(CAStateOpError::TableOpError)?;
+
+            // The delegated namespace goes away with the delegation.
+            if let CellData::Delegate(inner_delegate_cell) = &removed.inner {
+                if let Some(delegated_table_id) = &inner_delegate_cell.table {
+                    tables.remove_table(delegated_table_id);
+                }
+            }
+
+            return Ok(());
+        }
+
+        if table.get_entry(&set_opt.full_lookup_key).is_some() {
+            return table
+                .update_entry(set_opt.cell.to_owned(), &authority_key, now, &context)
+                .map_err(CAStateOpError::TableOpError);
         }
+
+        set_opt
+            .cell
+            .is_signed_by(&authority_key, &context)
+            .map_err(CAStateOpError::CellOpError)?;
+        table
+            .contains_enough_allowance(set_opt.cell.allowance())
+            .map_err(CAStateOpError::TableOpError)?;
+        table
+            .add_entry(set_opt.cell.to_owned())
+            .map_err(CAStateOpError::TableOpError)?;
+
+        // Create the table holding the delegated namespace.
+        if let CellData::Delegate(inner_delegate_cell) = &set_opt.cell.inner {
+            if let Some(delegated_table_id) = &inner_delegate_cell.table {
+                if tables.0.contains_key(delegated_table_id) {
+                    return Err(CAStateOpError::AlreadyExists);
+                }
+
+                tables.0.insert(
+                    delegated_table_id.to_owned(),
+                    Table::new(
+                        inner_delegate_cell.allowance,
+                        inner_delegate_cell.name_space.to_owned(),
+                    ),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn insert_cell(&mut self, root_entry_key: &RootEntryKey, cell: Cell) -> CAResult<()> {
+        let context = ErrorContext::namespace(&root_entry_key.0);
+        let root_table = self
+            .tables
+            .get_mut(root_entry_key)
+            .and_then(|root_tables| root_tables.0.get_mut(&TableId::root()))
+            .ok_or_else(|| CAError::new(context.clone(), CAStateOpError::RootTableNotFound))?;
+
+        root_table
+            .add_entry(cell)
+            .map_err(|err| CAError::new(context, err))
     }
 
     pub fn find_delegation_cell(
@@ -150,40 +343,290 @@ impl CAState {
         find_value_cell(root_table, &TableId::root(), cell_key)
     }
 
+    pub fn get_table(&self, application_identifier: &str, table_id: &TableId) -> Option<&Table> {
+        self.tables
+            .get(&RootEntryKey(application_identifier.to_owned()))?
+            .0
+            .get(table_id)
+    }
+
+    pub fn lookup<'a>(
+        &self,
+        application_identifier: &str,
+        key: &'a str,
+    ) -> CAResult<CellMerkleProof<'a>> {
+        self.prove_inclusion(application_identifier, key)
+            .map_err(|source| {
+                CAError::new(self.lookup_context(application_identifier, key), source)
+            })
+    }
+
+    fn prove_inclusion<'a>(
+        &self,
+        application_identifier: &str,
+        key: &'a str,
+    ) -> CAStateOpResult<CellMerkleProof<'a>> {
+        // Finds the cell for `key` following delegations and proves its
+        // inclusion in the merkle tree of the table holding it.
+        let root_key = &self
+            .root_listing
+            .0
+            .get(application_identifier)
+            .ok_or(CAStateOpError::RootTableNotFound)?
+            .namespace_root_key;
+        let tables = self
+            .tables
+            .get(&RootEntryKey(application_identifier.to_owned()))
+            .ok_or(CAStateOpError::RootTableNotFound)?;
+
+        let root_table_id = TableId::root();
+        let (table_id, _) = resolve_table(tables, &root_table_id, root_key, key)
+            .ok_or(CAStateOpError::RootTableNotFound)?;
+        let table = tables
+            .0
+            .get(table_id)
+            .ok_or(CAStateOpError::RootTableNotFound)?;
+
+        let entry_cell = table.get_entry(key).ok_or(CAStateOpError::NoExist)?;
+        let idx = *table
+            .merkle_leaf_index
+            .get(key)
+            .ok_or(CAStateOpError::NoExist)?;
+        let sibling_hashes = table
+            .merkle_tree
+            .gen_inclusion_proof(idx)
+            .map_err(|err| CAStateOpError::TableOpError(TableOpError::MerkleOpError(err)))?;
+
+        Ok(CellMerkleProof {
+            key,
+            idx,
+            sibling_hashes,
+            entry_cell: entry_cell.to_owned(),
+            tree_sig: None,
+            root: table.merkle_tree.root(),
+        })
+    }
+
+    pub fn get<'a>(&self, get_opt: &'a GetOperation) -> CAResult<CellMerkleProof<'a>> {
+        self.lookup(&get_opt.application_identifier, &get_opt.full_lookup_key)
+    }
+
     pub fn contains_root_entry(&self, application_identifier: &String) -> bool {
         self.root_listing.0.get(application_identifier).is_some()
     }
 
-    pub fn on_scp_operation(&mut self, scp_operation: &SCPCAOperation) {
-        // TODO: consider side effects
-        for operation in &scp_operation.0 {
-            match self.on_ca_operation(operation) {
-                Ok(_) => {}
-                Err(_) => todo!(),
+    pub fn on_scp_operation(&mut self, scp_operation: &SCPCAOperation) -> BatchResult {
+        // The close time never goes backwards, even if an earlier close time was
+        // externalized.
+        self.close_time = self.close_time.max(scp_operation.close_time);
+
+        // Every operation is applied to a staged copy of the namespaces the
+        // value touches. Later operations see the effects of earlier ones, and
+        // all of them are checked so that the errors of a rejected value are
+        // complete. The result only depends on the state and the value, so
+        // every node reaches the same one.
+        let namespaces: BTreeSet<&str> = scp_operation
+            .operations
+            .iter()
+            .filter_map(CAOperation::application_identifier)
+            .collect();
+        let mut staged = self.stage(&namespaces);
+
+        let errors: Vec<(usize, CAError)> = scp_operation
+            .operations
+            .iter()
+            .enumerate()
+            .filter_map(|(index, operation)| {
+                staged
+                    .apply_operation(operation, self.close_time)
+                    .err()
+                    .map(|err| (index, err))
+            })
+            .collect();
+
+        let result = if errors.is_empty() {
+            self.commit(staged, &namespaces);
+            BatchResult::Committed
+        } else {
+            BatchResult::Rejected(errors)
+        };
+
+        self.collect_garbage(self.close_time);
+        result
+    }
+
+    // The operations that apply one after the other on the state, in order and
+    // without repeats. Combining candidates with it keeps one bad or repeated
+    // operation from rejecting the whole value, and every node keeps the same
+    // operations.
+    pub fn applicable_operations<'a>(
+        &self,
+        operations: impl IntoIterator<Item = &'a CAOperation>,
+        close_time: Timestamp,
+    ) -> Vec<CAOperation> {
+        let close_time = self.close_time.max(close_time);
+        let mut seen = BTreeSet::new();
+        let operations: Vec<&CAOperation> = operations
+            .into_iter()
+            .filter(|operation| seen.insert(*operation))
+            .collect();
+
+        let namespaces: BTreeSet<&str> = operations
+            .iter()
+            .filter_map(|operation| operation.application_identifier())
+            .collect();
+        let mut staged = self.stage(&namespaces);
+
+        let mut applicable = vec![];
+        for operation in operations {
+            let namespace: BTreeSet<&str> =
+                operation.application_identifier().into_iter().collect();
+            let mut trial = staged.stage(&namespace);
+            if trial.on_ca_operation(operation, close_time).is_ok() {
+                staged.commit(trial, &namespace);
+                applicable.push(operation.to_owned());
             }
         }
+        applicable
+    }
+
+    pub fn apply_operation(
+        &mut self,
+        ca_operation: &CAOperation,
+        close_time: Timestamp,
+    ) -> CAResult<()> {
+        // Same as `on_ca_operation`, but errors say where they happened.
+        self.on_ca_operation(ca_operation, close_time)
+            .map_err(|source| CAError::new(self.error_context(ca_operation), source))
     }
 
-    pub fn on_ca_operation(&mut self, ca_operation: &CAOperation) -> CAStateOpResult<()> {
+    fn error_context(&self, ca_operation: &CAOperation) -> ErrorContext {
         match ca_operation {
-            CAOperation::Empty => {
-                panic!("Empty")
-            }
-            CAOperation::Set(set_operation) => {
-                todo!()
+            CAOperation::Set(set_operation) => self.lookup_context(
+                &set_operation.application_identifier,
+                &set_operation.full_lookup_key,
+            ),
+            _ => ca_operation
+                .application_identifier()
+                .map(ErrorContext::namespace)
+                .unwrap_or_default(),
+        }
+    }
+
+    fn lookup_context(&self, application_identifier: &str, key: &str) -> ErrorContext {
+        ErrorContext {
+            table_id: self.table_id_for_key(application_identifier, key),
+            ..ErrorContext::namespace(application_identifier).with_lookup_key(key)
+        }
+    }
+
+    fn table_id_for_key(&self, application_identifier: &str, key: &str) -> Option<TableId> {
+        let root_key = &self
+            .root_listing
+            .0
+            .get(application_identifier)?
+            .namespace_root_key;
+        let tables = self
+            .tables
+            .get(&RootEntryKey(application_identifier.to_owned()))?;
+        let root_table_id = TableId::root();
+        resolve_table(tables, &root_table_id, root_key, key)
+            .map(|(table_id, _)| table_id.to_owned())
+    }
+
+    fn stage(&self, namespaces: &BTreeSet<&str>) -> CAState {
+        // Copies the root listing and the tables of `namespaces` only, which
+        // are all an operation on those namespaces reads.
+        let tables = namespaces
+            .iter()
+            .filter_map(|namespace| {
+                let key = RootEntryKey(namespace.to_string());
+                let tables = self.tables.get(&key)?.to_owned();
+                Some((key, tables))
+            })
+            .collect();
+
+        CAState {
+            root_listing: self.root_listing.to_owned(),
+            tables,
+            close_time: self.close_time,
+            network_id: self.network_id,
+        }
+    }
+
+    fn commit(&mut self, mut staged: CAState, namespaces: &BTreeSet<&str>) {
+        self.root_listing = staged.root_listing;
+        for namespace in namespaces {
+            let key = RootEntryKey(namespace.to_string());
+            match staged.tables.remove(&key) {
+                Some(tables) => {
+                    self.tables.insert(key, tables);
+                }
+                // The namespace was removed.
+                None => {
+                    self.tables.remove(&key);
+                }
             }
+        }
+    }
+
+    pub fn collect_garbage(&mut self, now: Timestamp) -> usize {
+        // Drops removed cells whose commitment has expired and frees their
+        // allowance in the tables.
+        self.tables
+            .values_mut()
+            .map(|tables| tables.collect_garbage(now))
+            .sum()
+    }
+
+    fn on_ca_operation(
+        &mut self,
+        ca_operation: &CAOperation,
+        close_time: Timestamp,
+    ) -> CAStateOpResult<()> {
+        match ca_operation {
+            // Nominated values may be padded with empty operations.
+            CAOperation::Empty => Ok(()),
+            CAOperation::Set(set_operation) => self.apply_set_operation(set_operation, close_time),
             CAOperation::SetRoot(set_root_operation) => {
                 if set_root_operation.remove {
-                    if self.contains_root_entry(&set_root_operation.entry.application_identifier) {
+                    if set_root_operation.removal_reason().is_none() {
+                        Err(CAStateOpError::RootOpError(
+                            RootOpError::MissingRemovalReason,
+                        ))
+                    } else if self
+                        .contains_root_entry(&set_root_operation.entry.application_identifier)
+                    {
                         self.root_listing
                             .0
                             .remove(&set_root_operation.entry.application_identifier);
+                        self.tables.remove(&RootEntryKey(
+                            set_root_operation.entry.application_identifier.to_owned(),
+                        ));
                         Ok(())
                     } else {
                         Err(CAStateOpError::NoExist)
                     }
                 } else {
                     let entry = set_root_operation.entry.to_owned();
+                    self.root_listing
+                        .validate_set_root(&entry, &self.network_id)
+                        .map_err(CAStateOpError::RootOpError)?;
+
+                    let tables = self
+                        .tables
+                        .entry(RootEntryKey(entry.application_identifier.to_owned()))
+                        .or_insert_with(|| TableCollection::new(entry.allowance));
+                    let root_table = tables
+                        .0
+                        .get_mut(&TableId::root())
+                        .ok_or(CAStateOpError::RootTableNotFound)?;
+
+                    if entry.allowance != 0 && entry.allowance < root_table.used_allowance() {
+                        return Err(CAStateOpError::RootOpError(RootOpError::AllowanceTooSmall));
+                    }
+                    root_table.allowance = entry.allowance;
+
                     self.root_listing
                         .0
                         .insert(entry.application_identifier.to_owned(), entry);
@@ -193,12 +636,13 @@ impl CAState {
         }
     }
 
-    pub fn to_toml(&self) {
-        todo!()
+    pub fn to_toml(&self, slot_index: SlotIndex) -> Option<String> {
+        toml::to_string(&CAStateSnapshot::new(self, slot_index)).ok()
     }
 
-    pub fn from_toml(&self) {
-        todo!()
+    pub fn from_toml(toml_str: &str) -> Option<(Self, SlotIndex)> {
+        let snapshot: CAStateSnapshot = toml::from_str(toml_str).ok()?;
+        Some(snapshot.into_state())
     }
 }
 
@@ -220,10 +664,282 @@ impl CAState {
 
 #[cfg(test)]
 mod tests {
-    use crate::ca::state::CAState;
+    use crate::ca::{
+        cell::{InnerDelegateCell, InnerValueCell},
+        crypto::{mock_generate_private_key, mock_sig, PrivateKey, SignatureAlgorithm},
+        operation::{SCPCAOperation, SetRootOperation},
+        signing::mock_signing_context,
+        state::CAState,
+    };
+
+    use super::*;
+
+    fn test_make_cell(inner: CellData, owner_key: PublicKey, signer: &PrivateKey) -> Cell {
+        let mut cell = Cell {
+            create_time: 0,
+            revision_time: 0,
+            commitment_time: 0,
+            sig: mock_sig(),
+            owner_key,
+            inner,
+        };
+        cell.sign(signer, &mock_signing_context());
+        cell
+    }
+
+    fn test_make_value(value: &str) -> CellData {
+        CellData::Value(InnerValueCell {
+            value: value.to_string(),
+        })
+    }
+
+    fn test_make_state_with_namespace(root_key: &PrivateKey) -> CAState {
+        let mut ca_state = CAState::default();
+        let operation = CAOperation::SetRoot(SetRootOperation::set(RootEntry::new(
+            root_key,
+            &NetworkId::default(),
+            "namespace".to_string(),
+        )));
+        assert!(ca_state.on_ca_operation(&operation, 0).is_ok());
+        ca_state
+    }
 
     #[test]
     fn test_ca_state() {
         let mut ca_state = CAState::default();
     }
+
+    #[test]
+    fn new_cell_must_be_signed_by_table_authority() {
+        let root_key = mock_generate_private_key();
+        let owner = mock_generate_private_key();
+        let mut ca_state = test_make_state_with_namespace(&root_key);
+
+        let cell = test_make_cell(test_make_value("alice"), owner.public_key(), &owner);
+        let operation = SetOperation::new("namespace".to_string(), cell);
+        assert_eq!(
+            ca_state.apply_set_operation(&operation, 1),
+            Err(CAStateOpError::CellOpError(CellOpError::InvalidSignature))
+        );
+
+        let cell = test_make_cell(test_make_value("alice"), owner.public_key(), &root_key);
+        let operation = SetOperation::new("namespace".to_string(), cell);
+        assert!(ca_state.validate_set_operation(&operation, 1));
+        assert!(ca_state.apply_set_operation(&operation, 1).is_ok());
+        assert!(ca_state
+            .find_value_cell(&RootEntryKey("namespace".to_string()), &"alice".to_string())
+            .is_some_and(|cell| cell.owner_key == owner.public_key()));
+    }
+
+    #[test]
+    fn set_operation_rotates_owner_key() {
+        let root_key = mock_generate_private_key();
+        let owner = mock_generate_private_key();
+        let new_owner = mock_generate_private_key();
+        let mut ca_state = test_make_state_with_namespace(&root_key);
+
+        let cell = test_make_cell(test_make_value("alice"), owner.public_key(), &root_key);
+        let operation = SetOperation::new("namespace".to_string(), cell.clone());
+        assert!(ca_state.apply_set_operation(&operation, 1).is_ok());
+
+        let mut update = cell.clone();
+        update.revision_time = 1;
+        update.owner_key = new_owner.public_key();
+
+        // The new owner cannot take over the cell on its own.
+        update.sign(&new_owner, &mock_signing_context());
+        let operation = SetOperation::new("namespace".to_string(), update.clone());
+        assert!(ca_state.apply_set_operation(&operation, 0).is_err());
+
+        update.sign(&owner, &mock_signing_context());
+        let operation = SetOperation::new("namespace".to_string(), update);
+        assert!(ca_state.apply_set_operation(&operation, 0).is_ok());
+        assert!(ca_state
+            .find_value_cell(&RootEntryKey("namespace".to_string()), &"alice".to_string())
+            .is_some_and(|cell| cell.owner_key == new_owner.public_key()));
+    }
+
+    #[test]
+    fn delegee_controls_delegated_table() {
+        let root_key = mock_generate_private_key();
+        let delegee = mock_generate_private_key();
+        let mut ca_state = test_make_state_with_namespace(&root_key);
+
+        let delegation = test_make_cell(
+            CellData::Delegate(InnerDelegateCell {
+                name_space: "home/".to_string(),
+                allowance: 10,
+                table: Some(TableId("home".to_string())),
+            }),
+            delegee.public_key(),
+            &root_key,
+        );
+        let operation = SetOperation::new("namespace".to_string(), delegation);
+        assert!(ca_state.apply_set_operation(&operation, 1).is_ok());
+
+        // Cells under the delegated namespace are authorised by the delegee.
+        let cell = test_make_cell(test_make_value("home/bob"), delegee.public_key(), &root_key);
+        let operation = SetOperation::new("namespace".to_string(), cell);
+        assert!(ca_state.apply_set_operation(&operation, 1).is_err());
+
+        let cell = test_make_cell(test_make_value("home/bob"), delegee.public_key(), &delegee);
+        let operation = SetOperation::new("namespace".to_string(), cell);
+        assert!(ca_state.apply_set_operation(&operation, 1).is_ok());
+        assert!(ca_state
+            .find_value_cell(
+                &RootEntryKey("namespace".to_string()),
+                &"home/bob".to_string()
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn namespace_owners_pick_signature_scheme() {
+        // An Ed25519 namespace delegating to an ECDSA P-256 delegee.
+        let root_key = PrivateKey::generate(SignatureAlgorithm::Ed25519);
+        let delegee = PrivateKey::generate(SignatureAlgorithm::EcdsaP256);
+        let mut ca_state = test_make_state_with_namespace(&root_key);
+
+        let delegation = test_make_cell(
+            CellData::Delegate(InnerDelegateCell {
+                name_space: "home/".to_string(),
+                allowance: 10,
+                table: Some(TableId("home".to_string())),
+            }),
+            delegee.public_key(),
+            &root_key,
+        );
+        let operation = SetOperation::new("namespace".to_string(), delegation);
+        assert!(ca_state.apply_set_operation(&operation, 1).is_ok());
+
+        let cell = test_make_cell(test_make_value("home/bob"), delegee.public_key(), &delegee);
+        let operation = SetOperation::new("namespace".to_string(), cell);
+        assert!(ca_state.apply_set_operation(&operation, 1).is_ok());
+
+        let proof = ca_state.lookup("namespace", "home/bob").unwrap();
+        assert!(proof.verify(&mock_signing_context()).is_ok());
+        assert_eq!(
+            proof.entry_cell.owner_key.algorithm(),
+            SignatureAlgorithm::EcdsaP256
+        );
+
+        // Failed lookups say which table was asked.
+        let Err(err) = ca_state.lookup("namespace", "home/alice") else {
+            panic!("home/alice was never set");
+        };
+        assert_eq!(err.source, CAStateOpError::NoExist);
+        assert_eq!(
+            err.context,
+            ErrorContext {
+                application_identifier: Some("namespace".to_string()),
+                lookup_key: Some("home/alice".to_string()),
+                table_id: Some(TableId("home".to_string())),
+            }
+        );
+    }
+
+    #[test]
+    fn remove_delegation_after_commitment_expires() {
+        let root_key = mock_generate_private_key();
+        let delegee = mock_generate_private_key();
+        let mut ca_state = test_make_state_with_namespace(&root_key);
+
+        let mut delegation = Cell {
+            create_time: 0,
+            revision_time: 0,
+            commitment_time: 10,
+            sig: mock_sig(),
+            owner_key: delegee.public_key(),
+            inner: CellData::Delegate(InnerDelegateCell {
+                name_space: "home/".to_string(),
+                allowance: 10,
+                table: Some(TableId("home".to_string())),
+            }),
+        };
+        delegation.sign(&root_key, &mock_signing_context());
+        let operation = SetOperation::new("namespace".to_string(), delegation.clone());
+        assert!(ca_state.apply_set_operation(&operation, 1).is_ok());
+
+        let mut removed = delegation.to_removed(&root_key.public_key(), 11, 20);
+        removed.sign_removal(&root_key, &mock_signing_context(), "home/");
+        let operation = CAOperation::Set(SetOperation::remove(
+            "namespace".to_string(),
+            "home/".to_string(),
+            removed,
+        ));
+
+        // Commitments are checked against the consensus close time.
+        ca_state.on_scp_operation(&SCPCAOperation::new(vec![], 10));
+        assert!(ca_state.on_ca_operation(&operation, 10).is_err());
+
+        ca_state.on_scp_operation(&SCPCAOperation::new(vec![operation], 11));
+        let root_entry_key = RootEntryKey("namespace".to_string());
+        let tables = ca_state.tables.get(&root_entry_key).unwrap();
+        assert!(tables.0.get(&TableId("home".to_string())).is_none());
+        assert!(ca_state
+            .find_delegation_cell(&root_entry_key, &"home/".to_string())
+            .is_none());
+
+        // The empty cell is collected once its own commitment expires.
+        let root_table = &tables.0[&TableId::root()];
+        assert_eq!(root_table.removed_entries.len(), 1);
+        assert_eq!(root_table.used_allowance(), 10);
+
+        ca_state.on_scp_operation(&SCPCAOperation::new(vec![], 21));
+        let root_table = &ca_state.tables[&root_entry_key].0[&TableId::root()];
+        assert!(root_table.removed_entries.is_empty());
+        assert_eq!(root_table.used_allowance(), 0);
+    }
+
+    #[test]
+    fn test_values_apply_atomically() {
+        let root_key = mock_generate_private_key();
+        let other_key = mock_generate_private_key();
+        let mut ca_state = test_make_state_with_namespace(&root_key);
+
+        let set = |cell: Cell| CAOperation::Set(SetOperation::new("namespace".to_string(), cell));
+        let alice = set(test_make_cell(
+            test_make_value("alice"),
+            root_key.public_key(),
+            &root_key,
+        ));
+        let bob = set(test_make_cell(
+            test_make_value("bob"),
+            root_key.public_key(),
+            &root_key,
+        ));
+        // Not signed by the table authority.
+        let carol = set(test_make_cell(
+            test_make_value("carol"),
+            other_key.public_key(),
+            &other_key,
+        ));
+
+        // Every failing operation is reported, and nothing is applied.
+        let value = SCPCAOperation::new(
+            vec![alice.clone(), CAOperation::Empty, carol, alice.clone()],
+            1,
+        );
+        let mut other_state = ca_state.clone();
+        let result = ca_state.on_scp_operation(&value);
+        let BatchResult::Rejected(errors) = &result else {
+            panic!("not reached");
+        };
+        assert_eq!(
+            errors.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(other_state.on_scp_operation(&value), result);
+
+        let root_entry_key = RootEntryKey("namespace".to_string());
+        let root_table = &ca_state.tables[&root_entry_key].0[&TableId::root()];
+        assert!(root_table.get_entry("alice").is_none());
+        assert_eq!(ca_state.close_time, 1);
+
+        let value = SCPCAOperation::new(vec![alice, bob], 2);
+        assert!(ca_state.on_scp_operation(&value).is_committed());
+        let root_table = &ca_state.tables[&root_entry_key].0[&TableId::root()];
+        assert!(root_table.get_entry("alice").is_some());
+        assert!(root_table.get_entry("bob").is_some());
+    }
 }
@@ -0,0 +1,650 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::scp::slot::SlotIndex;
+
+use super::{
+    ca_type::Timestamp,
+    cell::Cell,
+    merkle::MerkleHash,
+    operation::SCPCAOperation,
+    root::{RootEntry, RootEntryKey, RootListing},
+    signing::NetworkId,
+    state::CAState,
+    table::{Table, TableCollection, TableId, TableRepr},
+    transparency::TransparencyLog,
+};
+
+// On-disk layout of a CA node's database. The state is periodically written
+// out as a TOML snapshot taken at a slot index. Every value externalized after
+// the snapshot is appended to an operation log, one JSON encoded entry per
+// line, so that a restarted node can replay the log on top of the snapshot.
+// Every externalized value is first appended to the transparency log, which
+// is never truncated, so values a crash kept out of the snapshot and the
+// operation log are replayed from it on load.
+// Root entry removals are also appended to their own log for auditing.
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TableSnapshot {
+    pub table_id: TableId,
+    pub allowance: u32,
+    pub name_space: String,
+    pub value_entries: Vec<Cell>,
+    pub delegate_entries: Vec<Cell>,
+    pub removed_entries: Vec<Cell>,
+    // The leaves are stored as they are so that the reloaded tree has the same
+    // root, including leaves of cells that have since been garbage collected.
+    pub merkle_leaves: Vec<MerkleHash>,
+    pub merkle_leaf_index: BTreeMap<String, usize>,
+}
+
+impl TableSnapshot {
+    pub fn new(table_id: &TableId, table: &Table) -> Self {
+        let repr = TableRepr::from(table);
+        Self {
+            table_id: table_id.to_owned(),
+            allowance: repr.allowance,
+            name_space: repr.name_space,
+            value_entries: repr.value_entries,
+            delegate_entries: repr.delegate_entries,
+            removed_entries: repr.removed_entries,
+            merkle_leaves: repr.merkle_leaves,
+            merkle_leaf_index: repr.merkle_leaf_index,
+        }
+    }
+
+    pub fn into_table(self) -> (TableId, Table) {
+        let repr = TableRepr {
+            allowance: self.allowance,
+            name_space: self.name_space,
+            value_entries: self.value_entries,
+            delegate_entries: self.delegate_entries,
+            removed_entries: self.removed_entries,
+            merkle_leaves: self.merkle_leaves,
+            merkle_leaf_index: self.merkle_leaf_index,
+        };
+
+        (self.table_id, repr.into_table())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct NamespaceSnapshot {
+    pub application_identifier: String,
+    pub tables: Vec<TableSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CAStateSnapshot {
+    pub slot_index: SlotIndex,
+    pub close_time: Timestamp,
+    pub network_id: NetworkId,
+    pub root_entries: Vec<RootEntry>,
+    pub namespaces: Vec<NamespaceSnapshot>,
+}
+
+impl CAStateSnapshot {
+    pub fn new(state: &CAState, slot_index: SlotIndex) -> Self {
+        // Entries are sorted so that the same state always produces the same
+        // snapshot.
+        let mut root_entries: Vec<RootEntry> = state.root_listing.0.values().cloned().collect();
+        root_entries.sort_by(|a, b| a.application_identifier.cmp(&b.application_identifier));
+
+        let mut namespaces: Vec<NamespaceSnapshot> = state
+            .tables
+            .iter()
+            .map(|(root_entry_key, tables)| {
+                let mut tables: Vec<TableSnapshot> = tables
+                    .0
+                    .iter()
+                    .map(|(table_id, table)| TableSnapshot::new(table_id, table))
+                    .collect();
+                tables.sort_by(|a, b| a.table_id.cmp(&b.table_id));
+
+                NamespaceSnapshot {
+                    application_identifier: root_entry_key.0.to_owned(),
+                    tables,
+                }
+            })
+            .collect();
+        namespaces.sort_by(|a, b| a.application_identifier.cmp(&b.application_identifier));
+
+        Self {
+            slot_index,
+            close_time: state.close_time,
+            network_id: state.network_id,
+            root_entries,
+            namespaces,
+        }
+    }
+
+    pub fn into_state(self) -> (CAState, SlotIndex) {
+        let root_listing = RootListing(
+            self.root_entries
+                .into_iter()
+                .map(|entry| (entry.application_identifier.to_owned(), entry))
+                .collect(),
+        );
+
+        let tables = self
+            .namespaces
+            .into_iter()
+            .map(|namespace| {
+                let tables = namespace
+                    .tables
+                    .into_iter()
+                    .map(TableSnapshot::into_table)
+                    .collect();
+                (
+                    RootEntryKey(namespace.application_identifier),
+                    TableCollection(tables),
+                )
+            })
+            .collect();
+
+        let state = CAState {
+            root_listing,
+            tables,
+            close_time: self.close_time,
+            network_id: self.network_id,
+        };
+
+        (state, self.slot_index)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CAOperationLogEntry {
+    pub slot_index: SlotIndex,
+    pub operation: SCPCAOperation,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RootRemovalLogEntry {
+    pub slot_index: SlotIndex,
+    pub application_identifier: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct CAStore {
+    dir: PathBuf,
+    snapshot_interval: u64,
+    last_snapshot_slot: Option<SlotIndex>,
+}
+
+impl CAStore {
+    pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 64;
+    const SNAPSHOT_FILE: &'static str = "snapshot.toml";
+    const LOG_FILE: &'static str = "operations.log";
+    // Unlike the operation log, never truncated by snapshots.
+    const TRANSPARENCY_LOG_FILE: &'static str = "transparency.log";
+    const REMOVAL_LOG_FILE: &'static str = "removals.log";
+
+    pub fn new(dir: &Path, snapshot_interval: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: dir.to_owned(),
+            snapshot_interval,
+            last_snapshot_slot: None,
+        })
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        self.dir.join(Self::SNAPSHOT_FILE)
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.join(Self::LOG_FILE)
+    }
+
+    fn transparency_log_path(&self) -> PathBuf {
+        self.dir.join(Self::TRANSPARENCY_LOG_FILE)
+    }
+
+    fn removal_log_path(&self) -> PathBuf {
+        self.dir.join(Self::REMOVAL_LOG_FILE)
+    }
+
+    pub fn write_snapshot(&mut self, state: &CAState, slot_index: SlotIndex) -> io::Result<()> {
+        let toml_str = state.to_toml(slot_index).ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Failed to serialize CA state",
+        ))?;
+
+        // Write to a temporary file first so that a crash never leaves a partial
+        // snapshot behind.
+        let tmp_path = self.snapshot_path().with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(toml_str.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(tmp_path, self.snapshot_path())?;
+
+        // Every logged operation is now part of the snapshot.
+        File::create(self.log_path())?.sync_all()?;
+        self.last_snapshot_slot = Some(slot_index);
+
+        Ok(())
+    }
+
+    pub fn append_operation(
+        &self,
+        slot_index: SlotIndex,
+        operation: &SCPCAOperation,
+    ) -> io::Result<()> {
+        append_entry(&self.log_path(), slot_index, operation)
+    }
+
+    pub fn append_transparency_entry(
+        &self,
+        slot_index: SlotIndex,
+        operation: &SCPCAOperation,
+    ) -> io::Result<()> {
+        append_entry(&self.transparency_log_path(), slot_index, operation)
+    }
+
+    pub fn append_removal(&self, entry: &RootRemovalLogEntry) -> io::Result<()> {
+        append_line(&self.removal_log_path(), entry)
+    }
+
+    pub fn on_externalized(
+        &mut self,
+        state: &CAState,
+        slot_index: SlotIndex,
+        operation: &SCPCAOperation,
+    ) -> io::Result<()> {
+        // `state` is the state after applying `operation`. The transparency
+        // entry goes first, and `load` replays it if the rest is not written.
+        self.append_transparency_entry(slot_index, operation)?;
+
+        let snapshot_due = match self.last_snapshot_slot {
+            Some(last_snapshot_slot) => slot_index >= last_snapshot_slot + self.snapshot_interval,
+            None => true,
+        };
+
+        if snapshot_due {
+            self.write_snapshot(state, slot_index)
+        } else {
+            self.append_operation(slot_index, operation)
+        }
+    }
+
+    pub fn load(&mut self) -> io::Result<(CAState, Option<SlotIndex>)> {
+        // Restores the latest snapshot and replays the operations logged after
+        // it. Returns the state and the index of the last slot applied.
+        // New entries must not be appended to a torn line.
+        drop_torn_entry::<CAOperationLogEntry>(&self.log_path())?;
+        drop_torn_entry::<CAOperationLogEntry>(&self.transparency_log_path())?;
+        drop_torn_entry::<RootRemovalLogEntry>(&self.removal_log_path())?;
+
+        let (mut state, mut last_slot) = match fs::read_to_string(self.snapshot_path()) {
+            Ok(toml_str) => {
+                let (state, slot_index) = CAState::from_toml(&toml_str).ok_or(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Failed to parse CA state snapshot",
+                ))?;
+                (state, Some(slot_index))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (CAState::default(), None),
+            Err(err) => return Err(err),
+        };
+        self.last_snapshot_slot = last_slot;
+
+        for entry in read_entries::<CAOperationLogEntry>(&self.log_path())? {
+            if last_slot.is_some_and(|slot_index| entry.slot_index <= slot_index) {
+                continue;
+            }
+
+            state.on_scp_operation(&entry.operation);
+            last_slot = Some(entry.slot_index);
+        }
+
+        // Values only the transparency log kept, because the node stopped
+        // before persisting the state.
+        for entry in self.transparency_entries()? {
+            if last_slot.is_some_and(|slot_index| entry.slot_index <= slot_index) {
+                continue;
+            }
+
+            state.on_scp_operation(&entry.operation);
+            self.append_operation(entry.slot_index, &entry.operation)?;
+            last_slot = Some(entry.slot_index);
+        }
+
+        Ok((state, last_slot))
+    }
+
+    pub fn transparency_entries(&self) -> io::Result<Vec<CAOperationLogEntry>> {
+        read_entries(&self.transparency_log_path())
+    }
+
+    pub fn removals(&self) -> io::Result<Vec<RootRemovalLogEntry>> {
+        read_entries(&self.removal_log_path())
+    }
+
+    pub fn load_transparency_log(&self) -> io::Result<TransparencyLog> {
+        let mut log = TransparencyLog::default();
+        for entry in self.transparency_entries()? {
+            log.append(entry.slot_index, &entry.operation);
+        }
+        Ok(log)
+    }
+}
+
+fn append_entry(path: &Path, slot_index: SlotIndex, operation: &SCPCAOperation) -> io::Result<()> {
+    let entry = CAOperationLogEntry {
+        slot_index,
+        operation: operation.to_owned(),
+    };
+    append_line(path, &entry)
+}
+
+fn append_line<T: Serialize>(path: &Path, entry: &T) -> io::Result<()> {
+    let line = serde_json::to_string(entry)?;
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)?;
+    file.sync_data()
+}
+
+fn read_entries<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    read_log(path).map(|(entries, _)| entries)
+}
+
+fn read_log<T: DeserializeOwned>(path: &Path) -> io::Result<(Vec<T>, u64)> {
+    // Returns the entries and the length of the file up to the end of the last
+    // one.
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((vec![], 0)),
+        Err(err) => return Err(err),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut entries = vec![];
+    let mut len = 0;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        let complete = line.ends_with('\n');
+        match serde_json::from_str(&line) {
+            Ok(entry) if complete => entries.push(entry),
+            Err(err) if complete && !reader.fill_buf()?.is_empty() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+            // A torn write can only affect the last line, which was never
+            // acknowledged, so stop reading there.
+            _ => break,
+        }
+        len += line.len() as u64;
+        line.clear();
+    }
+    Ok((entries, len))
+}
+
+fn drop_torn_entry<T: DeserializeOwned>(path: &Path) -> io::Result<()> {
+    let (_, len) = read_log::<T>(path)?;
+    match OpenOptions::new().write(true).open(path) {
+        Ok(file) if file.metadata()?.len() > len => file.set_len(len),
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use crate::ca::{
+        cell::{CellData, InnerDelegateCell, InnerValueCell},
+        crypto::TEST_OPENSSL_PRIVATE_KEY,
+        crypto::{mock_private_key, mock_sig},
+        local_state::LocalCAState,
+        operation::{CAOperation, SetOperation},
+        signing::mock_signing_context,
+    };
+
+    use super::*;
+
+    fn test_store_dir(name: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("ca_store_{}_{}", name, nanos))
+    }
+
+    fn test_make_set_operation(inner: CellData, revision_time: Timestamp) -> CAOperation {
+        let mut cell = Cell {
+            create_time: 0,
+            revision_time,
+            commitment_time: 0,
+            sig: mock_sig(),
+            owner_key: mock_private_key().public_key(),
+            inner,
+        };
+        cell.sign(&mock_private_key(), &mock_signing_context());
+        CAOperation::Set(SetOperation::new("namespace".to_string(), cell))
+    }
+
+    fn test_make_operations() -> Vec<SCPCAOperation> {
+        let local_state = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+
+        vec![
+            SCPCAOperation::new(vec![local_state.create_name_space("namespace").unwrap()], 1),
+            SCPCAOperation::new(
+                vec![
+                    test_make_set_operation(
+                        CellData::Value(InnerValueCell {
+                            value: "alice".to_string(),
+                        }),
+                        0,
+                    ),
+                    test_make_set_operation(
+                        CellData::Delegate(InnerDelegateCell {
+                            name_space: "home/".to_string(),
+                            allowance: 10,
+                            table: Some(TableId("home".to_string())),
+                        }),
+                        0,
+                    ),
+                ],
+                2,
+            ),
+            SCPCAOperation::new(
+                vec![
+                    test_make_set_operation(
+                        CellData::Value(InnerValueCell {
+                            value: "home/bob".to_string(),
+                        }),
+                        0,
+                    ),
+                    test_make_set_operation(
+                        CellData::Value(InnerValueCell {
+                            value: "alice".to_string(),
+                        }),
+                        1,
+                    ),
+                ],
+                3,
+            ),
+        ]
+    }
+
+    fn assert_same_state(state: &CAState, other: &CAState) {
+        assert_eq!(
+            CAStateSnapshot::new(state, 0),
+            CAStateSnapshot::new(other, 0)
+        );
+
+        for (root_entry_key, tables) in &state.tables {
+            for (table_id, table) in &tables.0 {
+                let other_table = &other.tables[root_entry_key].0[table_id];
+                assert_eq!(
+                    table.merkle_tree.root().as_bytes(),
+                    other_table.merkle_tree.root().as_bytes()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let mut state = CAState::default();
+        for operation in test_make_operations() {
+            state.on_scp_operation(&operation);
+        }
+        assert_eq!(
+            state.tables[&RootEntryKey("namespace".to_string())].0[&TableId::root()]
+                .merkle_tree
+                .len(),
+            2
+        );
+
+        let toml_str = state.to_toml(3).unwrap();
+        let (reloaded, slot_index) = CAState::from_toml(&toml_str).unwrap();
+
+        assert_eq!(slot_index, 3);
+        assert_same_state(&state, &reloaded);
+        assert_eq!(reloaded.to_toml(3).unwrap(), toml_str);
+    }
+
+    #[test]
+    fn reload_from_snapshot_and_log() {
+        let dir = test_store_dir("reload");
+        let mut store = CAStore::new(&dir, 3).unwrap();
+        let mut state = CAState::default();
+
+        let operations = test_make_operations();
+        for (slot_index, operation) in operations.iter().enumerate() {
+            state.on_scp_operation(operation);
+            store
+                .on_externalized(&state, slot_index as SlotIndex, operation)
+                .unwrap();
+        }
+
+        // Slot 0 was snapshotted, slots 1 and 2 are in the log.
+        let log = fs::read_to_string(dir.join(CAStore::LOG_FILE)).unwrap();
+        assert_eq!(log.lines().count(), 2);
+
+        let mut reloaded_store = CAStore::new(&dir, 3).unwrap();
+        let (reloaded, last_slot) = reloaded_store.load().unwrap();
+        assert_eq!(last_slot, Some(2));
+        assert_same_state(&state, &reloaded);
+
+        // The transparency log keeps every slot, including the snapshotted one.
+        let mut transparency_log = TransparencyLog::default();
+        for (slot_index, operation) in operations.iter().enumerate() {
+            transparency_log.append(slot_index as SlotIndex, operation);
+        }
+        let reloaded_log = reloaded_store.load_transparency_log().unwrap();
+        assert_eq!(reloaded_log.len(), 3);
+        assert_eq!(reloaded_log.tree_head(), transparency_log.tree_head());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_last_entry_may_be_torn() {
+        let dir = test_store_dir("torn");
+        let mut store = CAStore::new(&dir, 3).unwrap();
+        let mut state = CAState::default();
+        let operations = test_make_operations();
+        for (slot_index, operation) in operations.iter().enumerate() {
+            state.on_scp_operation(operation);
+            store
+                .on_externalized(&state, slot_index as SlotIndex, operation)
+                .unwrap();
+        }
+
+        // A torn write is dropped, and later entries start on a new line.
+        let log_path = dir.join(CAStore::LOG_FILE);
+        let log = fs::read_to_string(&log_path).unwrap();
+        fs::write(&log_path, format!("{}{{\"slot_index\":", log)).unwrap();
+        let mut reloaded_store = CAStore::new(&dir, 3).unwrap();
+        let (reloaded, last_slot) = reloaded_store.load().unwrap();
+        assert_eq!(last_slot, Some(2));
+        assert_same_state(&state, &reloaded);
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), log);
+
+        // Anywhere else, it is corruption.
+        let (first, rest) = log.split_once('\n').unwrap();
+        fs::write(&log_path, format!("{}\n{}", &first[1..], rest)).unwrap();
+        let err = CAStore::new(&dir, 3).unwrap().load().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replay_values_only_in_the_transparency_log() {
+        let dir = test_store_dir("transparency_only");
+        let mut store = CAStore::new(&dir, 3).unwrap();
+        let mut state = CAState::default();
+        let operations = test_make_operations();
+        for (slot_index, operation) in operations.iter().enumerate() {
+            state.on_scp_operation(operation);
+            store
+                .on_externalized(&state, slot_index as SlotIndex, operation)
+                .unwrap();
+        }
+
+        // The node stopped after logging the last value for transparency.
+        let log_path = dir.join(CAStore::LOG_FILE);
+        let log = fs::read_to_string(&log_path).unwrap();
+        let (first, _) = log.split_once('\n').unwrap();
+        fs::write(&log_path, format!("{}\n", first)).unwrap();
+
+        let mut reloaded_store = CAStore::new(&dir, 3).unwrap();
+        let (reloaded, last_slot) = reloaded_store.load().unwrap();
+        assert_eq!(last_slot, Some(2));
+        assert_same_state(&state, &reloaded);
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), log);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removals_are_kept_for_audit() {
+        let dir = test_store_dir("removals");
+        let mut local_state = LocalCAState::init_state_from_pkcs8_pem(TEST_OPENSSL_PRIVATE_KEY);
+        local_state.store = Some(CAStore::new(&dir, 1).unwrap());
+
+        let create = local_state.create_name_space("namespace").unwrap();
+        local_state.on_externalized(0, &SCPCAOperation::new(vec![create], 1));
+        let removal = local_state.propose_removal("namespace", "spam").unwrap();
+        local_state.on_externalized(1, &SCPCAOperation::new(vec![removal], 2));
+
+        // Snapshots truncate the operation log, not the removals.
+        let store = CAStore::new(&dir, 1).unwrap();
+        assert_eq!(
+            store.removals().unwrap(),
+            vec![RootRemovalLogEntry {
+                slot_index: 1,
+                application_identifier: "namespace".to_string(),
+                reason: "spam".to_string(),
+            }]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_empty_store() {
+        let dir = test_store_dir("empty");
+        let mut store = CAStore::new(&dir, CAStore::DEFAULT_SNAPSHOT_INTERVAL).unwrap();
+        let (state, last_slot) = store.load().unwrap();
+
+        assert!(state.root_listing.0.is_empty());
+        assert_eq!(last_slot, None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,10 +1,19 @@
-use std::{borrow::BorrowMut, cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    borrow::BorrowMut,
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use super::{
-    cell::Cell,
-    merkle::{MerkleHash, MerkleTree},
+    ca_type::Timestamp,
+    cell::{Cell, CellData, CellOpError, InnerDelegateCell},
+    crypto::PublicKey,
+    merkle::{MerkleHash, MerkleOpError, MerkleTree},
+    prefix::PrefixMap,
+    signing::SigningContext,
 };
 
 pub type TableOpResult<T> = std::result::Result<T, TableOpError>;
@@ -17,6 +26,59 @@ pub enum TableOpError {
     // NotEnoughAllowence(allowance_capacity, allowance_filled)
     NotEnoughAllowence(u32, u32),
     EmptyCell,
+    NoExist,
+    CellOpError(CellOpError),
+    MerkleOpError(MerkleOpError),
+    // A serialized table that is not in canonical form or whose cells do not
+    // match its Merkle tree.
+    InvalidEncoding(String),
+}
+
+impl Display for TableOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TableOpError::NamespaceError => write!(f, "cell is outside the namespace of the table"),
+            TableOpError::CellAddressIsPrefix => {
+                write!(f, "lookup key is a prefix of a delegated namespace")
+            }
+            TableOpError::CellAddressContainsPrefix => {
+                write!(f, "lookup key falls in a delegated namespace")
+            }
+            TableOpError::NotEnoughAllowence(capacity, filled) => {
+                write!(f, "not enough allowance: {} of {} used", filled, capacity)
+            }
+            TableOpError::EmptyCell => write!(f, "empty cell"),
+            TableOpError::NoExist => write!(f, "no cell at the lookup key"),
+            TableOpError::CellOpError(err) => write!(f, "{}", err),
+            TableOpError::MerkleOpError(err) => write!(f, "{}", err),
+            TableOpError::InvalidEncoding(reason) => {
+                write!(f, "invalid table encoding: {}", reason)
+            }
+        }
+    }
+}
+
+// Like `CAStateOpError`, the wrappers are transparent.
+impl std::error::Error for TableOpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TableOpError::CellOpError(err) => std::error::Error::source(err),
+            TableOpError::MerkleOpError(err) => std::error::Error::source(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<CellOpError> for TableOpError {
+    fn from(err: CellOpError) -> Self {
+        TableOpError::CellOpError(err)
+    }
+}
+
+impl From<MerkleOpError> for TableOpError {
+    fn from(err: MerkleOpError) -> Self {
+        TableOpError::MerkleOpError(err)
+    }
 }
 
 /// https://datatracker.ietf.org/doc/html/draft-watson-dinrg-delmap-01
@@ -32,6 +94,7 @@ pub enum TableOpError {
 
 /// Delegating the whole or part of a namespace requires adding a new lookup key for the namespace and a matching delegate cell.  Each delegation must be validated in the context of the other table entries and the table itself.  For example, the owner of a table delegated an /8 IPv4 block must not to delegate the same /16 block to two different tables.
 
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct TableMeta {
     pub allowance: u32,
     lookup_key: String,
@@ -39,7 +102,8 @@ pub struct TableMeta {
 
 impl TableMeta {
     pub fn to_merkle_hash(&self) -> Option<MerkleHash> {
-        todo!()
+        let bytes = bincode::serialize(self).ok()?;
+        Some(Sha256::digest(bytes).into())
     }
 }
 
@@ -52,18 +116,194 @@ impl TableId {
     }
 }
 
-#[derive(Clone, Debug)]
+// Tables refer to the tables they delegate to by `TableId`, so a collection
+// serializes as a map from IDs to tables, in ID order.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(
+    into = "BTreeMap<TableId, Table>",
+    try_from = "BTreeMap<TableId, Table>"
+)]
 pub struct TableCollection(pub HashMap<TableId, Table>);
-pub type HTable = Rc<RefCell<Table>>;
 
-#[derive(Clone, Debug)]
+impl From<TableCollection> for BTreeMap<TableId, Table> {
+    fn from(tables: TableCollection) -> Self {
+        tables.0.into_iter().collect()
+    }
+}
+
+impl TryFrom<BTreeMap<TableId, Table>> for TableCollection {
+    type Error = TableOpError;
+
+    fn try_from(tables: BTreeMap<TableId, Table>) -> TableOpResult<Self> {
+        // Every delegation has to point at a table of the collection.
+        if !tables.contains_key(&TableId::root()) {
+            return Err(TableOpError::InvalidEncoding("no root table".to_string()));
+        }
+        for table in tables.values() {
+            for cell in table.delegate_entries.values() {
+                if let CellData::Delegate(InnerDelegateCell {
+                    table: Some(table_id),
+                    ..
+                }) = &cell.inner
+                {
+                    if !tables.contains_key(table_id) {
+                        return Err(TableOpError::InvalidEncoding(format!(
+                            "delegation to missing table {:?}",
+                            table_id.0
+                        )));
+                    }
+                }
+            }
+        }
+
+        Ok(Self(tables.into_iter().collect()))
+    }
+}
+
+impl TableCollection {
+    pub fn new(allowance: u32) -> Self {
+        let mut tables = HashMap::new();
+        tables.insert(TableId::root(), Table::new(allowance, "".to_string()));
+        Self(tables)
+    }
+
+    pub fn remove_table(&mut self, table_id: &TableId) {
+        // Removes a table along with all the tables delegated from it.
+        if let Some(table) = self.0.remove(table_id) {
+            for entry in table.delegate_entries.values() {
+                if let CellData::Delegate(inner_delegate_cell) = &entry.inner {
+                    if let Some(delegated_table_id) = &inner_delegate_cell.table {
+                        self.remove_table(delegated_table_id);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn collect_garbage(&mut self, now: Timestamp) -> usize {
+        self.0
+            .values_mut()
+            .map(|table| table.collect_garbage(now))
+            .sum()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(into = "TableRepr", try_from = "TableRepr")]
 pub struct Table {
     pub allowance: u32,
     pub name_space: String,
-    // Need to change this to a map
+    // Live cells keyed by lookup key.
+    pub value_entries: PrefixMap<Cell>,
+    pub delegate_entries: PrefixMap<Cell>,
+    // Empty cells left behind by removals. They hold on to their allowance
+    // until their commitment timestamp expires and they are garbage collected.
+    pub removed_entries: Vec<Cell>,
+    pub merkle_tree: Box<MerkleTree>,
+    // Index of the leaf of each cell in the merkle tree, keyed by lookup key.
+    // A removed cell keeps its leaf, which then holds the empty cell.
+    pub merkle_leaf_index: BTreeMap<String, usize>,
+}
+
+// The serialized form of a table. Cells are listed in lookup key order and the
+// Merkle tree by its leaves, so equal tables always have the same encoding.
+// Decoding only accepts this canonical form and checks every live cell against
+// its leaf, so a table received from another node proves what it holds.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TableRepr {
+    pub allowance: u32,
+    pub name_space: String,
     pub value_entries: Vec<Cell>,
     pub delegate_entries: Vec<Cell>,
-    pub merkle_tree: Box<MerkleTree>,
+    pub removed_entries: Vec<Cell>,
+    // The leaves are kept as they are so that the decoded tree has the same
+    // root, including leaves of cells that have since been garbage collected.
+    pub merkle_leaves: Vec<MerkleHash>,
+    pub merkle_leaf_index: BTreeMap<String, usize>,
+}
+
+impl From<&Table> for TableRepr {
+    fn from(table: &Table) -> Self {
+        Self {
+            allowance: table.allowance,
+            name_space: table.name_space.to_owned(),
+            value_entries: table.value_entries.values().cloned().collect(),
+            delegate_entries: table.delegate_entries.values().cloned().collect(),
+            removed_entries: table.removed_entries.to_owned(),
+            merkle_leaves: table.merkle_tree.leaves().to_vec(),
+            merkle_leaf_index: table.merkle_leaf_index.to_owned(),
+        }
+    }
+}
+
+impl From<Table> for TableRepr {
+    fn from(table: Table) -> Self {
+        Self::from(&table)
+    }
+}
+
+impl TableRepr {
+    pub fn validate(&self) -> TableOpResult<()> {
+        let invalid = |reason: String| Err(TableOpError::InvalidEncoding(reason));
+
+        for (entries, is_value) in [(&self.value_entries, true), (&self.delegate_entries, false)] {
+            if !entries
+                .windows(2)
+                .all(|pair| pair[0].name_space_or_value() < pair[1].name_space_or_value())
+            {
+                return invalid("cells are not in lookup key order".to_string());
+            }
+
+            for cell in entries {
+                let key = cell.name_space_or_value();
+                if matches!(cell.inner, CellData::Value(_)) != is_value {
+                    return invalid(format!("cell {:?} is in the wrong list", key));
+                }
+
+                let leaf = self
+                    .merkle_leaf_index
+                    .get(key)
+                    .and_then(|idx| self.merkle_leaves.get(*idx));
+                if leaf != Some(&leaf_hash(cell)?) {
+                    return invalid(format!("cell {:?} does not match its leaf", key));
+                }
+            }
+        }
+
+        if self.merkle_leaf_index.len() != self.value_entries.len() + self.delegate_entries.len() {
+            return invalid("leaf index does not match the cells".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn into_table(self) -> Table {
+        // Does not validate, for tables read back from the local store.
+        let index_cells = |cells: Vec<Cell>| -> PrefixMap<Cell> {
+            cells
+                .into_iter()
+                .map(|cell| (cell.name_space_or_value().to_owned(), cell))
+                .collect()
+        };
+
+        Table {
+            allowance: self.allowance,
+            name_space: self.name_space,
+            value_entries: index_cells(self.value_entries),
+            delegate_entries: index_cells(self.delegate_entries),
+            removed_entries: self.removed_entries,
+            merkle_tree: Box::new(MerkleTree::from_leaves(&self.merkle_leaves)),
+            merkle_leaf_index: self.merkle_leaf_index,
+        }
+    }
+}
+
+impl TryFrom<TableRepr> for Table {
+    type Error = TableOpError;
+
+    fn try_from(repr: TableRepr) -> TableOpResult<Self> {
+        repr.validate()?;
+        Ok(repr.into_table())
+    }
 }
 
 //    Delegating the whole or part of a namespace requires adding a new
@@ -81,6 +321,8 @@ impl Default for Table {
             name_space: "".to_string(),
             value_entries: Default::default(),
             delegate_entries: Default::default(),
+            removed_entries: Default::default(),
+            merkle_leaf_index: Default::default(),
         }
     }
 }
@@ -93,19 +335,48 @@ impl Table {
             allowance,
             value_entries: Default::default(),
             delegate_entries: Default::default(),
+            removed_entries: Default::default(),
             merkle_tree: Default::default(),
+            merkle_leaf_index: Default::default(),
             name_space: namespace,
         }
     }
 
+    pub fn to_canonical_bytes(&self) -> TableOpResult<Vec<u8>> {
+        bincode::serialize(self).map_err(|err| TableOpError::InvalidEncoding(err.to_string()))
+    }
+
+    pub fn from_canonical_bytes(bytes: &[u8]) -> TableOpResult<Self> {
+        bincode::deserialize(bytes).map_err(|err| TableOpError::InvalidEncoding(err.to_string()))
+    }
+
     pub fn add_entry(&mut self, cell: Cell) -> TableOpResult<()> {
-        match self.check_cell_valid(&cell) {
-            Err(err) => Err(err),
-            Ok(_) => match &cell.inner {
-                super::cell::CellData::Value(_) => Ok(self.value_entries.push(cell)),
-                super::cell::CellData::Delegate(_) => Ok(self.delegate_entries.push(cell)),
-            },
-        }
+        self.check_cell_valid(&cell)?;
+
+        let leaf = leaf_hash(&cell)?;
+        self.merkle_leaf_index.insert(
+            cell.name_space_or_value().to_owned(),
+            self.merkle_tree.len(),
+        );
+        self.merkle_tree.push(leaf);
+
+        let key = cell.name_space_or_value().to_owned();
+        match &cell.inner {
+            CellData::Value(_) => self.value_entries.insert(key, cell),
+            CellData::Delegate(_) => self.delegate_entries.insert(key, cell),
+        };
+
+        Ok(())
+    }
+
+    fn update_leaf(&mut self, key: &str, cell: &Cell) -> TableOpResult<()> {
+        let idx = *self
+            .merkle_leaf_index
+            .get(key)
+            .ok_or(TableOpError::NoExist)?;
+        self.merkle_tree
+            .update(leaf_hash(cell)?, idx)
+            .map_err(TableOpError::MerkleOpError)
     }
 
     pub fn check_cell_valid(&self, cell: &Cell) -> TableOpResult<()> {
@@ -134,34 +405,16 @@ impl Table {
             return Err(TableOpError::NamespaceError);
         }
 
-        if self
-            .value_entries
-            .iter()
-            .any(|table_entry| table_entry.contains_prefix(&cell.name_space_or_value()))
-        {
-            return Err(TableOpError::CellAddressIsPrefix);
-        }
+        let key = cell.name_space_or_value();
 
-        if self
-            .value_entries
-            .iter()
-            .any(|table_entry| table_entry.contains_prefix_in_cell(cell))
-        {
-            return Err(TableOpError::CellAddressContainsPrefix);
-        }
-
-        if self
-            .delegate_entries
-            .iter()
-            .any(|table_entry| table_entry.contains_prefix_in_cell(cell))
+        if self.value_entries.extending(key).is_some()
+            || self.delegate_entries.extending(key).is_some()
         {
             return Err(TableOpError::CellAddressIsPrefix);
         }
 
-        if self
-            .delegate_entries
-            .iter()
-            .any(|table_entry| cell.contains_prefix_in_cell(table_entry))
+        if self.value_entries.prefix_of(key).is_some()
+            || self.delegate_entries.prefix_of(key).is_some()
         {
             return Err(TableOpError::CellAddressContainsPrefix);
         }
@@ -169,19 +422,21 @@ impl Table {
         Ok(())
     }
 
+    pub fn used_allowance(&self) -> u32 {
+        self.value_entries
+            .values()
+            .chain(self.delegate_entries.values())
+            .chain(self.removed_entries.iter())
+            .map(|e| e.allowance())
+            .sum()
+    }
+
     pub fn contains_enough_allowance(&self, allowance: u32) -> TableOpResult<()> {
         if self.allowance == 0 {
             return Ok(());
         }
 
-        let mut cur: u32 = 0;
-        self.value_entries.iter().for_each(|e| {
-            cur += 1;
-        });
-
-        self.delegate_entries.iter().for_each(|e| {
-            cur += e.allowance();
-        });
+        let cur = self.used_allowance();
 
         if cur + allowance > self.allowance {
             Err(TableOpError::NotEnoughAllowence(self.allowance, cur))
@@ -189,6 +444,140 @@ impl Table {
             Ok(())
         }
     }
+
+    pub fn get_entry(&self, key: &str) -> Option<&Cell> {
+        self.value_entries
+            .get(key)
+            .or_else(|| self.delegate_entries.get(key))
+    }
+
+    fn get_entry_mut(&mut self, key: &str) -> Option<&mut Cell> {
+        match self.value_entries.get_mut(key) {
+            Some(entry) => Some(entry),
+            None => self.delegate_entries.get_mut(key),
+        }
+    }
+
+    pub fn update_entry(
+        &mut self,
+        cell: Cell,
+        authority_key: &PublicKey,
+        now: Timestamp,
+        context: &SigningContext,
+    ) -> TableOpResult<()> {
+        // Replaces the cell stored under the same lookup key with a new version,
+        // after checking that the update is authorised and that the table has
+        // enough allowance left if a delegation grows.
+        let old_allowance = self
+            .get_entry(cell.name_space_or_value())
+            .ok_or(TableOpError::NoExist)?
+            .allowance();
+
+        if cell.allowance() > old_allowance {
+            self.contains_enough_allowance(cell.allowance() - old_allowance)?;
+        }
+
+        let key = cell.name_space_or_value().to_owned();
+        let entry = self.get_entry_mut(&key).ok_or(TableOpError::NoExist)?;
+        let entry = entry
+            .modify(cell, authority_key, now, context)
+            .map_err(TableOpError::CellOpError)?
+            .to_owned();
+
+        self.update_leaf(&key, &entry)
+    }
+
+    pub fn remove_entry(
+        &mut self,
+        key: &str,
+        removed: Cell,
+        authority_key: &PublicKey,
+        now: Timestamp,
+        context: &SigningContext,
+    ) -> TableOpResult<Cell> {
+        // Replaces the cell stored under `key` with an empty cell owned by the
+        // table authority and returns the cell that was removed.
+        let entries = match removed.inner {
+            CellData::Value(_) => &mut self.value_entries,
+            CellData::Delegate(_) => &mut self.delegate_entries,
+        };
+        entries
+            .get(key)
+            .ok_or(TableOpError::NoExist)?
+            .validate_removal(&removed, authority_key, now, context)
+            .map_err(TableOpError::CellOpError)?;
+
+        let cell = entries.remove(key).ok_or(TableOpError::NoExist)?;
+        self.update_leaf(key, &removed)?;
+        self.merkle_leaf_index.remove(key);
+        self.removed_entries.push(removed);
+        Ok(cell)
+    }
+
+    pub fn collect_garbage(&mut self, now: Timestamp) -> usize {
+        // Drops empty cells whose commitment timestamp has expired, freeing
+        // their allowance. Returns the number of cells dropped.
+        let len = self.removed_entries.len();
+        self.removed_entries
+            .retain(|e| e.commitment_expires(&now).is_err());
+        len - self.removed_entries.len()
+    }
+}
+
+fn leaf_hash(cell: &Cell) -> TableOpResult<MerkleHash> {
+    cell.to_merkle_hash().ok_or(TableOpError::MerkleOpError(
+        MerkleOpError::InternalTreeError,
+    ))
+}
+
+pub fn resolve_table<'a>(
+    table_maps: &'a TableCollection,
+    table_id: &'a TableId,
+    authority_key: &'a PublicKey,
+    key: &str,
+) -> Option<(&'a TableId, &'a PublicKey)> {
+    // Follows the delegation chain starting at `table_id` and returns the table
+    // responsible for `key` together with the public key of its authority. The
+    // delegate cell for a namespace lives in the delegator's table, so a key
+    // equal to a delegated namespace resolves to the delegator's table.
+    let table = table_maps.0.get(table_id)?;
+
+    if let Some((_, entry)) = table.delegate_entries.strict_prefix_of(key) {
+        if let CellData::Delegate(inner_delegate_cell) = &entry.inner {
+            if let Some(new_table_id) = &inner_delegate_cell.table {
+                return resolve_table(table_maps, new_table_id, &entry.owner_key, key);
+            }
+        }
+    }
+
+    Some((table_id, authority_key))
+}
+
+pub fn longest_prefix_match<'a>(
+    table_maps: &'a TableCollection,
+    table_id: &'a TableId,
+    key: &str,
+) -> Option<(&'a TableId, &'a Cell)> {
+    // Returns the cell with the longest lookup key that is a prefix of `key`
+    // across the delegation chain starting at `table_id`, together with the
+    // table holding it. A delegation is followed into the delegated table and
+    // is only returned if nothing more specific is found there.
+    let table = table_maps.0.get(table_id)?;
+
+    if let Some((_, entry)) = table.value_entries.prefix_of(key) {
+        return Some((table_id, entry));
+    }
+
+    let (_, entry) = table.delegate_entries.prefix_of(key)?;
+    if let CellData::Delegate(inner_delegate_cell) = &entry.inner {
+        if let Some(new_table_id) = &inner_delegate_cell.table {
+            if let Some(found) = longest_prefix_match(table_maps, new_table_id, key) {
+                return Some(found);
+            }
+        }
+    }
+
+    Some((table_id, entry))
 }
 
 pub fn find_delegation_cell<'a>(
@@ -198,22 +587,17 @@ pub fn find_delegation_cell<'a>(
 ) -> Option<&'a Cell> {
     let table = table_maps.0.get(table_id)?;
 
-    for entry in &table.delegate_entries {
-        match &entry.inner {
-            super::cell::CellData::Value(_) => {}
-            super::cell::CellData::Delegate(inner_delegate_cell) => {
-                if entry.name_space_or_value() == key {
-                    return Some(entry);
-                }
+    if let Some(entry) = table.delegate_entries.get(key) {
+        return Some(entry);
+    }
 
-                if let Some(new_table_id) = &inner_delegate_cell.table {
-                    return find_delegation_cell(table_maps, new_table_id, key);
-                }
-            }
+    let (_, entry) = table.delegate_entries.strict_prefix_of(key)?;
+    match &entry.inner {
+        CellData::Delegate(inner_delegate_cell) => {
+            find_delegation_cell(table_maps, inner_delegate_cell.table.as_ref()?, key)
         }
+        CellData::Value(_) => None,
     }
-
-    None
 }
 
 pub fn find_value_cell<'a>(
@@ -223,36 +607,31 @@ pub fn find_value_cell<'a>(
 ) -> Option<&'a Cell> {
     let table = table_maps.0.get(table_id)?;
 
-    if let Some(val) = table
-        .value_entries
-        .iter()
-        .find(|e| e.name_space_or_value() == key)
-    {
+    if let Some(val) = table.value_entries.get(key) {
         return Some(val);
     }
 
-    if let Some(del_entry) = table
-        .delegate_entries
-        .iter()
-        .find(|e| key.starts_with(e.name_space_or_value()))
-    {
-        match &del_entry.inner {
-            super::cell::CellData::Value(_) => panic!("This should not happen"),
-            super::cell::CellData::Delegate(inner_delegate_cell) => {
-                if let Some(new_table_id) = &inner_delegate_cell.table {
-                    return find_value_cell(table_maps, new_table_id, key);
-                }
-            }
+    let (_, del_entry) = table.delegate_entries.strict_prefix_of(key)?;
+    match &del_entry.inner {
+        CellData::Delegate(inner_delegate_cell) => {
+            find_value_cell(table_maps, inner_delegate_cell.table.as_ref()?, key)
         }
+        CellData::Value(_) => None,
     }
-
-    None
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::OnceLock;
+
+    use proptest::prelude::*;
+
     use super::*;
-    use crate::ca::cell::{test_make_new_delegate_cell, test_make_new_value_cell};
+    use crate::ca::{
+        cell::{test_make_new_delegate_cell, test_make_new_value_cell, InnerValueCell},
+        crypto::{mock_generate_private_key, PrivateKey, SCPSignature, SignatureAlgorithm},
+        signing::mock_signing_context,
+    };
 
     #[test]
     fn prefix_delegation_rule() {
@@ -296,4 +675,204 @@ mod tests {
             .contains_enough_allowance(1)
             .is_err_and(|err| { err == TableOpError::NotEnoughAllowence(1, 1) }));
     }
+
+    #[test]
+    fn removed_cells_hold_allowance_until_collected() {
+        let authority = mock_generate_private_key();
+        let mut table = Table::new(1, "".to_string());
+        let cell = test_make_new_value_cell(String::from("home/cell"), 10);
+        assert!(table.add_entry(cell.clone()).is_ok());
+
+        let now = cell.revision_time + 1;
+        let mut removed = cell.to_removed(&authority.public_key(), now, now + 10);
+        removed.sign_removal(&authority, &mock_signing_context(), "home/cell");
+
+        // The commitment has not expired yet.
+        assert!(table
+            .remove_entry(
+                "home/cell",
+                removed.clone(),
+                &authority.public_key(),
+                10,
+                &mock_signing_context(),
+            )
+            .is_err_and(|err| {
+                err == TableOpError::CellOpError(CellOpError::CommitmentNotExpires)
+            }));
+
+        assert!(table
+            .remove_entry(
+                "home/cell",
+                removed,
+                &authority.public_key(),
+                now,
+                &mock_signing_context(),
+            )
+            .is_ok());
+        assert!(table.get_entry("home/cell").is_none());
+        assert!(table
+            .contains_enough_allowance(1)
+            .is_err_and(|err| { err == TableOpError::NotEnoughAllowence(1, 1) }));
+
+        assert_eq!(table.collect_garbage(now + 10), 0);
+        assert_eq!(table.collect_garbage(now + 11), 1);
+        assert!(table.contains_enough_allowance(1).is_ok());
+    }
+
+    #[test]
+    fn longest_prefix_across_delegations() {
+        let root_id = TableId::root();
+        let mut tables = TableCollection::new(0);
+        let mut delegation = test_make_new_delegate_cell(String::from("home/"), 10);
+        if let CellData::Delegate(inner) = &mut delegation.inner {
+            inner.table = Some(TableId(String::from("home/")));
+        }
+        let root = tables.0.get_mut(&root_id).unwrap();
+        assert!(root.add_entry(delegation).is_ok());
+
+        let mut home = Table::new(10, String::from("home/"));
+        assert!(home
+            .add_entry(test_make_new_value_cell(String::from("home/alice"), 0))
+            .is_ok());
+        tables.0.insert(TableId(String::from("home/")), home);
+
+        let (table_id, cell) = longest_prefix_match(&tables, &root_id, "home/alice/x").unwrap();
+        assert_eq!(table_id, &TableId(String::from("home/")));
+        assert_eq!(cell.name_space_or_value(), "home/alice");
+
+        let (table_id, cell) = longest_prefix_match(&tables, &root_id, "home/bob").unwrap();
+        assert_eq!(table_id, &root_id);
+        assert_eq!(cell.name_space_or_value(), "home/");
+
+        assert!(longest_prefix_match(&tables, &root_id, "work/").is_none());
+        assert!(find_value_cell(&tables, &root_id, &String::from("home/alice")).is_some());
+    }
+
+    fn test_signature_and_key() -> (SCPSignature, PublicKey) {
+        static SIGNATURE_AND_KEY: OnceLock<(SCPSignature, PublicKey)> = OnceLock::new();
+        // Ed25519 keys are much cheaper to decode than DSA keys.
+        SIGNATURE_AND_KEY
+            .get_or_init(|| {
+                let private_key = PrivateKey::generate(SignatureAlgorithm::Ed25519);
+                (
+                    SCPSignature::sign(&private_key, b"Ok"),
+                    private_key.public_key(),
+                )
+            })
+            .to_owned()
+    }
+
+    prop_compose! {
+        fn arb_cell()(
+            key in "[a-c]{1,3}(/[a-c]{1,2}){0,2}",
+            delegate in any::<bool>(),
+            allowance in 0u32..5,
+            create_time in 0u64..1000,
+            revision in 0u64..1000,
+            commitment_time in 0u64..1000,
+        ) -> Cell {
+            let inner = if delegate {
+                CellData::Delegate(InnerDelegateCell {
+                    table: Some(TableId(key.to_owned())),
+                    name_space: key,
+                    allowance,
+                })
+            } else {
+                CellData::Value(InnerValueCell { value: key })
+            };
+            // Tables do not check signatures, and signing every cell makes the
+            // cases slow.
+            let (sig, owner_key) = test_signature_and_key();
+            Cell {
+                create_time,
+                revision_time: create_time + revision,
+                commitment_time,
+                sig,
+                owner_key,
+                inner,
+            }
+        }
+    }
+
+    fn test_make_table(cells: Vec<Cell>) -> Table {
+        // Cells breaking the prefix rule are left out.
+        let mut table = Table::new(0, "".to_string());
+        for cell in cells {
+            let _ = table.add_entry(cell);
+        }
+        table
+    }
+
+    proptest! {
+        #[test]
+        fn table_encoding_round_trips(cells in prop::collection::vec(arb_cell(), 0..12)) {
+            let table = test_make_table(cells);
+            let bytes = table.to_canonical_bytes().unwrap();
+
+            let decoded = Table::from_canonical_bytes(&bytes).unwrap();
+            prop_assert_eq!(&decoded.to_canonical_bytes().unwrap(), &bytes);
+            prop_assert_eq!(decoded.merkle_tree.root(), table.merkle_tree.root());
+            for cell in table.value_entries.values().chain(table.delegate_entries.values()) {
+                prop_assert_eq!(decoded.get_entry(cell.name_space_or_value()), Some(cell));
+            }
+
+            let json = serde_json::to_string(&table).unwrap();
+            let decoded: Table = serde_json::from_str(&json).unwrap();
+            prop_assert_eq!(decoded.to_canonical_bytes().unwrap(), bytes);
+        }
+
+        #[test]
+        fn tampered_cells_are_rejected(
+            cells in prop::collection::vec(arb_cell(), 1..12),
+            revision in 1u64..10,
+        ) {
+            let table = test_make_table(cells);
+            let mut repr = TableRepr::from(&table);
+            let entries = if repr.value_entries.is_empty() {
+                &mut repr.delegate_entries
+            } else {
+                &mut repr.value_entries
+            };
+            entries[0].revision_time += revision;
+
+            prop_assert!(matches!(
+                Table::try_from(repr),
+                Err(TableOpError::InvalidEncoding(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn only_canonical_encodings_decode() {
+        let table = test_make_table(vec![
+            test_make_new_value_cell(String::from("alice"), 0),
+            test_make_new_value_cell(String::from("bob"), 0),
+        ]);
+
+        let mut repr = TableRepr::from(&table);
+        repr.value_entries.reverse();
+        assert!(repr
+            .validate()
+            .is_err_and(|err| matches!(err, TableOpError::InvalidEncoding(_))));
+
+        // Delegations refer to tables of the same collection.
+        let mut delegation = test_make_new_delegate_cell(String::from("home/"), 10);
+        if let CellData::Delegate(inner) = &mut delegation.inner {
+            inner.table = Some(TableId(String::from("home/")));
+        }
+        let mut tables = TableCollection::new(0);
+        let root = tables.0.get_mut(&TableId::root()).unwrap();
+        assert!(root.add_entry(delegation).is_ok());
+
+        let bytes = bincode::serialize(&tables).unwrap();
+        assert!(bincode::deserialize::<TableCollection>(&bytes).is_err());
+
+        tables.0.insert(
+            TableId(String::from("home/")),
+            Table::new(10, String::from("home/")),
+        );
+        let bytes = bincode::serialize(&tables).unwrap();
+        let decoded: TableCollection = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(bincode::serialize(&decoded).unwrap(), bytes);
+    }
 }
@@ -0,0 +1,339 @@
+use std::fmt::Display;
+
+use ct_merkle::{consistency::ConsistencyProof, inclusion::InclusionProof, CtMerkleTree, RootHash};
+use serde::{Deserialize, Serialize};
+use sha2::{digest, Sha256};
+
+use crate::scp::slot::SlotIndex;
+
+use super::{
+    ca_type::Timestamp,
+    crypto::{PrivateKey, PublicKey, SCPSignature},
+    merkle::MerkleHash,
+    operation::SCPCAOperation,
+    signing::{NetworkId, SigningContext, SigningDomain},
+    store::CAOperationLogEntry,
+};
+
+// Certificate Transparency style log (RFC 6962) of every value externalized by
+// a node. Honest nodes externalize the same values in the same order and so
+// hold identical logs. A node signs the head of its log, and auditors holding
+// two heads signed by the same node ask for a consistency proof between them
+// to check the node never rewrote its history, or compare heads signed by
+// different nodes to catch a node showing different views.
+
+pub type TransparencyOpResult<T> = std::result::Result<T, TransparencyOpError>;
+
+#[derive(PartialEq, Debug)]
+pub enum TransparencyOpError {
+    EmptyLog,
+    InvalidIndex,
+    // The tree sizes of a consistency proof are not increasing or exceed the
+    // size of the log.
+    InvalidTreeSize,
+    InvalidSignature,
+    MalformedProof,
+    VerificationFailure,
+}
+
+impl Display for TransparencyOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransparencyOpError::EmptyLog => write!(f, "transparency log is empty"),
+            TransparencyOpError::InvalidIndex => write!(f, "log index out of range"),
+            TransparencyOpError::InvalidTreeSize => write!(f, "invalid tree size"),
+            TransparencyOpError::InvalidSignature => write!(f, "invalid tree head signature"),
+            TransparencyOpError::MalformedProof => write!(f, "malformed proof"),
+            TransparencyOpError::VerificationFailure => write!(f, "proof does not verify"),
+        }
+    }
+}
+
+impl std::error::Error for TransparencyOpError {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct TreeHead {
+    pub tree_size: u64,
+    pub root_hash: MerkleHash,
+    // Close time of the last value in the log.
+    pub close_time: Timestamp,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SignedTreeHead {
+    pub tree_head: TreeHead,
+    pub signature: SCPSignature,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct LogInclusionProof {
+    pub index: u64,
+    pub tree_size: u64,
+    pub proof: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct LogConsistencyProof {
+    pub old_size: u64,
+    pub new_size: u64,
+    pub proof: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransparencyLog {
+    // Leaves are bincode encoded `CAOperationLogEntry`s.
+    tree: CtMerkleTree<Sha256, Vec<u8>>,
+    close_time: Timestamp,
+}
+
+impl TreeHead {
+    fn signing_bytes(&self, network_id: &NetworkId) -> Vec<u8> {
+        SigningContext::new(*network_id, "").payload(SigningDomain::TreeHead, "", self)
+    }
+
+    pub fn sign(&self, private_key: &PrivateKey, network_id: &NetworkId) -> SignedTreeHead {
+        SignedTreeHead {
+            tree_head: *self,
+            signature: SCPSignature::sign(private_key, &self.signing_bytes(network_id)),
+        }
+    }
+
+    fn root(&self) -> RootHash<Sha256> {
+        RootHash::new(
+            digest::Output::<Sha256>::clone_from_slice(&self.root_hash),
+            self.tree_size as usize,
+        )
+    }
+
+    pub fn verify_inclusion(
+        &self,
+        entry: &CAOperationLogEntry,
+        proof: &LogInclusionProof,
+    ) -> TransparencyOpResult<()> {
+        if proof.tree_size != self.tree_size || proof.index >= self.tree_size {
+            return Err(TransparencyOpError::InvalidIndex);
+        }
+        if !proof.proof.len().is_multiple_of(32) {
+            return Err(TransparencyOpError::MalformedProof);
+        }
+
+        let leaf = entry_bytes(entry)?;
+        self.root()
+            .verify_inclusion(
+                &leaf,
+                proof.index as usize,
+                &InclusionProof::from_bytes(proof.proof.to_owned()),
+            )
+            .map_err(|_| TransparencyOpError::VerificationFailure)
+    }
+
+    pub fn verify_consistency(
+        &self,
+        old: &TreeHead,
+        proof: &LogConsistencyProof,
+    ) -> TransparencyOpResult<()> {
+        // Checks that `old` is the head of a prefix of the log with head `self`.
+        if proof.old_size != old.tree_size || proof.new_size != self.tree_size {
+            return Err(TransparencyOpError::InvalidTreeSize);
+        }
+        if old.tree_size == 0 || old.tree_size > self.tree_size {
+            return Err(TransparencyOpError::InvalidTreeSize);
+        }
+        if !proof.proof.len().is_multiple_of(32) {
+            return Err(TransparencyOpError::MalformedProof);
+        }
+
+        self.root()
+            .verify_consistency(
+                &old.root(),
+                &ConsistencyProof::from_bytes(proof.proof.to_owned()),
+            )
+            .map_err(|_| TransparencyOpError::VerificationFailure)
+    }
+}
+
+impl SignedTreeHead {
+    pub fn verify(
+        &self,
+        public_key: &PublicKey,
+        network_id: &NetworkId,
+    ) -> TransparencyOpResult<()> {
+        if self
+            .signature
+            .verify(public_key, &self.tree_head.signing_bytes(network_id))
+        {
+            Ok(())
+        } else {
+            Err(TransparencyOpError::InvalidSignature)
+        }
+    }
+}
+
+fn entry_bytes(entry: &CAOperationLogEntry) -> TransparencyOpResult<Vec<u8>> {
+    bincode::serialize(entry).map_err(|_| TransparencyOpError::MalformedProof)
+}
+
+impl TransparencyLog {
+    pub fn len(&self) -> u64 {
+        self.tree.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn append(&mut self, slot_index: SlotIndex, operation: &SCPCAOperation) {
+        let entry = CAOperationLogEntry {
+            slot_index,
+            operation: operation.to_owned(),
+        };
+        // Values externalized by consensus always serialize.
+        if let Ok(leaf) = entry_bytes(&entry) {
+            self.tree.push(leaf);
+            self.close_time = self.close_time.max(operation.close_time);
+        }
+    }
+
+    pub fn entry(&self, index: u64) -> Option<CAOperationLogEntry> {
+        let leaf = self.tree.get(index as usize)?;
+        bincode::deserialize(leaf).ok()
+    }
+
+    pub fn tree_head(&self) -> TreeHead {
+        let mut root_hash = MerkleHash::default();
+        root_hash.copy_from_slice(self.tree.root().as_bytes());
+
+        TreeHead {
+            tree_size: self.len(),
+            root_hash,
+            close_time: self.close_time,
+        }
+    }
+
+    pub fn signed_tree_head(
+        &self,
+        private_key: &PrivateKey,
+        network_id: &NetworkId,
+    ) -> SignedTreeHead {
+        self.tree_head().sign(private_key, network_id)
+    }
+
+    pub fn prove_inclusion(&self, index: u64) -> TransparencyOpResult<LogInclusionProof> {
+        if index >= self.len() {
+            return Err(TransparencyOpError::InvalidIndex);
+        }
+
+        Ok(LogInclusionProof {
+            index,
+            tree_size: self.len(),
+            proof: self
+                .tree
+                .prove_inclusion(index as usize)
+                .as_bytes()
+                .to_vec(),
+        })
+    }
+
+    pub fn prove_consistency(&self, old_size: u64) -> TransparencyOpResult<LogConsistencyProof> {
+        if self.is_empty() {
+            return Err(TransparencyOpError::EmptyLog);
+        }
+        if old_size == 0 || old_size > self.len() {
+            return Err(TransparencyOpError::InvalidTreeSize);
+        }
+
+        Ok(LogConsistencyProof {
+            old_size,
+            new_size: self.len(),
+            proof: self
+                .tree
+                .prove_consistency(old_size as usize)
+                .as_bytes()
+                .to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ca::crypto::mock_generate_private_key;
+
+    use super::*;
+
+    fn make_log(size: u64) -> TransparencyLog {
+        let mut log = TransparencyLog::default();
+        for slot_index in 0..size {
+            log.append(slot_index, &SCPCAOperation::new(vec![], slot_index));
+        }
+        log
+    }
+
+    #[test]
+    fn signed_tree_head_and_inclusion() {
+        let private_key = mock_generate_private_key();
+        let network_id = NetworkId::default();
+        let log = make_log(5);
+
+        let sth = log.signed_tree_head(&private_key, &network_id);
+        assert_eq!(sth.tree_head.tree_size, 5);
+        assert!(sth.verify(&private_key.public_key(), &network_id).is_ok());
+        assert_eq!(
+            sth.verify(
+                &private_key.public_key(),
+                &NetworkId::from_passphrase("other network")
+            ),
+            Err(TransparencyOpError::InvalidSignature)
+        );
+
+        let proof = log.prove_inclusion(3).unwrap();
+        let entry = log.entry(3).unwrap();
+        assert!(sth.tree_head.verify_inclusion(&entry, &proof).is_ok());
+
+        let other_entry = log.entry(2).unwrap();
+        assert_eq!(
+            sth.tree_head.verify_inclusion(&other_entry, &proof),
+            Err(TransparencyOpError::VerificationFailure)
+        );
+        assert_eq!(
+            log.prove_inclusion(5),
+            Err(TransparencyOpError::InvalidIndex)
+        );
+    }
+
+    #[test]
+    fn consistency_between_sizes() {
+        let mut log = make_log(3);
+        let old_head = log.tree_head();
+        for slot_index in 3..7 {
+            log.append(slot_index, &SCPCAOperation::new(vec![], slot_index));
+        }
+        let new_head = log.tree_head();
+
+        let proof = log.prove_consistency(3).unwrap();
+        assert!(new_head.verify_consistency(&old_head, &proof).is_ok());
+
+        // A log that rewrote one of the first entries is not consistent with
+        // the old head.
+        let mut forked = TransparencyLog::default();
+        forked.append(0, &SCPCAOperation::new(vec![], 100));
+        for slot_index in 1..7 {
+            forked.append(slot_index, &SCPCAOperation::new(vec![], slot_index));
+        }
+        let forked_proof = forked.prove_consistency(3).unwrap();
+        assert_eq!(
+            forked
+                .tree_head()
+                .verify_consistency(&old_head, &forked_proof),
+            Err(TransparencyOpError::VerificationFailure)
+        );
+
+        assert_eq!(
+            log.prove_consistency(0),
+            Err(TransparencyOpError::InvalidTreeSize)
+        );
+        assert_eq!(
+            new_head.verify_consistency(&new_head, &log.prove_consistency(7).unwrap()),
+            Ok(())
+        );
+    }
+}
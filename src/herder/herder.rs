@@ -46,7 +46,20 @@ where
         ValidationLevel::FullyValidated
     }
 
-    fn externalize_value(&mut self, value: &N) {}
+    fn externalize_value(&mut self, _slot_index: SlotIndex, value: &N) {}
+
+    // The latest slot the application state already includes, e.g. after it was
+    // restored from a store. Earlier slots are not caught up on.
+    fn last_externalized_slot(&self) -> Option<SlotIndex> {
+        None
+    }
+
+    // The value to nominate for the slot, built from whatever is waiting to be
+    // externalized, like a transaction queue. Herders without one only follow
+    // the values of the leaders.
+    fn pending_value(&self, _slot_index: SlotIndex) -> Option<N> {
+        None
+    }
 
     fn combine_candidates(&self, candidates: &BTreeSet<Arc<N>>) -> Option<N>;
     fn emit_envelope(&self, envelope: &SCPEnvelope<N>) {}
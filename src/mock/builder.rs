@@ -10,7 +10,8 @@ use crate::{
         tcp_conn::{TCPConn, TCPConnBuilder},
     },
     scp::{
-        local_node::LocalNodeInfoBuilderFromFile, nomination_protocol::NominationValue, scp::NodeID,
+        local_node::{LocalNodeInfoBuilderFromFile, LocalNodeOpResult},
+        nomination_protocol::NominationValue, scp::NodeID,
     },
 };
 use crate::scp::builder::InMemoryNodeBuilder;
@@ -52,7 +53,7 @@ impl MockTCPNodeBuilder {
         }
     }
 
-    pub fn build_node(&mut self, node_idx: &str) -> Option<MockTCPPeerNode> {
+    pub fn build_node(&mut self, node_idx: &str) -> LocalNodeOpResult<MockTCPPeerNode> {
         let local_node_info = self.local_node_info_builder.build_from_file(node_idx)?;
 
         let conn_builder = TCPConnBuilder::new();
@@ -67,7 +68,7 @@ impl MockTCPNodeBuilder {
             work_scheduler,
         );
 
-        Some(peer)
+        Ok(peer)
     }
 }
 
@@ -82,12 +83,12 @@ mod tests {
         let mut builder = super::MockInMemoryNodeBuilder::new(NodeBuilderDir::Test.get_dir_path());
 
         let node1 = builder.build_node("node1");
-        assert!(node1.is_some());
+        assert!(node1.is_ok());
 
         let node2 = builder.build_node("node2");
-        assert!(node2.is_some());
+        assert!(node2.is_ok());
 
         let node3 = builder.build_node("node3");
-        assert!(node3.is_none());
+        assert!(node3.is_err());
     }
 }
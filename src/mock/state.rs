@@ -5,7 +5,7 @@ use std::{
     rc::Rc,
 };
 
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -37,6 +37,19 @@ impl MockState {
         // Generate a random sample containing a vector of size 3.
         Self(vec)
     }
+
+    // A value that only depends on the seed, for runs that need to be replayed.
+    pub fn from_seed(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut vec: Vec<[u8; 32]> = Default::default();
+        for _ in 0..3 {
+            let mut e = [0u8; 32];
+            rng.fill(&mut e[..]);
+            vec.push(e);
+        }
+
+        Self(vec)
+    }
 }
 
 impl Debug for MockState {
@@ -72,6 +85,8 @@ impl NominationValue for MockState {}
 pub struct MockStateDriver {
     // TODO:
     quorum_set_map: BTreeMap<HashValue, QuorumSet>,
+    // Values waiting to be externalized.
+    pub pending_values: Vec<MockState>,
 }
 
 pub struct MockStateDriverBuilder {}
@@ -94,14 +109,19 @@ impl Into<Rc<RefCell<MockStateDriver>>> for MockStateDriver {
     }
 }
 
-impl MockStateDriver {}
+impl MockStateDriver {
+    pub fn submit_value(&mut self, value: MockState) {
+        self.pending_values.push(value);
+    }
+}
 
 impl HerderDriver<MockState> for MockStateDriver {
     fn combine_candidates(
         &self,
         candidates: &std::collections::BTreeSet<std::sync::Arc<MockState>>,
     ) -> Option<MockState> {
-        let mut state = MockState::default();
+        // Every node has to combine the same candidates into the same value.
+        let mut state = MockState::empty();
 
         for candidate in candidates {
             for ele in &candidate.0 {
@@ -133,6 +153,20 @@ impl HerderDriver<MockState> for MockStateDriver {
 
     fn nominating_value(&self, value: &MockState, slot_index: &SlotIndex) {}
 
+    fn externalize_value(&mut self, _slot_index: SlotIndex, value: &MockState) {
+        self.pending_values
+            .retain(|pending| !pending.0.iter().all(|ele| value.0.contains(ele)));
+    }
+
+    fn pending_value(&self, _slot_index: SlotIndex) -> Option<MockState> {
+        // An empty value closes the slot even if nothing is pending.
+        let mut state = MockState::empty();
+        for pending in &self.pending_values {
+            state.0.extend(pending.0.iter());
+        }
+        Some(state)
+    }
+
     fn compute_timeout(&self, round_number: SlotIndex) -> std::time::Duration {
         const MAX_TIMEOUT_SECONDS: SlotIndex = 30 * 60;
 
@@ -146,6 +180,7 @@ impl HerderDriver<MockState> for MockStateDriver {
     fn new() -> Self {
         MockStateDriver {
             quorum_set_map: Default::default(),
+            pending_values: Default::default(),
         }
     }
 }
@@ -193,6 +228,7 @@ mod tests {
 
     use crate::{
         application::work_queue::WorkScheduler,
+        ca::crypto::{PrivateKey, SignatureAlgorithm},
         mock::state::{MockState, MockStateDriver},
     };
 
@@ -208,6 +244,7 @@ mod tests {
 
         let local_node = LocalNodeBuilder::<MockState>::new()
             .is_validator(true)
+            .private_key(PrivateKey::generate(SignatureAlgorithm::Ed25519))
             .quorum_set(quorum_set)
             .node_id(node_id)
             .build()
@@ -235,6 +272,7 @@ mod tests {
 
         let local_node = LocalNodeBuilder::<MockState>::new()
             .is_validator(true)
+            .private_key(PrivateKey::generate(SignatureAlgorithm::Ed25519))
             .quorum_set(quorum_set)
             .node_id(node_id.clone())
             .build()
@@ -280,6 +318,7 @@ mod tests {
 
         let local_node = LocalNodeBuilder::<MockState>::new()
             .is_validator(true)
+            .private_key(PrivateKey::generate(SignatureAlgorithm::Ed25519))
             .quorum_set(quorum_set)
             .node_id(node_id)
             .build()
@@ -423,7 +462,7 @@ mod tests {
         );
 
         for node in nodes.values() {
-            assert_eq!(node.leaders, vec!["node1".to_string()]);
+            assert_eq!(node.scp.leaders, vec!["node1".to_string()]);
         }
 
         assert!(nodes["node1"].get_current_nomination_state(&0).is_none());
@@ -434,7 +473,7 @@ mod tests {
         assert!(InMemoryGlobalState::process_messages(&builder.global_state, &mut nodes) > 0);
 
         for node in nodes.values() {
-            assert_eq!(node.leaders, vec!["node1".to_string()]);
+            assert_eq!(node.scp.leaders, vec!["node1".to_string()]);
         }
 
         let node1_nomnination_state: NominationProtocolState<MockState> =
@@ -446,8 +485,8 @@ mod tests {
             node2_nomnination_state.round_leaders
         );
 
-        assert_eq!(nodes["node1"].scp_envelope_controller.envs_to_emit.len(), 0);
-        assert_eq!(nodes["node2"].scp_envelope_controller.envs_to_emit.len(), 0);
+        assert_eq!(nodes["node1"].scp.envelope_controller.envs_to_emit.len(), 0);
+        assert_eq!(nodes["node2"].scp.envelope_controller.envs_to_emit.len(), 0);
 
         assert_eq!(node1_nomnination_state.nomination_started, false);
         assert_eq!(node2_nomnination_state.nomination_started, false);
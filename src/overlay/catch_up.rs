@@ -0,0 +1,277 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Display,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::scp::{
+    envelope::SCPEnvelope,
+    local_node::LocalNodeInfo,
+    nomination_protocol::NominationValue,
+    proof::{ExternalizationProof, ProofOpError},
+    scp::NodeID,
+    slot::SlotIndex,
+};
+
+use super::peer::PeerID;
+
+// A node that has no state for the next slot it should externalize, but hears
+// about a later one, is behind. It holds back the envelopes of later slots, asks
+// the sender for the proofs of the slots it missed and applies them in order.
+// Once it caught up the held back envelopes are processed as if they just
+// arrived. A peer that answers without moving the node forward, or does not
+// answer in time, may be asked again.
+
+// Envelopes held back beyond this drop the oldest ones.
+pub const MAX_HELD_ENVELOPES: usize = 1000;
+pub const CATCH_UP_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type CatchUpOpResult<T> = std::result::Result<T, CatchUpOpError>;
+
+#[derive(Debug, PartialEq)]
+pub enum CatchUpOpError {
+    InvalidProof {
+        slot_index: SlotIndex,
+        error: ProofOpError,
+    },
+}
+
+impl Display for CatchUpOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CatchUpOpError::InvalidProof { slot_index, error } => {
+                write!(f, "proof of slot {} is invalid: {}", slot_index, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CatchUpOpError {}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct CatchUpRequest {
+    pub node_id: NodeID,
+    pub from_slot: SlotIndex,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct CatchUpResponse<N>
+where
+    N: NominationValue,
+{
+    pub node_id: NodeID,
+    // Ordered by slot.
+    pub proofs: Vec<ExternalizationProof<N>>,
+}
+
+pub struct CatchUp<N>
+where
+    N: NominationValue,
+{
+    // Slots externalized live or applied from proofs.
+    pub applied: BTreeSet<SlotIndex>,
+    // Proofs applied from peers, kept to serve other lagging nodes.
+    pub proofs: BTreeMap<SlotIndex, ExternalizationProof<N>>,
+    // Envelopes held back while catching up, oldest first.
+    pub held: VecDeque<SCPEnvelope<N>>,
+    // Peers asked since we last made progress, and when.
+    pub asked: BTreeMap<PeerID, SystemTime>,
+}
+
+impl<N> Default for CatchUp<N>
+where
+    N: NominationValue,
+{
+    fn default() -> Self {
+        Self {
+            applied: Default::default(),
+            proofs: Default::default(),
+            held: Default::default(),
+            asked: Default::default(),
+        }
+    }
+}
+
+impl<N> CatchUp<N>
+where
+    N: NominationValue,
+{
+    // The first slot from `first_slot` on that was not externalized yet.
+    pub fn next_slot(&self, first_slot: SlotIndex) -> SlotIndex {
+        (first_slot..)
+            .find(|slot_index| !self.applied.contains(slot_index))
+            .unwrap()
+    }
+
+    pub fn is_catching_up(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    pub fn hold(&mut self, scp_env: SCPEnvelope<N>) {
+        if self.held.len() >= MAX_HELD_ENVELOPES {
+            self.held.pop_front();
+        }
+        self.held.push_back(scp_env);
+    }
+
+    // Whether to ask the peer to catch up: it was not asked yet, or did not
+    // answer in time. Records the request if so.
+    pub fn should_ask(&mut self, peer_id: &PeerID, now: SystemTime) -> bool {
+        if let Some(asked_at) = self.asked.get(peer_id) {
+            if now < *asked_at + CATCH_UP_TIMEOUT {
+                return false;
+            }
+        }
+        self.asked.insert(peer_id.clone(), now);
+        true
+    }
+
+    // The proof has to be signed by a quorum covering one of the local node's
+    // slices on its own, with the keys from the local node's configuration.
+    pub fn check_proof(
+        &self,
+        proof: &ExternalizationProof<N>,
+        local_node: &LocalNodeInfo<N>,
+    ) -> CatchUpOpResult<()> {
+        let slot_index = proof.slot_index;
+        proof
+            .verify(
+                &local_node.public_keys,
+                &local_node.network_id,
+                &local_node.quorum_set,
+            )
+            .map_err(|error| CatchUpOpError::InvalidProof { slot_index, error })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        mock::state::{MockState, MockStateDriver},
+        overlay::events::NodeEvent,
+        overlay_impl::simulation::{test_utils::*, SimulationConfig},
+        scp::statement::{SCPStatement, SCPStatementNominate},
+    };
+
+    use super::*;
+
+    #[test]
+    fn restarted_node_catches_up() {
+        let mut sim = mock_simulation(SimulationConfig::with_seed(13));
+        nominate_slots(&mut sim, 0..2);
+
+        let lagging = "node4".to_string();
+        sim.restart_node(&lagging).unwrap();
+        let events = sim.nodes.get_mut(&lagging).unwrap().subscribe();
+
+        nominate_slots(&mut sim, 2..3);
+
+        let externalized: Vec<(SlotIndex, MockState)> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                NodeEvent::Externalized {
+                    slot_index, value, ..
+                } => Some((slot_index, value)),
+                _ => None,
+            })
+            .collect();
+        let slot_indexes: Vec<SlotIndex> = externalized.iter().map(|(slot, _)| *slot).collect();
+        assert_eq!(slot_indexes, vec![0, 1, 2]);
+        for (slot_index, value) in externalized {
+            assert_eq!(
+                sim.externalized_value(&"node1".to_string(), &slot_index),
+                Some(value)
+            );
+        }
+        assert_eq!(sim.nodes[&lagging].next_slot(), 3);
+        assert!(!sim.nodes[&lagging].catch_up.is_catching_up());
+    }
+
+    #[test]
+    fn proofs_need_a_trusted_quorum() {
+        let mut sim = mock_simulation(SimulationConfig::with_seed(17));
+        nominate_slots(&mut sim, 0..1);
+
+        let proof = sim.nodes["node1"].externalization_proof(0).unwrap();
+        let local_node = sim.nodes["node4"].scp.local_node().as_ref().clone();
+        let catch_up = &sim.nodes["node4"].catch_up;
+        assert_eq!(catch_up.check_proof(&proof, &local_node), Ok(()));
+
+        let mut partial = proof.clone();
+        partial.envelopes.retain(|env| env.node_id != "node2");
+        assert!(matches!(
+            catch_up.check_proof(&partial, &local_node),
+            Err(CatchUpOpError::InvalidProof { slot_index: 0, .. })
+        ));
+
+        // Only keys from the node's configuration are trusted.
+        let mut untrusted = local_node.clone();
+        untrusted.public_keys.remove("node3");
+        assert!(matches!(
+            catch_up.check_proof(&proof, &untrusted),
+            Err(CatchUpOpError::InvalidProof { slot_index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn messages_survive_the_wire() {
+        let mut sim = mock_simulation(SimulationConfig::with_seed(19));
+        nominate_slots(&mut sim, 0..1);
+
+        let request = CatchUpRequest {
+            node_id: "node4".to_string(),
+            from_slot: 0,
+        };
+        let encoded = bincode::serialize(&request).unwrap();
+        let decoded: CatchUpRequest = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(decoded, request);
+
+        let response = CatchUpResponse {
+            node_id: "node1".to_string(),
+            proofs: vec![sim.nodes["node1"].externalization_proof(0).unwrap()],
+        };
+        let encoded = bincode::serialize(&response).unwrap();
+        let decoded: CatchUpResponse<MockState> = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(decoded, response);
+
+        let local_node = sim.nodes["node4"].scp.local_node();
+        let catch_up = &sim.nodes["node4"].catch_up;
+        assert_eq!(catch_up.check_proof(&decoded.proofs[0], local_node), Ok(()));
+    }
+
+    #[test]
+    fn held_envelopes_are_capped() {
+        let nomination = |slot_index| {
+            let statement = SCPStatement::Nominate(SCPStatementNominate {
+                node_id: "node2".to_string(),
+                quorum_set_hash: [0; 64],
+                votes: vec![],
+                accepted: vec![],
+                quorum_set: None,
+            });
+            SCPEnvelope::<MockState>::new(statement, "node2".to_string(), slot_index)
+        };
+        let mut catch_up = CatchUp::<MockState>::default();
+        for slot_index in 0..MAX_HELD_ENVELOPES as SlotIndex + 2 {
+            catch_up.hold(nomination(slot_index));
+        }
+        assert_eq!(catch_up.held.len(), MAX_HELD_ENVELOPES);
+        assert_eq!(catch_up.held.front().unwrap().slot_index, 2);
+    }
+
+    #[test]
+    fn unanswered_peers_are_asked_again() {
+        let mut catch_up = CatchUp::<MockState>::default();
+        let peer_id = "node2".to_string();
+        let start = SystemTime::UNIX_EPOCH;
+        assert!(catch_up.should_ask(&peer_id, start));
+        assert!(!catch_up.should_ask(&peer_id, start + CATCH_UP_TIMEOUT / 2));
+        assert!(catch_up.should_ask(&peer_id, start + CATCH_UP_TIMEOUT));
+
+        // A reply that does not move the node forward frees the peer.
+        catch_up.asked.remove(&peer_id);
+        assert!(catch_up.should_ask(&peer_id, start + CATCH_UP_TIMEOUT));
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    crypto::types::Blake2Hasher,
+    scp::{nomination_protocol::NominationValue, scp::NodeID, slot::SlotIndex},
+};
+
+// Closes slots one after the other: once slot `n` is externalized the node
+// nominates the values pending in its herder for `n + 1`, no earlier than the
+// target close interval after it started nominating `n`. The value of `n` seeds
+// the leader election of `n + 1`, so every node agrees on the leader without
+// coordination.
+
+#[derive(Clone, Debug)]
+pub struct CloseLoopConfig {
+    pub target_close_interval: Duration,
+    // The loop stops once this slot is closed.
+    pub last_slot: Option<SlotIndex>,
+}
+
+impl Default for CloseLoopConfig {
+    fn default() -> Self {
+        Self {
+            target_close_interval: Duration::from_secs(5),
+            last_slot: None,
+        }
+    }
+}
+
+pub struct CloseLoop<N>
+where
+    N: NominationValue,
+{
+    pub config: CloseLoopConfig,
+    // The latest closed slot and its value.
+    pub last_closed: Option<(SlotIndex, N)>,
+    pub close_times: BTreeMap<SlotIndex, SystemTime>,
+    // When the node started nominating the latest slot.
+    last_nomination: Option<SystemTime>,
+    // The slot to nominate next and when.
+    next_nomination: Option<(SlotIndex, SystemTime)>,
+}
+
+impl<N> CloseLoop<N>
+where
+    N: NominationValue,
+{
+    pub fn new(
+        config: CloseLoopConfig,
+        first_slot: SlotIndex,
+        last_closed: Option<(SlotIndex, N)>,
+        now: SystemTime,
+    ) -> Self {
+        Self {
+            config,
+            last_closed,
+            close_times: Default::default(),
+            last_nomination: None,
+            next_nomination: Some((first_slot, now)),
+        }
+    }
+
+    pub fn next_nomination_time(&self) -> Option<SystemTime> {
+        self.next_nomination.map(|(_, time)| time)
+    }
+
+    // The slot to nominate if its time has come.
+    pub fn due_slot(&self, now: SystemTime) -> Option<SlotIndex> {
+        self.next_nomination
+            .filter(|(_, time)| *time <= now)
+            .map(|(slot_index, _)| slot_index)
+    }
+
+    pub fn nominated(&mut self, now: SystemTime) {
+        self.last_nomination = Some(now);
+        self.next_nomination = None;
+    }
+
+    pub fn slot_closed(&mut self, slot_index: SlotIndex, value: &N, now: SystemTime) {
+        if self
+            .last_closed
+            .as_ref()
+            .is_some_and(|(last_slot, _)| *last_slot >= slot_index)
+        {
+            return;
+        }
+        self.last_closed = Some((slot_index, value.clone()));
+        self.close_times.insert(slot_index, now);
+
+        if self
+            .config
+            .last_slot
+            .is_some_and(|last_slot| slot_index >= last_slot)
+        {
+            self.next_nomination = None;
+            return;
+        }
+        let time = self.last_nomination.map_or(now, |last| {
+            now.max(last + self.config.target_close_interval)
+        });
+        self.next_nomination = Some((slot_index + 1, time));
+    }
+
+    // The value the slot builds on, if the slot before it is the last closed.
+    pub fn previous_value(&self, slot_index: SlotIndex) -> Option<&N> {
+        self.last_closed
+            .as_ref()
+            .filter(|(last_slot, _)| last_slot + 1 == slot_index)
+            .map(|(_, value)| value)
+    }
+
+    // Every node ranks the nodes by the hash of the slot, the previous value,
+    // the nomination round and the node id, and follows the highest. A new
+    // round brings in a new leader in case the previous ones are down.
+    pub fn round_leader(
+        &self,
+        slot_index: SlotIndex,
+        round: u64,
+        nodes: &BTreeSet<NodeID>,
+    ) -> Option<NodeID> {
+        let previous_value = self.previous_value(slot_index);
+        nodes
+            .iter()
+            .max_by_key(|node_id| {
+                Blake2Hasher::<(SlotIndex, Option<&N>, u64, &NodeID)>::hash(&(
+                    slot_index,
+                    previous_value,
+                    round,
+                    *node_id,
+                ))
+            })
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        application::quorum::{QuorumNode, QuorumSet, QuorumSlice},
+        mock::state::{MockState, MockStateDriver},
+        overlay::peer_node::PeerNode,
+        overlay_impl::{
+            byzantine::ByzantineBehaviour,
+            in_memory_peer::create_mock_state_in_memory_peer_builder,
+            simulation::{test_utils::*, SimulationConfig},
+        },
+        scp::local_node::LocalNodeInfo,
+    };
+
+    use super::*;
+
+    #[test]
+    fn nominations_keep_the_target_interval() {
+        let start = SystemTime::UNIX_EPOCH;
+        let config = CloseLoopConfig {
+            target_close_interval: Duration::from_secs(5),
+            last_slot: Some(2),
+        };
+        let mut close_loop = CloseLoop::<MockState>::new(config, 0, None, start);
+        assert_eq!(close_loop.due_slot(start), Some(0));
+        close_loop.nominated(start);
+        assert_eq!(close_loop.due_slot(start), None);
+
+        // A fast close waits for the interval.
+        close_loop.slot_closed(0, &MockState::from_seed(0), start + Duration::from_secs(1));
+        assert_eq!(
+            close_loop.next_nomination_time(),
+            Some(start + Duration::from_secs(5))
+        );
+        assert_eq!(close_loop.due_slot(start + Duration::from_secs(4)), None);
+        assert_eq!(close_loop.due_slot(start + Duration::from_secs(5)), Some(1));
+        close_loop.nominated(start + Duration::from_secs(5));
+
+        // A slow one is followed right away.
+        close_loop.slot_closed(1, &MockState::from_seed(1), start + Duration::from_secs(12));
+        assert_eq!(
+            close_loop.next_nomination_time(),
+            Some(start + Duration::from_secs(12))
+        );
+        assert!(close_loop.previous_value(2).is_some());
+        assert!(close_loop.previous_value(3).is_none());
+        close_loop.nominated(start + Duration::from_secs(12));
+
+        close_loop.slot_closed(2, &MockState::from_seed(2), start + Duration::from_secs(13));
+        assert_eq!(close_loop.next_nomination_time(), None);
+    }
+
+    #[test]
+    fn nodes_close_slots_sequentially() {
+        let mut sim = mock_simulation(SimulationConfig::with_seed(19));
+        for (i, node_id) in SIM_NODES.iter().enumerate() {
+            sim.nodes
+                .get_mut(*node_id)
+                .unwrap()
+                .scp
+                .herder
+                .submit_value(MockState::from_seed(i as u64));
+        }
+        let interval = Duration::from_secs(1);
+        let last_slot = 7;
+        sim.start_close_loop(CloseLoopConfig {
+            target_close_interval: interval,
+            last_slot: Some(last_slot),
+        });
+        sim.run();
+
+        sim.check_agreement().unwrap();
+        for node_id in SIM_NODES {
+            let node = &sim.nodes[node_id];
+            let close_loop = node.close_loop.as_ref().unwrap();
+            assert_eq!(
+                close_loop.close_times.keys().copied().collect::<Vec<_>>(),
+                (0..=last_slot).collect::<Vec<_>>()
+            );
+            assert_eq!(node.next_close_time(), None);
+        }
+        assert!(sim.now() >= interval * last_slot as u32);
+
+        let leaders: BTreeSet<NodeID> = (0..=last_slot)
+            .flat_map(|slot_index| {
+                sim.nodes["node1"].scp.nomination_protocol_states[&slot_index]
+                    .round_leaders
+                    .clone()
+            })
+            .collect();
+        assert!(leaders.len() > 1);
+        // Leaders got their pending values externalized.
+        for leader in leaders {
+            assert!(sim.nodes[&leader].scp.herder.pending_values.is_empty());
+        }
+    }
+
+    #[test]
+    fn crashed_leaders_are_replaced() {
+        // Slices hold three of the four nodes, so the others go on without the
+        // first leader of slot 0.
+        let nodes: BTreeSet<NodeID> = SIM_NODES
+            .iter()
+            .map(|node_id| node_id.to_string())
+            .collect();
+        let config = CloseLoopConfig {
+            target_close_interval: Duration::from_secs(1),
+            last_slot: Some(3),
+        };
+        let crashed = CloseLoop::<MockState>::new(config.clone(), 0, None, SystemTime::UNIX_EPOCH)
+            .round_leader(0, 1, &nodes)
+            .unwrap();
+
+        let mut sim =
+            MockSimulation::new("byzantine", &SIM_NODES, SimulationConfig::with_seed(3)).unwrap();
+        sim.make_byzantine(&crashed, ByzantineBehaviour::Crash)
+            .unwrap();
+        sim.start_close_loop(config);
+        sim.run();
+
+        sim.check_agreement().unwrap();
+        for node_id in nodes.iter().filter(|node_id| **node_id != crashed) {
+            let node = &sim.nodes[node_id];
+            assert_eq!(
+                node.close_loop.as_ref().unwrap().close_times.len(),
+                4,
+                "{}",
+                node_id
+            );
+            assert!(node.scp.nomination_protocol_states[&0].round_leaders.len() > 1);
+        }
+    }
+
+    #[test]
+    fn work_scheduler_drives_the_close_loop() {
+        // A node that is its own quorum closes slots without a simulation, as
+        // long as its scheduler's clock events run.
+        let node_info = LocalNodeInfo::new(
+            false,
+            QuorumSet::from([QuorumSlice::from([QuorumNode::new("node1".into(), None)])]),
+            "node1".to_string(),
+        );
+        let node = create_mock_state_in_memory_peer_builder().build_node(node_info);
+        let work_scheduler = node.borrow().work_scheduler();
+        let start = work_scheduler.borrow().now();
+        let interval = Duration::from_secs(5);
+
+        node.borrow_mut().add_leader(&"node1".to_string());
+        node.borrow_mut()
+            .scp
+            .herder
+            .submit_value(MockState::from_seed(0));
+        node.borrow_mut().start_close_loop(CloseLoopConfig {
+            target_close_interval: interval,
+            last_slot: Some(1),
+        });
+        PeerNode::schedule_close_loop(&node);
+
+        assert_eq!(work_scheduler.borrow().execute_clock_events(), 1);
+        assert_eq!(node.borrow().next_close_time(), Some(start + interval));
+
+        // Slot 1 waits for the target interval.
+        node.borrow_mut()
+            .scp
+            .herder
+            .submit_value(MockState::from_seed(1));
+        assert_eq!(work_scheduler.borrow().execute_clock_events(), 0);
+        work_scheduler.borrow().advance_clock(start + interval);
+        assert_eq!(work_scheduler.borrow().execute_clock_events(), 1);
+
+        let node = node.borrow();
+        let close_loop = node.close_loop.as_ref().unwrap();
+        assert_eq!(
+            close_loop.close_times.keys().copied().collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(node.next_close_time(), None);
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use crate::{
+    application::quorum_manager::QuorumManager,
+    scp::{
+        ballot_protocol::{BallotProtocolState, SCPBallot, SCPPhase},
+        envelope::EnvMap,
+        local_node::LocalNodeInfo,
+        nomination_protocol::{HSCPNominationValue, NominationProtocolState, NominationValue},
+        proof::ExternalizationProof,
+        slot::SlotIndex,
+    },
+};
+
+use super::trace::TimerKind;
+
+// Events are derived by comparing the slot state after every step with what
+// was seen before, so the protocol code does not need to know about them.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeEvent<N>
+where
+    N: NominationValue,
+{
+    NominationStarted {
+        slot_index: SlotIndex,
+    },
+    CandidateConfirmed {
+        slot_index: SlotIndex,
+        value: N,
+    },
+    BallotPrepared {
+        slot_index: SlotIndex,
+        ballot: SCPBallot<N>,
+    },
+    // The node accepted to commit the ballot.
+    BallotCommitted {
+        slot_index: SlotIndex,
+        ballot: SCPBallot<N>,
+    },
+    Externalized {
+        slot_index: SlotIndex,
+        value: N,
+        proof: ExternalizationProof<N>,
+    },
+    TimerFired {
+        slot_index: SlotIndex,
+        timer: TimerKind,
+    },
+}
+
+impl<N> NodeEvent<N>
+where
+    N: NominationValue,
+{
+    pub fn slot_index(&self) -> SlotIndex {
+        match self {
+            NodeEvent::NominationStarted { slot_index }
+            | NodeEvent::CandidateConfirmed { slot_index, .. }
+            | NodeEvent::BallotPrepared { slot_index, .. }
+            | NodeEvent::BallotCommitted { slot_index, .. }
+            | NodeEvent::Externalized { slot_index, .. }
+            | NodeEvent::TimerFired { slot_index, .. } => *slot_index,
+        }
+    }
+}
+
+struct SlotProgress<N>
+where
+    N: NominationValue,
+{
+    nomination_started: bool,
+    candidates: BTreeSet<HSCPNominationValue<N>>,
+    prepared: Option<SCPBallot<N>>,
+    phase: SCPPhase,
+}
+
+impl<N> Default for SlotProgress<N>
+where
+    N: NominationValue,
+{
+    fn default() -> Self {
+        Self {
+            nomination_started: false,
+            candidates: Default::default(),
+            prepared: None,
+            phase: SCPPhase::PhasePrepare,
+        }
+    }
+}
+
+pub struct EventPublisher<N>
+where
+    N: NominationValue,
+{
+    subscribers: Vec<Sender<NodeEvent<N>>>,
+    progress: BTreeMap<SlotIndex, SlotProgress<N>>,
+    // Slots whose progress was dropped once they externalized.
+    externalized: BTreeSet<SlotIndex>,
+}
+
+impl<N> Default for EventPublisher<N>
+where
+    N: NominationValue,
+{
+    fn default() -> Self {
+        Self {
+            subscribers: Default::default(),
+            progress: Default::default(),
+            externalized: Default::default(),
+        }
+    }
+}
+
+impl<N> EventPublisher<N>
+where
+    N: NominationValue,
+{
+    pub fn subscribe(&mut self) -> Receiver<NodeEvent<N>> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+
+    // Subscribers that dropped their receiver are forgotten.
+    pub fn publish(&mut self, event: NodeEvent<N>) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    // Publishes whatever changed in the slot since it was last observed.
+    // Progress is tracked even without subscribers, so a late subscriber does
+    // not get replayed what happened before it subscribed.
+    pub fn observe_slot(
+        &mut self,
+        slot_index: SlotIndex,
+        local_node: &LocalNodeInfo<N>,
+        nomination_state: &NominationProtocolState<N>,
+        ballot_state: &BallotProtocolState<N>,
+        env_map: &EnvMap<N>,
+        quorum_manager: &QuorumManager,
+    ) {
+        if self.externalized.contains(&slot_index) {
+            return;
+        }
+
+        let mut events = vec![];
+        let progress = self.progress.entry(slot_index).or_default();
+
+        if nomination_state.nomination_started && !progress.nomination_started {
+            progress.nomination_started = true;
+            events.push(NodeEvent::NominationStarted { slot_index });
+        }
+
+        for candidate in nomination_state.candidates.iter() {
+            if progress.candidates.insert(candidate.clone()) {
+                events.push(NodeEvent::CandidateConfirmed {
+                    slot_index,
+                    value: candidate.as_ref().clone(),
+                });
+            }
+        }
+
+        if ballot_state.prepared.is_some() && ballot_state.prepared != progress.prepared {
+            progress.prepared = ballot_state.prepared.clone();
+            events.push(NodeEvent::BallotPrepared {
+                slot_index,
+                ballot: ballot_state.prepared.clone().unwrap(),
+            });
+        }
+
+        if ballot_state.phase != progress.phase {
+            let commit = ballot_state
+                .commit
+                .clone()
+                .expect("Commit is set past the prepare phase");
+            if progress.phase == SCPPhase::PhasePrepare {
+                events.push(NodeEvent::BallotCommitted {
+                    slot_index,
+                    ballot: commit.clone(),
+                });
+            }
+            if let Some(proof) = ExternalizationProof::from_slot(
+                slot_index,
+                local_node,
+                ballot_state,
+                env_map,
+                quorum_manager,
+            ) {
+                events.push(NodeEvent::Externalized {
+                    slot_index,
+                    value: commit.value,
+                    proof,
+                });
+            }
+            progress.phase = ballot_state.phase.clone();
+        }
+
+        if progress.phase == SCPPhase::PhaseExternalize {
+            self.progress.remove(&slot_index);
+            self.externalized.insert(slot_index);
+        }
+
+        for event in events {
+            self.publish(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        mock::state::MockState,
+        overlay_impl::simulation::{test_utils::*, SimulationConfig},
+    };
+
+    use super::*;
+
+    #[test]
+    fn subscribers_see_slots_progress() {
+        let mut sim = mock_simulation(SimulationConfig::with_seed(7));
+        let events = sim.nodes.get_mut("node1").unwrap().subscribe();
+        let dropped = sim.nodes.get_mut("node2").unwrap().subscribe();
+        drop(dropped);
+
+        nominate_slots(&mut sim, 0..2);
+        let events: Vec<_> = events.try_iter().collect();
+        assert!(!sim.nodes["node2"].events.has_subscribers());
+
+        for slot_index in 0..2 {
+            let slot_events: Vec<_> = events
+                .iter()
+                .filter(|event| event.slot_index() == slot_index)
+                .filter(|event| !matches!(event, NodeEvent::TimerFired { .. }))
+                .collect();
+            assert!(matches!(
+                slot_events.first(),
+                Some(NodeEvent::NominationStarted { .. })
+            ));
+            assert!(matches!(
+                slot_events.last(),
+                Some(NodeEvent::Externalized { .. })
+            ));
+            let has = |matcher: fn(&NodeEvent<MockState>) -> bool| {
+                slot_events.iter().any(|event| matcher(event))
+            };
+            assert!(has(|event| matches!(
+                event,
+                NodeEvent::CandidateConfirmed { .. }
+            )));
+            assert!(has(|event| matches!(
+                event,
+                NodeEvent::BallotPrepared { .. }
+            )));
+            assert!(has(|event| matches!(
+                event,
+                NodeEvent::BallotCommitted { .. }
+            )));
+        }
+
+        let (value, proof) = events
+            .iter()
+            .find_map(|event| match event {
+                NodeEvent::Externalized {
+                    slot_index: 1,
+                    value,
+                    proof,
+                } => Some((value, proof)),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            Some(value.clone()),
+            sim.externalized_value(&"node1".to_string(), &1)
+        );
+        assert_eq!(&proof.value, value);
+        assert!(proof.nodes().contains(&"node1".to_string()));
+    }
+
+    #[test]
+    fn late_subscribers_only_see_new_progress() {
+        let mut sim = mock_simulation(SimulationConfig::with_seed(7));
+        nominate_slots(&mut sim, 0..1);
+        let events = sim.nodes.get_mut("node1").unwrap().subscribe();
+        nominate_slots(&mut sim, 1..2);
+
+        let events: Vec<_> = events.try_iter().collect();
+        assert!(events.iter().all(|event| event.slot_index() == 1));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, NodeEvent::Externalized { .. })));
+
+        let publisher = &sim.nodes["node1"].events;
+        assert!(publisher.progress.is_empty());
+        assert_eq!(publisher.externalized, BTreeSet::from([0, 1]));
+    }
+}
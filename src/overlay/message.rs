@@ -7,7 +7,10 @@ use crate::{
     scp::{envelope::SCPEnvelope, nomination_protocol::NominationValue},
 };
 
-use super::peer::PeerID;
+use super::{
+    catch_up::{CatchUpRequest, CatchUpResponse},
+    peer::PeerID,
+};
 
 #[derive(Clone, Serialize, Debug)]
 pub enum SCPMessage<N>
@@ -16,6 +19,8 @@ where
 {
     SCP(SCPEnvelope<N>),
     Hello(HelloEnvelope),
+    CatchUpRequest(CatchUpRequest),
+    CatchUpResponse(CatchUpResponse<N>),
 }
 
 impl<N> Blake2Hashable for SCPMessage<N> where N: NominationValue {}
@@ -27,6 +32,15 @@ where
     pub fn is_boardcast_msg(&self) -> bool {
         true
     }
+
+    pub fn sender(&self) -> &PeerID {
+        match self {
+            SCPMessage::SCP(env) => &env.node_id,
+            SCPMessage::Hello(hello) => &hello.id,
+            SCPMessage::CatchUpRequest(request) => &request.node_id,
+            SCPMessage::CatchUpResponse(response) => &response.node_id,
+        }
+    }
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -1,4 +1,7 @@
+pub mod catch_up;
+pub mod close_loop;
 pub mod conn;
+pub mod events;
 pub mod peer_node;
 pub mod loopback_peer;
 pub mod message;
@@ -8,4 +11,5 @@ pub mod overlay_manager;
 pub mod peer;
 pub mod peer_manager;
 pub mod rpc_gateway;
+pub mod trace;
 mod rpc_gateway_test;
@@ -1,9 +1,8 @@
-use std::{cell::RefCell, collections::HashMap, marker::PhantomData, rc::Rc};
+use std::{cell::RefCell, marker::PhantomData, rc::Rc};
 
 use crate::{
     herder::herder::HerderDriver,
-    mock::state::{MockState, MockStateDriver},
-    scp::{nomination_protocol::NominationValue, scp::NodeID},
+    scp::nomination_protocol::NominationValue,
 };
 
 use super::overlay_manager::OverlayManager;
@@ -19,8 +18,4 @@ where
     phantom: PhantomData<N>,
 }
 
-pub struct Simulation {
-    pub nodes: HashMap<NodeID, LocalNode<MockState, MockStateDriver>>,
-}
-
 
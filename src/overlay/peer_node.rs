@@ -1,10 +1,12 @@
 use std::{
     cell::RefCell,
-    collections::{BTreeMap, BTreeSet, VecDeque},
+    collections::{BTreeMap, BTreeSet},
     fmt::Debug,
-    rc::Rc,
-    slice::IterMut,
-    sync::Arc,
+    io,
+    path::Path,
+    rc::{Rc, Weak},
+    sync::{mpsc::Receiver, Arc},
+    time::SystemTime,
 };
 
 use bincode::de;
@@ -13,26 +15,32 @@ use pkcs8::der::DerOrd;
 use tracing::field::debug;
 
 use crate::{
-    application::{quorum_manager::QuorumManager, work_queue::WorkScheduler},
+    application::work_queue::{ClockEvent, HClockEvent, WorkScheduler},
     herder::herder::HerderDriver,
     scp::{
-        self,
-        ballot_protocol::BallotProtocolState,
-        envelope::{SCPEnvelope, SCPEnvelopeController, SCPEnvelopeID},
+        ballot_protocol::SCPPhase,
+        envelope::SCPEnvelope,
         local_node::LocalNodeInfo,
-        nomination_protocol::{NominationProtocol, NominationProtocolState, NominationValue},
-        scp::NodeID,
-        scp_driver::SlotDriver,
-        scp_driver_builder::SlotDriverBuilder,
+        nomination_protocol::{NominationProtocolState, NominationValue},
+        proof::ExternalizationProof,
+        queue::SlotTask,
+        scp::{NodeID, SCP},
+        scp_driver::SlotStateTimer,
+        scp_impl::SCPNode,
         slot::SlotIndex,
+        statement::SCPStatement,
     },
 };
 
 use super::{
+    catch_up::{CatchUp, CatchUpOpResult, CatchUpRequest, CatchUpResponse},
+    close_loop::{CloseLoop, CloseLoopConfig},
     conn::{PeerConn, PeerConnBuilder},
+    events::{EventPublisher, NodeEvent},
     message::{HelloEnvelope, MessageController, SCPMessage},
     node,
     peer::PeerID,
+    trace::{TimerKind, TraceEvent, TraceRecorder},
 };
 
 pub struct PeerNode<N, H, C, CB>
@@ -45,19 +53,18 @@ where
     pub peer_idx: PeerID,
     pub message_controller: Rc<RefCell<MessageController<N>>>,
     pub peer_conns: BTreeMap<PeerID, C>,
-    pub slots: BTreeMap<SlotIndex, SlotDriver<N, H>>,
-    pub nomination_protocol_states: BTreeMap<SlotIndex, NominationProtocolState<N>>,
-    pub ballot_protocol_states: BTreeMap<SlotIndex, BallotProtocolState<N>>,
-
     conn_builder: CB,
-    pub scp_envelope_controller: SCPEnvelopeController<N>,
-    pub herder: H,
-
-    work_scheduler: Rc<RefCell<WorkScheduler>>,
-    local_node_info: Arc<LocalNodeInfo<N>>,
-
-    pub leaders: VecDeque<NodeID>,
-    pub quorum_manager: QuorumManager,
+    // Consensus itself; the node adds networking, catch up and closing slots.
+    pub scp: SCPNode<N, H>,
+
+    // Records consensus events when tracing is enabled.
+    pub trace_recorder: Option<TraceRecorder<N>>,
+    pub events: EventPublisher<N>,
+    pub catch_up: CatchUp<N>,
+    pub close_loop: Option<CloseLoop<N>>,
+    // Set when the close loop runs on the work scheduler.
+    close_loop_handle: Option<Weak<RefCell<Self>>>,
+    close_loop_event: Option<HClockEvent>,
 }
 
 impl<N, H, C, CB> Debug for PeerNode<N, H, C, CB>
@@ -78,8 +85,8 @@ impl<N, H, C, CB> PeerNode<N, H, C, CB>
 where
     N: NominationValue,
     H: HerderDriver<N> + 'static,
-    C: PeerConn<N> + Debug,
-    CB: PeerConnBuilder<N, C>,
+    C: PeerConn<N> + Debug + 'static,
+    CB: PeerConnBuilder<N, C> + 'static,
 {
     pub fn new(
         peer_idx: PeerID,
@@ -98,30 +105,204 @@ where
         Self {
             peer_idx,
             message_controller: MessageController::new_handle(),
-            herder,
             conn_builder,
             peer_conns: conns,
-            scp_envelope_controller: SCPEnvelopeController::new(),
-            slots: Default::default(),
-            work_scheduler,
-            local_node_info: Arc::new(local_node_info),
-            nomination_protocol_states: Default::default(),
-            ballot_protocol_states: Default::default(),
-            leaders: Default::default(),
-            quorum_manager: Default::default(),
+            scp: SCPNode::new(local_node_info, herder, work_scheduler),
+            trace_recorder: None,
+            events: Default::default(),
+            catch_up: Default::default(),
+            close_loop: None,
+            close_loop_handle: None,
+            close_loop_event: None,
+        }
+    }
+
+    pub fn work_scheduler(&self) -> Rc<RefCell<WorkScheduler>> {
+        self.scp.work_scheduler().clone()
+    }
+
+    // Starts recording a trace of the node, also written to `path` if given.
+    pub fn record_trace(&mut self, path: Option<&Path>) -> io::Result<()> {
+        let mut recorder = TraceRecorder::new(path)?;
+        recorder.record(TraceEvent::Start {
+            node_id: self.peer_idx.clone(),
+            leaders: self.scp.leaders.iter().cloned().collect(),
+        });
+        self.trace_recorder = Some(recorder);
+        Ok(())
+    }
+
+    // Returns a channel receiving the node's consensus events from now on.
+    pub fn subscribe(&mut self) -> Receiver<NodeEvent<N>> {
+        self.events.subscribe()
+    }
+
+    fn publish_slot_events(&mut self, slot_idx: SlotIndex) {
+        if let (Some(nomination_state), Some(ballot_state)) = (
+            self.scp.nomination_protocol_states.get(&slot_idx),
+            self.scp.ballot_protocol_states.get(&slot_idx),
+        ) {
+            self.events.observe_slot(
+                slot_idx,
+                self.scp.local_node(),
+                nomination_state,
+                ballot_state,
+                &self.scp.envelope_controller.envelopes,
+                &self.scp.quorum_manager,
+            );
+        }
+    }
+
+    // The next slot the node has to externalize, either live or from a proof.
+    pub fn next_slot(&self) -> SlotIndex {
+        let first_slot = self
+            .scp
+            .herder
+            .last_externalized_slot()
+            .map_or(0, |slot_idx| slot_idx + 1);
+        self.catch_up.next_slot(first_slot)
+    }
+
+    // The value the slot closed with, live or from a proof.
+    fn closed_value(&self, slot_idx: SlotIndex) -> Option<N> {
+        if let Some(proof) = self.catch_up.proofs.get(&slot_idx) {
+            return Some(proof.value.clone());
+        }
+        self.scp.externalized_value(slot_idx).cloned()
+    }
+
+    fn record_externalized(&mut self, slot_idx: SlotIndex) {
+        if self.catch_up.applied.contains(&slot_idx) {
+            return;
+        }
+        if let Some(value) = self.closed_value(slot_idx) {
+            self.catch_up.applied.insert(slot_idx);
+            self.on_slot_closed(slot_idx, &value);
+            self.release_held();
+        }
+    }
+
+    fn on_slot_closed(&mut self, slot_idx: SlotIndex, value: &N) {
+        let now = self.scp.work_scheduler().borrow().now();
+        if let Some(close_loop) = self.close_loop.as_mut() {
+            close_loop.slot_closed(slot_idx, value, now);
+        }
+        self.post_close_loop_event();
+    }
+
+    // Processes what was held back now that the slots before it are known.
+    fn release_held(&mut self) {
+        self.catch_up.asked.clear();
+        for scp_env in std::mem::take(&mut self.catch_up.held) {
+            self.on_scp_env(scp_env);
+        }
+    }
+
+    // Starts closing slots from the next one the node has to externalize.
+    pub fn start_close_loop(&mut self, config: CloseLoopConfig) {
+        let first_slot = self.next_slot();
+        let last_closed = first_slot
+            .checked_sub(1)
+            .and_then(|slot_idx| self.closed_value(slot_idx).map(|value| (slot_idx, value)));
+        let now = self.scp.work_scheduler().borrow().now();
+        self.close_loop = Some(CloseLoop::new(config, first_slot, last_closed, now));
+        self.post_close_loop_event();
+    }
+
+    // Polls the close loop from a clock event on the node's work scheduler
+    // whenever the next slot is due, so a node on a real clock closes slots as
+    // long as its application loop runs the scheduler's clock events.
+    pub fn schedule_close_loop(node: &Rc<RefCell<Self>>) {
+        let mut peer = node.borrow_mut();
+        peer.close_loop_handle = Some(Rc::downgrade(node));
+        peer.post_close_loop_event();
+    }
+
+    fn post_close_loop_event(&mut self) {
+        if let Some(event) = self.close_loop_event.take() {
+            event.replace(None);
+        }
+        let (Some(handle), Some(timestamp)) =
+            (self.close_loop_handle.clone(), self.next_close_time())
+        else {
+            return;
+        };
+
+        let callback = move || {
+            if let Some(node) = handle.upgrade() {
+                node.borrow_mut().poll_close_loop();
+            }
+        };
+        let event = ClockEvent::new(timestamp, Box::new(callback)).to_handle();
+        self.scp
+            .work_scheduler()
+            .borrow()
+            .post_clock_event(&timestamp, event.clone());
+        self.close_loop_event = Some(event);
+    }
+
+    pub fn next_close_time(&self) -> Option<SystemTime> {
+        self.close_loop.as_ref()?.next_nomination_time()
+    }
+
+    // Nominates the pending value for the next slot of the close loop once it
+    // is due. Returns the slot.
+    pub fn poll_close_loop(&mut self) -> Option<SlotIndex> {
+        let now = self.scp.work_scheduler().borrow().now();
+        let close_loop = self.close_loop.as_mut()?;
+        let slot_idx = close_loop.due_slot(now)?;
+        close_loop.nominated(now);
+        let previous_value = close_loop
+            .previous_value(slot_idx)
+            .cloned()
+            .unwrap_or_default();
+
+        if let Some(value) = self.scp.herder.pending_value(slot_idx) {
+            self.slot_nominate_with_previous(slot_idx, value, &previous_value);
+        }
+        Some(slot_idx)
+    }
+
+    pub fn externalization_proof(&self, slot_idx: SlotIndex) -> Option<ExternalizationProof<N>> {
+        self.scp.externalization_proof(slot_idx)
+    }
+
+    fn trace(&mut self, event: TraceEvent<N>) {
+        if let Some(recorder) = self.trace_recorder.as_mut() {
+            recorder.record(event);
+        }
+    }
+
+    fn trace_externalized(&mut self, slot_idx: SlotIndex) {
+        let Some(recorder) = self.trace_recorder.as_mut() else {
+            return;
+        };
+        if recorder.has_externalized(&slot_idx) {
+            return;
+        }
+        if let Some(state) = self.scp.ballot_protocol_states.get(&slot_idx) {
+            if state.phase == SCPPhase::PhaseExternalize {
+                if let Some(commit) = state.commit.as_ref() {
+                    recorder.record(TraceEvent::Externalized {
+                        slot_index: slot_idx,
+                        value: commit.value.clone(),
+                    });
+                }
+            }
         }
     }
 
     pub fn add_leader(&mut self, node_idx: &NodeID) {
         println!("Node {:?} add leader {:?}", self.peer_idx, node_idx);
-        self.leaders.push_front(node_idx.clone());
+        self.scp.add_leader(node_idx);
     }
 
     pub fn get_current_nomination_state(
         &self,
         slot_idx: &SlotIndex,
     ) -> Option<NominationProtocolState<N>> {
-        self.nomination_protocol_states
+        self.scp
+            .nomination_protocol_states
             .get(slot_idx)
             .and_then(|val| Some(val.clone()))
     }
@@ -130,8 +311,8 @@ where
         let mut peer_conn = self.peer_conns.get_mut(peer_id);
         if peer_conn.is_none() {
             peer_conn = match msg {
-                SCPMessage::SCP(_) => None,
                 SCPMessage::Hello(_) => Some(self.add_connection(peer_id)),
+                _ => None,
             };
         }
 
@@ -143,24 +324,29 @@ where
     fn flush_all_broadcast_msg(&mut self) {
         debug!("flush_all_broadcast_msg: node {:?}", self.peer_idx);
 
-        // Enforce here that we do not send redundant messages.
-        let mut envs_sent: BTreeSet<SCPEnvelopeID> = BTreeSet::new();
-
-        while let Some(env_id) = self.scp_envelope_controller.pop_next_env_to_emit() {
-            if envs_sent.contains(&env_id) {
-                continue;
-            }
-
-            let scp_env = self
-                .scp_envelope_controller
-                .get_envelope(&env_id)
-                .unwrap()
-                .clone();
+        let envelopes = self.scp.take_envelopes_to_emit();
+        let envs_sent = envelopes.len();
+        let mut slots_emitted: BTreeSet<SlotIndex> = BTreeSet::new();
 
+        for scp_env in envelopes {
+            slots_emitted.insert(scp_env.slot_index);
+            self.trace(TraceEvent::Emitted(scp_env.clone()));
             let scp_msg = SCPMessage::SCP(scp_env);
 
             self.send_broadcast_message(&scp_msg);
-            envs_sent.insert(env_id);
+        }
+
+        // Statements are sent again until the slot externalizes, in case they
+        // got lost.
+        for slot_idx in slots_emitted {
+            let Some(slot) = self.scp.slots.get(&slot_idx) else {
+                continue;
+            };
+            if self.closed_value(slot_idx).is_some() {
+                slot.stop_timer(SlotStateTimer::Rebroadcast);
+            } else {
+                slot.start_rebroadcast_timer();
+            }
         }
 
         debug!(
@@ -170,7 +356,7 @@ where
     }
 
     fn send_broadcast_message(&mut self, msg: &SCPMessage<N>) {
-        for peer in self.local_node_info.quorum_set.nodes().iter() {
+        for peer in self.scp.local_node().quorum_set.nodes().iter() {
             if peer.node_id == self.peer_idx {
                 continue;
             }
@@ -184,25 +370,31 @@ where
     }
 
     pub fn slot_nominate(&mut self, slot_idx: SlotIndex, value: N) {
+        self.slot_nominate_with_previous(slot_idx, value, &Default::default());
+    }
+
+    pub fn slot_nominate_with_previous(
+        &mut self,
+        slot_idx: SlotIndex,
+        value: N,
+        previous_value: &N,
+    ) {
         log::debug!(
             "slot_nominate: node {:?} slot_idx {:?} value {:?}",
             self.peer_idx,
             slot_idx,
             value
         );
+        self.trace(TraceEvent::Nominate {
+            slot_index: slot_idx,
+            value: value.clone(),
+        });
         self.maybe_create_slot_and_state(slot_idx);
-
-        self.slots.get(&slot_idx).unwrap().nominate(
-            self.nomination_protocol_states.get_mut(&slot_idx).unwrap(),
-            self.ballot_protocol_states.get_mut(&slot_idx).unwrap(),
-            Arc::new(value),
-            &Default::default(),
-            &mut self.scp_envelope_controller,
-            &mut self.quorum_manager,
-            &mut self.herder,
-        );
+        self.scp.nominate(slot_idx, Arc::new(value), previous_value);
 
         self.flush_all_broadcast_msg();
+        self.publish_slot_events(slot_idx);
+        self.record_externalized(slot_idx);
     }
 
     pub fn slot_nominate_with_default_val(&mut self, slot_idx: SlotIndex) {
@@ -246,6 +438,8 @@ where
                     SCPMessage::Hello(hello_env) => {
                         self.on_hello_env(hello_env);
                     }
+                    SCPMessage::CatchUpRequest(request) => self.on_catch_up_request(request),
+                    SCPMessage::CatchUpResponse(response) => self.on_catch_up_response(response),
                 }
                 true
             }
@@ -271,43 +465,36 @@ where
         }
     }
 
-    fn build_slot(&self, slot_idx: SlotIndex) -> SlotDriver<N, H> {
-        // TODO: what is multiple leaders?
-        let leader = self.leaders.front().unwrap().to_owned();
-
-        SlotDriverBuilder::<N, H>::new()
-            .slot_index(slot_idx)
-            .timer(self.work_scheduler.clone())
-            .local_node(self.local_node_info.clone())
-            .nomination_protocol_state(NominationProtocolState::new(leader))
-            .build()
-            .unwrap()
+    // The nodes of the local quorum set and the node itself.
+    fn quorum_nodes(&self) -> BTreeSet<NodeID> {
+        let mut nodes: BTreeSet<NodeID> = self
+            .scp
+            .local_node()
+            .quorum_set
+            .nodes()
+            .into_iter()
+            .map(|node| node.node_id)
+            .collect();
+        nodes.insert(self.peer_idx.clone());
+        nodes
     }
 
     fn maybe_create_slot_and_state(&mut self, slot_idx: SlotIndex) {
-        let insert = !self.slots.contains_key(&slot_idx);
-
-        if insert {
-            info!(
-                "Node {:?} creates slot {:?}, leader: {:?}",
-                self.local_node_info.node_id, slot_idx, self.leaders
-            );
-            let val = self.build_slot(slot_idx);
-
-            self.slots.insert(slot_idx.clone(), val);
-            info!(
-                "bk1 Node {:?} creates slot {:?}, leader: {:?}",
-                self.local_node_info.node_id, slot_idx, self.leaders
-            );
-
-            self.nomination_protocol_states.insert(
-                slot_idx.clone(),
-                NominationProtocolState::new(self.leaders.front().unwrap().to_owned()),
-            );
-
-            self.ballot_protocol_states
-                .insert(slot_idx.clone(), Default::default());
+        if self.scp.slots.contains_key(&slot_idx) {
+            return;
         }
+        info!(
+            "Node {:?} creates slot {:?}, leader: {:?}",
+            self.peer_idx, slot_idx, self.scp.leaders
+        );
+
+        let leader = match &self.close_loop {
+            // Nomination rounds start at 1.
+            Some(close_loop) => close_loop.round_leader(slot_idx, 1, &self.quorum_nodes()),
+            None => self.scp.leaders.front().cloned(),
+        };
+        // TODO: what is multiple leaders?
+        self.scp.create_slot(slot_idx, leader.unwrap());
     }
 
     fn on_scp_env(&mut self, scp_env: SCPEnvelope<N>) {
@@ -319,7 +506,7 @@ where
         // TODO: when should we reject msgs from non-leaders?
 
         // // Do not process it if it is not from the leader.
-        // if let Some(leader) = self.leaders.front() {
+        // if let Some(leader) = self.scp.leaders.front() {
         //     if leader != &scp_env.node_id {
         //         info!(
         //             "on_scp_env: node {:?} skipped processing because sender {:?} is not leader {:?}",
@@ -337,31 +524,200 @@ where
         // }
 
         let slot_idx: u64 = scp_env.slot_index.clone();
-
-        if let Some(quorum_set) = scp_env.get_quorum_set() {
-            self.quorum_manager.add_quorum_set(quorum_set);
+        if self.catch_up.proofs.contains_key(&slot_idx) {
+            debug!(
+                "on_scp_env: node {:?} already applied slot {:?} from a proof",
+                self.peer_idx, slot_idx
+            );
+            return;
+        }
+        let next_slot = self.next_slot();
+        // Nodes closing slots in a loop only start a slot once the previous one
+        // closed, as it decides the leader.
+        if slot_idx > next_slot
+            && (self.catch_up.is_catching_up()
+                || self.close_loop.is_some()
+                || !self.scp.slots.contains_key(&next_slot))
+        {
+            self.hold_for_catch_up(scp_env, next_slot);
+            return;
         }
 
-        let env_id = self.scp_envelope_controller.add_envelope(scp_env);
+        self.trace(TraceEvent::Received(scp_env.clone()));
+
+        // A peer that is still working on a slot the node externalized may
+        // have lost the messages that closed it.
+        let sender = scp_env.node_id.clone();
+        let answer_externalized = self.closed_value(slot_idx).is_some()
+            && !matches!(scp_env.statement, SCPStatement::Externalize(_));
 
         self.maybe_create_slot_and_state(slot_idx);
-        let slot = self.slots.get(&slot_idx).unwrap();
-
-        let res = slot.recv_scp_envelvope(
-            self.nomination_protocol_states.get_mut(&slot_idx).unwrap(),
-            self.ballot_protocol_states.get_mut(&slot_idx).unwrap(),
-            &env_id,
-            &mut self.scp_envelope_controller,
-            &mut self.quorum_manager,
-            &mut self.herder,
-        );
+        let res = self.scp.recv_envelope(Arc::new(scp_env));
 
         debug!(
             "on_scp_env res: node {:?} processed an env with response {:?}",
-            self.local_node_info.node_id, res
+            self.peer_idx, res
         );
 
         self.flush_all_broadcast_msg();
+        if answer_externalized {
+            self.send_externalized(&sender, slot_idx);
+        }
+        self.trace_externalized(slot_idx);
+        self.publish_slot_events(slot_idx);
+        self.record_externalized(slot_idx);
+    }
+
+    fn send_externalized(&mut self, peer_id: &PeerID, slot_idx: SlotIndex) {
+        let scp_env = self
+            .scp
+            .ballot_protocol_states
+            .get(&slot_idx)
+            .and_then(|state| state.latest_envelopes.get(&self.peer_idx))
+            .and_then(|env_id| self.scp.envelope_controller.get_envelope(env_id))
+            .cloned();
+        if let Some(scp_env) = scp_env {
+            self.send_message(peer_id, &SCPMessage::SCP(scp_env));
+        }
+    }
+
+    fn hold_for_catch_up(&mut self, scp_env: SCPEnvelope<N>, next_slot: SlotIndex) {
+        let peer_id = scp_env.node_id.clone();
+        self.catch_up.hold(scp_env);
+
+        // A node still running the slot is not behind, it only waits for it.
+        if self.scp.slots.contains_key(&next_slot) {
+            return;
+        }
+        let now = self.scp.work_scheduler().borrow().now();
+        if self.catch_up.should_ask(&peer_id, now) {
+            info!(
+                "Node {:?} is behind at slot {:?}, asks {:?} to catch up",
+                self.peer_idx, next_slot, peer_id
+            );
+            let request = CatchUpRequest {
+                node_id: self.peer_idx.clone(),
+                from_slot: next_slot,
+            };
+            self.send_message(&peer_id, &SCPMessage::CatchUpRequest(request));
+        }
+    }
+
+    fn on_catch_up_request(&mut self, request: CatchUpRequest) {
+        let mut proofs: BTreeMap<SlotIndex, ExternalizationProof<N>> = self
+            .catch_up
+            .proofs
+            .range(request.from_slot..)
+            .map(|(slot_idx, proof)| (*slot_idx, proof.clone()))
+            .collect();
+        let slot_indexes: Vec<SlotIndex> = self
+            .scp
+            .ballot_protocol_states
+            .range(request.from_slot..)
+            .map(|(slot_idx, _)| *slot_idx)
+            .collect();
+        for slot_idx in slot_indexes {
+            if let Some(proof) = self.externalization_proof(slot_idx) {
+                proofs.insert(slot_idx, proof);
+            }
+        }
+
+        // An empty response still tells the node to ask someone else.
+        let response = CatchUpResponse {
+            node_id: self.peer_idx.clone(),
+            proofs: proofs.into_values().collect(),
+        };
+        self.send_message(&request.node_id, &SCPMessage::CatchUpResponse(response));
+    }
+
+    fn on_catch_up_response(&mut self, response: CatchUpResponse<N>) {
+        let next_slot = self.next_slot();
+        if let Err(err) = self.apply_proofs(response.proofs) {
+            info!(
+                "Node {:?} rejects catch up from {:?}: {}",
+                self.peer_idx, response.node_id, err
+            );
+        }
+        if self.next_slot() != next_slot {
+            self.release_held();
+        } else {
+            self.catch_up.asked.remove(&response.node_id);
+        }
+    }
+
+    // Applies the proofs of the slots following the ones already externalized,
+    // in slot order. Returns the number of slots applied.
+    pub fn apply_proofs(
+        &mut self,
+        mut proofs: Vec<ExternalizationProof<N>>,
+    ) -> CatchUpOpResult<usize> {
+        proofs.sort_by_key(|proof| proof.slot_index);
+
+        let mut applied = 0;
+        for proof in proofs {
+            let slot_idx = proof.slot_index;
+            if slot_idx != self.next_slot() {
+                continue;
+            }
+            self.catch_up.check_proof(&proof, self.scp.local_node())?;
+
+            // Whatever ran for the slot live is superseded by the proof.
+            self.scp.slots.remove(&slot_idx);
+            self.scp.nomination_protocol_states.remove(&slot_idx);
+            self.scp.ballot_protocol_states.remove(&slot_idx);
+
+            self.scp.herder.externalize_value(slot_idx, &proof.value);
+            self.events.publish(NodeEvent::Externalized {
+                slot_index: slot_idx,
+                value: proof.value.clone(),
+                proof: proof.clone(),
+            });
+            self.catch_up.applied.insert(slot_idx);
+            self.on_slot_closed(slot_idx, &proof.value);
+            self.catch_up.proofs.insert(slot_idx, proof);
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    // Fires the next pending timer of the slot. Timers do not expire on their
+    // own; whoever drives the node decides when they do.
+    pub fn fire_timer(&mut self, slot_idx: SlotIndex) -> Option<TimerKind> {
+        let task = self.scp.pop_timer(slot_idx)?;
+        let timer = TimerKind::from(&task);
+        self.trace(TraceEvent::TimerFired {
+            slot_index: slot_idx,
+            timer,
+        });
+        self.events.publish(NodeEvent::TimerFired {
+            slot_index: slot_idx,
+            timer,
+        });
+
+        // Every retry follows one more leader, so a leader that is down does
+        // not stall the slot.
+        if let (SlotTask::RetryNominate(_), Some(close_loop)) = (&task, &self.close_loop) {
+            let round = self.scp.nomination_protocol_states[&slot_idx].round_number + 1;
+            if let Some(leader) = close_loop.round_leader(slot_idx, round, &self.quorum_nodes()) {
+                let nomination_state = self.scp.nomination_protocol_states.get_mut(&slot_idx);
+                nomination_state.unwrap().round_leaders.insert(leader);
+            }
+        }
+        self.scp.run_timer(slot_idx, task);
+
+        self.flush_all_broadcast_msg();
+        self.trace_externalized(slot_idx);
+        self.publish_slot_events(slot_idx);
+        self.record_externalized(slot_idx);
+        Some(timer)
+    }
+
+    pub fn pending_timers(&self, slot_idx: &SlotIndex) -> usize {
+        self.scp.pending_timers(*slot_idx)
+    }
+
+    pub fn next_timer(&self) -> Option<(SystemTime, SlotIndex)> {
+        self.scp.next_timer()
     }
 
     pub fn process_all_messages(&mut self) -> usize {
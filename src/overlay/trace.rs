@@ -0,0 +1,390 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    herder::herder::HerderDriver,
+    scp::{
+        envelope::SCPEnvelope, nomination_protocol::NominationValue, queue::SlotTask, scp::NodeID,
+        slot::SlotIndex,
+    },
+};
+
+use super::{
+    conn::{PeerConn, PeerConnBuilder},
+    message::SCPMessage,
+    peer_node::PeerNode,
+};
+
+// Trace files hold one JSON encoded event per line, so a trace survives the
+// node crashing half way through it.
+
+pub type TraceOpResult<T> = std::result::Result<T, TraceOpError>;
+
+#[derive(Debug, PartialEq)]
+pub enum TraceOpError {
+    // The trace does not start with a `Start` event.
+    MissingStart,
+    // The trace was recorded by another node.
+    WrongNode {
+        expected: NodeID,
+        actual: NodeID,
+    },
+    // The replayed node fired another timer, or none at all.
+    TimerMismatch {
+        index: usize,
+    },
+    // The replayed node's output differs from the recording at `index`.
+    Diverged {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Display for TraceOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceOpError::MissingStart => write!(f, "trace does not start with a start event"),
+            TraceOpError::WrongNode { expected, actual } => {
+                write!(f, "trace was recorded by {} and not {}", expected, actual)
+            }
+            TraceOpError::TimerMismatch { index } => {
+                write!(f, "replay fired a different timer at event {}", index)
+            }
+            TraceOpError::Diverged {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "replay diverged at output {}: expected {}, got {}",
+                index, expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TraceOpError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimerKind {
+    Nomination,
+    Ballot,
+    Rebroadcast,
+}
+
+impl<N: NominationValue> From<&SlotTask<N>> for TimerKind {
+    fn from(task: &SlotTask<N>) -> Self {
+        match task {
+            SlotTask::RetryNominate(_) => TimerKind::Nomination,
+            SlotTask::AbandonBallot(_) => TimerKind::Ballot,
+            SlotTask::Rebroadcast(_) => TimerKind::Rebroadcast,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TraceEvent<N>
+where
+    N: NominationValue,
+{
+    // The node and the leaders it follows when recording starts.
+    Start {
+        node_id: NodeID,
+        leaders: Vec<NodeID>,
+    },
+    Nominate {
+        slot_index: SlotIndex,
+        value: N,
+    },
+    Received(SCPEnvelope<N>),
+    TimerFired {
+        slot_index: SlotIndex,
+        timer: TimerKind,
+    },
+    Emitted(SCPEnvelope<N>),
+    Externalized {
+        slot_index: SlotIndex,
+        value: N,
+    },
+}
+
+impl<N> TraceEvent<N>
+where
+    N: NominationValue,
+{
+    // Events produced by the node rather than fed to it.
+    pub fn is_output(&self) -> bool {
+        matches!(
+            self,
+            TraceEvent::Emitted(_) | TraceEvent::Externalized { .. }
+        )
+    }
+}
+
+pub struct TraceRecorder<N>
+where
+    N: NominationValue,
+{
+    pub events: Vec<TraceEvent<N>>,
+    externalized: BTreeSet<SlotIndex>,
+    file: Option<File>,
+}
+
+impl<N> TraceRecorder<N>
+where
+    N: NominationValue,
+{
+    pub fn new(path: Option<&Path>) -> io::Result<Self> {
+        Ok(Self {
+            events: Default::default(),
+            externalized: Default::default(),
+            file: path.map(File::create).transpose()?,
+        })
+    }
+
+    pub fn record(&mut self, event: TraceEvent<N>) {
+        if let TraceEvent::Externalized { slot_index, .. } = &event {
+            if !self.externalized.insert(*slot_index) {
+                return;
+            }
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            // Tracing must not take the node down, so a failing trace file only
+            // loses events.
+            if let Ok(line) = serde_json::to_string(&event) {
+                let _ = writeln!(file, "{}", line).and_then(|_| file.flush());
+            }
+        }
+        self.events.push(event);
+    }
+
+    pub fn has_externalized(&self, slot_index: &SlotIndex) -> bool {
+        self.externalized.contains(slot_index)
+    }
+}
+
+pub fn read_trace<N: NominationValue + DeserializeOwned>(
+    path: &Path,
+) -> io::Result<Vec<TraceEvent<N>>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|line| {
+            serde_json::from_str(&line?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+        .collect()
+}
+
+// Feeds the inputs of a recorded trace to a fresh node and checks that it
+// produces the same envelopes and externalizes the same values, in the same
+// order.
+pub fn replay_trace<N, H, C, CB>(
+    node: &mut PeerNode<N, H, C, CB>,
+    events: &[TraceEvent<N>],
+) -> TraceOpResult<()>
+where
+    N: NominationValue,
+    H: HerderDriver<N> + 'static,
+    C: PeerConn<N> + std::fmt::Debug + 'static,
+    CB: PeerConnBuilder<N, C> + 'static,
+{
+    match events.first() {
+        Some(TraceEvent::Start { node_id, leaders }) => {
+            if node_id != &node.peer_idx {
+                return Err(TraceOpError::WrongNode {
+                    expected: node_id.clone(),
+                    actual: node.peer_idx.clone(),
+                });
+            }
+            node.scp.leaders = leaders.iter().cloned().collect();
+        }
+        _ => return Err(TraceOpError::MissingStart),
+    }
+
+    // The recorder only keeps events in memory here.
+    node.trace_recorder = TraceRecorder::new(None).ok();
+
+    for (index, event) in events.iter().enumerate().skip(1) {
+        match event {
+            TraceEvent::Nominate { slot_index, value } => {
+                node.slot_nominate(*slot_index, value.clone())
+            }
+            TraceEvent::Received(env) => {
+                node.message_controller
+                    .borrow_mut()
+                    .add_message(SCPMessage::SCP(env.clone()));
+                node.process_one_message();
+            }
+            TraceEvent::TimerFired { slot_index, timer } => {
+                let fired = node.fire_timer(*slot_index);
+                if fired != Some(*timer) {
+                    return Err(TraceOpError::TimerMismatch { index });
+                }
+            }
+            // Outputs are compared below.
+            _ => {}
+        }
+    }
+
+    let expected = events.iter().filter(|event| event.is_output());
+    let actual = node
+        .trace_recorder
+        .as_ref()
+        .map(|recorder| recorder.events.clone())
+        .unwrap_or_default();
+    let mut actual = actual.into_iter().filter(|event| event.is_output());
+
+    for (index, expected) in expected.enumerate() {
+        match actual.next() {
+            Some(actual) if &actual == expected => {}
+            actual => {
+                return Err(TraceOpError::Diverged {
+                    index,
+                    expected: format!("{:?}", expected),
+                    actual: format!("{:?}", actual),
+                })
+            }
+        }
+    }
+    if let Some(extra) = actual.next() {
+        return Err(TraceOpError::Diverged {
+            index: events.iter().filter(|event| event.is_output()).count(),
+            expected: "end of trace".to_string(),
+            actual: format!("{:?}", extra),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::{
+        mock::{
+            builder::{MockInMemoryNodeBuilder, MockInMemoryPeerNode},
+            state::{MockState, MockStateDriver},
+        },
+        overlay_impl::simulation::{test_utils::*, SimulationConfig},
+    };
+
+    use super::*;
+
+    fn test_trace_path(name: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("scp_trace_{}_{}.jsonl", name, nanos))
+    }
+
+    // A node with the same quorum data as in the simulation, whose peers are
+    // only there to receive what it sends.
+    fn fresh_node(node_id: &str) -> MockInMemoryPeerNode {
+        let mut builder = MockInMemoryNodeBuilder::new(SIM_DIR);
+        let mut nodes = SIM_NODES
+            .iter()
+            .map(|id| (id.to_string(), builder.build_node(id).unwrap()))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        nodes.remove(node_id).unwrap()
+    }
+
+    fn record_run(path: &Path) -> Vec<TraceEvent<MockState>> {
+        let mut sim = mock_simulation(SimulationConfig {
+            reorder: true,
+            max_delay: std::time::Duration::from_millis(50),
+            ..SimulationConfig::with_seed(3)
+        });
+        sim.nodes
+            .get_mut("node2")
+            .unwrap()
+            .record_trace(Some(path))
+            .unwrap();
+
+        nominate_slots(&mut sim, 0..2);
+
+        sim.nodes["node2"]
+            .trace_recorder
+            .as_ref()
+            .unwrap()
+            .events
+            .clone()
+    }
+
+    #[test]
+    fn recorded_traces_replay() {
+        let path = test_trace_path("replay");
+        let events = record_run(&path);
+
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, TraceEvent::Externalized { slot_index: 1, .. })));
+        assert_eq!(read_trace::<MockState>(&path).unwrap(), events);
+
+        assert_eq!(replay_trace(&mut fresh_node("node2"), &events), Ok(()));
+        assert_eq!(
+            replay_trace(&mut fresh_node("node3"), &events),
+            Err(TraceOpError::WrongNode {
+                expected: "node2".to_string(),
+                actual: "node3".to_string()
+            })
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn replay_detects_divergence() {
+        let path = test_trace_path("divergence");
+        let mut events = record_run(&path);
+
+        // Pretend node2 externalized something else for slot 0.
+        for event in events.iter_mut() {
+            if let TraceEvent::Externalized {
+                slot_index: 0,
+                value,
+            } = event
+            {
+                *value = MockState::from_seed(42);
+            }
+        }
+
+        assert!(matches!(
+            replay_trace(&mut fresh_node("node2"), &events),
+            Err(TraceOpError::Diverged { .. })
+        ));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn timers_are_traced_and_replayed() {
+        let mut node = fresh_node("node1");
+        node.add_leader(&"node1".to_string());
+        node.record_trace(None).unwrap();
+
+        node.slot_nominate(0, MockState::from_seed(0));
+        // The nomination timer is due before the rebroadcast.
+        assert_eq!(node.pending_timers(&0), 2);
+        assert_eq!(node.fire_timer(0), Some(TimerKind::Nomination));
+
+        let events = node.trace_recorder.as_ref().unwrap().events.clone();
+        assert!(events.contains(&TraceEvent::TimerFired {
+            slot_index: 0,
+            timer: TimerKind::Nomination
+        }));
+        assert_eq!(replay_trace(&mut fresh_node("node1"), &events), Ok(()));
+    }
+}
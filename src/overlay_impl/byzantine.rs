@@ -0,0 +1,315 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    application::quorum::{QuorumSet, QuorumSlice},
+    overlay::{message::SCPMessage, peer::PeerID},
+    scp::{
+        envelope::SCPEnvelope, local_node::LocalNodeInfo, nomination_protocol::NominationValue,
+        slot::SlotIndex, statement::SCPStatement,
+    },
+};
+
+// How a byzantine node misbehaves. The node itself runs the honest protocol;
+// its adapter rewrites what it sends on the wire.
+#[derive(Clone, Debug)]
+pub enum ByzantineBehaviour<N>
+where
+    N: NominationValue,
+{
+    // Sends statements about `value` to `peers` and its own statements to
+    // everyone else.
+    Equivocate { value: N, peers: BTreeSet<PeerID> },
+    // Sends an envelope it sent or received earlier along with every message.
+    ReplayStale,
+    // Sends ballot statements that fail `is_statement_sane`.
+    Insane,
+    // Advertises a different quorum set for every slot.
+    RotateQuorumSet,
+    // Sends nothing, as if the node crashed.
+    Crash,
+}
+
+pub struct ByzantineAdapter<N>
+where
+    N: NominationValue,
+{
+    pub behaviour: ByzantineBehaviour<N>,
+    // The node signs what it rewrites with its own key.
+    local_node: Arc<LocalNodeInfo<N>>,
+    // Envelopes seen by the node, oldest first.
+    history: Vec<SCPEnvelope<N>>,
+}
+
+impl<N> ByzantineAdapter<N>
+where
+    N: NominationValue,
+{
+    pub fn new(behaviour: ByzantineBehaviour<N>, local_node: Arc<LocalNodeInfo<N>>) -> Self {
+        Self {
+            behaviour,
+            local_node,
+            history: Default::default(),
+        }
+    }
+
+    // Records a message delivered to the node.
+    pub fn observe(&mut self, msg: &SCPMessage<N>) {
+        if let SCPMessage::SCP(env) = msg {
+            self.history.push(env.clone());
+        }
+    }
+
+    // Returns the messages actually sent in place of `msg`.
+    pub fn tamper(
+        &mut self,
+        to: &PeerID,
+        msg: SCPMessage<N>,
+        rng: &mut StdRng,
+    ) -> Vec<SCPMessage<N>> {
+        let env = match msg {
+            _ if matches!(self.behaviour, ByzantineBehaviour::Crash) => return vec![],
+            SCPMessage::SCP(env) => env,
+            _ => return vec![msg],
+        };
+
+        match &self.behaviour {
+            ByzantineBehaviour::Equivocate { value, peers } => {
+                let mut env = env;
+                if peers.contains(to) {
+                    replace_value(&mut env.statement, value);
+                    self.local_node.sign_envelope(&mut env);
+                }
+                vec![SCPMessage::SCP(env)]
+            }
+            ByzantineBehaviour::ReplayStale => {
+                let mut sent = vec![];
+                if !self.history.is_empty() {
+                    let stale = self.history[rng.gen_range(0..self.history.len())].clone();
+                    sent.push(SCPMessage::SCP(stale));
+                }
+                self.history.push(env.clone());
+                sent.push(SCPMessage::SCP(env));
+                sent
+            }
+            ByzantineBehaviour::Insane => {
+                let mut env = env;
+                make_insane(&mut env.statement);
+                self.local_node.sign_envelope(&mut env);
+                vec![SCPMessage::SCP(env)]
+            }
+            ByzantineBehaviour::RotateQuorumSet => {
+                let mut env = env;
+                let quorum_set = env
+                    .get_quorum_set()
+                    .map(|quorum_set| rotate_quorum_set(quorum_set, &env.node_id, env.slot_index));
+                if let Some(quorum_set) = quorum_set {
+                    set_quorum_set(&mut env.statement, quorum_set);
+                    self.local_node.sign_envelope(&mut env);
+                }
+                vec![SCPMessage::SCP(env)]
+            }
+            ByzantineBehaviour::Crash => vec![],
+        }
+    }
+}
+
+fn replace_value<N: NominationValue>(statement: &mut SCPStatement<N>, value: &N) {
+    match statement {
+        SCPStatement::Nominate(st) => {
+            st.votes = vec![value.clone()];
+            if !st.accepted.is_empty() {
+                st.accepted = vec![value.clone()];
+            }
+        }
+        SCPStatement::Prepare(st) => {
+            st.ballot.value = value.clone();
+            if let Some(prepared) = st.prepared.as_mut() {
+                prepared.value = value.clone();
+            }
+            // p' has to stay incompatible with p.
+            st.prepared_prime = None;
+        }
+        SCPStatement::Confirm(st) => st.ballot.value = value.clone(),
+        SCPStatement::Externalize(st) => st.commit.value = value.clone(),
+    }
+}
+
+fn make_insane<N: NominationValue>(statement: &mut SCPStatement<N>) {
+    match statement {
+        // Nomination statements are not checked for sanity yet.
+        SCPStatement::Nominate(_) => {}
+        // Remote ballot statements never have a zero counter.
+        SCPStatement::Prepare(st) => st.ballot.counter = 0,
+        SCPStatement::Confirm(st) => st.ballot.counter = 0,
+        SCPStatement::Externalize(st) => st.commit.counter = 0,
+    }
+}
+
+// A quorum set with a single slice holding the local node and one peer that
+// changes with the slot.
+fn rotate_quorum_set(quorum_set: &QuorumSet, node_id: &PeerID, slot_index: SlotIndex) -> QuorumSet {
+    let (local, peers): (Vec<_>, Vec<_>) = quorum_set
+        .nodes()
+        .into_iter()
+        .partition(|node| &node.node_id == node_id);
+    let mut slice = local.into_iter().collect::<BTreeSet<_>>();
+    if !peers.is_empty() {
+        slice.insert(peers[slot_index as usize % peers.len()].clone());
+    }
+
+    QuorumSet {
+        slices: BTreeSet::from([QuorumSlice { data: slice }]),
+        threshold: 0,
+    }
+}
+
+fn set_quorum_set<N: NominationValue>(statement: &mut SCPStatement<N>, quorum_set: QuorumSet) {
+    let hash = quorum_set.hash_value();
+    match statement {
+        SCPStatement::Nominate(st) => {
+            st.quorum_set_hash = hash;
+            st.quorum_set = Some(quorum_set);
+        }
+        SCPStatement::Prepare(st) => {
+            st.quorum_set_hash = hash;
+            st.quorum_set = Some(quorum_set);
+        }
+        SCPStatement::Confirm(st) => {
+            st.quorum_set_hash = hash;
+            st.quorum_set = Some(quorum_set);
+        }
+        SCPStatement::Externalize(st) => {
+            st.commit_quorum_set_hash = hash;
+            st.commit_quorum_set = Some(quorum_set);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        mock::state::{MockState, MockStateDriver},
+        overlay_impl::simulation::{Simulation, SimulationConfig},
+    };
+
+    use super::*;
+
+    // Every slice holds three of the four nodes, so node1..node3 form a
+    // quorum on their own and stay intact whatever node4 does.
+    const BYZANTINE_DIR: &str = "byzantine";
+    const NODES: [&str; 4] = ["node1", "node2", "node3", "node4"];
+    const BYZANTINE_NODE: &str = "node4";
+
+    // Runs two slots led by `leader` while node4 misbehaves.
+    fn assert_intact_nodes_agree(behaviour: ByzantineBehaviour<MockState>, leader: &str) {
+        for seed in 0..5 {
+            let config = SimulationConfig {
+                seed,
+                reorder: true,
+                duplicate_probability: 0.1,
+                min_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(100),
+                ..Default::default()
+            };
+            let mut sim =
+                Simulation::<MockState, MockStateDriver>::new(BYZANTINE_DIR, &NODES, config)
+                    .unwrap();
+            sim.make_byzantine(&BYZANTINE_NODE.to_string(), behaviour.clone())
+                .unwrap();
+            sim.set_leader(&leader.to_string()).unwrap();
+
+            // Every node nominates, but only the votes of the leader count.
+            for slot_index in 0..2 {
+                for node_id in NODES {
+                    sim.nominate(
+                        &node_id.to_string(),
+                        slot_index,
+                        MockState::from_seed(seed + slot_index),
+                    )
+                    .unwrap();
+                }
+                sim.run();
+            }
+
+            if let Err(err) = sim.check_agreement() {
+                panic!("{:?}: {}", behaviour, err);
+            }
+            let values = sim.externalized_values();
+            for slot_index in 0..2 {
+                assert_eq!(
+                    values[&slot_index].keys().collect::<Vec<_>>(),
+                    NODES[..3].iter().collect::<Vec<_>>(),
+                    "{:?}, seed {}",
+                    behaviour,
+                    seed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn equivocating_leaders_do_not_split_intact_nodes() {
+        assert_intact_nodes_agree(
+            ByzantineBehaviour::Equivocate {
+                value: MockState::from_seed(100),
+                peers: BTreeSet::from(["node2".to_string()]),
+            },
+            BYZANTINE_NODE,
+        );
+    }
+
+    #[test]
+    fn stale_envelopes_do_not_split_intact_nodes() {
+        assert_intact_nodes_agree(ByzantineBehaviour::ReplayStale, "node1");
+    }
+
+    #[test]
+    fn insane_statements_do_not_split_intact_nodes() {
+        assert_intact_nodes_agree(ByzantineBehaviour::Insane, "node1");
+    }
+
+    #[test]
+    fn rotating_quorum_sets_do_not_split_intact_nodes() {
+        assert_intact_nodes_agree(ByzantineBehaviour::RotateQuorumSet, "node1");
+    }
+
+    #[test]
+    fn equivocators_send_different_values() {
+        let value = MockState::from_seed(1);
+        let mut adapter = ByzantineAdapter::new(
+            ByzantineBehaviour::Equivocate {
+                value: value.clone(),
+                peers: BTreeSet::from(["node2".to_string()]),
+            },
+            Default::default(),
+        );
+        let quorum_set = QuorumSet::default();
+        let env = SCPEnvelope::new(
+            SCPStatement::Nominate(crate::scp::statement::SCPStatementNominate::new(
+                &quorum_set,
+                vec![MockState::from_seed(2)],
+                vec![],
+            )),
+            BYZANTINE_NODE.to_string(),
+            0,
+        );
+        let mut rng = rand::SeedableRng::seed_from_u64(0);
+
+        let to_node1 = adapter.tamper(&"node1".to_string(), SCPMessage::SCP(env.clone()), &mut rng);
+        let to_node2 = adapter.tamper(&"node2".to_string(), SCPMessage::SCP(env), &mut rng);
+        let votes = |msgs: &Vec<SCPMessage<MockState>>| match &msgs[0] {
+            SCPMessage::SCP(env) => match &env.statement {
+                SCPStatement::Nominate(st) => st.votes.clone(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        assert_eq!(votes(&to_node1), vec![MockState::from_seed(2)]);
+        assert_eq!(votes(&to_node2), vec![value]);
+    }
+}
@@ -1,5 +1,8 @@
+pub mod byzantine;
 pub mod in_memory_conn;
 pub mod in_memory_global;
 pub mod in_memory_peer;
+pub mod model_check;
+pub mod simulation;
 pub mod tcp_conn;
 pub mod tcp_peer;
\ No newline at end of file
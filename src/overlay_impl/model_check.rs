@@ -0,0 +1,667 @@
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
+    fmt::Display,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    rc::Rc,
+    time::SystemTime,
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    application::clock::VirtualClock,
+    herder::herder::HerderDriver,
+    mock::builder::InMemoryPeerNode,
+    overlay::{message::SCPMessage, peer_node::PeerNode},
+    scp::{
+        ballot_protocol::SCPPhase, builder::InMemoryNodeBuilder, envelope::SCPEnvelope,
+        local_node::LocalNodeOpError, nomination_protocol::NominationValue, scp::NodeID,
+        slot::SlotIndex,
+    },
+};
+
+use super::byzantine::{ByzantineAdapter, ByzantineBehaviour};
+
+// Exhaustive exploration of small networks of in-memory nodes. Messages stay
+// in the `MessageController` queue of the node they are sent to, and which
+// queued message is processed next, or which timer fires, is left open. The
+// checker tries every choice up to a depth bound. Nodes cannot be cloned, so
+// every explored state is rebuilt by replaying the choices leading to it.
+
+pub type ModelCheckOpResult<T> = std::result::Result<T, ModelCheckOpError>;
+
+#[derive(Debug, PartialEq)]
+pub enum ModelCheckOpError {
+    // The node is not part of the configuration or has no quorum data on file.
+    UnknownNode(NodeID),
+    // The node's configuration on file is rejected, e.g. it lacks a key.
+    InvalidNode(LocalNodeOpError),
+    // Replaying a path did not reach the same state again.
+    Nondeterministic(Vec<Choice>),
+}
+
+impl Display for ModelCheckOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelCheckOpError::UnknownNode(node_id) => write!(f, "unknown node {}", node_id),
+            ModelCheckOpError::InvalidNode(err) => write!(f, "invalid node: {}", err),
+            ModelCheckOpError::Nondeterministic(path) => {
+                write!(f, "replaying {:?} is not deterministic", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModelCheckOpError {}
+
+impl From<LocalNodeOpError> for ModelCheckOpError {
+    fn from(err: LocalNodeOpError) -> Self {
+        match err {
+            LocalNodeOpError::MissingConfig(node_id) => ModelCheckOpError::UnknownNode(node_id),
+            err => ModelCheckOpError::InvalidNode(err),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Choice {
+    // Processes the message at `index` in the queue of `to`.
+    Deliver {
+        to: NodeID,
+        index: usize,
+    },
+    FireTimer {
+        node_id: NodeID,
+        slot_index: SlotIndex,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation<N>
+where
+    N: NominationValue,
+{
+    // Intact nodes externalized different values for the slot.
+    Disagreement {
+        slot_index: SlotIndex,
+        values: BTreeMap<NodeID, N>,
+    },
+    // Delivering every message and firing every timer in order does not make
+    // the node externalize the slot.
+    Stuck {
+        node_id: NodeID,
+        slot_index: SlotIndex,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Counterexample<N>
+where
+    N: NominationValue,
+{
+    pub violation: Violation<N>,
+    // The choices leading to the violating state. For `Stuck`, the
+    // synchronous schedule starts from the end of the path.
+    pub path: Vec<Choice>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ModelCheckConfig {
+    // Longest path explored.
+    pub max_depth: usize,
+    // Upper bound on the number of distinct states visited.
+    pub max_states: usize,
+    // Upper bound on the steps of the synchronous schedule used to find stuck
+    // nodes.
+    pub max_settle_steps: usize,
+}
+
+impl Default for ModelCheckConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            max_states: 10_000,
+            max_settle_steps: 1_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModelCheckReport<N>
+where
+    N: NominationValue,
+{
+    // Distinct states visited.
+    pub states: usize,
+    // Paths cut off at `max_depth`.
+    pub frontier: usize,
+    // Whether `max_states` stopped the exploration early.
+    pub truncated: bool,
+    pub counterexample: Option<Counterexample<N>>,
+}
+
+pub struct ModelChecker<N, H>
+where
+    N: NominationValue,
+    H: HerderDriver<N> + 'static,
+{
+    pub config: ModelCheckConfig,
+    quorum_dir_path: String,
+    node_ids: Vec<NodeID>,
+    nominations: Vec<(NodeID, SlotIndex, N)>,
+    byzantine_nodes: BTreeMap<NodeID, ByzantineBehaviour<N>>,
+    phantom: PhantomData<H>,
+}
+
+impl<N, H> ModelChecker<N, H>
+where
+    N: NominationValue,
+    H: HerderDriver<N> + 'static,
+{
+    pub fn new(quorum_dir_path: &str, node_ids: &[&str], config: ModelCheckConfig) -> Self {
+        Self {
+            config,
+            quorum_dir_path: quorum_dir_path.to_string(),
+            node_ids: node_ids.iter().map(|node_id| node_id.to_string()).collect(),
+            nominations: Default::default(),
+            byzantine_nodes: Default::default(),
+            phantom: PhantomData,
+        }
+    }
+
+    // Nominates `value` at the start of every explored run.
+    pub fn nominate(
+        &mut self,
+        node_id: &NodeID,
+        slot_index: SlotIndex,
+        value: N,
+    ) -> ModelCheckOpResult<()> {
+        self.check_node(node_id)?;
+        self.nominations.push((node_id.clone(), slot_index, value));
+        Ok(())
+    }
+
+    pub fn make_byzantine(
+        &mut self,
+        node_id: &NodeID,
+        behaviour: ByzantineBehaviour<N>,
+    ) -> ModelCheckOpResult<()> {
+        self.check_node(node_id)?;
+        self.byzantine_nodes.insert(node_id.clone(), behaviour);
+        Ok(())
+    }
+
+    fn check_node(&self, node_id: &NodeID) -> ModelCheckOpResult<()> {
+        if !self.node_ids.contains(node_id) {
+            return Err(ModelCheckOpError::UnknownNode(node_id.clone()));
+        }
+        Ok(())
+    }
+
+    // Explores every interleaving up to `max_depth` depth first and stops at
+    // the first violation.
+    pub fn check(&self) -> ModelCheckOpResult<ModelCheckReport<N>> {
+        let mut report = ModelCheckReport::default();
+        // The shallowest depth each state was seen at. A state seen again
+        // deeper has nothing new to explore.
+        let mut visited: BTreeMap<u64, usize> = BTreeMap::new();
+        let mut stack: Vec<Vec<Choice>> = vec![vec![]];
+
+        while let Some(path) = stack.pop() {
+            let mut world = self.replay(&path)?;
+            let fingerprint = world.fingerprint();
+            if visited
+                .get(&fingerprint)
+                .is_some_and(|depth| *depth <= path.len())
+            {
+                continue;
+            }
+            visited.insert(fingerprint, path.len());
+
+            report.states += 1;
+            if report.states > self.config.max_states {
+                report.truncated = true;
+                break;
+            }
+
+            if let Some(violation) = world.disagreement() {
+                report.counterexample = Some(Counterexample { violation, path });
+                break;
+            }
+
+            let choices = world.choices();
+            if path.len() < self.config.max_depth && !choices.is_empty() {
+                // Pushed in reverse so the first choice is explored first.
+                for choice in choices.into_iter().rev() {
+                    let mut next = path.clone();
+                    next.push(choice);
+                    stack.push(next);
+                }
+                continue;
+            }
+
+            if !choices.is_empty() {
+                report.frontier += 1;
+            }
+            world.settle(self.config.max_settle_steps);
+            if let Some(violation) = world.disagreement().or_else(|| world.stuck()) {
+                report.counterexample = Some(Counterexample { violation, path });
+                break;
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Builds the nodes, nominates and applies `path`.
+    pub fn replay(&self, path: &[Choice]) -> ModelCheckOpResult<ModelWorld<N, H>> {
+        let mut world = ModelWorld::new(self)?;
+        for (index, choice) in path.iter().enumerate() {
+            if !world.apply(choice) {
+                return Err(ModelCheckOpError::Nondeterministic(path[..=index].to_vec()));
+            }
+        }
+        Ok(world)
+    }
+}
+
+// One state of the explored network.
+pub struct ModelWorld<N, H>
+where
+    N: NominationValue,
+    H: HerderDriver<N> + 'static,
+{
+    pub nodes: BTreeMap<NodeID, InMemoryPeerNode<N, H>>,
+    // Nodes whose externalized values have to agree.
+    pub intact_nodes: BTreeSet<NodeID>,
+    slot_indices: BTreeSet<SlotIndex>,
+    byzantine_nodes: BTreeMap<NodeID, ByzantineAdapter<N>>,
+    builder: InMemoryNodeBuilder<N, H>,
+    rng: StdRng,
+}
+
+impl<N, H> ModelWorld<N, H>
+where
+    N: NominationValue,
+    H: HerderDriver<N> + 'static,
+{
+    fn new(checker: &ModelChecker<N, H>) -> ModelCheckOpResult<Self> {
+        let clock = Rc::new(RefCell::new(VirtualClock::new(SystemTime::UNIX_EPOCH)));
+        let mut builder = InMemoryNodeBuilder::new(&checker.quorum_dir_path).with_clock(clock);
+
+        let mut nodes = BTreeMap::new();
+        for node_id in &checker.node_ids {
+            let node = builder.build_node(node_id)?;
+            nodes.insert(node_id.clone(), node);
+        }
+
+        // The first node leads every slot.
+        if let Some(leader) = checker.node_ids.first() {
+            PeerNode::add_leader_for_nodes(nodes.values_mut(), leader);
+        }
+
+        let byzantine_nodes = checker
+            .byzantine_nodes
+            .iter()
+            .map(|(node_id, behaviour)| {
+                let local_node = nodes[node_id].scp.local_node().clone();
+                (
+                    node_id.clone(),
+                    ByzantineAdapter::new(behaviour.clone(), local_node),
+                )
+            })
+            .collect();
+
+        let mut world = Self {
+            nodes,
+            intact_nodes: checker
+                .node_ids
+                .iter()
+                .filter(|node_id| !checker.byzantine_nodes.contains_key(*node_id))
+                .cloned()
+                .collect(),
+            slot_indices: Default::default(),
+            byzantine_nodes,
+            builder,
+            rng: StdRng::seed_from_u64(0),
+        };
+
+        for (node_id, slot_index, value) in &checker.nominations {
+            world.slot_indices.insert(*slot_index);
+            world
+                .nodes
+                .get_mut(node_id)
+                .unwrap()
+                .slot_nominate(*slot_index, value.clone());
+            world.collect_sent_messages();
+        }
+
+        Ok(world)
+    }
+
+    // Messages are already queued at the node they were sent to; only
+    // byzantine senders get to rewrite them.
+    fn collect_sent_messages(&mut self) {
+        let sent = std::mem::take(&mut self.builder.global_state.borrow_mut().msg_peer_id_queue);
+        if self.byzantine_nodes.is_empty() {
+            return;
+        }
+
+        // New messages sit at the back of each queue, in the order they were
+        // sent.
+        let mut counts: BTreeMap<NodeID, usize> = BTreeMap::new();
+        for to in sent {
+            *counts.entry(to).or_default() += 1;
+        }
+        for (to, count) in counts {
+            let Some(node) = self.nodes.get(&to) else {
+                continue;
+            };
+            let mut controller = node.message_controller.borrow_mut();
+            let at = controller.messages.len().saturating_sub(count);
+            let msgs = controller.messages.split_off(at);
+            for msg in msgs {
+                let from = msg.sender().clone();
+                match self.byzantine_nodes.get_mut(&from) {
+                    Some(adapter) => {
+                        controller
+                            .messages
+                            .extend(adapter.tamper(&to, msg, &mut self.rng));
+                    }
+                    None => controller.messages.push_back(msg),
+                }
+            }
+        }
+    }
+
+    // Identical messages queued at the same node lead to the same state, so
+    // only the first one of them is a choice.
+    pub fn choices(&self) -> Vec<Choice> {
+        let mut choices = vec![];
+        for (node_id, node) in &self.nodes {
+            let mut seen = BTreeSet::new();
+            for (index, msg) in node.message_controller.borrow().messages.iter().enumerate() {
+                if seen.insert(message_key(msg)) {
+                    choices.push(Choice::Deliver {
+                        to: node_id.clone(),
+                        index,
+                    });
+                }
+            }
+        }
+        for (node_id, node) in &self.nodes {
+            for slot_index in node.scp.slots.keys() {
+                if node.pending_timers(slot_index) > 0 {
+                    choices.push(Choice::FireTimer {
+                        node_id: node_id.clone(),
+                        slot_index: *slot_index,
+                    });
+                }
+            }
+        }
+        choices
+    }
+
+    // Returns false if the choice is not available in this state.
+    pub fn apply(&mut self, choice: &Choice) -> bool {
+        match choice {
+            Choice::Deliver { to, index } => {
+                let Some(node) = self.nodes.get_mut(to) else {
+                    return false;
+                };
+                {
+                    let mut controller = node.message_controller.borrow_mut();
+                    let Some(msg) = controller.messages.remove(*index) else {
+                        return false;
+                    };
+                    if let Some(adapter) = self.byzantine_nodes.get_mut(to) {
+                        adapter.observe(&msg);
+                    }
+                    controller.messages.push_front(msg);
+                }
+                node.process_one_message();
+            }
+            Choice::FireTimer {
+                node_id,
+                slot_index,
+            } => {
+                let Some(node) = self.nodes.get_mut(node_id) else {
+                    return false;
+                };
+                if node.fire_timer(*slot_index).is_none() {
+                    return false;
+                }
+            }
+        }
+        self.collect_sent_messages();
+        true
+    }
+
+    // The synchronous schedule: every queued message is delivered in the order
+    // it was sent, and timers only fire once the network is quiet and some
+    // intact node still has a slot to externalize.
+    pub fn settle(&mut self, max_steps: usize) {
+        let mut steps = 0;
+        while steps < max_steps {
+            let next = self
+                .nodes
+                .iter()
+                .find(|(_, node)| !node.message_controller.borrow().messages.is_empty())
+                .map(|(node_id, _)| Choice::Deliver {
+                    to: node_id.clone(),
+                    index: 0,
+                });
+            let next = next.or_else(|| {
+                self.stuck()?;
+                self.choices()
+                    .into_iter()
+                    .find(|choice| matches!(choice, Choice::FireTimer { .. }))
+            });
+
+            match next {
+                Some(choice) => {
+                    self.apply(&choice);
+                    steps += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    pub fn externalized_value(&self, node_id: &NodeID, slot_index: &SlotIndex) -> Option<N> {
+        let state = self
+            .nodes
+            .get(node_id)?
+            .scp
+            .ballot_protocol_states
+            .get(slot_index)?;
+        if state.phase != SCPPhase::PhaseExternalize {
+            return None;
+        }
+        state.commit.as_ref().map(|ballot| ballot.value.clone())
+    }
+
+    pub fn disagreement(&self) -> Option<Violation<N>> {
+        for slot_index in &self.slot_indices {
+            let values: BTreeMap<NodeID, N> = self
+                .intact_nodes
+                .iter()
+                .filter_map(|node_id| {
+                    self.externalized_value(node_id, slot_index)
+                        .map(|value| (node_id.clone(), value))
+                })
+                .collect();
+            if values.values().collect::<BTreeSet<_>>().len() > 1 {
+                return Some(Violation::Disagreement {
+                    slot_index: *slot_index,
+                    values,
+                });
+            }
+        }
+        None
+    }
+
+    // The first intact node that has not externalized a nominated slot.
+    pub fn stuck(&self) -> Option<Violation<N>> {
+        for slot_index in &self.slot_indices {
+            for node_id in &self.intact_nodes {
+                if self.externalized_value(node_id, slot_index).is_none() {
+                    return Some(Violation::Stuck {
+                        node_id: node_id.clone(),
+                        slot_index: *slot_index,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    // Hashes what the nodes will act on: their protocol state, the latest
+    // statements they hold, their queued messages and pending timers.
+    // Envelope IDs are creation times, so statements are hashed instead.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for (node_id, node) in &self.nodes {
+            node_id.hash(&mut hasher);
+            let envelopes = &node.scp.envelope_controller.envelopes;
+            let statement = |env_id| envelopes.0.get(env_id).map(|env| &env.statement);
+
+            for (slot_index, state) in &node.scp.ballot_protocol_states {
+                slot_index.hash(&mut hasher);
+                format!("{:?}", state.phase).hash(&mut hasher);
+                state.current_ballot.lock().unwrap().hash(&mut hasher);
+                state.prepared.hash(&mut hasher);
+                state.prepared_prime.hash(&mut hasher);
+                state.high_ballot.hash(&mut hasher);
+                state.commit.hash(&mut hasher);
+                for (peer_id, env_id) in &state.latest_envelopes {
+                    (peer_id, statement(env_id)).hash(&mut hasher);
+                }
+            }
+            for (slot_index, state) in &node.scp.nomination_protocol_states {
+                slot_index.hash(&mut hasher);
+                state.round_number.hash(&mut hasher);
+                state.votes.hash(&mut hasher);
+                state.accepted.hash(&mut hasher);
+                state.candidates.hash(&mut hasher);
+                for (peer_id, env_id) in &state.latest_nominations {
+                    (peer_id, statement(env_id)).hash(&mut hasher);
+                }
+                node.pending_timers(slot_index).hash(&mut hasher);
+            }
+
+            // Queued messages can be processed in any order, so the queue
+            // counts as a multiset.
+            let mut queued = node
+                .message_controller
+                .borrow()
+                .messages
+                .iter()
+                .map(message_key)
+                .collect::<Vec<_>>();
+            queued.sort();
+            queued.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+}
+
+fn message_key<N: NominationValue>(msg: &SCPMessage<N>) -> Option<SCPEnvelope<N>> {
+    match msg {
+        SCPMessage::SCP(env) => Some(env.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::state::{MockState, MockStateDriver};
+
+    use super::*;
+
+    type MockModelChecker = ModelChecker<MockState, MockStateDriver>;
+
+    fn three_nodes(quorum_dir_path: &str, config: ModelCheckConfig) -> MockModelChecker {
+        let mut checker =
+            MockModelChecker::new(quorum_dir_path, &["node1", "node2", "node3"], config);
+        checker
+            .nominate(&"node1".to_string(), 0, MockState::from_seed(0))
+            .unwrap();
+        checker
+    }
+
+    #[test]
+    fn three_nodes_agree_in_every_interleaving() {
+        let checker = three_nodes(
+            "model_check",
+            ModelCheckConfig {
+                max_depth: 4,
+                ..Default::default()
+            },
+        );
+        let report = checker.check().unwrap();
+
+        assert_eq!(report.counterexample, None);
+        assert!(!report.truncated);
+        assert!(report.states > 200, "{:?}", report);
+        assert!(report.frontier > 0);
+    }
+
+    #[test]
+    fn four_nodes_agree_in_every_interleaving() {
+        let mut checker = MockModelChecker::new(
+            "sim",
+            &["node1", "node2", "node3", "node4"],
+            ModelCheckConfig {
+                max_depth: 2,
+                ..Default::default()
+            },
+        );
+        checker
+            .nominate(&"node1".to_string(), 0, MockState::from_seed(1))
+            .unwrap();
+        let report = checker.check().unwrap();
+
+        assert_eq!(report.counterexample, None);
+        assert!(report.states > 10, "{:?}", report);
+    }
+
+    #[test]
+    fn insane_quorum_members_leave_nodes_stuck() {
+        // node3 is in every slice, but none of its ballot statements pass the
+        // sanity checks.
+        let mut checker = three_nodes(
+            "model_check",
+            ModelCheckConfig {
+                max_depth: 2,
+                ..Default::default()
+            },
+        );
+        checker
+            .make_byzantine(&"node3".to_string(), ByzantineBehaviour::Insane)
+            .unwrap();
+        let report = checker.check().unwrap();
+
+        let counterexample = report.counterexample.unwrap();
+        assert!(matches!(
+            counterexample.violation,
+            Violation::Stuck { slot_index: 0, .. }
+        ));
+
+        // The counterexample replays to a state that is still stuck.
+        let mut world = checker.replay(&counterexample.path).unwrap();
+        world.settle(checker.config.max_settle_steps);
+        assert_eq!(world.stuck(), Some(counterexample.violation));
+    }
+
+    #[test]
+    fn unknown_nodes_are_rejected() {
+        let mut checker = MockModelChecker::new("model_check", &["node1"], Default::default());
+        assert_eq!(
+            checker.nominate(&"node9".to_string(), 0, MockState::from_seed(0)),
+            Err(ModelCheckOpError::UnknownNode("node9".to_string()))
+        );
+    }
+}
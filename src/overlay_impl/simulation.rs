@@ -0,0 +1,687 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    rc::Rc,
+    time::{Duration, SystemTime},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    application::clock::{HVirtualClock, VirtualClock},
+    herder::herder::HerderDriver,
+    mock::builder::InMemoryPeerNode,
+    overlay::{
+        close_loop::CloseLoopConfig, message::SCPMessage, peer::PeerID, peer_node::PeerNode,
+    },
+    scp::{
+        ballot_protocol::SCPPhase, builder::InMemoryNodeBuilder, local_node::LocalNodeOpError,
+        nomination_protocol::NominationValue, scp::NodeID, slot::SlotIndex,
+    },
+};
+
+use super::byzantine::{ByzantineAdapter, ByzantineBehaviour};
+
+// A deterministic network of in-memory nodes. Every decision the network makes
+// (delays, drops, duplicates) comes from a RNG seeded by the configuration, and
+// time only advances on the simulation's virtual clock, so a run is fully
+// determined by its seed and the values nominated.
+
+pub type SimulationOpResult<T> = std::result::Result<T, SimulationOpError>;
+
+#[derive(Debug, PartialEq)]
+pub enum SimulationOpError {
+    // The node is not part of the simulation or has no quorum data on file.
+    UnknownNode(NodeID),
+    // The node's configuration on file is rejected, e.g. it lacks a key.
+    InvalidNode(LocalNodeOpError),
+    // Honest nodes externalized different values for the same slot.
+    Disagreement {
+        seed: u64,
+        slot_index: SlotIndex,
+        values: BTreeMap<NodeID, String>,
+    },
+}
+
+impl Display for SimulationOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulationOpError::UnknownNode(node_id) => write!(f, "unknown node {}", node_id),
+            SimulationOpError::InvalidNode(err) => write!(f, "invalid node: {}", err),
+            SimulationOpError::Disagreement {
+                seed,
+                slot_index,
+                values,
+            } => write!(
+                f,
+                "nodes externalized different values for slot {}: {:?} (replay with seed {})",
+                slot_index, values, seed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SimulationOpError {}
+
+impl From<LocalNodeOpError> for SimulationOpError {
+    fn from(err: LocalNodeOpError) -> Self {
+        match err {
+            LocalNodeOpError::MissingConfig(node_id) => SimulationOpError::UnknownNode(node_id),
+            err => SimulationOpError::InvalidNode(err),
+        }
+    }
+}
+
+// Splits `nodes` from the rest of the network between `start` and `heal`,
+// measured from the beginning of the simulation. Messages crossing the
+// partition are held back and delivered once it heals.
+#[derive(Clone, Debug)]
+pub struct Partition {
+    pub nodes: BTreeSet<NodeID>,
+    pub start: Duration,
+    pub heal: Duration,
+}
+
+impl Partition {
+    fn separates(&self, from: &NodeID, to: &NodeID, now: Duration) -> bool {
+        self.start <= now && now < self.heal && self.nodes.contains(from) != self.nodes.contains(to)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    pub seed: u64,
+    pub drop_probability: f64,
+    pub duplicate_probability: f64,
+    // Whether messages on the same link can overtake each other.
+    pub reorder: bool,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    pub partitions: Vec<Partition>,
+    // Upper bound on the number of deliveries in a run.
+    pub max_steps: usize,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+            reorder: false,
+            min_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+            partitions: Default::default(),
+            max_steps: 100_000,
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulationEventKind {
+    Sent,
+    Dropped,
+    Duplicated,
+    // Held back by a partition until it heals.
+    Held,
+    Delivered,
+}
+
+// A message event, recorded in the order it happened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimulationEvent {
+    pub time: Duration,
+    pub kind: SimulationEventKind,
+    pub from: NodeID,
+    pub to: NodeID,
+    pub slot_index: Option<SlotIndex>,
+}
+
+// What a node does on its own once its time comes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LocalEvent {
+    Close,
+    Timer(SlotIndex),
+}
+
+struct InFlightMessage<N>
+where
+    N: NominationValue,
+{
+    from: NodeID,
+    to: PeerID,
+    msg: SCPMessage<N>,
+}
+
+pub struct Simulation<N, H>
+where
+    N: NominationValue,
+    H: HerderDriver<N> + 'static,
+{
+    pub config: SimulationConfig,
+    pub nodes: BTreeMap<NodeID, InMemoryPeerNode<N, H>>,
+    // Nodes whose externalized values have to agree.
+    pub honest_nodes: BTreeSet<NodeID>,
+    pub trace: Vec<SimulationEvent>,
+    pub clock: HVirtualClock,
+    byzantine_nodes: BTreeMap<NodeID, ByzantineAdapter<N>>,
+    builder: InMemoryNodeBuilder<N, H>,
+    rng: StdRng,
+    start: SystemTime,
+    // Ordered by delivery time, then by the order messages were sent in.
+    in_flight: BTreeMap<(Duration, u64), InFlightMessage<N>>,
+    // The latest delivery time scheduled on each link.
+    link_times: BTreeMap<(NodeID, PeerID), Duration>,
+    next_seq: u64,
+}
+
+impl<N, H> Simulation<N, H>
+where
+    N: NominationValue,
+    H: HerderDriver<N> + 'static,
+{
+    pub fn new(
+        quorum_dir_path: &str,
+        node_ids: &[&str],
+        config: SimulationConfig,
+    ) -> SimulationOpResult<Self> {
+        let start = SystemTime::UNIX_EPOCH;
+        let clock = Rc::new(RefCell::new(VirtualClock::new(start)));
+        let mut builder = InMemoryNodeBuilder::new(quorum_dir_path).with_clock(clock.clone());
+
+        let mut nodes = BTreeMap::new();
+        for node_id in node_ids {
+            let node = builder.build_node(node_id)?;
+            nodes.insert(node_id.to_string(), node);
+        }
+
+        // The first node leads every slot.
+        if let Some(leader) = node_ids.first() {
+            PeerNode::add_leader_for_nodes(nodes.values_mut(), &leader.to_string());
+        }
+
+        Ok(Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            honest_nodes: nodes.keys().cloned().collect(),
+            config,
+            nodes,
+            trace: Default::default(),
+            clock,
+            byzantine_nodes: Default::default(),
+            builder,
+            start,
+            in_flight: Default::default(),
+            link_times: Default::default(),
+            next_seq: 0,
+        })
+    }
+
+    // Every node follows `leader` for the slots it creates from now on.
+    pub fn set_leader(&mut self, leader: &NodeID) -> SimulationOpResult<()> {
+        if !self.nodes.contains_key(leader) {
+            return Err(SimulationOpError::UnknownNode(leader.clone()));
+        }
+        PeerNode::add_leader_for_nodes(self.nodes.values_mut(), leader);
+        Ok(())
+    }
+
+    // The node keeps running the protocol, but what it sends is rewritten
+    // according to `behaviour`, and it no longer counts as honest.
+    pub fn make_byzantine(
+        &mut self,
+        node_id: &NodeID,
+        behaviour: ByzantineBehaviour<N>,
+    ) -> SimulationOpResult<()> {
+        let local_node = self
+            .nodes
+            .get(node_id)
+            .ok_or_else(|| SimulationOpError::UnknownNode(node_id.clone()))?
+            .scp
+            .local_node()
+            .clone();
+        self.honest_nodes.remove(node_id);
+        self.byzantine_nodes.insert(
+            node_id.clone(),
+            ByzantineAdapter::new(behaviour, local_node),
+        );
+        Ok(())
+    }
+
+    // Replaces the node with a fresh one that forgot every slot, as after a
+    // restart without persisted state. It keeps following the same leader.
+    pub fn restart_node(&mut self, node_id: &NodeID) -> SimulationOpResult<()> {
+        let old_node = self
+            .nodes
+            .get(node_id)
+            .ok_or_else(|| SimulationOpError::UnknownNode(node_id.clone()))?;
+        let leaders = old_node.scp.leaders.clone();
+
+        let mut node = self.builder.build_node(node_id)?;
+        node.scp.leaders = leaders;
+        self.nodes.insert(node_id.clone(), node);
+        Ok(())
+    }
+
+    pub fn now(&self) -> Duration {
+        self.clock
+            .borrow()
+            .time_now()
+            .duration_since(self.start)
+            .unwrap_or_default()
+    }
+
+    pub fn nominate(
+        &mut self,
+        node_id: &NodeID,
+        slot_index: SlotIndex,
+        value: N,
+    ) -> SimulationOpResult<()> {
+        self.nodes
+            .get_mut(node_id)
+            .ok_or_else(|| SimulationOpError::UnknownNode(node_id.clone()))?
+            .slot_nominate(slot_index, value);
+        self.collect_sent_messages();
+        Ok(())
+    }
+
+    // Moves the messages nodes sent through their in-memory connections into
+    // the simulated network.
+    fn collect_sent_messages(&mut self) {
+        loop {
+            let sent = {
+                let mut global_state = self.builder.global_state.borrow_mut();
+                global_state.msg_peer_id_queue.pop_front().and_then(|to| {
+                    let msg = global_state
+                        .peer_msg_queues
+                        .get(&to)?
+                        .borrow_mut()
+                        .messages
+                        .pop_front()?;
+                    Some((to, msg))
+                })
+            };
+
+            match sent {
+                Some((to, msg)) => self.send(to, msg),
+                None => break,
+            }
+        }
+    }
+
+    fn send(&mut self, to: PeerID, msg: SCPMessage<N>) {
+        let from = msg.sender().clone();
+        let msgs = match self.byzantine_nodes.get_mut(&from) {
+            Some(adapter) => adapter.tamper(&to, msg, &mut self.rng),
+            None => vec![msg],
+        };
+
+        for msg in msgs {
+            self.transmit(InFlightMessage {
+                from: from.clone(),
+                to: to.clone(),
+                msg,
+            });
+        }
+    }
+
+    fn transmit(&mut self, msg: InFlightMessage<N>) {
+        self.record(SimulationEventKind::Sent, &msg);
+
+        if self.rng.gen_bool(self.config.drop_probability) {
+            self.record(SimulationEventKind::Dropped, &msg);
+            return;
+        }
+
+        if self.rng.gen_bool(self.config.duplicate_probability) {
+            self.record(SimulationEventKind::Duplicated, &msg);
+            let duplicate = InFlightMessage {
+                from: msg.from.clone(),
+                to: msg.to.clone(),
+                msg: msg.msg.clone(),
+            };
+            let time = self.delivery_time(&duplicate);
+            self.schedule(time, duplicate);
+        }
+
+        let time = self.delivery_time(&msg);
+        self.schedule(time, msg);
+    }
+
+    fn delivery_time(&mut self, msg: &InFlightMessage<N>) -> Duration {
+        let delay = if self.config.min_delay < self.config.max_delay {
+            self.rng
+                .gen_range(self.config.min_delay..=self.config.max_delay)
+        } else {
+            self.config.min_delay
+        };
+        let mut time = self.now() + delay;
+
+        if !self.config.reorder {
+            let link = (msg.from.clone(), msg.to.clone());
+            if let Some(last) = self.link_times.get(&link) {
+                time = time.max(*last);
+            }
+            self.link_times.insert(link, time);
+        }
+
+        time
+    }
+
+    fn schedule(&mut self, time: Duration, msg: InFlightMessage<N>) {
+        self.in_flight.insert((time, self.next_seq), msg);
+        self.next_seq += 1;
+    }
+
+    fn record(&mut self, kind: SimulationEventKind, msg: &InFlightMessage<N>) {
+        let slot_index = match &msg.msg {
+            SCPMessage::SCP(env) => Some(env.slot_index),
+            _ => None,
+        };
+        self.trace.push(SimulationEvent {
+            time: self.now(),
+            kind,
+            from: msg.from.clone(),
+            to: msg.to.clone(),
+            slot_index,
+        });
+    }
+
+    // Every node closes slots in a loop from the next one it has to
+    // externalize, instead of waiting for `nominate`.
+    pub fn start_close_loop(&mut self, config: CloseLoopConfig) {
+        for node in self.nodes.values_mut() {
+            node.start_close_loop(config.clone());
+        }
+    }
+
+    // The earliest close loop nomination or slot timer due on any node.
+    fn next_local_event(&self) -> Option<(Duration, NodeID, LocalEvent)> {
+        self.nodes
+            .iter()
+            .flat_map(|(node_id, node)| {
+                let close = node
+                    .next_close_time()
+                    .map(|time| (time, node_id.clone(), LocalEvent::Close));
+                let timer = node.next_timer().map(|(time, slot_index)| {
+                    (time, node_id.clone(), LocalEvent::Timer(slot_index))
+                });
+                close.into_iter().chain(timer)
+            })
+            .map(|(time, node_id, event)| {
+                (
+                    time.duration_since(self.start).unwrap_or_default(),
+                    node_id,
+                    event,
+                )
+            })
+            .min()
+    }
+
+    // Delivers the next message in flight, or starts the next nomination of a
+    // close loop or fires a slot timer if it is due first. Returns false once
+    // the network is idle.
+    pub fn step(&mut self) -> bool {
+        let next_delivery = self.in_flight.first_key_value().map(|((time, _), _)| *time);
+        if let Some((time, node_id, event)) = self.next_local_event() {
+            if next_delivery.is_none_or(|delivery| time <= delivery) {
+                let time = time.max(self.now());
+                self.clock
+                    .borrow_mut()
+                    .set_current_virtual_time(self.start + time);
+                let node = self.nodes.get_mut(&node_id).unwrap();
+                match event {
+                    LocalEvent::Close => {
+                        node.poll_close_loop();
+                    }
+                    LocalEvent::Timer(slot_index) => {
+                        node.fire_timer(slot_index);
+                    }
+                }
+                self.collect_sent_messages();
+                return true;
+            }
+        }
+
+        let ((time, _), msg) = match self.in_flight.pop_first() {
+            Some(next) => next,
+            None => return false,
+        };
+        self.clock
+            .borrow_mut()
+            .set_current_virtual_time(self.start + time);
+
+        let heal = self
+            .config
+            .partitions
+            .iter()
+            .filter(|partition| partition.separates(&msg.from, &msg.to, time))
+            .map(|partition| partition.heal)
+            .max();
+        if let Some(heal) = heal {
+            self.record(SimulationEventKind::Held, &msg);
+            self.schedule(heal, msg);
+            return true;
+        }
+
+        self.record(SimulationEventKind::Delivered, &msg);
+        if let Some(adapter) = self.byzantine_nodes.get_mut(&msg.to) {
+            adapter.observe(&msg.msg);
+        }
+        if let Some(node) = self.nodes.get_mut(&msg.to) {
+            node.message_controller.borrow_mut().add_message(msg.msg);
+            node.process_one_message();
+        }
+        self.collect_sent_messages();
+
+        true
+    }
+
+    // Runs until the network is idle or the step limit is hit. Returns the
+    // number of steps taken.
+    pub fn run(&mut self) -> usize {
+        let mut steps = 0;
+        while steps < self.config.max_steps && self.step() {
+            steps += 1;
+        }
+        steps
+    }
+
+    pub fn externalized_value(&self, node_id: &NodeID, slot_index: &SlotIndex) -> Option<N> {
+        let state = self
+            .nodes
+            .get(node_id)?
+            .scp
+            .ballot_protocol_states
+            .get(slot_index)?;
+        if state.phase != SCPPhase::PhaseExternalize {
+            return None;
+        }
+        state.commit.as_ref().map(|ballot| ballot.value.clone())
+    }
+
+    // The values externalized by honest nodes, by slot.
+    pub fn externalized_values(&self) -> BTreeMap<SlotIndex, BTreeMap<NodeID, N>> {
+        let mut values: BTreeMap<SlotIndex, BTreeMap<NodeID, N>> = BTreeMap::new();
+        for node_id in &self.honest_nodes {
+            let Some(node) = self.nodes.get(node_id) else {
+                continue;
+            };
+            for slot_index in node.scp.ballot_protocol_states.keys() {
+                if let Some(value) = self.externalized_value(node_id, slot_index) {
+                    values
+                        .entry(*slot_index)
+                        .or_default()
+                        .insert(node_id.clone(), value);
+                }
+            }
+        }
+        values
+    }
+
+    // Checks that all honest nodes that externalized a slot agree on its value.
+    pub fn check_agreement(&self) -> SimulationOpResult<()> {
+        for (slot_index, values) in self.externalized_values() {
+            let distinct = values.values().collect::<BTreeSet<_>>();
+            if distinct.len() > 1 {
+                return Err(SimulationOpError::Disagreement {
+                    seed: self.config.seed,
+                    slot_index,
+                    values: values
+                        .iter()
+                        .map(|(node_id, value)| (node_id.clone(), format!("{:?}", value)))
+                        .collect(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Setup shared by the tests running consensus between the simulated nodes.
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        ca::crypto::{PrivateKey, PublicKey},
+        mock::state::{MockState, MockStateDriver},
+        scp::{
+            local_node::{LocalNodeInfo, LocalNodeInfoBuilderFromFile},
+            scp::NodeID,
+            slot::SlotIndex,
+        },
+    };
+
+    use super::{Simulation, SimulationConfig};
+
+    pub const SIM_DIR: &str = "sim";
+    pub const SIM_NODES: [&str; 4] = ["node1", "node2", "node3", "node4"];
+
+    pub type MockSimulation = Simulation<MockState, MockStateDriver>;
+
+    pub fn mock_simulation(config: SimulationConfig) -> MockSimulation {
+        MockSimulation::new(SIM_DIR, &SIM_NODES, config).unwrap()
+    }
+
+    // The simulated nodes read their keys from their configuration on file.
+    fn node_info(node_id: &str) -> LocalNodeInfo<MockState> {
+        LocalNodeInfoBuilderFromFile::new(SIM_DIR)
+            .build_from_file(node_id)
+            .unwrap()
+    }
+
+    pub fn private_key(node_id: &str) -> PrivateKey {
+        node_info(node_id).private_key.unwrap()
+    }
+
+    // All simulated nodes share the same quorum set, and so the same keys.
+    pub fn public_keys() -> BTreeMap<NodeID, PublicKey> {
+        node_info(SIM_NODES[0]).public_keys
+    }
+
+    // Has node1 nominate a value for each slot and runs the network until it
+    // is quiet again.
+    pub fn nominate_slots(sim: &mut MockSimulation, slots: std::ops::Range<SlotIndex>) {
+        for slot_index in slots {
+            sim.nominate(
+                &"node1".to_string(),
+                slot_index,
+                MockState::from_seed(slot_index),
+            )
+            .unwrap();
+            sim.run();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test_utils::*, *};
+
+    fn run_slots(config: SimulationConfig, slots: u64) -> MockSimulation {
+        let mut sim = mock_simulation(config);
+        nominate_slots(&mut sim, 0..slots);
+        sim
+    }
+
+    #[test]
+    fn honest_nodes_externalize_in_order() {
+        let sim = run_slots(SimulationConfig::with_seed(1), 2);
+        assert_eq!(sim.check_agreement(), Ok(()));
+
+        let values = sim.externalized_values();
+        for slot_index in 0..2 {
+            assert_eq!(values[&slot_index].len(), SIM_NODES.len());
+        }
+    }
+
+    #[test]
+    fn faulty_networks_agree_and_replay() {
+        for seed in 0..20 {
+            let config = SimulationConfig {
+                seed,
+                drop_probability: 0.05,
+                duplicate_probability: 0.1,
+                reorder: true,
+                min_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(200),
+                partitions: vec![Partition {
+                    nodes: BTreeSet::from(["node4".to_string()]),
+                    start: Duration::ZERO,
+                    heal: Duration::from_millis(300),
+                }],
+                ..Default::default()
+            };
+
+            let sim = run_slots(config.clone(), 2);
+            if let Err(err) = sim.check_agreement() {
+                panic!("{}", err);
+            }
+
+            let replayed = run_slots(config, 2);
+            assert_eq!(sim.trace, replayed.trace, "seed {}", seed);
+            assert_eq!(sim.externalized_values(), replayed.externalized_values());
+            // Nodes send their statements again until the slot externalizes,
+            // so lost messages do not stall anyone.
+            let values = sim.externalized_values();
+            for slot_index in 0..2 {
+                assert_eq!(values[&slot_index].len(), SIM_NODES.len(), "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn partitions_hold_messages_until_healed() {
+        let config = SimulationConfig {
+            partitions: vec![Partition {
+                nodes: BTreeSet::from(["node1".to_string(), "node2".to_string()]),
+                start: Duration::ZERO,
+                heal: Duration::from_secs(5),
+            }],
+            ..SimulationConfig::with_seed(7)
+        };
+        let sim = run_slots(config, 1);
+
+        let held = sim
+            .trace
+            .iter()
+            .filter(|event| event.kind == SimulationEventKind::Held)
+            .count();
+        assert!(held > 0);
+        assert!(sim.trace.iter().any(|event| {
+            event.kind == SimulationEventKind::Delivered && event.time >= Duration::from_secs(5)
+        }));
+        assert_eq!(sim.check_agreement(), Ok(()));
+        assert_eq!(sim.externalized_values()[&0].len(), SIM_NODES.len());
+    }
+}
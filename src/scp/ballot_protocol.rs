@@ -4,7 +4,6 @@ use std::{
     hash::Hash,
     marker::PhantomData,
     sync::{Arc, Mutex},
-    time::SystemTime,
 };
 
 use log::debug;
@@ -198,7 +197,7 @@ impl<N: NominationValue> Default for SCPBallot<N> {
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum SCPPhase {
     PhasePrepare,
     PhaseConfirm,
@@ -786,7 +785,7 @@ where
                 } else {
                     0
                 };
-                let num_high = if let Some(val) = self.commit.as_ref() {
+                let num_high = if let Some(val) = self.high_ballot.as_ref() {
                     val.counter.clone()
                 } else {
                     0
@@ -818,7 +817,7 @@ where
                     .expect("Current ballot")
                     .clone(),
                 num_prepared: self.prepared.as_ref().expect("Prepared").counter.clone(),
-                num_commit: self.prepared.as_ref().expect("Commit").counter.clone(),
+                num_commit: self.commit.as_ref().expect("Commit").counter.clone(),
                 num_high: self
                     .high_ballot
                     .as_ref()
@@ -840,6 +839,61 @@ where
             }),
         }
     }
+
+    // Restores b, p, p', h, c and the phase from an envelope this node emitted
+    // earlier, e.g. when it restarts in the middle of a slot. Returns false if
+    // the ballot protocol already started or the envelope is not a ballot one.
+    pub fn set_state_from_envelope(
+        &mut self,
+        env_id: &SCPEnvelopeID,
+        envelope_controller: &SCPEnvelopeController<N>,
+    ) -> bool {
+        if self.current_ballot.lock().unwrap().is_some() {
+            return false;
+        }
+        let Some(envelope) = envelope_controller.get_envelope(env_id) else {
+            return false;
+        };
+
+        match envelope.get_statement() {
+            SCPStatement::Prepare(st) => {
+                let value = &st.ballot.value;
+                self.bump_to_ballot(true, &st.ballot);
+                self.prepared = st.prepared.clone();
+                self.prepared_prime = st.prepared_prime.clone();
+                if st.num_high != 0 {
+                    self.high_ballot = Some(SCPBallot::new(st.num_high, value.clone()));
+                }
+                if st.num_commit != 0 {
+                    self.commit = Some(SCPBallot::new(st.num_commit, value.clone()));
+                }
+                self.phase = SCPPhase::PhasePrepare;
+            }
+            SCPStatement::Confirm(st) => {
+                let value = &st.ballot.value;
+                self.bump_to_ballot(true, &st.ballot);
+                self.prepared = Some(SCPBallot::new(st.num_prepared, value.clone()));
+                self.high_ballot = Some(SCPBallot::new(st.num_high, value.clone()));
+                self.commit = Some(SCPBallot::new(st.num_commit, value.clone()));
+                self.phase = SCPPhase::PhaseConfirm;
+            }
+            SCPStatement::Externalize(st) => {
+                let value = &st.commit.value;
+                self.bump_to_ballot(true, &SCPBallot::new(u32::MAX, value.clone()));
+                self.prepared = Some(SCPBallot::new(u32::MAX, value.clone()));
+                self.high_ballot = Some(SCPBallot::new(st.num_high, value.clone()));
+                self.commit = Some(st.commit.clone());
+                self.phase = SCPPhase::PhaseExternalize;
+            }
+            SCPStatement::Nominate(_) => return false,
+        }
+
+        self.latest_envelopes
+            .insert(envelope.node_id.to_owned(), env_id.to_owned());
+        self.last_envelope = Some(envelope.clone().into());
+        self.last_envelope_emitted = self.last_envelope.clone();
+        true
+    }
 }
 
 impl<N> Default for BallotProtocolState<N>
@@ -1060,13 +1114,9 @@ where
         );
 
         let local_node_id = self.local_node.node_id.clone();
-        // TODO:
-        let envelope = SCPEnvelope::<N>::new(
-            statement,
-            local_node_id.to_owned(),
-            self.slot_index,
-            [0; 64],
-        );
+        let mut envelope =
+            SCPEnvelope::<N>::new(statement, local_node_id.to_owned(), self.slot_index);
+        self.local_node.sign_envelope(&mut envelope);
         let env_id = env_map.add_envelope(envelope.clone());
 
         debug!("latest envelopes: {:?}", state.latest_envelopes);
@@ -1340,9 +1390,18 @@ where
         // transition to higher counters (messages are ignored upstream)
         // therefore the local node will not flip flop between "seen" and "not
         // seen" for a given counter on the local node
-        if let Some(current_ballot) = ballot_state.current_ballot.lock().unwrap().as_ref() {
+        // Release the lock on the current ballot before starting the timer,
+        // which locks it again.
+        let current_counter = ballot_state
+            .current_ballot
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|current_ballot| current_ballot.counter);
+
+        if let Some(current_counter) = current_counter {
             let heard_predicate = |statement: &SCPStatement<N>| match statement {
-                SCPStatement::Prepare(st) => current_ballot.counter <= st.ballot.counter,
+                SCPStatement::Prepare(st) => current_counter <= st.ballot.counter,
                 SCPStatement::Confirm(_) => true,
                 SCPStatement::Externalize(_) => true,
                 SCPStatement::Nominate(_) => {
@@ -1408,7 +1467,7 @@ where
 
             let abandon_ballot_job = SlotJob {
                 id: self.slot_index.clone(),
-                timestamp: SystemTime::now() + timeout,
+                timestamp: self.scheduler.borrow().now() + timeout,
                 task: SlotTask::AbandonBallot(abandon_ballot_arg),
             };
 
@@ -1417,9 +1476,7 @@ where
     }
 
     fn stop_ballot_protocol_timer(&self, ballot_state: &BallotProtocolState<N>) {
-        self.slot_state
-            .borrow_mut()
-            .stop_timer(&SlotStateTimer::BallotProtocol)
+        self.stop_timer(SlotStateTimer::BallotProtocol)
     }
 }
 
@@ -1535,9 +1592,11 @@ where
         // TODO: we do we need to loop through all candidates?
         for candidate in &candidates {
             if state.phase == SCPPhase::PhaseConfirm {
+                // only consider the ballot if it may help us increase p (note: at this point,
+                // p ~ c)
                 match state.prepared.as_ref() {
                     Some(prepared_ballot) => {
-                        if prepared_ballot.less_and_compatible(&candidate) {
+                        if !prepared_ballot.less_and_compatible(&candidate) {
                             continue;
                         }
                     }
@@ -2264,7 +2323,7 @@ where
             herder_driver,
         );
 
-        if attempted_accept_commit {
+        if attempted_confirm_commit {
             self.value_externalized(
                 self.slot_index,
                 &ballot_state
@@ -2316,3 +2375,262 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, sync::OnceLock, time::Duration};
+
+    use proptest::prelude::*;
+
+    use crate::{
+        application::work_queue::WorkScheduler,
+        ca::crypto::{PrivateKey, SignatureAlgorithm},
+        mock::state::{MockState, MockStateDriver},
+        overlay::trace::TraceEvent,
+        overlay_impl::simulation::{Simulation, SimulationConfig},
+        scp::{local_node_builder::LocalNodeBuilder, scp_driver_builder::SlotDriverBuilder},
+    };
+
+    use super::*;
+
+    const NODES: [&str; 4] = ["node1", "node2", "node3", "node4"];
+
+    // Every node of the "sim" quorum has the same single slice, so any peer on
+    // its own is v-blocking for node1. Random statements that pass the sanity
+    // checks would therefore make a faulty v-blocking set, which SCP does not
+    // tolerate; the valid envelopes are taken from honest runs instead.
+    struct HonestRun {
+        quorum_set: QuorumSet,
+        // Ballot envelopes node1 received for slot 0, in delivery order.
+        envelopes: Vec<SCPEnvelope<MockState>>,
+        externalized: MockState,
+    }
+
+    fn honest_run(seed: u64) -> HonestRun {
+        let config = SimulationConfig {
+            reorder: true,
+            max_delay: Duration::from_millis(50),
+            ..SimulationConfig::with_seed(seed)
+        };
+        let mut sim = Simulation::<MockState, MockStateDriver>::new("sim", &NODES, config).unwrap();
+        let node_id = "node1".to_string();
+        sim.nodes
+            .get_mut(&node_id)
+            .unwrap()
+            .record_trace(None)
+            .unwrap();
+        sim.nominate(&node_id, 0, MockState::from_seed(seed))
+            .unwrap();
+        sim.run();
+
+        let node = &sim.nodes[&node_id];
+        let envelopes = node
+            .trace_recorder
+            .as_ref()
+            .unwrap()
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                TraceEvent::Received(env)
+                    if env.slot_index == 0
+                        && !matches!(env.statement, SCPStatement::Nominate(_)) =>
+                {
+                    Some(env.clone())
+                }
+                _ => None,
+            })
+            .collect();
+
+        HonestRun {
+            quorum_set: node.scp.slots[&0].local_node.quorum_set.clone(),
+            envelopes,
+            externalized: sim.externalized_value(&node_id, &0).unwrap(),
+        }
+    }
+
+    fn honest_runs() -> &'static Vec<HonestRun> {
+        static RUNS: OnceLock<Vec<HonestRun>> = OnceLock::new();
+        RUNS.get_or_init(|| (0..4).map(honest_run).collect())
+    }
+
+    fn phase_rank(phase: &SCPPhase) -> u8 {
+        match phase {
+            SCPPhase::PhasePrepare => 0,
+            SCPPhase::PhaseConfirm => 1,
+            SCPPhase::PhaseExternalize => 2,
+        }
+    }
+
+    fn arb_ballot(min_counter: u32) -> impl Strategy<Value = SCPBallot<MockState>> {
+        (min_counter..4u32, 0u64..4).prop_map(|(counter, value)| SCPBallot {
+            counter,
+            value: MockState::from_seed(value),
+        })
+    }
+
+    // Statements with zero counters, bad commit ranges or an unknown quorum
+    // set.
+    fn arb_invalid_statement() -> impl Strategy<Value = SCPStatement<MockState>> {
+        let prepare = (
+            arb_ballot(0),
+            prop::option::of(arb_ballot(1)),
+            prop::option::of(arb_ballot(1)),
+            0u32..4,
+            0u32..4,
+        )
+            .prop_map(|(ballot, prepared, prepared_prime, num_commit, num_high)| {
+                SCPStatement::Prepare(SCPStatementPrepare {
+                    quorum_set_hash: [0; 64],
+                    ballot,
+                    prepared,
+                    prepared_prime,
+                    num_commit,
+                    num_high,
+                    quorum_set: None,
+                })
+            });
+        let confirm = (arb_ballot(0), 0u32..4, 0u32..4, 0u32..4).prop_map(
+            |(ballot, num_prepared, num_commit, num_high)| {
+                SCPStatement::Confirm(SCPStatementConfirm {
+                    quorum_set_hash: [0; 64],
+                    ballot,
+                    num_prepared,
+                    num_commit,
+                    num_high,
+                    quorum_set: None,
+                })
+            },
+        );
+        let externalize = (arb_ballot(0), 0u32..4).prop_map(|(commit, num_high)| {
+            SCPStatement::Externalize(SCPStatementExternalize {
+                commit_quorum_set_hash: [0; 64],
+                commit,
+                num_high,
+                commit_quorum_set: None,
+            })
+        });
+
+        (prop_oneof![prepare, confirm, externalize], any::<bool>()).prop_filter_map(
+            "statement is valid",
+            |(mut statement, known_quorum_set)| {
+                if known_quorum_set {
+                    let hash = honest_runs()[0].quorum_set.hash_value();
+                    match &mut statement {
+                        SCPStatement::Prepare(st) => st.quorum_set_hash = hash,
+                        SCPStatement::Confirm(st) => st.quorum_set_hash = hash,
+                        SCPStatement::Externalize(st) => st.commit_quorum_set_hash = hash,
+                        SCPStatement::Nominate(_) => unreachable!(),
+                    }
+                    if statement.is_statement_sane(false) {
+                        return None;
+                    }
+                }
+                Some(statement)
+            },
+        )
+    }
+
+    #[derive(Clone, Debug)]
+    enum Step {
+        // Delivers the honest envelope with this index, possibly again or
+        // after a newer one.
+        Honest(usize),
+        Invalid(SCPEnvelope<MockState>),
+    }
+
+    fn arb_step() -> impl Strategy<Value = Step> {
+        // node1 only processes its own statements as it emits them.
+        let invalid = (1..NODES.len(), arb_invalid_statement()).prop_map(|(node, statement)| {
+            Step::Invalid(SCPEnvelope::new(statement, NODES[node].into(), 0))
+        });
+        prop_oneof![3 => any::<usize>().prop_map(Step::Honest), 1 => invalid]
+    }
+
+    proptest! {
+        #[test]
+        fn ballot_invariants_hold(
+            run in 0..honest_runs().len(),
+            in_order in any::<bool>(),
+            steps in prop::collection::vec(arb_step(), 1..60),
+        ) {
+            let run = &honest_runs()[run];
+            let local_node = LocalNodeBuilder::<MockState>::new()
+                .is_validator(true)
+                .private_key(PrivateKey::generate(SignatureAlgorithm::Ed25519))
+                .quorum_set(run.quorum_set.clone())
+                .node_id("node1".into())
+                .build()
+                .unwrap();
+            let slot_driver = SlotDriverBuilder::<MockState, MockStateDriver>::new()
+                .slot_index(0)
+                .timer(Rc::new(RefCell::new(WorkScheduler::new(None))))
+                .local_node(Arc::new(local_node))
+                .build()
+                .unwrap();
+
+            let mut ballot_state = BallotProtocolState::default();
+            let mut nomination_state = NominationProtocolState::new("node1".into());
+            let mut envelope_controller = SCPEnvelopeController::new();
+            let mut quorum_manager = QuorumManager::default();
+            quorum_manager.add_quorum_set(&run.quorum_set);
+            let mut herder = MockStateDriver::new();
+
+            // Either replay the honest run as it happened, followed by the
+            // random steps, or only the random steps.
+            let mut envelopes = vec![];
+            if in_order {
+                envelopes.extend(run.envelopes.iter().cloned().map(|env| (env, true)));
+            }
+            for step in steps {
+                envelopes.push(match step {
+                    Step::Honest(index) => {
+                        (run.envelopes[index % run.envelopes.len()].clone(), true)
+                    }
+                    Step::Invalid(env) => (env, false),
+                });
+            }
+
+            let mut phase = 0;
+            let mut committed: Option<MockState> = None;
+
+            for (envelope, valid) in envelopes {
+                let env_id = envelope_controller.add_envelope(envelope);
+                let env_state = slot_driver.process_ballot_envelope(
+                    &mut ballot_state,
+                    &mut nomination_state,
+                    &env_id,
+                    false,
+                    &mut envelope_controller.envelopes,
+                    &mut envelope_controller.envs_to_emit,
+                    &quorum_manager,
+                    &mut herder,
+                );
+                if !valid {
+                    prop_assert_eq!(env_state, EnvelopeState::Invalid);
+                }
+
+                ballot_state.check_invariants();
+
+                let next_phase = phase_rank(&ballot_state.phase);
+                prop_assert!(next_phase >= phase, "phase went from {} to {}", phase, next_phase);
+                phase = next_phase;
+
+                // Once a commit is confirmed, the value can no longer change.
+                if ballot_state.phase == SCPPhase::PhaseExternalize {
+                    let value = ballot_state.commit.as_ref().unwrap().value.clone();
+                    if let Some(committed) = committed.as_ref() {
+                        prop_assert_eq!(committed, &value);
+                    }
+                    committed = Some(value);
+                }
+            }
+
+            if let Some(committed) = committed {
+                prop_assert_eq!(committed, run.externalized.clone());
+            }
+            if in_order {
+                prop_assert_eq!(ballot_state.phase, SCPPhase::PhaseExternalize);
+            }
+        }
+    }
+}
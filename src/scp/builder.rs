@@ -1,10 +1,12 @@
+use crate::application::clock::HVirtualClock;
 use crate::application::work_queue::WorkScheduler;
+use crate::ca::signing::NetworkId;
 use crate::herder::herder::HerderDriver;
 use crate::mock::builder::InMemoryPeerNode;
 use crate::overlay::peer_node::PeerNode;
 use crate::overlay_impl::in_memory_conn::{InMemoryConn, InMemoryConnBuilder};
 use crate::overlay_impl::in_memory_global::InMemoryGlobalState;
-use crate::scp::local_node::LocalNodeInfoBuilderFromFile;
+use crate::scp::local_node::{LocalNodeInfoBuilderFromFile, LocalNodeOpResult};
 use crate::scp::nomination_protocol::NominationValue;
 use crate::scp::scp::NodeID;
 use std::cell::RefCell;
@@ -20,6 +22,8 @@ where
     pub global_state: Rc<RefCell<InMemoryGlobalState<N>>>,
     pub nodes: HashMap<NodeID, Rc<RefCell<InMemoryPeerNode<N, H>>>>,
     local_node_info_builder: LocalNodeInfoBuilderFromFile,
+    clock: Option<HVirtualClock>,
+    network_id: Option<NetworkId>,
 }
 
 impl<N, H> InMemoryNodeBuilder<N, H>
@@ -34,19 +38,37 @@ where
             local_node_info_builder,
             global_state: InMemoryGlobalState::new_handle(),
             nodes: Default::default(),
+            clock: None,
+            network_id: None,
         }
     }
 
+    // Nodes built afterwards schedule their work on the given clock.
+    pub fn with_clock(mut self, clock: HVirtualClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    // Nodes built afterwards run on the given network, whatever their
+    // configuration says.
+    pub fn with_network_id(mut self, network_id: NetworkId) -> Self {
+        self.network_id = Some(network_id);
+        self
+    }
+
     pub fn build_node_with_herder(
         &mut self,
         node_idx: &str,
         herder: H,
-    ) -> Option<PeerNode<N, H, InMemoryConn<N>, InMemoryConnBuilder<N>>> {
-        let local_node_info: crate::scp::local_node::LocalNodeInfo<N> =
+    ) -> LocalNodeOpResult<PeerNode<N, H, InMemoryConn<N>, InMemoryConnBuilder<N>>> {
+        let mut local_node_info: crate::scp::local_node::LocalNodeInfo<N> =
             self.local_node_info_builder.build_from_file(node_idx)?;
+        if let Some(network_id) = self.network_id {
+            local_node_info.network_id = network_id;
+        }
 
         let conn_builder = InMemoryConnBuilder::new(&self.global_state);
-        let work_scheduler = Rc::new(RefCell::new(WorkScheduler::new(None)));
+        let work_scheduler = Rc::new(RefCell::new(WorkScheduler::new(self.clock.clone())));
 
         let peer = PeerNode::new(
             node_idx.to_owned(),
@@ -62,13 +84,13 @@ where
             .peer_msg_queues
             .insert(node_idx.to_owned(), msg_controller);
 
-        Some(peer)
+        Ok(peer)
     }
 
     pub fn build_node(
         &mut self,
         node_idx: &str,
-    ) -> Option<PeerNode<N, H, InMemoryConn<N>, InMemoryConnBuilder<N>>> {
+    ) -> LocalNodeOpResult<PeerNode<N, H, InMemoryConn<N>, InMemoryConnBuilder<N>>> {
         self.build_node_with_herder(node_idx, H::new())
     }
 }
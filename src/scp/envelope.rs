@@ -5,13 +5,22 @@ use std::{
 
 use serde_derive::{Deserialize, Serialize};
 
-use crate::application::quorum::QuorumSet;
+use crate::{
+    application::quorum::QuorumSet,
+    ca::{
+        crypto::{PrivateKey, PublicKey, SCPSignature},
+        signing::NetworkId,
+    },
+};
 
 use super::{
-    nomination_protocol::NominationValue, scp::NodeID, scp_driver::HashValue, slot::SlotIndex,
-    statement::SCPStatement,
+    nomination_protocol::NominationValue, scp::NodeID, slot::SlotIndex, statement::SCPStatement,
 };
 
+// Envelopes are signed over the network they are sent on, so they are not
+// accepted by nodes of any other network.
+const SIGNING_PREFIX: &str = "general_scp/scp/v1";
+
 pub trait MakeEnvelope<N>
 where
     N: NominationValue,
@@ -28,11 +37,34 @@ where
     pub node_id: NodeID,
     pub slot_index: SlotIndex,
 
+    // Encoded `SCPSignature`, empty while the envelope is unsigned.
     #[serde(with = "serde_bytes")]
-    pub signature: HashValue,
+    pub signature: Vec<u8>,
 }
 
 impl<N: NominationValue> SCPEnvelope<N> {
+    // The signature covers everything in the envelope but itself.
+    fn signed_bytes(&self, network_id: &NetworkId) -> Vec<u8> {
+        bincode::serialize(&(
+            SIGNING_PREFIX,
+            network_id,
+            &self.statement,
+            &self.node_id,
+            self.slot_index,
+        ))
+        .unwrap()
+    }
+
+    pub fn sign(&mut self, private_key: &PrivateKey, network_id: &NetworkId) {
+        let signed_bytes = self.signed_bytes(network_id);
+        self.signature = SCPSignature::sign(private_key, &signed_bytes).to_bytes();
+    }
+
+    pub fn verify_signature(&self, public_key: &PublicKey, network_id: &NetworkId) -> bool {
+        SCPSignature::from_bytes(&self.signature)
+            .is_some_and(|signature| signature.verify(public_key, &self.signed_bytes(network_id)))
+    }
+
     pub fn get_quorum_set(&self) -> Option<&QuorumSet> {
         // TODO: should really refactor quorum set out into the scpenvelope struct.
         match &self.statement {
@@ -114,4 +146,13 @@ where
     pub fn get_envelope(&self, env_id: &SCPEnvelopeID) -> Option<&SCPEnvelope<N>> {
         self.envelopes.0.get(env_id)
     }
+
+    // Drops the envelopes of the slots `keep` rejects, including those still
+    // waiting to be emitted.
+    pub fn retain_slots(&mut self, keep: impl Fn(SlotIndex) -> bool) {
+        self.envelopes.0.retain(|_, env| keep(env.slot_index));
+        let envelopes = &self.envelopes.0;
+        self.envs_to_emit
+            .retain(|env_id| envelopes.contains_key(env_id));
+    }
 }
@@ -2,6 +2,7 @@ use std::{
     cell::RefCell,
     collections::{BTreeMap, BTreeSet},
     env,
+    fmt::Display,
     fs::{self, create_dir_all},
     io::Write,
     iter::{self},
@@ -18,12 +19,16 @@ use crate::{
         is_v_blocking, nodes_fill_quorum_slice, nodes_form_quorum, HQuorumSet, QuorumNode,
         QuorumSet, QuorumSlice,
     },
+    ca::{
+        crypto::{PrivateKey, PublicKey},
+        signing::NetworkId,
+    },
     mock::state::MockState,
     utils::config::test_data_dir,
 };
 
 use super::{
-    envelope::{EnvMap, SCPEnvelopeController, SCPEnvelopeID},
+    envelope::{EnvMap, SCPEnvelope, SCPEnvelopeController, SCPEnvelopeID},
     nomination_protocol::NominationValue,
     scp::NodeID,
     statement::SCPStatement,
@@ -31,6 +36,44 @@ use super::{
 
 pub type HLocalNode<N> = Rc<RefCell<LocalNodeInfo<N>>>;
 
+pub type LocalNodeOpResult<T> = std::result::Result<T, LocalNodeOpError>;
+
+#[derive(Debug, PartialEq)]
+pub enum LocalNodeOpError {
+    // The node has no configuration on file, or it does not parse.
+    MissingConfig(NodeID),
+    // A node of the quorum set has no configuration on file.
+    UnknownQuorumNode(NodeID),
+    // Validators sign every envelope they send.
+    MissingPrivateKey(NodeID),
+    InvalidPrivateKey(NodeID),
+    InvalidPublicKey(NodeID),
+}
+
+impl Display for LocalNodeOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocalNodeOpError::MissingConfig(node_id) => {
+                write!(f, "no configuration for node {}", node_id)
+            }
+            LocalNodeOpError::UnknownQuorumNode(node_id) => {
+                write!(f, "no configuration for quorum node {}", node_id)
+            }
+            LocalNodeOpError::MissingPrivateKey(node_id) => {
+                write!(f, "validator {} has no private key", node_id)
+            }
+            LocalNodeOpError::InvalidPrivateKey(node_id) => {
+                write!(f, "invalid private key for node {}", node_id)
+            }
+            LocalNodeOpError::InvalidPublicKey(node_id) => {
+                write!(f, "invalid public key for node {}", node_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LocalNodeOpError {}
+
 pub struct LocalNodeInfoBuilderFromFile {
     nodes: BTreeMap<NodeID, QuorumNode>,
     quorum_dir: PathBuf,
@@ -51,7 +94,7 @@ impl LocalNodeInfoBuilderFromFile {
     fn build_local_info_from_toml<N: NominationValue>(
         &mut self,
         toml_info: LocalNodeInfoToml,
-    ) -> Option<LocalNodeInfo<N>> {
+    ) -> LocalNodeOpResult<LocalNodeInfo<N>> {
         for node_id in toml_info
             .quorum_set
             .iter()
@@ -59,7 +102,8 @@ impl LocalNodeInfoBuilderFromFile {
             .chain(iter::once(&toml_info.node_id))
         {
             if !self.nodes.contains_key(node_id) {
-                let node = QuorumNode::from_toml(node_id)?;
+                let node = QuorumNode::from_toml(node_id)
+                    .ok_or_else(|| LocalNodeOpError::UnknownQuorumNode(node_id.to_owned()))?;
                 self.nodes.insert(node_id.to_owned(), node);
             }
         }
@@ -80,21 +124,19 @@ impl LocalNodeInfoBuilderFromFile {
             threshold: 0,
         };
 
-        let local_node_info = LocalNodeInfo::<N>::from_toml_info(toml_info, quorum_set);
-
-        Some(local_node_info)
+        LocalNodeInfo::<N>::from_toml_info(toml_info, quorum_set)
     }
 
     pub fn build_from_file<N: NominationValue>(
         &mut self,
         node_id: &str,
-    ) -> Option<LocalNodeInfo<N>> {
+    ) -> LocalNodeOpResult<LocalNodeInfo<N>> {
+        let missing_config = || LocalNodeOpError::MissingConfig(node_id.to_owned());
         let path = self.quorum_dir.join(node_id);
-        let toml_str = fs::read_to_string(path).ok()?;
-        let node_toml = toml::from_str(&toml_str).ok()?;
+        let toml_str = fs::read_to_string(path).map_err(|_| missing_config())?;
+        let node_toml = toml::from_str(&toml_str).map_err(|_| missing_config())?;
 
-        let node_info = self.build_local_info_from_toml(node_toml)?;
-        Some(node_info)
+        self.build_local_info_from_toml(node_toml)
     }
 }
 
@@ -103,6 +145,12 @@ pub struct LocalNodeInfoToml {
     pub is_validator: bool,
     pub quorum_set: BTreeSet<BTreeSet<NodeID>>,
     pub node_id: NodeID,
+    // PKCS#8 PEM encoded, validators need one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+    // Nodes without one run on the default network.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_id: Option<NetworkId>,
 }
 
 impl<N> From<LocalNodeInfo<N>> for LocalNodeInfoToml
@@ -126,12 +174,18 @@ where
         Self {
             is_validator: local_node_info.is_validator,
             quorum_set,
+            private_key: local_node_info
+                .private_key
+                .as_ref()
+                .and_then(PrivateKey::to_pkcs8_pem),
+            network_id: (local_node_info.network_id != NetworkId::default())
+                .then_some(local_node_info.network_id),
             node_id: local_node_info.node_id,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct LocalNodeInfo<N>
 where
     N: NominationValue + 'static,
@@ -139,6 +193,15 @@ where
     pub is_validator: bool,
     pub quorum_set: QuorumSet,
     pub node_id: NodeID,
+    // Envelopes are signed with it. Only nodes that are not validators may go
+    // without one.
+    #[serde(skip)]
+    pub private_key: Option<PrivateKey>,
+    // Keys of the nodes in the quorum set, from their configuration.
+    #[serde(skip)]
+    pub public_keys: BTreeMap<NodeID, PublicKey>,
+    // Envelopes are signed for this network and only accepted from it.
+    pub network_id: NetworkId,
     phantom: PhantomData<N>,
 }
 
@@ -158,21 +221,69 @@ where
     const TEST_DATA_DIR: &'static str = "node_info";
 
     pub fn new(is_validator: bool, quorum_set: QuorumSet, node_id: NodeID) -> Self {
+        let public_keys = quorum_set
+            .nodes()
+            .iter()
+            .filter_map(|node| Some((node.node_id.clone(), node.decode_public_key()?)))
+            .collect();
+
         Self {
             is_validator,
             quorum_set,
             node_id,
+            private_key: None,
+            public_keys,
+            network_id: Default::default(),
             phantom: PhantomData,
         }
     }
 
-    pub fn from_toml_info(toml_info: LocalNodeInfoToml, quorum_set: QuorumSet) -> Self {
-        Self {
-            is_validator: toml_info.is_validator,
-            quorum_set,
-            node_id: toml_info.node_id,
-            phantom: PhantomData,
+    pub fn from_toml_info(
+        toml_info: LocalNodeInfoToml,
+        quorum_set: QuorumSet,
+    ) -> LocalNodeOpResult<Self> {
+        let mut local_node_info = Self::new(toml_info.is_validator, quorum_set, toml_info.node_id);
+        if let Some(pem) = &toml_info.private_key {
+            let private_key = PrivateKey::try_from_pkcs8_pem(pem).ok_or_else(|| {
+                LocalNodeOpError::InvalidPrivateKey(local_node_info.node_id.clone())
+            })?;
+            local_node_info.private_key = Some(private_key);
         }
+        if let Some(network_id) = toml_info.network_id {
+            local_node_info.network_id = network_id;
+        }
+        local_node_info.check_keys()?;
+        Ok(local_node_info)
+    }
+
+    pub fn check_keys(&self) -> LocalNodeOpResult<()> {
+        if self.is_validator && self.private_key.is_none() {
+            return Err(LocalNodeOpError::MissingPrivateKey(self.node_id.clone()));
+        }
+        match self
+            .quorum_set
+            .nodes()
+            .into_iter()
+            .find(|node| node.public_key.is_some() && !self.public_keys.contains_key(&node.node_id))
+        {
+            Some(node) => Err(LocalNodeOpError::InvalidPublicKey(node.node_id)),
+            None => Ok(()),
+        }
+    }
+
+    // Validator configurations without a key are rejected when they are built,
+    // so only nodes that are not validators send unsigned envelopes.
+    pub fn sign_envelope(&self, envelope: &mut SCPEnvelope<N>) {
+        if let Some(private_key) = &self.private_key {
+            envelope.sign(private_key, &self.network_id);
+        }
+    }
+
+    // Envelopes are only accepted from nodes with a known key.
+    pub fn verify_envelope(&self, envelope: &SCPEnvelope<N>) -> bool {
+        self.public_keys
+            .get(&envelope.node_id)
+            .is_some_and(|public_key| envelope.verify_signature(public_key, &self.network_id))
     }
 
     pub fn write_toml(&self, dir_name: &str) {
@@ -269,6 +380,9 @@ where
             is_validator: Default::default(),
             quorum_set: Default::default(),
             node_id: Default::default(),
+            private_key: None,
+            public_keys: Default::default(),
+            network_id: Default::default(),
             phantom: PhantomData,
         }
     }
@@ -283,6 +397,7 @@ mod tests {
 
     use crate::{
         application::quorum::QuorumNode,
+        ca::crypto::SignatureAlgorithm,
         scp::{
             envelope::SCPEnvelope, nomination_protocol::SCPNominationValue, scp_driver::HashValue,
         },
@@ -296,6 +411,7 @@ mod tests {
         let node = QuorumNode {
             node_id: node_id.clone(),
             ip_addr: Some(sock),
+            public_key: None,
         };
         (node_id, node)
     }
@@ -402,4 +518,37 @@ mod tests {
             assert_eq!(nodes_form_quorum(get_quorum_set_predicate, &nodes), false);
         }
     }
+
+    #[test]
+    fn validators_need_a_private_key() {
+        let private_key = PrivateKey::generate(SignatureAlgorithm::Ed25519);
+        let (node_id, node) = create_test_node(1);
+        let node = node.with_public_key(&private_key.public_key());
+        let quorum_set = QuorumSet::from([QuorumSlice::from([node])]);
+
+        let toml_info = |private_key: Option<&PrivateKey>| LocalNodeInfoToml {
+            is_validator: true,
+            quorum_set: BTreeSet::from([BTreeSet::from([node_id.clone()])]),
+            node_id: node_id.clone(),
+            private_key: private_key.and_then(PrivateKey::to_pkcs8_pem),
+            network_id: None,
+        };
+        assert_eq!(
+            LocalNodeInfo::<MockState>::from_toml_info(toml_info(None), quorum_set.clone()),
+            Err(LocalNodeOpError::MissingPrivateKey(node_id.clone()))
+        );
+
+        let local_node =
+            LocalNodeInfo::<MockState>::from_toml_info(toml_info(Some(&private_key)), quorum_set)
+                .unwrap();
+        assert_eq!(local_node.private_key, Some(private_key));
+
+        let mut env = SCPEnvelope::test_make_scp_envelope(node_id.clone());
+        local_node.sign_envelope(&mut env);
+        assert!(local_node.verify_envelope(&env));
+
+        let mut other_network = local_node.clone();
+        other_network.network_id = NetworkId::from_passphrase("other network");
+        assert!(!other_network.verify_envelope(&env));
+    }
 }
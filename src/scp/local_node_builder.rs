@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 
-use crate::application::quorum::QuorumSet;
+use crate::{application::quorum::QuorumSet, ca::crypto::PrivateKey};
 
 use super::local_node::{HLocalNode, LocalNodeInfo};
 use super::nomination_protocol::NominationValue;
@@ -13,6 +13,7 @@ where
     is_validator: Option<bool>,
     quorum_set: Option<QuorumSet>,
     node_id: Option<NodeID>,
+    private_key: Option<PrivateKey>,
     phantom: PhantomData<N>,
 }
 
@@ -25,6 +26,7 @@ where
             is_validator: Default::default(),
             quorum_set: Default::default(),
             node_id: Default::default(),
+            private_key: Default::default(),
             phantom: Default::default(),
         }
     }
@@ -53,6 +55,11 @@ where
         self
     }
 
+    pub fn private_key(mut self, private_key: PrivateKey) -> Self {
+        self.private_key = Some(private_key);
+        self
+    }
+
     pub fn build(self) -> Result<LocalNodeInfo<N>, &'static str> {
         if self.is_validator.is_none() {
             return Err("Missing is_validator.");
@@ -66,11 +73,17 @@ where
             return Err("Missing node id.");
         }
 
-        Ok(LocalNodeInfo::<N>::new(
+        // Validators sign every envelope they send.
+        if self.is_validator == Some(true) && self.private_key.is_none() {
+            return Err("Missing private key.");
+        }
+
+        let mut local_node = LocalNodeInfo::<N>::new(
             self.is_validator.unwrap(),
             self.quorum_set.unwrap(),
             self.node_id.unwrap(),
-        )
-        .into())
+        );
+        local_node.private_key = self.private_key;
+        Ok(local_node)
     }
 }
@@ -7,7 +7,6 @@ use std::{
 use std::{
     collections::{BTreeMap, BTreeSet},
     sync::{Arc, Mutex},
-    time::SystemTime,
 };
 
 use bincode::de;
@@ -356,17 +355,19 @@ where
         // I think it's not needed for SCP - just some routine bookkeeping.
     }
 
-    fn set_state_from_envelope(
+    pub fn set_state_from_envelope(
         &mut self,
         env_id: &SCPEnvelopeID,
         envelope_controller: &SCPEnvelopeController<N>,
-    ) {
+    ) -> bool {
         if self.nomination_started {
-            panic!("Cannot set state after nomination is started.")
+            return false;
         }
+        let Some(nomination_env) = envelope_controller.get_envelope(env_id) else {
+            return false;
+        };
 
         self.record_envelope(env_id, envelope_controller);
-        let nomination_env = envelope_controller.get_envelope(env_id).unwrap();
         let nomination_statement = nomination_env.get_statement();
         nomination_statement
             .get_accepted()
@@ -382,6 +383,7 @@ where
             });
 
         self.latest_envelope = Some(env_id.clone());
+        true
     }
 
     fn get_current_votes(&self) -> Vec<N> {
@@ -577,7 +579,7 @@ where
             let renominate_task = SlotTask::RetryNominate(renominate_task_arg);
             let renominate_job = SlotJob {
                 id: self.slot_index.clone(),
-                timestamp: SystemTime::now() + timeout,
+                timestamp: self.scheduler.borrow().now() + timeout,
                 task: renominate_task,
             };
 
@@ -608,6 +610,7 @@ where
 
     fn stop_nomination(&self, state: &mut NominationProtocolState<N>) {
         state.nomination_started = false;
+        self.stop_timer(SlotStateTimer::NominationProtocol);
     }
 
     fn update_round_learders(&mut self) {
@@ -691,9 +694,7 @@ where
                 // per the whitepaper:
                 // "As soon as `v` has a candidate value, however, it must cease
                 // voting to nominate `x` for any new values `x`"
-                self.slot_state
-                    .borrow_mut()
-                    .stop_timer(&SlotStateTimer::NominationProtocol);
+                self.stop_timer(SlotStateTimer::NominationProtocol);
 
                 return true;
             }
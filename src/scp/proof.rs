@@ -0,0 +1,357 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    application::{
+        quorum::{nodes_fill_one_quorum_slice_in_quorum_set, nodes_form_quorum, QuorumSet},
+        quorum_manager::QuorumManager,
+    },
+    ca::{crypto::PublicKey, signing::NetworkId},
+};
+
+use super::{
+    ballot_protocol::{BallotProtocolState, SCPPhase},
+    envelope::{EnvMap, SCPEnvelope},
+    local_node::LocalNodeInfo,
+    nomination_protocol::NominationValue,
+    scp::NodeID,
+    scp_driver::HashValue,
+    slot::SlotIndex,
+    statement::SCPStatement,
+};
+
+// An externalization proof carries everything needed to check offline that a
+// quorum committed to a value: the signed envelopes of the committing nodes and
+// the quorum sets their statements refer to.
+
+pub type ProofOpResult<T> = std::result::Result<T, ProofOpError>;
+
+#[derive(Debug, PartialEq)]
+pub enum ProofOpError {
+    // The envelope of the node is for another slot.
+    WrongSlot(NodeID),
+    // There is no verifying key for the node.
+    UnknownNode(NodeID),
+    BadSignature(NodeID),
+    DuplicateNode(NodeID),
+    // The envelope of the node does not commit to the proven value.
+    NotCommitted(NodeID),
+    // The quorum set the node refers to is not part of the proof.
+    MissingQuorumSet(NodeID),
+    // The committing nodes do not contain a quorum.
+    NoQuorum,
+    // The committing quorum does not contain a slice the verifier trusts.
+    UntrustedQuorum,
+}
+
+impl Display for ProofOpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProofOpError::WrongSlot(node_id) => {
+                write!(f, "envelope of {} is for another slot", node_id)
+            }
+            ProofOpError::UnknownNode(node_id) => write!(f, "no key for node {}", node_id),
+            ProofOpError::BadSignature(node_id) => {
+                write!(f, "envelope of {} has a bad signature", node_id)
+            }
+            ProofOpError::DuplicateNode(node_id) => {
+                write!(f, "node {} has more than one envelope", node_id)
+            }
+            ProofOpError::NotCommitted(node_id) => {
+                write!(f, "envelope of {} does not commit to the value", node_id)
+            }
+            ProofOpError::MissingQuorumSet(node_id) => {
+                write!(f, "quorum set of {} is missing", node_id)
+            }
+            ProofOpError::NoQuorum => write!(f, "committing nodes do not form a quorum"),
+            ProofOpError::UntrustedQuorum => {
+                write!(f, "committing quorum does not contain a trusted slice")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProofOpError {}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExternalizationProof<N>
+where
+    N: NominationValue,
+{
+    pub slot_index: SlotIndex,
+    pub value: N,
+    pub envelopes: Vec<SCPEnvelope<N>>,
+    pub quorum_sets: BTreeSet<QuorumSet>,
+}
+
+impl<N> ExternalizationProof<N>
+where
+    N: NominationValue,
+{
+    // Assembles the proof from the latest ballot envelopes of an externalized
+    // slot. Envelopes whose quorum set is unknown are left out.
+    pub fn from_slot(
+        slot_index: SlotIndex,
+        local_node: &LocalNodeInfo<N>,
+        ballot_state: &BallotProtocolState<N>,
+        env_map: &EnvMap<N>,
+        quorum_manager: &QuorumManager,
+    ) -> Option<Self> {
+        if ballot_state.phase != SCPPhase::PhaseExternalize {
+            return None;
+        }
+        let value = ballot_state.commit.as_ref()?.value.clone();
+
+        let mut envelopes = vec![];
+        let mut quorum_sets = BTreeSet::new();
+        for env_id in ballot_state.latest_envelopes.values() {
+            let Some(env) = env_map.0.get(env_id) else {
+                continue;
+            };
+            if !commits_to(env.get_statement(), &value) {
+                continue;
+            }
+
+            let quorum_set = if env.node_id == local_node.node_id {
+                Some(&local_node.quorum_set)
+            } else {
+                quorum_manager.get_quorum_set(env.get_statement())
+            };
+            if let Some(quorum_set) = quorum_set {
+                quorum_sets.insert(quorum_set.clone());
+                envelopes.push(env.clone());
+            }
+        }
+
+        Some(Self {
+            slot_index,
+            value,
+            envelopes,
+            quorum_sets,
+        })
+    }
+
+    pub fn nodes(&self) -> Vec<NodeID> {
+        self.envelopes
+            .iter()
+            .map(|env| env.node_id.clone())
+            .collect()
+    }
+
+    // The quorum sets in the proof are the signers' own claims, so the quorum
+    // they form only counts if it contains one of the verifier's slices.
+    pub fn verify(
+        &self,
+        public_keys: &BTreeMap<NodeID, PublicKey>,
+        network_id: &NetworkId,
+        trusted_quorum_set: &QuorumSet,
+    ) -> ProofOpResult<()> {
+        let quorum_sets: BTreeMap<HashValue, &QuorumSet> = self
+            .quorum_sets
+            .iter()
+            .map(|quorum_set| (quorum_set.hash_value(), quorum_set))
+            .collect();
+
+        let mut node_quorum_sets: BTreeMap<NodeID, &QuorumSet> = BTreeMap::new();
+        for env in self.envelopes.iter() {
+            let node_id = &env.node_id;
+            if env.slot_index != self.slot_index {
+                return Err(ProofOpError::WrongSlot(node_id.clone()));
+            }
+            let public_key = public_keys
+                .get(node_id)
+                .ok_or_else(|| ProofOpError::UnknownNode(node_id.clone()))?;
+            if !env.verify_signature(public_key, network_id) {
+                return Err(ProofOpError::BadSignature(node_id.clone()));
+            }
+            if !commits_to(env.get_statement(), &self.value) {
+                return Err(ProofOpError::NotCommitted(node_id.clone()));
+            }
+
+            let quorum_set = quorum_sets
+                .get(&env.get_statement().quorum_set_hash_value())
+                .ok_or_else(|| ProofOpError::MissingQuorumSet(node_id.clone()))?;
+            if node_quorum_sets
+                .insert(node_id.clone(), quorum_set)
+                .is_some()
+            {
+                return Err(ProofOpError::DuplicateNode(node_id.clone()));
+            }
+        }
+
+        // Drop nodes without a slice among the rest until the rest is stable,
+        // what remains is the largest quorum among the committing nodes.
+        let mut nodes: Vec<NodeID> = node_quorum_sets.keys().cloned().collect();
+        loop {
+            let kept: Vec<NodeID> = nodes
+                .iter()
+                .filter(|node| {
+                    nodes_fill_one_quorum_slice_in_quorum_set(node_quorum_sets[*node], &nodes)
+                })
+                .cloned()
+                .collect();
+            if kept.len() == nodes.len() {
+                break;
+            }
+            nodes = kept;
+        }
+
+        if !nodes_form_quorum(|node| node_quorum_sets.get(node).copied(), &nodes) {
+            return Err(ProofOpError::NoQuorum);
+        }
+        if !nodes_fill_one_quorum_slice_in_quorum_set(trusted_quorum_set, &nodes) {
+            return Err(ProofOpError::UntrustedQuorum);
+        }
+        Ok(())
+    }
+}
+
+pub fn commits_to<N: NominationValue>(statement: &SCPStatement<N>, value: &N) -> bool {
+    match statement {
+        SCPStatement::Confirm(st) => &st.ballot.value == value,
+        SCPStatement::Externalize(st) => &st.commit.value == value,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        application::quorum::{QuorumNode, QuorumSlice},
+        mock::state::MockState,
+        overlay_impl::simulation::{test_utils::*, SimulationConfig},
+    };
+
+    use super::*;
+
+    // All simulated nodes share the same quorum set.
+    fn trusted_quorum_set(proof: &ExternalizationProof<MockState>) -> QuorumSet {
+        proof.quorum_sets.iter().next().unwrap().clone()
+    }
+
+    fn externalized_proof() -> ExternalizationProof<MockState> {
+        let mut sim = mock_simulation(SimulationConfig::with_seed(11));
+        nominate_slots(&mut sim, 0..1);
+
+        let proof = sim.nodes["node2"].externalization_proof(0).unwrap();
+        assert_eq!(
+            Some(proof.value.clone()),
+            sim.externalized_value(&"node2".to_string(), &0)
+        );
+        proof
+    }
+
+    #[test]
+    fn externalized_slots_can_be_proven() {
+        let proof = externalized_proof();
+        let trusted = trusted_quorum_set(&proof);
+        assert_eq!(proof.nodes(), SIM_NODES.to_vec());
+        assert_eq!(
+            proof.verify(&public_keys(), &NetworkId::default(), &trusted),
+            Ok(())
+        );
+
+        let encoded = bincode::serialize(&proof).unwrap();
+        let decoded: ExternalizationProof<MockState> = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(
+            decoded.verify(&public_keys(), &NetworkId::default(), &trusted),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn tampered_proofs_are_rejected() {
+        let proof = externalized_proof();
+        let trusted = trusted_quorum_set(&proof);
+        let keys = public_keys();
+        let network_id = NetworkId::default();
+
+        let mut missing_node = proof.clone();
+        missing_node.envelopes.pop();
+        assert_eq!(
+            missing_node.verify(&keys, &network_id, &trusted),
+            Err(ProofOpError::NoQuorum)
+        );
+
+        let mut other_value = proof.clone();
+        other_value.value = MockState::from_seed(1);
+        assert_eq!(
+            other_value.verify(&keys, &network_id, &trusted),
+            Err(ProofOpError::NotCommitted("node1".to_string()))
+        );
+
+        let mut forged = proof.clone();
+        forged.envelopes[1].node_id = "node1".to_string();
+        assert_eq!(
+            forged.verify(&keys, &network_id, &trusted),
+            Err(ProofOpError::BadSignature("node1".to_string()))
+        );
+
+        let mut duplicated = proof.clone();
+        duplicated.envelopes.push(proof.envelopes[0].clone());
+        assert_eq!(
+            duplicated.verify(&keys, &network_id, &trusted),
+            Err(ProofOpError::DuplicateNode("node1".to_string()))
+        );
+
+        let mut no_quorum_sets = proof.clone();
+        no_quorum_sets.quorum_sets.clear();
+        assert_eq!(
+            no_quorum_sets.verify(&keys, &network_id, &trusted),
+            Err(ProofOpError::MissingQuorumSet("node1".to_string()))
+        );
+
+        assert_eq!(
+            proof.verify(
+                &keys,
+                &NetworkId::from_passphrase("other network"),
+                &trusted
+            ),
+            Err(ProofOpError::BadSignature("node1".to_string()))
+        );
+
+        let mut unknown_keys = keys.clone();
+        unknown_keys.remove("node3");
+        assert_eq!(
+            proof.verify(&unknown_keys, &network_id, &trusted),
+            Err(ProofOpError::UnknownNode("node3".to_string()))
+        );
+    }
+
+    #[test]
+    fn self_only_slices_are_not_trusted() {
+        let proof = externalized_proof();
+        let trusted = trusted_quorum_set(&proof);
+
+        // node1 claims a slice with only itself and commits to another value.
+        let mut self_only = QuorumSet::new(1);
+        self_only.insert(QuorumSlice::from([QuorumNode::new(
+            "node1".to_string(),
+            None,
+        )]));
+        let other_value = MockState::from_seed(42);
+        let mut env = proof.envelopes[0].clone();
+        let SCPStatement::Externalize(st) = &mut env.statement else {
+            panic!("node1 externalized the slot");
+        };
+        st.commit.value = other_value.clone();
+        st.commit_quorum_set_hash = self_only.hash_value();
+        st.commit_quorum_set = Some(self_only.clone());
+        env.sign(&private_key("node1"), &NetworkId::default());
+
+        let forged = ExternalizationProof {
+            slot_index: proof.slot_index,
+            value: other_value,
+            envelopes: vec![env],
+            quorum_sets: BTreeSet::from([self_only]),
+        };
+        assert_eq!(
+            forged.verify(&public_keys(), &NetworkId::default(), &trusted),
+            Err(ProofOpError::UntrustedQuorum)
+        );
+    }
+}
@@ -16,7 +16,7 @@ use super::{
     nomination_protocol::{
         HSCPNominationValue, NominationProtocol, NominationProtocolState, NominationValue,
     },
-    scp_driver::SlotDriver,
+    scp_driver::{SlotDriver, SlotStateTimer},
     slot::SlotIndex,
 };
 
@@ -41,10 +41,39 @@ where
         }
     }
 
+    // A timer has at most one job queued, a new one supersedes the old one.
     pub fn submit(&mut self, job: SlotJob<N>) {
+        self.cancel(&job.task.timer());
         self.jobs.push_back(job);
     }
 
+    pub fn cancel(&mut self, timer: &SlotStateTimer) {
+        self.jobs.retain(|job| &job.task.timer() != timer);
+    }
+
+    // The job with the earliest deadline.
+    pub fn pop(&mut self) -> Option<SlotJob<N>> {
+        let index = self
+            .jobs
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, job)| job.timestamp)
+            .map(|(index, _)| index)?;
+        self.jobs.remove(index)
+    }
+
+    pub fn next_timestamp(&self) -> Option<SystemTime> {
+        self.jobs.iter().map(|job| job.timestamp).min()
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
     pub fn process_one(
         &mut self,
         slots: &HashMap<SlotIndex, Arc<SlotDriver<N, H>>>,
@@ -54,7 +83,7 @@ where
         quorum_manager: &mut QuorumManager,
         herder_driver: &mut H,
     ) {
-        if let Some(job) = self.jobs.pop_front() {
+        if let Some(job) = self.pop() {
             if let Some(slot_driver) = slots.get(&job.id) {
                 let nomination_state = nomination_states
                     .get_mut(&job.id)
@@ -79,6 +108,12 @@ where
                         quorum_manager,
                         herder_driver,
                     ),
+                    SlotTask::Rebroadcast(arg) => arg.execute(
+                        slot_driver,
+                        nomination_state,
+                        ballot_state,
+                        envelope_controller,
+                    ),
                 }
             }
         }
@@ -100,6 +135,20 @@ where
 {
     RetryNominate(RetryNominateArg<N>),
     AbandonBallot(AbandonBallotArg<N>),
+    Rebroadcast(RebroadcastArg),
+}
+
+impl<N> SlotTask<N>
+where
+    N: NominationValue,
+{
+    pub fn timer(&self) -> SlotStateTimer {
+        match self {
+            SlotTask::RetryNominate(_) => SlotStateTimer::NominationProtocol,
+            SlotTask::AbandonBallot(_) => SlotStateTimer::BallotProtocol,
+            SlotTask::Rebroadcast(_) => SlotStateTimer::Rebroadcast,
+        }
+    }
 }
 
 pub struct RetryNominateArg<N>
@@ -118,7 +167,7 @@ where
 {
     pub fn execute<H: HerderDriver<N> + 'static>(
         self,
-        slot_driver: &Arc<SlotDriver<N, H>>,
+        slot_driver: &SlotDriver<N, H>,
         nomination_state: &mut NominationProtocolState<N>,
         ballot_state: &mut BallotProtocolState<N>,
         envelope_controller: &mut SCPEnvelopeController<N>,
@@ -164,7 +213,7 @@ where
 
     pub fn execute<H: HerderDriver<N> + 'static>(
         self,
-        slot_driver: &Arc<SlotDriver<N, H>>,
+        slot_driver: &SlotDriver<N, H>,
         nomination_state: &mut NominationProtocolState<N>,
         ballot_state: &mut BallotProtocolState<N>,
         envelope_controller: &mut SCPEnvelopeController<N>,
@@ -182,3 +231,70 @@ where
         );
     }
 }
+
+pub struct RebroadcastArg {
+    pub slot: SlotIndex,
+}
+
+impl RebroadcastArg {
+    pub fn new(slot: SlotIndex) -> Self {
+        Self { slot }
+    }
+
+    pub fn execute<N: NominationValue, H: HerderDriver<N> + 'static>(
+        self,
+        slot_driver: &SlotDriver<N, H>,
+        nomination_state: &NominationProtocolState<N>,
+        ballot_state: &BallotProtocolState<N>,
+        envelope_controller: &mut SCPEnvelopeController<N>,
+    ) {
+        slot_driver.rebroadcast(nomination_state, ballot_state, envelope_controller);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::mock::state::{MockState, MockStateDriver};
+
+    use super::*;
+
+    fn retry_nominate_job(timestamp: SystemTime) -> SlotJob<MockState> {
+        SlotJob {
+            id: 0,
+            timestamp,
+            task: SlotTask::RetryNominate(RetryNominateArg {
+                slot_idx: 0,
+                value: Arc::new(MockState::from_seed(0)),
+                previous_value: MockState::empty(),
+            }),
+        }
+    }
+
+    fn abandon_ballot_job(timestamp: SystemTime) -> SlotJob<MockState> {
+        SlotJob {
+            id: 0,
+            timestamp,
+            task: SlotTask::AbandonBallot(AbandonBallotArg::new(0, 0)),
+        }
+    }
+
+    #[test]
+    fn stopped_and_superseded_timers_do_not_fire() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut queue = SlotJobQueue::<MockState, MockStateDriver>::new();
+
+        queue.submit(retry_nominate_job(start + Duration::from_secs(1)));
+        queue.submit(retry_nominate_job(start + Duration::from_secs(3)));
+        queue.submit(abandon_ballot_job(start + Duration::from_secs(2)));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.next_timestamp(), Some(start + Duration::from_secs(2)));
+
+        queue.cancel(&SlotStateTimer::BallotProtocol);
+        let job = queue.pop().unwrap();
+        assert_eq!(job.task.timer(), SlotStateTimer::NominationProtocol);
+        assert_eq!(job.timestamp, start + Duration::from_secs(3));
+        assert!(queue.pop().is_none());
+    }
+}
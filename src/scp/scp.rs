@@ -1,9 +1,7 @@
 
 
 use super::{
-    nomination_protocol::{
-        HSCPNominationValue, NominationValue, SCPNominationValue,
-    },
+    nomination_protocol::{HSCPNominationValue, NominationValue},
     scp_driver::HSCPEnvelope,
     slot::SlotIndex,
 };
@@ -30,15 +28,20 @@ pub trait SCP {
     type N: NominationValue;
 
     fn recv_envelope(&mut self, envelope: HSCPEnvelope<Self::N>) -> EnvelopeState;
-    fn set_state_from_envelope(&mut self, slot_index: SlotIndex, envelope: HSCPEnvelope<Self::N>);
+    // Restores the state of the slot from an envelope the node emitted itself.
+    fn set_state_from_envelope(
+        &mut self,
+        slot_index: SlotIndex,
+        envelope: HSCPEnvelope<Self::N>,
+    ) -> EnvelopeState;
 
     fn nominate(
         &mut self,
         slot_index: SlotIndex,
         value: HSCPNominationValue<Self::N>,
-        prev_value: &SCPNominationValue,
+        prev_value: &Self::N,
     ) -> bool;
-    fn stop_nomination(&mut self) -> bool;
+    fn stop_nomination(&mut self, slot_index: SlotIndex) -> bool;
 
     fn purge_slots(&mut self, max_slot_index: u64, slot_to_keep: u64);
     fn is_slot_fully_validated(&self, slot_index: u64) -> bool;
@@ -3,6 +3,7 @@ use std::{
     collections::{BTreeMap, HashMap},
     rc::Rc,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 // pub type HashValue = Vec<u8>;
@@ -18,7 +19,7 @@ use crate::{
         quorum_manager::{self, QuorumManager},
         work_queue::{HClockEvent, WorkScheduler},
     },
-    crypto::types::{test_default_blake2, Blake2Hashable},
+    crypto::types::Blake2Hashable,
     herder::herder::HerderDriver,
     scp::{
         local_node::extract_nodes_from_statement_with_filter,
@@ -32,7 +33,7 @@ use super::{
     envelope::{EnvMap, SCPEnvelope, SCPEnvelopeController, SCPEnvelopeID},
     local_node::{HLocalNode, LocalNodeInfo},
     nomination_protocol::{NominationProtocolState, NominationValue},
-    queue::SlotJobQueue,
+    queue::{RebroadcastArg, SlotJob, SlotJobQueue, SlotTask},
     scp::{EnvelopeState, NodeID},
     slot::SlotIndex,
     statement::{SCPStatement, SCPStatementNominate},
@@ -58,10 +59,11 @@ where
     pub task_queue: Rc<RefCell<SlotJobQueue<N, H>>>,
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum SlotStateTimer {
     BallotProtocol,
     NominationProtocol,
+    Rebroadcast,
 }
 
 pub struct SlotState {
@@ -124,17 +126,13 @@ impl<N> SCPEnvelope<N>
 where
     N: NominationValue,
 {
-    pub fn new(
-        statement: SCPStatement<N>,
-        node_id: NodeID,
-        slot_index: SlotIndex,
-        signature: HashValue,
-    ) -> Self {
+    // Envelopes start out unsigned.
+    pub fn new(statement: SCPStatement<N>, node_id: NodeID, slot_index: SlotIndex) -> Self {
         Self {
             statement: statement,
             node_id: node_id,
             slot_index: slot_index,
-            signature: signature,
+            signature: vec![],
         }
     }
 
@@ -161,7 +159,7 @@ where
             }),
             node_id: node_id,
             slot_index: 0,
-            signature: test_default_blake2(),
+            signature: vec![],
         }
     }
 
@@ -182,7 +180,7 @@ where
             }),
             node_id: node_id,
             slot_index: 0,
-            signature: test_default_blake2(),
+            signature: vec![],
         };
         envelope_controller.add_envelope(env)
     }
@@ -215,8 +213,6 @@ where
     // implementation don't really need to do anything with these.
 
     fn emit_envelope(envelope: &SCPEnvelope<N>);
-
-    fn sign_envelope(envelope: &mut SCPEnvelope<N>);
 }
 
 impl<N, H> SlotDriver<N, H>
@@ -224,6 +220,39 @@ where
     N: NominationValue,
     H: HerderDriver<N> + 'static,
 {
+    const REBROADCAST_INTERVAL: Duration = Duration::from_secs(2);
+
+    // Stopping a timer also drops its queued job, so it cannot fire anymore.
+    pub fn stop_timer(&self, timer: SlotStateTimer) {
+        self.slot_state.borrow_mut().stop_timer(&timer);
+        self.task_queue.borrow_mut().cancel(&timer);
+    }
+
+    // Re-sends the latest statements of the node after a while without
+    // emitting, as peers may have lost them.
+    pub fn start_rebroadcast_timer(&self) {
+        let rebroadcast_job = SlotJob {
+            id: self.slot_index,
+            timestamp: self.scheduler.borrow().now() + Self::REBROADCAST_INTERVAL,
+            task: SlotTask::Rebroadcast(RebroadcastArg::new(self.slot_index)),
+        };
+        self.task_queue.borrow_mut().submit(rebroadcast_job);
+    }
+
+    pub fn rebroadcast(
+        &self,
+        nomination_state: &NominationProtocolState<N>,
+        ballot_state: &BallotProtocolState<N>,
+        envelope_controller: &mut SCPEnvelopeController<N>,
+    ) {
+        let node_id = &self.local_node.node_id;
+        let latest_nomination = nomination_state.latest_nominations.get(node_id);
+        let latest_ballot = ballot_state.latest_envelopes.get(node_id);
+        for env_id in latest_nomination.into_iter().chain(latest_ballot) {
+            envelope_controller.add_env_to_emit(env_id);
+        }
+    }
+
     pub fn new(
         slot_index: SlotIndex,
         local_node: Arc<LocalNodeInfo<N>>,
@@ -328,7 +357,7 @@ where
         quorum_manager: &mut QuorumManager,
         herder_driver: &mut H,
     ) -> EnvelopeState {
-        let is_ballot = {
+        let (is_ballot, from_self) = {
             let env = envelope_controller.get_envelope(env_id).unwrap();
             info!(
                 "recv_scp_envelvope: node {:?} receives an envelope: {:?}",
@@ -342,7 +371,7 @@ where
                 | SCPStatement::Externalize(_) => true,
                 SCPStatement::Nominate(st) => false,
             };
-            is_ballot
+            (is_ballot, env.node_id == self.node_idx())
         };
 
         if is_ballot {
@@ -350,7 +379,7 @@ where
                 ballot_state,
                 nomination_state,
                 env_id,
-                true,
+                from_self,
                 &mut envelope_controller.envelopes,
                 &mut envelope_controller.envs_to_emit,
                 &quorum_manager,
@@ -368,6 +397,40 @@ where
         }
     }
 
+    pub fn set_state_from_envelope(
+        &self,
+        nomination_state: &mut NominationProtocolState<N>,
+        ballot_state: &mut BallotProtocolState<N>,
+        env_id: &SCPEnvelopeID,
+        envelope_controller: &SCPEnvelopeController<N>,
+    ) -> EnvelopeState {
+        let Some(env) = envelope_controller.get_envelope(env_id) else {
+            return EnvelopeState::Invalid;
+        };
+        if env.slot_index != self.slot_index || env.node_id != self.node_idx() {
+            debug!(
+                "set_state_from_envelope: node {:?} ignores an envelope from {:?}",
+                self.node_idx(),
+                env.node_id
+            );
+            return EnvelopeState::Invalid;
+        }
+
+        let restored = match env.get_statement() {
+            SCPStatement::Nominate(_) => {
+                nomination_state.set_state_from_envelope(env_id, envelope_controller)
+            }
+            _ => ballot_state.set_state_from_envelope(env_id, envelope_controller),
+        };
+
+        self.maybe_got_v_blocking(nomination_state, ballot_state);
+        if restored {
+            EnvelopeState::Valid
+        } else {
+            EnvelopeState::Invalid
+        }
+    }
+
     pub fn federated_accept(
         &self,
         voted_predicate: impl Fn(&SCPStatement<N>) -> bool,
@@ -477,12 +540,13 @@ where
             statement
         );
 
-        let env = SCPEnvelope {
+        let mut env = SCPEnvelope {
             statement,
             node_id: self.local_node.node_id.clone(),
             slot_index: self.slot_index.clone(),
-            signature: test_default_blake2(),
+            signature: vec![],
         };
+        self.local_node.sign_envelope(&mut env);
 
         let env_id = envelope_controller.add_envelope(env);
         envelope_controller.add_env_to_emit(&env_id);
@@ -515,7 +579,7 @@ where
     }
 
     pub fn maybe_got_v_blocking(
-        &mut self,
+        &self,
         nomination_state: &NominationProtocolState<N>,
         ballot_state: &BallotProtocolState<N>,
     ) {
@@ -564,12 +628,7 @@ where
             self.local_node.node_id, value
         );
 
-        herder.externalize_value(value);
-    }
-
-    fn sign_envelope(envelope: &mut SCPEnvelope<N>) {
-        // TODO: for now just pretend we're signing...
-        envelope.signature = test_default_blake2();
+        herder.externalize_value(slot_index, value);
     }
 
     fn accepted_ballot_prepared(&self, slot_index: &u64, ballot: &SCPBallot<N>) {}
@@ -99,7 +99,7 @@ where
 
         match local_node_info_builder.build_from_file::<N>(node_idx) {
             // TODO: need to share state???
-            Some(local_node_info) => {
+            Ok(local_node_info) => {
                 let slot_driver = SlotDriver::<N, H>::new(
                     self.slot_index.clone().unwrap(),
                     Arc::new(local_node_info),
@@ -111,7 +111,7 @@ where
                 Ok(slot_driver)
             }
 
-            None => Err("Build error."),
+            Err(_) => Err("Build error."),
         }
     }
 
@@ -0,0 +1,662 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Debug,
+    iter,
+    rc::Rc,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use log::debug;
+
+use crate::{
+    application::{quorum_manager::QuorumManager, work_queue::WorkScheduler},
+    herder::herder::HerderDriver,
+};
+
+use super::{
+    ballot_protocol::{BallotProtocolState, SCPPhase},
+    envelope::{SCPEnvelope, SCPEnvelopeController, SCPEnvelopeID},
+    local_node::LocalNodeInfo,
+    nomination_protocol::{
+        HSCPNominationValue, NominationProtocol, NominationProtocolState, NominationValue,
+    },
+    proof::ExternalizationProof,
+    queue::SlotTask,
+    scp::{EnvelopeState, NodeID},
+    scp_driver::{HSCPEnvelope, HashValue, SlotDriver},
+    scp_driver_builder::SlotDriverBuilder,
+    slot::SlotIndex,
+};
+
+// Runs consensus for a single node without any networking. Envelopes are
+// handed in through `recv_envelope` and whatever the node wants to broadcast
+// is collected with `take_envelopes_to_emit`. `PeerNode` runs one of these
+// behind its connections.
+pub struct SCPNode<N, H>
+where
+    N: NominationValue + 'static,
+    H: HerderDriver<N>,
+{
+    local_node: Arc<LocalNodeInfo<N>>,
+    pub herder: H,
+    work_scheduler: Rc<RefCell<WorkScheduler>>,
+    pub leaders: VecDeque<NodeID>,
+
+    pub slots: BTreeMap<SlotIndex, SlotDriver<N, H>>,
+    pub nomination_protocol_states: BTreeMap<SlotIndex, NominationProtocolState<N>>,
+    pub ballot_protocol_states: BTreeMap<SlotIndex, BallotProtocolState<N>>,
+
+    pub envelope_controller: SCPEnvelopeController<N>,
+    pub quorum_manager: QuorumManager,
+}
+
+impl<N, H> Debug for SCPNode<N, H>
+where
+    N: NominationValue,
+    H: HerderDriver<N>,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SCPNode")
+            .field("node_id", &self.local_node.node_id)
+            .field("slots", &self.slots.keys())
+            .finish()
+    }
+}
+
+impl<N, H> SCPNode<N, H>
+where
+    N: NominationValue,
+    H: HerderDriver<N> + 'static,
+{
+    pub fn new(
+        local_node: LocalNodeInfo<N>,
+        herder: H,
+        work_scheduler: Rc<RefCell<WorkScheduler>>,
+    ) -> Self {
+        let quorum_manager = QuorumManager::new(&local_node.quorum_set);
+
+        Self {
+            local_node: Arc::new(local_node),
+            herder,
+            work_scheduler,
+            leaders: Default::default(),
+            slots: Default::default(),
+            nomination_protocol_states: Default::default(),
+            ballot_protocol_states: Default::default(),
+            envelope_controller: SCPEnvelopeController::new(),
+            quorum_manager,
+        }
+    }
+
+    pub fn node_id(&self) -> &NodeID {
+        &self.local_node.node_id
+    }
+
+    pub fn local_node(&self) -> &Arc<LocalNodeInfo<N>> {
+        &self.local_node
+    }
+
+    pub fn work_scheduler(&self) -> &Rc<RefCell<WorkScheduler>> {
+        &self.work_scheduler
+    }
+
+    pub fn add_leader(&mut self, node_id: &NodeID) {
+        self.leaders.push_front(node_id.clone());
+    }
+
+    pub fn herder(&self) -> &H {
+        &self.herder
+    }
+
+    // Returns the envelopes emitted since the last call, oldest first. The
+    // caller is responsible for broadcasting them.
+    pub fn take_envelopes_to_emit(&mut self) -> Vec<SCPEnvelope<N>> {
+        let mut envs_sent: BTreeSet<SCPEnvelopeID> = BTreeSet::new();
+        let mut envelopes = vec![];
+
+        while let Some(env_id) = self.envelope_controller.pop_next_env_to_emit() {
+            if !envs_sent.insert(env_id) {
+                continue;
+            }
+            if let Some(env) = self.envelope_controller.get_envelope(&env_id) {
+                envelopes.push(env.clone());
+            }
+        }
+        envelopes
+    }
+
+    // Fires the next pending timer of the slot, returns false if there is none.
+    pub fn fire_timer(&mut self, slot_index: SlotIndex) -> bool {
+        match self.pop_timer(slot_index) {
+            Some(task) => self.run_timer(slot_index, task),
+            None => false,
+        }
+    }
+
+    // Takes the next pending timer of the slot off its queue without running
+    // it, so the caller can act on it first.
+    pub fn pop_timer(&mut self, slot_index: SlotIndex) -> Option<SlotTask<N>> {
+        let job = self.slots.get(&slot_index)?.task_queue.borrow_mut().pop()?;
+        Some(job.task)
+    }
+
+    // Returns false if the slot is gone, e.g. it was purged since the timer
+    // was set.
+    pub fn run_timer(&mut self, slot_index: SlotIndex, task: SlotTask<N>) -> bool {
+        let (Some(slot), Some(nomination_state), Some(ballot_state)) = (
+            self.slots.get(&slot_index),
+            self.nomination_protocol_states.get_mut(&slot_index),
+            self.ballot_protocol_states.get_mut(&slot_index),
+        ) else {
+            return false;
+        };
+        match task {
+            SlotTask::RetryNominate(arg) => arg.execute(
+                slot,
+                nomination_state,
+                ballot_state,
+                &mut self.envelope_controller,
+                &mut self.quorum_manager,
+                &mut self.herder,
+            ),
+            SlotTask::AbandonBallot(arg) => arg.execute(
+                slot,
+                nomination_state,
+                ballot_state,
+                &mut self.envelope_controller,
+                &self.quorum_manager,
+                &mut self.herder,
+            ),
+            SlotTask::Rebroadcast(arg) => arg.execute(
+                slot,
+                nomination_state,
+                ballot_state,
+                &mut self.envelope_controller,
+            ),
+        }
+        true
+    }
+
+    pub fn pending_timers(&self, slot_index: SlotIndex) -> usize {
+        self.slots
+            .get(&slot_index)
+            .map_or(0, |slot| slot.task_queue.borrow().len())
+    }
+
+    // The earliest pending timer over all slots and when it is due.
+    pub fn next_timer(&self) -> Option<(SystemTime, SlotIndex)> {
+        self.slots
+            .iter()
+            .filter_map(|(slot_index, slot)| {
+                let timestamp = slot.task_queue.borrow().next_timestamp()?;
+                Some((timestamp, *slot_index))
+            })
+            .min()
+    }
+
+    pub fn externalization_proof(&self, slot_index: SlotIndex) -> Option<ExternalizationProof<N>> {
+        ExternalizationProof::from_slot(
+            slot_index,
+            &self.local_node,
+            self.ballot_protocol_states.get(&slot_index)?,
+            &self.envelope_controller.envelopes,
+            &self.quorum_manager,
+        )
+    }
+
+    pub fn externalized_value(&self, slot_index: SlotIndex) -> Option<&N> {
+        let state = self.ballot_protocol_states.get(&slot_index)?;
+        if state.phase != SCPPhase::PhaseExternalize {
+            return None;
+        }
+        state.commit.as_ref().map(|commit| &commit.value)
+    }
+
+    // Slots are created lazily, and only once we know who leads nomination.
+    fn maybe_create_slot_and_state(&mut self, slot_index: SlotIndex) -> bool {
+        if self.slots.contains_key(&slot_index) {
+            return true;
+        }
+        let Some(leader) = self.leaders.front() else {
+            debug!(
+                "SCP: node {:?} has no leader to create slot {:?}",
+                self.local_node.node_id, slot_index
+            );
+            return false;
+        };
+
+        self.create_slot(slot_index, leader.to_owned());
+        true
+    }
+
+    // Creates the slot with `leader` leading the first nomination round.
+    pub fn create_slot(&mut self, slot_index: SlotIndex, leader: NodeID) {
+        let slot = SlotDriverBuilder::<N, H>::new()
+            .slot_index(slot_index)
+            .timer(self.work_scheduler.clone())
+            .local_node(self.local_node.clone())
+            .nomination_protocol_state(NominationProtocolState::new(leader.clone()))
+            .build()
+            .unwrap();
+        self.slots.insert(slot_index, slot);
+        self.nomination_protocol_states
+            .insert(slot_index, NominationProtocolState::new(leader));
+        self.ballot_protocol_states
+            .insert(slot_index, Default::default());
+    }
+}
+
+impl<N, H> super::scp::SCP for SCPNode<N, H>
+where
+    N: NominationValue,
+    H: HerderDriver<N> + 'static,
+{
+    type N = N;
+
+    fn recv_envelope(&mut self, envelope: HSCPEnvelope<N>) -> EnvelopeState {
+        if !self.local_node.verify_envelope(&envelope) {
+            return EnvelopeState::Invalid;
+        }
+
+        let slot_index = envelope.slot_index;
+        if !self.maybe_create_slot_and_state(slot_index) {
+            return EnvelopeState::Invalid;
+        }
+
+        if let Some(quorum_set) = envelope.get_quorum_set() {
+            self.quorum_manager.add_quorum_set(quorum_set);
+        }
+        let env_id = self
+            .envelope_controller
+            .add_envelope(envelope.as_ref().clone());
+
+        let (Some(slot), Some(nomination_state), Some(ballot_state)) = (
+            self.slots.get(&slot_index),
+            self.nomination_protocol_states.get_mut(&slot_index),
+            self.ballot_protocol_states.get_mut(&slot_index),
+        ) else {
+            return EnvelopeState::Invalid;
+        };
+        let res = slot.recv_scp_envelvope(
+            nomination_state,
+            ballot_state,
+            &env_id,
+            &mut self.envelope_controller,
+            &mut self.quorum_manager,
+            &mut self.herder,
+        );
+        slot.maybe_got_v_blocking(nomination_state, ballot_state);
+        res
+    }
+
+    fn set_state_from_envelope(
+        &mut self,
+        slot_index: SlotIndex,
+        envelope: HSCPEnvelope<N>,
+    ) -> EnvelopeState {
+        if envelope.slot_index != slot_index || !self.maybe_create_slot_and_state(slot_index) {
+            return EnvelopeState::Invalid;
+        }
+
+        let env_id = self
+            .envelope_controller
+            .add_envelope(envelope.as_ref().clone());
+        let (Some(slot), Some(nomination_state), Some(ballot_state)) = (
+            self.slots.get(&slot_index),
+            self.nomination_protocol_states.get_mut(&slot_index),
+            self.ballot_protocol_states.get_mut(&slot_index),
+        ) else {
+            return EnvelopeState::Invalid;
+        };
+        slot.set_state_from_envelope(
+            nomination_state,
+            ballot_state,
+            &env_id,
+            &self.envelope_controller,
+        )
+    }
+
+    fn nominate(
+        &mut self,
+        slot_index: SlotIndex,
+        value: HSCPNominationValue<N>,
+        prev_value: &N,
+    ) -> bool {
+        if !self.maybe_create_slot_and_state(slot_index) {
+            return false;
+        }
+
+        // Processing our own nomination may already supersede it, so report
+        // whether anything new is waiting to be emitted instead.
+        let queued = self.envelope_controller.envs_to_emit.len();
+        let (Some(slot), Some(nomination_state), Some(ballot_state)) = (
+            self.slots.get(&slot_index),
+            self.nomination_protocol_states.get_mut(&slot_index),
+            self.ballot_protocol_states.get_mut(&slot_index),
+        ) else {
+            return false;
+        };
+        slot.nominate(
+            nomination_state,
+            ballot_state,
+            value,
+            prev_value,
+            &mut self.envelope_controller,
+            &mut self.quorum_manager,
+            &mut self.herder,
+        );
+        self.envelope_controller.envs_to_emit.len() > queued
+    }
+
+    fn stop_nomination(&mut self, slot_index: SlotIndex) -> bool {
+        match (
+            self.slots.get(&slot_index),
+            self.nomination_protocol_states.get_mut(&slot_index),
+        ) {
+            (Some(slot), Some(nomination_state)) => {
+                slot.stop_nomination(nomination_state);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn purge_slots(&mut self, max_slot_index: u64, slot_to_keep: u64) {
+        let purge =
+            |slot_index: &SlotIndex| *slot_index < max_slot_index && *slot_index != slot_to_keep;
+
+        self.slots.retain(|slot_index, _| !purge(slot_index));
+        self.nomination_protocol_states
+            .retain(|slot_index, _| !purge(slot_index));
+        self.ballot_protocol_states
+            .retain(|slot_index, _| !purge(slot_index));
+
+        // Quorum sets are shared between slots, so only those no remaining
+        // envelope refers to go. The local one is always known.
+        self.envelope_controller
+            .retain_slots(|slot_index| !purge(&slot_index));
+        let in_use: BTreeSet<HashValue> = self
+            .envelope_controller
+            .envelopes
+            .0
+            .values()
+            .map(|env| env.get_statement().quorum_set_hash_value())
+            .chain(iter::once(self.local_node.quorum_set.hash_value()))
+            .collect();
+        self.quorum_manager.retain_quorum_sets(&in_use);
+    }
+
+    fn is_slot_fully_validated(&self, slot_index: u64) -> bool {
+        self.slots
+            .get(&slot_index)
+            .is_some_and(|slot| slot.slot_state.borrow().fully_validated)
+    }
+
+    fn is_validator(&self) -> bool {
+        self.local_node.is_validator
+    }
+
+    fn got_v_blocking(&self, slot_index: u64) -> bool {
+        self.slots
+            .get(&slot_index)
+            .is_some_and(|slot| slot.slot_state.borrow().got_v_blocking)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ca::signing::NetworkId,
+        mock::state::{MockState, MockStateDriver},
+        scp::{
+            ballot_protocol::SCPBallot,
+            local_node::LocalNodeInfoBuilderFromFile,
+            scp::SCP as _,
+            statement::{SCPStatement, SCPStatementNominate, SCPStatementPrepare},
+        },
+    };
+
+    use super::*;
+
+    type MockSCP = SCPNode<MockState, MockStateDriver>;
+
+    const NODES: [&str; 3] = ["node1", "node2", "node3"];
+
+    fn build_nodes() -> BTreeMap<NodeID, MockSCP> {
+        let mut builder = LocalNodeInfoBuilderFromFile::new("model_check");
+        NODES
+            .iter()
+            .map(|node_id| {
+                let local_node = builder.build_from_file(node_id).unwrap();
+                let work_scheduler = Rc::new(RefCell::new(WorkScheduler::new(None)));
+                let mut scp = MockSCP::new(local_node, MockStateDriver::new(), work_scheduler);
+                scp.add_leader(&"node1".to_string());
+                (node_id.to_string(), scp)
+            })
+            .collect()
+    }
+
+    // Passes every emitted envelope to all other nodes until nobody has
+    // anything left to say. Returns everything that was emitted.
+    fn exchange(nodes: &mut BTreeMap<NodeID, MockSCP>) -> Vec<SCPEnvelope<MockState>> {
+        let mut emitted = vec![];
+        let mut queue: VecDeque<SCPEnvelope<MockState>> = VecDeque::new();
+        for node in nodes.values_mut() {
+            queue.extend(node.take_envelopes_to_emit());
+        }
+
+        while let Some(env) = queue.pop_front() {
+            let env = Arc::new(env);
+            for (node_id, node) in nodes.iter_mut() {
+                if node_id == &env.node_id {
+                    continue;
+                }
+                node.recv_envelope(env.clone());
+                queue.extend(node.take_envelopes_to_emit());
+            }
+            emitted.push(env.as_ref().clone());
+        }
+        emitted
+    }
+
+    #[test]
+    fn nodes_externalize_through_the_facade() {
+        let mut nodes = build_nodes();
+        let value = MockState::from_seed(0);
+
+        let node1 = nodes.get_mut("node1").unwrap();
+        assert!(node1.nominate(0, Arc::new(value.clone()), &MockState::empty()));
+        exchange(&mut nodes);
+
+        for node in nodes.values() {
+            assert_eq!(node.externalized_value(0), Some(&value));
+            assert_eq!(
+                node.externalization_proof(0).map(|proof| proof.value),
+                Some(value.clone())
+            );
+            assert!(node.got_v_blocking(0));
+            assert!(node.is_slot_fully_validated(0));
+        }
+        assert!(!nodes["node1"].got_v_blocking(1));
+    }
+
+    #[test]
+    fn state_is_restored_from_own_envelope() {
+        let mut nodes = build_nodes();
+        let value = MockState::from_seed(1);
+        nodes
+            .get_mut("node1")
+            .unwrap()
+            .nominate(0, Arc::new(value.clone()), &MockState::empty());
+        let emitted = exchange(&mut nodes);
+
+        let last_ballot_env = emitted
+            .iter()
+            .rev()
+            .find(|env| {
+                env.node_id == "node2" && !matches!(env.statement, SCPStatement::Nominate(_))
+            })
+            .unwrap()
+            .clone();
+
+        let mut restarted = build_nodes().remove("node2").unwrap();
+        assert_eq!(
+            restarted.set_state_from_envelope(1, Arc::new(last_ballot_env.clone())),
+            EnvelopeState::Invalid
+        );
+        assert_eq!(
+            restarted.set_state_from_envelope(0, Arc::new(last_ballot_env.clone())),
+            EnvelopeState::Valid
+        );
+        assert_eq!(restarted.externalized_value(0), Some(&value));
+        // The ballot protocol already started from the first envelope.
+        assert_eq!(
+            restarted.set_state_from_envelope(0, Arc::new(last_ballot_env)),
+            EnvelopeState::Invalid
+        );
+    }
+
+    #[test]
+    fn purged_slots_are_forgotten() {
+        let mut nodes = build_nodes();
+        let node1 = nodes.get_mut("node1").unwrap();
+        for slot_index in 0..4 {
+            node1.nominate(
+                slot_index,
+                Arc::new(MockState::from_seed(slot_index)),
+                &MockState::empty(),
+            );
+        }
+
+        // node2 announces a quorum set only slot 0 knows about.
+        let node2 = nodes["node2"].local_node.clone();
+        let mut quorum_set = node2.quorum_set.clone();
+        quorum_set.threshold += 1;
+        let value = MockState::from_seed(0);
+        let statement = SCPStatement::Prepare(SCPStatementPrepare {
+            quorum_set_hash: quorum_set.hash_value(),
+            ballot: SCPBallot::new(1, value.clone()),
+            prepared: Some(SCPBallot::new(1, value)),
+            prepared_prime: None,
+            num_commit: 0,
+            num_high: 0,
+            quorum_set: Some(quorum_set.clone()),
+        });
+        let mut env = SCPEnvelope::new(statement, "node2".to_string(), 0);
+        node2.sign_envelope(&mut env);
+
+        let node1 = nodes.get_mut("node1").unwrap();
+        node1.recv_envelope(Arc::new(env.clone()));
+        assert!(node1
+            .quorum_manager
+            .get_quorum_set(&env.statement)
+            .is_some());
+        let timer = node1.pop_timer(0).unwrap();
+
+        node1.purge_slots(3, 1);
+        assert_eq!(node1.slots.keys().copied().collect::<Vec<_>>(), vec![1, 3]);
+        assert!(!node1.stop_nomination(0));
+        assert!(node1.stop_nomination(3));
+        assert!(!node1.run_timer(0, timer));
+
+        let slot_indexes: BTreeSet<SlotIndex> = node1
+            .envelope_controller
+            .envelopes
+            .0
+            .values()
+            .map(|env| env.slot_index)
+            .collect();
+        assert_eq!(slot_indexes, BTreeSet::from([1, 3]));
+        assert!(node1
+            .quorum_manager
+            .get_quorum_set(&env.statement)
+            .is_none());
+        let local_statement = SCPStatement::<MockState>::Nominate(SCPStatementNominate::new(
+            &node1.local_node.quorum_set,
+            vec![],
+            vec![],
+        ));
+        assert!(node1
+            .quorum_manager
+            .get_quorum_set(&local_statement)
+            .is_some());
+    }
+
+    #[test]
+    fn slots_need_a_leader() {
+        let mut builder = LocalNodeInfoBuilderFromFile::new("model_check");
+        let local_node = builder.build_from_file("node1").unwrap();
+        let work_scheduler = Rc::new(RefCell::new(WorkScheduler::new(None)));
+        let mut scp = MockSCP::new(local_node, MockStateDriver::new(), work_scheduler);
+
+        assert!(!scp.nominate(0, Arc::new(MockState::from_seed(0)), &MockState::empty()));
+        assert!(scp.take_envelopes_to_emit().is_empty());
+    }
+
+    // Only the node's own prepare statements may carry a zero ballot counter.
+    #[test]
+    fn remote_prepares_need_a_ballot_counter() {
+        let mut nodes = build_nodes();
+        let node2 = nodes["node2"].local_node.clone();
+        let quorum_set = node2.quorum_set.clone();
+        // The prepared ballot keeps the values of the statement valid.
+        let prepare = |counter| {
+            let value = MockState::from_seed(0);
+            let statement = SCPStatement::Prepare(SCPStatementPrepare {
+                quorum_set_hash: quorum_set.hash_value(),
+                ballot: SCPBallot::new(counter, value.clone()),
+                prepared: Some(SCPBallot::new(1, value)),
+                prepared_prime: None,
+                num_commit: 0,
+                num_high: 0,
+                quorum_set: Some(quorum_set.clone()),
+            });
+            let mut env = SCPEnvelope::new(statement, "node2".to_string(), 0);
+            node2.sign_envelope(&mut env);
+            Arc::new(env)
+        };
+
+        let node1 = nodes.get_mut("node1").unwrap();
+        assert_eq!(node1.recv_envelope(prepare(0)), EnvelopeState::Invalid);
+        assert_eq!(node1.recv_envelope(prepare(1)), EnvelopeState::Valid);
+    }
+
+    #[test]
+    fn envelopes_need_the_senders_signature() {
+        let mut nodes = build_nodes();
+        let value = MockState::from_seed(2);
+        let node2 = nodes.get_mut("node2").unwrap();
+        node2.add_leader(&"node2".to_string());
+        assert!(node2.nominate(0, Arc::new(value), &MockState::empty()));
+        let env = node2.take_envelopes_to_emit().remove(0);
+
+        let node1 = nodes.get_mut("node1").unwrap();
+        let mut unsigned = env.clone();
+        unsigned.signature.clear();
+        assert_eq!(
+            node1.recv_envelope(Arc::new(unsigned)),
+            EnvelopeState::Invalid
+        );
+
+        let mut forged = env.clone();
+        forged.node_id = "node3".to_string();
+        assert_eq!(
+            node1.recv_envelope(Arc::new(forged)),
+            EnvelopeState::Invalid
+        );
+
+        let mut other_network = env.clone();
+        let mut node2_info = nodes["node2"].local_node.as_ref().clone();
+        node2_info.network_id = NetworkId::from_passphrase("other network");
+        node2_info.sign_envelope(&mut other_network);
+        let node1 = nodes.get_mut("node1").unwrap();
+        assert_eq!(
+            node1.recv_envelope(Arc::new(other_network)),
+            EnvelopeState::Invalid
+        );
+
+        assert_eq!(node1.recv_envelope(Arc::new(env)), EnvelopeState::Valid);
+    }
+}
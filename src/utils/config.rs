@@ -1,23 +1,30 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 const REL_TEST_DATA_DIR: &str = "test_data";
 
 pub fn project_src_path() -> PathBuf {
-    let output = String::from_utf8(
-        std::process::Command::new(env!("CARGO"))
-            .arg("locate-project")
-            .arg("--workspace")
-            .arg("--message-format=plain")
-            .output()
-            .unwrap()
-            .stdout,
-    )
-    .unwrap();
+    // Locating the project runs cargo, so only do it once per process.
+    static PATH: OnceLock<PathBuf> = OnceLock::new();
 
-    let (output, _) = output.rsplit_once("/").unwrap();
-    let path = Path::new(output).join("src");
+    PATH.get_or_init(|| {
+        let output = String::from_utf8(
+            std::process::Command::new(env!("CARGO"))
+                .arg("locate-project")
+                .arg("--workspace")
+                .arg("--message-format=plain")
+                .output()
+                .unwrap()
+                .stdout,
+        )
+        .unwrap();
 
-    path
+        let (output, _) = output.rsplit_once("/").unwrap();
+        Path::new(output).join("src")
+    })
+    .clone()
 }
 
 pub fn test_data_dir() -> PathBuf {